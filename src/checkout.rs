@@ -0,0 +1,365 @@
+/// This module updates the working tree and the index to the files of another tree, keeping the
+/// local changes of the files that are the same in both trees, like `git checkout` does.
+use crate::diff::tree::{self, Files};
+use crate::index::entry::IndexEntry;
+use crate::index::Index;
+use crate::objects;
+use crate::objects::blob::Blob;
+use crate::objects::id::ObjectID;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::tree::Tree;
+use crate::objects::Object;
+use crate::status;
+use crate::workspace::Workspace;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// The permissions of the written files, before the umask is applied, like git.
+const FILE_PERMISSIONS: u32 = 0o666;
+/// The permissions of the written executable files, before the umask is applied.
+const EXECUTABLE_PERMISSIONS: u32 = 0o777;
+
+/// Represents a change of the working tree and of the index: a file to write with its mode and
+/// object ID, or to delete.
+type Update = (PathBuf, Option<(EntryMode, ObjectID)>);
+
+/// Represents the command that updates the working tree, which is named in the errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Checkout,
+    Merge,
+}
+
+impl Operation {
+    /// Returns the name of the operation, as in "would be overwritten by checkout".
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Checkout => "checkout",
+            Operation::Merge => "merge",
+        }
+    }
+
+    /// Returns what the user is about to do, as in "before you switch branches".
+    fn action(&self) -> &'static str {
+        match self {
+            Operation::Checkout => "switch branches",
+            Operation::Merge => "merge",
+        }
+    }
+}
+
+/// Updates the working tree and the index from the tree of HEAD to another tree.
+///
+/// # Arguments
+///
+/// * `workspace` - The working tree.
+/// * `index` - The index, which is updated but not written.
+/// * `target` - The ID of the tree to check out.
+/// * `force` - If true, discard the local changes: the index and the working tree are made to
+///   match the target tree, except for untracked files.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if local changes or untracked files
+/// would be overwritten, or a file cannot be read or written.
+pub(crate) fn checkout_tree(
+    workspace: &Workspace,
+    index: &mut Index,
+    target: &ObjectID,
+    force: bool,
+) -> anyhow::Result<()> {
+    let target = Tree::flatten(&target.hash())?;
+    checkout_files(workspace, index, &target, force, Operation::Checkout)
+}
+
+/// Updates the working tree and the index from the tree of HEAD to the given files.
+///
+/// Only the files that differ between HEAD and the target are touched, so local changes to the
+/// other files are kept. A file that differs is updated if its index entry and working tree file
+/// match HEAD, or if the index already matches the target. Otherwise the update is refused,
+/// before anything is written, and so it is when an untracked file is in the way of a new file
+/// or when the index has conflicted files. Deleted files are removed first, with the directories
+/// they leave empty, and then new and changed files are written and staged with fresh stat data.
+/// The resolved conflicts remembered by the index are forgotten, since they no longer apply.
+///
+/// # Arguments
+///
+/// * `workspace` - The working tree.
+/// * `index` - The index, which is updated but not written.
+/// * `target` - The files to check out, whose blobs must be in the database.
+/// * `force` - If true, discard the local changes and the conflicts: the index and the working
+///   tree are made to match the target, except for untracked files.
+/// * `operation` - The command that updates the working tree, named in the errors.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if local changes or untracked files
+/// would be overwritten, or a file cannot be read or written.
+pub(crate) fn checkout_files(
+    workspace: &Workspace,
+    index: &mut Index,
+    target: &Files,
+    force: bool,
+    operation: Operation,
+) -> anyhow::Result<()> {
+    let head = status::head_files()?;
+    let staged = status::index_files(index.entries());
+
+    let updates = if force {
+        forced_updates(workspace, index, &staged, target)?
+    } else {
+        checked_updates(workspace, index, &head, &staged, target, operation)?
+    };
+
+    for (path, _) in updates.iter().filter(|(_, file)| file.is_none()) {
+        remove_file(workspace, path)?;
+        index.remove(path);
+    }
+    for (path, file) in &updates {
+        let Some((mode, oid)) = file else {
+            continue;
+        };
+        write_file(workspace, path, *mode, oid)?;
+        let meta = std::fs::symlink_metadata(workspace.dir.join(path))
+            .with_context(|| format!("Failed to read metadata for {}", path.display()))?;
+        index.add(IndexEntry::new(path.clone(), oid.clone(), &meta));
+    }
+    index.clear_resolve_undo();
+    Ok(())
+}
+
+/// Lists the updates of a checkout that keeps local changes, and checks that they overwrite none.
+fn checked_updates(
+    workspace: &Workspace,
+    index: &Index,
+    head: &Files,
+    staged: &Files,
+    target: &Files,
+    operation: Operation,
+) -> anyhow::Result<Vec<Update>> {
+    if index.has_conflicts() {
+        let conflicted = index
+            .conflicts()
+            .keys()
+            .map(|path| format!("{}: needs merge\n", path.display()))
+            .collect::<String>();
+        anyhow::bail!("{}you need to resolve your current index first", conflicted);
+    }
+
+    let mut updates = Vec::new();
+    let mut modified = Vec::new();
+    let mut untracked = Vec::new();
+    for change in tree::diff_files(head, target) {
+        let path = change.path().to_path_buf();
+        let (in_head, in_index, in_target) =
+            (head.get(&path), staged.get(&path), target.get(&path));
+        // The change is already staged.
+        if in_index == in_target {
+            continue;
+        }
+        if in_index != in_head {
+            modified.push(path);
+            continue;
+        }
+        match index.entry(&path) {
+            Some(entry) if is_modified(workspace, index, entry)? => modified.push(path.clone()),
+            Some(_) => {}
+            None => untracked.extend(untracked_in_the_way(workspace, index, &path)?),
+        }
+        updates.push((path, in_target.cloned()));
+    }
+
+    let list = |paths: &[PathBuf]| -> String {
+        paths
+            .iter()
+            .map(|path| format!("\t{}\n", path.display()))
+            .collect()
+    };
+    if !modified.is_empty() {
+        anyhow::bail!(
+            "Your local changes to the following files would be overwritten by {}:\n{}\
+             Please commit your changes or stash them before you {}.\nAborting",
+            operation.name(),
+            list(&modified),
+            operation.action()
+        );
+    }
+    if !untracked.is_empty() {
+        anyhow::bail!(
+            "The following untracked working tree files would be overwritten by {}:\n{}\
+             Please move or remove them before you {}.\nAborting",
+            operation.name(),
+            list(&untracked),
+            operation.action()
+        );
+    }
+    Ok(updates)
+}
+
+/// Lists the updates of a checkout that discards local changes: the files whose index entry or
+/// working tree file differs from the target tree, and the conflicted files.
+fn forced_updates(
+    workspace: &Workspace,
+    index: &Index,
+    staged: &Files,
+    target: &Files,
+) -> anyhow::Result<Vec<Update>> {
+    let mut updates = Vec::new();
+    let paths = index
+        .entries()
+        .iter()
+        .map(|entry| entry.path().to_path_buf())
+        .chain(target.keys().cloned())
+        .collect::<BTreeSet<_>>();
+    for path in &paths {
+        let in_target = target.get(path);
+        let changed = match index.entry_at(path, 0) {
+            Some(entry) => staged.get(path) != in_target || is_modified(workspace, index, entry)?,
+            None => true,
+        };
+        if changed {
+            updates.push((path.clone(), in_target.cloned()));
+        }
+    }
+    Ok(updates)
+}
+
+/// Returns true if the file of an index entry was changed in the working tree. A deleted file
+/// does not count as changed, since checking it out again loses nothing, and neither does a file
+/// marked as unchanged.
+fn is_modified(workspace: &Workspace, index: &Index, entry: &IndexEntry) -> anyhow::Result<bool> {
+    if entry.is_assumed_unchanged() {
+        return Ok(false);
+    }
+    let path = workspace.dir.join(entry.path());
+    let Ok(meta) = std::fs::symlink_metadata(&path) else {
+        return Ok(false);
+    };
+    if meta.is_dir() {
+        return Ok(true);
+    }
+    if index.stat_matches(entry, &meta) {
+        return Ok(false);
+    }
+    let oid = Blob::from_worktree(&path)?
+        .hash()
+        .with_context(|| format!("Failed to hash {}", path.display()))?;
+    Ok(oid != entry.oid
+        || EntryMode::from_index_mode(entry.mode) != Some(EntryMode::from_metadata(&meta)))
+}
+
+/// Returns the untracked files that a new file would overwrite: a file at its path, the files
+/// of a directory at its path, or a file where one of its parent directories should be. Ignored
+/// files are not listed, and are overwritten.
+fn untracked_in_the_way(
+    workspace: &Workspace,
+    index: &Index,
+    path: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for ancestor in path.ancestors() {
+        if ancestor.as_os_str().is_empty() {
+            break;
+        }
+        let Ok(meta) = std::fs::symlink_metadata(workspace.dir.join(ancestor)) else {
+            continue;
+        };
+        if meta.is_dir() {
+            if ancestor == path {
+                files.extend(
+                    workspace
+                        .list_files_in(path, false)?
+                        .into_iter()
+                        .filter(|file| index.entry(file).is_none()),
+                );
+            }
+        } else if index.entry(ancestor).is_none() && !workspace.is_ignored(ancestor, false)? {
+            files.push(ancestor.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// Removes a file from the working tree, and the directories it leaves empty.
+pub(crate) fn remove_file(workspace: &Workspace, path: &Path) -> anyhow::Result<()> {
+    let full_path = workspace.dir.join(path);
+    match std::fs::symlink_metadata(&full_path) {
+        Ok(meta) if !meta.is_dir() => std::fs::remove_file(&full_path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?,
+        _ => {}
+    }
+    for parent in path.ancestors().skip(1) {
+        if parent.as_os_str().is_empty() || std::fs::remove_dir(workspace.dir.join(parent)).is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a blob to a file of the working tree, as a symbolic link or as a regular file with
+/// the executable bit of its mode. Files and directories in the way are removed.
+fn write_file(
+    workspace: &Workspace,
+    path: &Path,
+    mode: EntryMode,
+    oid: &ObjectID,
+) -> anyhow::Result<()> {
+    // Make room for the file: its parents must be directories and the file must not exist.
+    for ancestor in path
+        .ancestors()
+        .skip(1)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        let dir = workspace.dir.join(ancestor);
+        if std::fs::symlink_metadata(&dir).is_ok_and(|meta| !meta.is_dir()) {
+            std::fs::remove_file(&dir)
+                .with_context(|| format!("Failed to remove {}", ancestor.display()))?;
+        }
+    }
+    let full_path = workspace.dir.join(path);
+    if let Some(parent) = full_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    match std::fs::symlink_metadata(&full_path) {
+        Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(&full_path),
+        Ok(_) => std::fs::remove_file(&full_path),
+        Err(_) => Ok(()),
+    }
+    .with_context(|| format!("Failed to remove {}", path.display()))?;
+
+    let (_, _, mut reader) = objects::read_object(&oid.hash())?;
+    match mode {
+        EntryMode::Symlink => {
+            let mut target = Vec::new();
+            reader
+                .read_to_end(&mut target)
+                .with_context(|| format!("Failed to read blob {}", oid))?;
+            std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(&target), &full_path)
+                .with_context(|| format!("Failed to create symbolic link {}", path.display()))?;
+        }
+        _ => {
+            let permissions = match mode {
+                EntryMode::Executable => EXECUTABLE_PERMISSIONS,
+                _ => FILE_PERMISSIONS,
+            };
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(permissions)
+                .open(&full_path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            std::io::copy(&mut reader, &mut file)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
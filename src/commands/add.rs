@@ -1,27 +1,194 @@
+use crate::ignore;
 use crate::index;
-use crate::objects;
+use crate::objects::blob::Blob;
+use crate::objects::tree::mode::EntryMode;
 use crate::objects::Object;
+use crate::workspace::Workspace;
 use anyhow::Context;
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
 
-pub(crate) fn invoke(path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+/// Invokes the `add` subcommand.
+///
+/// Each pathspec is either a path, whose files are added recursively if it is a directory, or a
+/// glob such as `'*.rs'`, which is matched against the files of the working tree and the index.
+/// Tracked files that were deleted from the working tree are removed from the index. Untracked
+/// files that are ignored are skipped when found in a directory, and rejected when named
+/// explicitly, unless `force` is set.
+///
+/// # Arguments
+///
+/// * `pathspecs` - The paths or globs of the files to add.
+/// * `all` - If true, and no pathspec is given, add the whole working tree.
+/// * `update` - If true, only update files that are already in the index. Without a pathspec,
+///   this updates the whole working tree.
+/// * `force` - If true, add ignored files too.
+/// * `dry_run` - If true, print what would be added and removed without changing the index.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the files were added, or an `anyhow::Error` if a pathspec
+/// matches no file, an ignored file is named explicitly, or the index cannot be updated.
+pub(crate) fn invoke(
+    pathspecs: &[String],
+    all: bool,
+    update: bool,
+    force: bool,
+    dry_run: bool,
+) -> anyhow::Result<()> {
     let index_path = ".git/index";
-    let mut index = index::Index::init(index_path)?;
-    let path = path.as_ref();
-    if path.is_dir() {
-        anyhow::bail!("add is not implemented for directories yet.");
-    } else {
-        let mut blob = objects::blob::Blob::from_file(path)
+    let mut index = index::Index::lock(index_path)?;
+    let workspace = Workspace::new(Path::new("."))?;
+
+    let pathspecs = match pathspecs {
+        [] if all || update => vec![String::from(".")],
+        [] => anyhow::bail!("Nothing specified, nothing added."),
+        pathspecs => pathspecs.to_vec(),
+    };
+
+    // Collect the files to add and the files to remove.
+    let mut additions = BTreeSet::new();
+    let mut deletions = BTreeSet::new();
+    let mut ignored = Vec::new();
+    for pathspec in &pathspecs {
+        let mut matched = false;
+        if is_glob(pathspec) {
+            if !update {
+                for path in workspace.list_files_in(Path::new(""), force)? {
+                    if ignore::fnmatch(pathspec, &path) {
+                        additions.insert(path);
+                        matched = true;
+                    }
+                }
+            }
+            for entry in index.entries() {
+                if ignore::fnmatch(pathspec, entry.path()) {
+                    additions.insert(entry.path().to_path_buf());
+                    matched = true;
+                }
+            }
+        } else {
+            let path = normalize(pathspec)?;
+            if let Ok(meta) = std::fs::symlink_metadata(workspace.dir.join(&path)) {
+                if meta.is_dir() {
+                    if !update {
+                        additions.extend(workspace.list_files_in(&path, force)?);
+                    }
+                    matched = true;
+                } else if index.entry(&path).is_some() {
+                    additions.insert(path.clone());
+                    matched = true;
+                } else if !update {
+                    if !force && workspace.is_ignored(&path, false)? {
+                        ignored.push(path.clone());
+                    } else {
+                        additions.insert(path.clone());
+                    }
+                    matched = true;
+                }
+            }
+            // Tracked files are updated even if they are ignored.
+            for entry in index.entries() {
+                if entry.path().starts_with(&path) {
+                    additions.insert(entry.path().to_path_buf());
+                    matched = true;
+                }
+            }
+        }
+        anyhow::ensure!(matched, "pathspec '{}' did not match any files", pathspec);
+    }
+    // Files marked as unchanged are left as they are in the index.
+    additions.retain(|path| {
+        index
+            .entry(path)
+            .is_none_or(|entry| !entry.is_assumed_unchanged())
+    });
+    additions.retain(|path| {
+        let exists =
+            std::fs::symlink_metadata(workspace.dir.join(path)).is_ok_and(|meta| !meta.is_dir());
+        if !exists {
+            deletions.insert(path.clone());
+        }
+        exists
+    });
+
+    // Update the index.
+    for path in additions {
+        let full_path = workspace.dir.join(&path);
+        let meta = std::fs::symlink_metadata(&full_path)
+            .with_context(|| format!("Failed to get metadata for: {}", path.display()))?;
+        let existing = index.entry(&path);
+        if existing.is_some_and(|entry| index.stat_matches(entry, &meta)) {
+            continue;
+        }
+
+        let mut blob = Blob::from_worktree(&full_path)
             .with_context(|| format!("Failed to create blob for: {}", path.display()))?;
-        let oid = blob
-            .write()
+        let oid = if dry_run { blob.hash() } else { blob.write() }
             .with_context(|| format!("Failed to write blob for: {}", path.display()))?;
-        let meta = std::fs::metadata(path)
-            .with_context(|| format!("Failed to get metadata for: {}", path.display()))?;
-        let entry = index::entry::IndexEntry::new(path.to_path_buf(), oid, &meta);
-        index.add(entry);
+        let changed = existing.is_none_or(|entry| {
+            entry.oid != oid
+                || EntryMode::from_index_mode(entry.mode) != Some(EntryMode::from_metadata(&meta))
+        });
+        if dry_run {
+            if changed {
+                println!("add '{}'", path.display());
+            }
+            continue;
+        }
+        index.add(index::entry::IndexEntry::new(path, oid, &meta));
+    }
+    for path in deletions {
+        if dry_run {
+            println!("remove '{}'", path.display());
+        } else {
+            index.remove(&path);
+        }
     }
 
-    index
-        .write(index_path)
-        .with_context(|| format!("Failed to write index to: {}", index_path))
+    if !dry_run {
+        index.write()?;
+    }
+
+    if !ignored.is_empty() {
+        let paths = ignored
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "The following paths are ignored by one of your .gitignore files:\n{}\n\
+             Use -f if you really want to add them.",
+            paths.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Returns true if the pathspec contains glob characters.
+pub(crate) fn is_glob(pathspec: &str) -> bool {
+    pathspec.contains(['*', '?', '['])
+}
+
+/// Turns a pathspec into a path relative to the root of the working tree, resolving `.` and `..`
+/// components, e.g. `./src/../README.md` becomes `README.md`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the normalized path, or an `anyhow::Error` if the path is outside
+/// the working tree.
+pub(crate) fn normalize(pathspec: &str) -> anyhow::Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(pathspec).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                anyhow::ensure!(path.pop(), "'{}' is outside repository", pathspec);
+            }
+            Component::Normal(name) => path.push(name),
+            Component::RootDir | Component::Prefix(_) => {
+                anyhow::bail!("'{}' is outside repository", pathspec)
+            }
+        }
+    }
+    Ok(path)
 }
@@ -0,0 +1,464 @@
+use crate::commands::cli::BranchAction;
+use crate::config;
+use crate::config::file::ConfigFile;
+use crate::ignore;
+use crate::objects::commit::Commit;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::refs::{self, Expected};
+use crate::rev_walk::{CommitGraph, RevWalk};
+use crate::revision;
+use crate::workspace::Workspace;
+use anyhow::Context;
+use std::path::Path;
+
+/// Prefix of the refs holding branches.
+const HEADS_PREFIX: &str = "refs/heads/";
+/// Prefix of the refs holding remote-tracking branches.
+const REMOTES_PREFIX: &str = "refs/remotes/";
+/// The remote of a branch whose upstream is another local branch.
+const LOCAL_REMOTE: &str = ".";
+
+/// Invokes the `branch` subcommand.
+///
+/// Without arguments, or with `list`, prints the branches matching any of the patterns, marking
+/// the current one. Otherwise creates a branch named by the first argument, starting at the
+/// commit named by the second argument or at HEAD, unless an action asks to delete, rename or
+/// set the upstream of branches instead.
+///
+/// # Arguments
+///
+/// * `args` - The patterns to list, the name and start point of the new branch, or the branches
+///   the action applies to.
+/// * `action` - What to do instead of creating a branch.
+/// * `list` - If true, list the branches.
+/// * `verbose` - How much to show about each listed branch: with 1, its commit and how far it
+///   is from its upstream, and with 2, the name of its upstream as well.
+/// * `track` - If true, set the start point as the upstream of the new branch.
+/// * `force` - If true, reset an existing branch to the start point, or with `delete`, delete
+///   branches that are not merged.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if a revision cannot be resolved or
+/// a ref or the config file cannot be written.
+pub(crate) fn invoke(
+    args: &[String],
+    action: &BranchAction,
+    list: bool,
+    verbose: u8,
+    track: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    if action.delete || action.force_delete {
+        return delete_branches(args, action.force_delete || force);
+    }
+    if action.rename || action.force_rename {
+        return rename_branch(args, action.force_rename || force);
+    }
+    if let Some(upstream) = &action.set_upstream_to {
+        let branch = branch_argument(args, "set upstream of")?;
+        return set_upstream(&branch, upstream);
+    }
+    if action.unset_upstream {
+        let branch = branch_argument(args, "unset upstream of")?;
+        return unset_upstream(&branch);
+    }
+    if list || args.is_empty() {
+        return list_branches(args, verbose);
+    }
+
+    match args {
+        [name] => {
+            // Like git, the reflog names the current branch as the start point.
+            let head = refs::current_branch()?;
+            let start = head
+                .as_deref()
+                .map_or(refs::HEAD, |head| refs::shorten(head));
+            create_branch(name, start, force, track)
+        }
+        [name, start] => create_branch(name, start, force, track),
+        _ => anyhow::bail!("Too many arguments."),
+    }
+}
+
+/// Prints the branches matching any of the patterns, or all of them if there are none, with a
+/// `*` before the current branch, or before the detached HEAD.
+fn list_branches(patterns: &[String], verbose: u8) -> anyhow::Result<()> {
+    let current = refs::current_branch()?;
+    let mut rows = Vec::new();
+    if current.is_none() && patterns.is_empty() {
+        if let Some(oid) = refs::resolve(refs::HEAD)? {
+            let name = format!("(HEAD detached at {})", revision::abbreviate(&oid, 7)?);
+            rows.push((true, name, None, oid));
+        }
+    }
+    for (full_name, oid) in refs::list_refs(HEADS_PREFIX)? {
+        let name = &full_name[HEADS_PREFIX.len()..];
+        let matches = patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|pattern| ignore::fnmatch(pattern, Path::new(name)));
+        if matches {
+            let is_current = current.as_deref() == Some(full_name.as_str());
+            rows.push((is_current, name.to_string(), Some(name.to_string()), oid));
+        }
+    }
+
+    let width = rows
+        .iter()
+        .map(|(_, name, _, _)| name.chars().count())
+        .max()
+        .unwrap_or_default();
+    for (is_current, name, branch, oid) in rows {
+        let marker = if is_current { '*' } else { ' ' };
+        if verbose == 0 {
+            println!("{} {}", marker, name);
+            continue;
+        }
+        let tracking = match &branch {
+            Some(branch) => format_tracking(branch, &oid, verbose > 1)?,
+            None => String::new(),
+        };
+        println!(
+            "{} {:<width$} {} {}{}",
+            marker,
+            name,
+            revision::abbreviate(&oid, 7)?,
+            tracking,
+            Commit::read(&oid.hash())?.subject(),
+        );
+    }
+    Ok(())
+}
+
+/// Formats how far a branch is from its upstream for `branch -v`, e.g. `[ahead 1, behind 2] `,
+/// with the name of the upstream first if `show_upstream`. Branches without an upstream have
+/// nothing to show.
+fn format_tracking(branch: &str, oid: &ObjectID, show_upstream: bool) -> anyhow::Result<String> {
+    let Some(upstream) = upstream(branch)? else {
+        return Ok(String::new());
+    };
+    let counts = match refs::resolve(&upstream)? {
+        Some(upstream) => {
+            let (ahead, behind) = ahead_behind(oid, &upstream)?;
+            [("ahead", ahead), ("behind", behind)]
+                .iter()
+                .filter(|(_, count)| *count > 0)
+                .map(|(label, count)| format!("{} {}", label, count))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        None => String::from("gone"),
+    };
+    let name = refs::shorten(&upstream);
+    Ok(match (show_upstream, counts.is_empty()) {
+        (true, true) => format!("[{}] ", name),
+        (true, false) => format!("[{}: {}] ", name, counts),
+        (false, true) => String::new(),
+        (false, false) => format!("[{}] ", counts),
+    })
+}
+
+/// Creates a branch, or resets it with `force`, at the commit named by `start`.
+fn create_branch(name: &str, start: &str, force: bool, track: bool) -> anyhow::Result<()> {
+    let full_name = branch_ref(name)?;
+    let old = refs::resolve(&full_name)?;
+    if old.is_some() {
+        anyhow::ensure!(force, "a branch named '{}' already exists", name);
+        anyhow::ensure!(
+            refs::current_branch()?.as_deref() != Some(full_name.as_str()),
+            "cannot force update the branch '{}' checked out at '{}'",
+            name,
+            worktree_dir()?
+        );
+    }
+
+    let oid = revision::resolve(start)
+        .and_then(|oid| revision::peel(&oid, ObjectKind::Commit))
+        .with_context(|| format!("not a valid object name: '{}'", start))?;
+    let (expected, message) = match old {
+        Some(old) => (Expected::Value(old), format!("branch: Reset to {}", start)),
+        None => (Expected::Missing, format!("branch: Created from {}", start)),
+    };
+    refs::update_ref(&full_name, &oid, expected, &message, true)?;
+
+    // Like git, remote-tracking branches are always tracked by the branches started from them.
+    let remote_tracking = refs::read_ref(&format!("{}{}", REMOTES_PREFIX, start))?.is_some();
+    if track || remote_tracking {
+        set_upstream(name, start)?;
+    }
+    Ok(())
+}
+
+/// Deletes branches. Unless `force`, a branch must be merged into its upstream, or into HEAD if
+/// it has none. Branches that cannot be deleted are reported, and the others are still deleted.
+fn delete_branches(names: &[String], force: bool) -> anyhow::Result<()> {
+    anyhow::ensure!(!names.is_empty(), "branch name required");
+    let current = refs::current_branch()?;
+    let head = refs::resolve(refs::HEAD)?;
+    let mut graph = CommitGraph::default();
+    let mut errors = 0;
+    for name in names {
+        let full_name = format!("{}{}", HEADS_PREFIX, name);
+        if current.as_deref() == Some(full_name.as_str()) {
+            eprintln!(
+                "error: Cannot delete branch '{}' checked out at '{}'",
+                name,
+                worktree_dir()?
+            );
+            errors += 1;
+            continue;
+        }
+        let Some(oid) = refs::resolve(&full_name)? else {
+            eprintln!("error: branch '{}' not found.", name);
+            errors += 1;
+            continue;
+        };
+
+        if !force {
+            let upstream = match upstream(name)? {
+                Some(upstream) => refs::resolve(&upstream)?.map(|oid| (upstream, oid)),
+                None => None,
+            };
+            let reference = upstream.as_ref().map(|(_, oid)| oid).or(head.as_ref());
+            let merged = match reference {
+                Some(reference) => graph.is_ancestor(&oid, reference)?,
+                None => false,
+            };
+            if !merged {
+                eprintln!(
+                    "error: The branch '{}' is not fully merged.\n\
+                     If you are sure you want to delete it, run 'bran branch -D {}'.",
+                    name, name
+                );
+                errors += 1;
+                continue;
+            }
+            if let (Some((upstream, _)), Some(head)) = (&upstream, &head) {
+                if !graph.is_ancestor(&oid, head)? {
+                    eprintln!(
+                        "warning: deleting branch '{}' that has been merged to\n         '{}', \
+                         but not yet merged to HEAD.",
+                        name,
+                        refs::shorten(upstream)
+                    );
+                }
+            }
+        }
+
+        refs::delete_ref(&full_name, Expected::Value(oid.clone()), true)?;
+        let mut file = ConfigFile::read(config::LOCAL_CONFIG)?;
+        if file.remove_section("branch", Some(name))? {
+            file.write()?;
+        }
+        println!(
+            "Deleted branch {} (was {}).",
+            name,
+            revision::abbreviate(&oid, 7)?
+        );
+    }
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Renames a branch, the current one if only the new name is given, along with its reflog and
+/// its config. With `force`, an existing branch with the new name is replaced.
+fn rename_branch(args: &[String], force: bool) -> anyhow::Result<()> {
+    let current = refs::current_branch()?;
+    let (old, new) = match args {
+        [new] => match &current {
+            Some(current) => (current[HEADS_PREFIX.len()..].to_string(), new),
+            None => anyhow::bail!("cannot rename the current branch while not on any."),
+        },
+        [old, new] => (old.clone(), new),
+        _ => anyhow::bail!("branch name required"),
+    };
+    let old_ref = format!("{}{}", HEADS_PREFIX, old);
+    let new_ref = branch_ref(new)?;
+    let is_current = current.as_deref() == Some(old_ref.as_str());
+    let exists = refs::read_ref(&old_ref)?.is_some();
+    anyhow::ensure!(exists || is_current, "No branch named '{}'.", old);
+    if old != *new {
+        anyhow::ensure!(
+            force || refs::read_ref(&new_ref)?.is_none(),
+            "a branch named '{}' already exists",
+            new
+        );
+    }
+
+    if exists {
+        let message = format!("Branch: renamed {} to {}", old_ref, new_ref);
+        refs::rename_ref(&old_ref, &new_ref, &message, force)?;
+    } else {
+        // The current branch does not have any commit yet, so only HEAD moves.
+        refs::write_symbolic_ref(refs::HEAD, &new_ref)?;
+    }
+
+    let mut file = ConfigFile::read(config::LOCAL_CONFIG)?;
+    if file.rename_section("branch", &old, new)? {
+        file.write()?;
+    }
+    Ok(())
+}
+
+/// Sets the upstream of a branch: another local branch, or a remote-tracking branch such as
+/// `origin/main`, recorded as `branch.<name>.remote` and `branch.<name>.merge`.
+fn set_upstream(branch: &str, upstream: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        refs::read_ref(&format!("{}{}", HEADS_PREFIX, branch))?.is_some(),
+        "branch '{}' does not exist",
+        branch
+    );
+    let (remote, merge) = if refs::read_ref(&format!("{}{}", REMOTES_PREFIX, upstream))?.is_some() {
+        let (remote, name) = upstream
+            .split_once('/')
+            .with_context(|| format!("Invalid remote-tracking branch: {}", upstream))?;
+        (remote.to_string(), format!("{}{}", HEADS_PREFIX, name))
+    } else if refs::read_ref(&format!("{}{}", HEADS_PREFIX, upstream))?.is_some() {
+        (
+            LOCAL_REMOTE.to_string(),
+            format!("{}{}", HEADS_PREFIX, upstream),
+        )
+    } else {
+        anyhow::bail!(
+            "the requested upstream branch '{}' does not exist",
+            upstream
+        );
+    };
+
+    let mut file = ConfigFile::read(config::LOCAL_CONFIG)?;
+    file.set(&format!("branch.{}.remote", branch), &remote, false)?;
+    file.set(&format!("branch.{}.merge", branch), &merge, false)?;
+    file.write()?;
+    println!("branch '{}' set up to track '{}'.", branch, upstream);
+    Ok(())
+}
+
+/// Removes the upstream of a branch, and its config section if nothing else is left in it.
+fn unset_upstream(branch: &str) -> anyhow::Result<()> {
+    let mut file = ConfigFile::read(config::LOCAL_CONFIG)?;
+    let merge_key = format!("branch.{}.merge", branch);
+    anyhow::ensure!(
+        file.entries()
+            .iter()
+            .any(|entry| entry.key() == config::normalize_key(&merge_key).unwrap_or_default()),
+        "Branch '{}' has no upstream information",
+        branch
+    );
+    file.unset(&format!("branch.{}.remote", branch), true)?;
+    file.unset(&merge_key, true)?;
+    let empty = !file
+        .entries()
+        .iter()
+        .any(|entry| entry.section == "branch" && entry.subsection.as_deref() == Some(branch));
+    if empty {
+        file.remove_section("branch", Some(branch))?;
+    }
+    file.write()
+}
+
+/// Returns the full name of the upstream of a branch, e.g. `refs/remotes/origin/main` or
+/// `refs/heads/main`, or `None` if the branch has no upstream.
+///
+/// # Arguments
+///
+/// * `branch` - The short name of the branch.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the name of the upstream, which may not exist anymore, or an
+/// `anyhow::Error` if the config cannot be read.
+pub(crate) fn upstream(branch: &str) -> anyhow::Result<Option<String>> {
+    let config = config::Config::load()?;
+    let remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+    let (Some(remote), Some(merge)) = (remote, merge) else {
+        return Ok(None);
+    };
+    if remote == LOCAL_REMOTE {
+        return Ok(Some(merge.to_string()));
+    }
+    let name = merge.strip_prefix(HEADS_PREFIX).unwrap_or(merge);
+    Ok(Some(format!("{}{}/{}", REMOTES_PREFIX, remote, name)))
+}
+
+/// Counts the commits of a branch that are not in its upstream, and the commits of the upstream
+/// that are not in the branch.
+///
+/// # Arguments
+///
+/// * `branch` - The commit of the branch.
+/// * `upstream` - The commit of its upstream.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the number of commits ahead and behind, or an `anyhow::Error`
+/// if a commit cannot be read.
+pub(crate) fn ahead_behind(
+    branch: &ObjectID,
+    upstream: &ObjectID,
+) -> anyhow::Result<(usize, usize)> {
+    let count = |from: &ObjectID, to: &ObjectID| -> anyhow::Result<usize> {
+        let mut walk = RevWalk::new();
+        walk.push(&format!("{}..{}", from, to))?;
+        Ok(walk.walk()?.len())
+    };
+    Ok((count(upstream, branch)?, count(branch, upstream)?))
+}
+
+/// Returns the full name of a new branch, checking that it is valid.
+fn branch_ref(name: &str) -> anyhow::Result<String> {
+    let full_name = format!("{}{}", HEADS_PREFIX, name);
+    refs::check_name(&full_name)
+        .map_err(|_| anyhow::anyhow!("'{}' is not a valid branch name", name))?;
+    Ok(full_name)
+}
+
+/// Returns the branch an upstream action applies to: the given one, or the current branch.
+fn branch_argument(args: &[String], action: &str) -> anyhow::Result<String> {
+    match args {
+        [branch] => Ok(branch.clone()),
+        [] => match refs::current_branch()? {
+            Some(branch) => Ok(branch[HEADS_PREFIX.len()..].to_string()),
+            None => anyhow::bail!(
+                "could not {} HEAD when it does not point to any branch.",
+                action
+            ),
+        },
+        _ => anyhow::bail!("Too many arguments."),
+    }
+}
+
+/// Returns the absolute path of the working tree, for error messages.
+fn worktree_dir() -> anyhow::Result<String> {
+    let workspace = Workspace::new(Path::new("."))?;
+    let dir = std::fs::canonicalize(&workspace.dir).unwrap_or(workspace.dir);
+    Ok(dir.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn counts_commits_ahead_and_behind() {
+        let repo = TestRepo::new();
+        // The commits share a date, so the counts cannot rely on dates.
+        let base = repo.commit(&[("file", "base")], &[], "base");
+        let upstream = repo.commit(&[("file", "upstream")], &[&base], "upstream");
+        let mut branch = upstream.clone();
+        for message in ["one", "two", "three"] {
+            branch = repo.commit(&[("file", message)], &[&branch], message);
+        }
+        assert_eq!(ahead_behind(&branch, &upstream).unwrap(), (3, 0));
+        assert_eq!(ahead_behind(&upstream, &branch).unwrap(), (0, 3));
+        assert_eq!(ahead_behind(&branch, &branch).unwrap(), (0, 0));
+
+        let other = repo.commit(&[("file", "other")], &[&base], "other");
+        assert_eq!(ahead_behind(&branch, &other).unwrap(), (4, 1));
+    }
+}
@@ -1,13 +1,15 @@
 use crate::objects;
 use crate::objects::Object;
+use crate::revision;
 use anyhow::Context;
+use std::io::Write;
 
 /// Invokes the `cat-file` subcommand.
 ///
 /// # Arguments
 ///
 /// * `pretty_print` - Pretty print flag.
-/// * `object_hash` - Object hash to cat.
+/// * `object_hash` - The object to cat, as a revision expression.
 ///
 /// # Returns
 ///
@@ -18,6 +20,7 @@ pub(crate) fn invoke(pretty_print: bool, object_hash: &str) -> anyhow::Result<()
         "Mode must be given without -p, and we don't support mode yet."
     );
 
+    let object_hash = &revision::resolve(object_hash)?.hash();
     let (kind, size, reader) = objects::read_object(object_hash)?;
 
     match kind {
@@ -41,7 +44,18 @@ pub(crate) fn invoke(pretty_print: bool, object_hash: &str) -> anyhow::Result<()
                 println!("{}", entry);
             }
         }
-        _ => anyhow::bail!("Object type not supported yet."),
+        objects::kind::ObjectKind::Commit => {
+            let commit = objects::commit::Commit::read(object_hash)?;
+            std::io::stdout()
+                .write_all(&commit.to_bytes())
+                .context("Failed to write to stdout.")?;
+        }
+        objects::kind::ObjectKind::Tag => {
+            let tag = objects::tag::Tag::read(object_hash)?;
+            std::io::stdout()
+                .write_all(&tag.to_bytes())
+                .context("Failed to write to stdout.")?;
+        }
     };
 
     Ok(())
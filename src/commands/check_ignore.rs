@@ -0,0 +1,55 @@
+use crate::index;
+use crate::workspace::Workspace;
+use std::path::Path;
+
+/// Invokes the `check-ignore` subcommand.
+///
+/// Prints each path that is ignored by the ignore rules of the working tree. Paths that are in
+/// the index are never reported as ignored. Exits with status 1 if none of the paths is ignored.
+///
+/// # Arguments
+///
+/// * `paths` - The paths to check.
+/// * `verbose` - If true, print the deciding pattern as `<source>:<line>:<pattern>\t<path>`.
+///   Negated patterns are printed too, even though the path they match is not ignored.
+/// * `non_matching` - If true, also print the paths that match no pattern, as `::\t<path>`.
+pub(crate) fn invoke(paths: &[String], verbose: bool, non_matching: bool) -> anyhow::Result<()> {
+    let workspace = Workspace::new(Path::new("."))?;
+    let index = index::Index::init(".git/index")?;
+
+    let mut found = false;
+    for path in paths {
+        let is_dir = path.ends_with('/') || Path::new(path).is_dir();
+        let tracked = index
+            .entries()
+            .iter()
+            .any(|entry| entry.path() == Path::new(path));
+        let pattern = if tracked {
+            None
+        } else {
+            workspace
+                .ignore
+                .with_match(Path::new(path), is_dir, |pattern| {
+                    pattern.map(|pattern| (pattern.to_string(), pattern.is_negated()))
+                })?
+        };
+
+        match pattern {
+            Some((pattern, negated)) => {
+                found |= !negated;
+                if verbose {
+                    println!("{}\t{}", pattern, path);
+                } else if !negated {
+                    println!("{}", path);
+                }
+            }
+            None if non_matching => println!("::\t{}", path),
+            None => {}
+        }
+    }
+
+    if !found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
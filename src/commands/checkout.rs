@@ -0,0 +1,171 @@
+use crate::checkout;
+use crate::index::Index;
+use crate::objects::commit::Commit;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::refs::{self, reflog, Expected};
+use crate::revision;
+use crate::workspace::Workspace;
+use anyhow::Context;
+
+/// Represents what HEAD points to after a checkout.
+pub(crate) enum Target {
+    /// An existing branch, by its short name.
+    Branch(String),
+    /// A new branch, by its short name, created at the given start point.
+    NewBranch(String, String),
+    /// A commit, by the revision naming it, with a detached HEAD.
+    Detached(String),
+}
+
+/// Invokes the `checkout` subcommand.
+///
+/// Checks out a branch, or a commit with a detached HEAD when the target is not the name of a
+/// branch, or creates a new branch and checks it out.
+///
+/// # Arguments
+///
+/// * `target` - The branch or commit to check out, or the start point of the new branch.
+/// * `new_branch` - The name of a branch to create and check out.
+/// * `detach` - If true, detach HEAD at the commit even if the target is a branch.
+/// * `force` - If true, discard the local changes instead of refusing to overwrite them.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if the target cannot be resolved or
+/// local changes would be overwritten.
+pub(crate) fn invoke(
+    target: Option<&str>,
+    new_branch: Option<&str>,
+    detach: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    let target = match (new_branch, target) {
+        (Some(name), start) => Target::NewBranch(name.to_string(), start.unwrap_or("HEAD").into()),
+        (None, Some(target)) if !detach && branch_exists(target)? => {
+            Target::Branch(target.to_string())
+        }
+        (None, Some(target)) => {
+            anyhow::ensure!(
+                revision::resolve(target).is_ok(),
+                "pathspec '{}' did not match any file(s) known to git",
+                target
+            );
+            Target::Detached(target.to_string())
+        }
+        (None, None) if detach => Target::Detached(String::from("HEAD")),
+        (None, None) => anyhow::bail!("You must specify a branch or commit to check out."),
+    };
+    switch_to(&target, force)
+}
+
+/// Returns true if a branch with the given short name exists.
+pub(crate) fn branch_exists(name: &str) -> anyhow::Result<bool> {
+    Ok(refs::read_ref(&format!("refs/heads/{}", name))?.is_some())
+}
+
+/// Updates the working tree, the index and HEAD to a branch or commit.
+///
+/// The files are checked out first, so nothing changes if local changes would be overwritten.
+/// HEAD then points to the branch, or to the commit when it is detached, and the move is
+/// recorded in the reflog of HEAD. Progress messages are printed to stderr, like git does.
+///
+/// # Arguments
+///
+/// * `target` - What HEAD should point to.
+/// * `force` - If true, discard the local changes instead of refusing to overwrite them.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if the target cannot be resolved,
+/// local changes would be overwritten or a ref cannot be updated.
+pub(crate) fn switch_to(target: &Target, force: bool) -> anyhow::Result<()> {
+    let old_branch = refs::current_branch()?;
+    let old_oid = refs::resolve(refs::HEAD)?;
+
+    let (name, rev) = match target {
+        Target::Branch(name) => (Some(name.as_str()), format!("refs/heads/{}", name)),
+        Target::NewBranch(name, start) => (Some(name.as_str()), start.clone()),
+        Target::Detached(rev) => (None, rev.clone()),
+    };
+    if let Target::NewBranch(name, _) = target {
+        refs::check_name(&format!("refs/heads/{}", name))
+            .map_err(|_| anyhow::anyhow!("'{}' is not a valid branch name", name))?;
+        anyhow::ensure!(
+            !branch_exists(name)?,
+            "a branch named '{}' already exists",
+            name
+        );
+    }
+
+    // Creating a branch on a branch without commits only moves HEAD.
+    let unborn =
+        matches!(target, Target::NewBranch(_, start) if start == "HEAD") && old_oid.is_none();
+    let oid = if unborn {
+        None
+    } else {
+        let oid = revision::resolve(&rev)
+            .and_then(|oid| revision::peel(&oid, ObjectKind::Commit))
+            .with_context(|| format!("reference is not a tree: {}", rev))?;
+        Some(oid)
+    };
+
+    if let Some(oid) = &oid {
+        let workspace = Workspace::new(std::path::Path::new("."))?;
+        let mut index = Index::lock(".git/index")?;
+        let tree = Commit::read(&oid.hash())?.tree;
+        checkout::checkout_tree(&workspace, &mut index, &tree, force)?;
+        index.write().context("Failed to write index")?;
+    }
+
+    let from = match (&old_branch, &old_oid) {
+        (Some(branch), _) => refs::shorten(branch).to_string(),
+        (None, Some(oid)) => oid.to_string(),
+        (None, None) => String::from("HEAD"),
+    };
+    let to = match target {
+        Target::Branch(name) | Target::NewBranch(name, _) => name.clone(),
+        Target::Detached(rev) => rev.clone(),
+    };
+    let message = format!("checkout: moving from {} to {}", from, to);
+    if let (None, Some(old), Some(new)) = (&old_branch, &old_oid, &oid) {
+        if old != new {
+            eprintln!("Previous HEAD position was {}", describe(old)?);
+        }
+    }
+
+    match (name, &oid) {
+        (Some(name), _) => {
+            let full_name = format!("refs/heads/{}", name);
+            if let (Target::NewBranch(_, start), Some(oid)) = (target, &oid) {
+                let reason = format!("branch: Created from {}", start);
+                refs::update_ref(&full_name, oid, Expected::Missing, &reason, false)?;
+            }
+            let already_on = old_branch.as_deref() == Some(full_name.as_str());
+            refs::write_symbolic_ref(refs::HEAD, &full_name)?;
+            if let Some(oid) = &oid {
+                reflog::append(refs::HEAD, old_oid.as_ref(), oid, &message)?;
+            }
+            match target {
+                Target::NewBranch(..) => eprintln!("Switched to a new branch '{}'", name),
+                _ if already_on => eprintln!("Already on '{}'", name),
+                _ => eprintln!("Switched to branch '{}'", name),
+            }
+        }
+        (None, Some(oid)) => {
+            refs::update_ref(refs::HEAD, oid, Expected::Any, &message, true)?;
+            eprintln!("HEAD is now at {}", describe(oid)?);
+        }
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// Describes a commit by its abbreviated ID and subject, e.g. `1a2b3c4 Add checkout`.
+fn describe(oid: &ObjectID) -> anyhow::Result<String> {
+    Ok(format!(
+        "{} {}",
+        revision::abbreviate(oid, 7)?,
+        Commit::read(&oid.hash())?.subject()
+    ))
+}
@@ -1,4 +1,4 @@
-use clap;
+use std::ffi::OsString;
 
 #[derive(clap::Parser)]
 #[command(version, about, long_about = None)]
@@ -38,27 +38,717 @@ pub(crate) enum Commands {
         #[arg(short, long)]
         name_only: bool,
 
-        /// Object hash to list
+        /// Tree, commit or tag to list
         object_hash: String,
     },
 
     WriteTree {
+        /// Write the tree of a directory instead of the staged files
+        #[arg(long)]
+        from_dir: bool,
+
+        /// Directory to write with --from-dir
+        #[arg(requires = "from_dir")]
         tree_path: Option<String>,
     },
 
     CommitTree {
         tree_hash: String,
 
-        #[arg(short = 'p', long)]
-        parent_hash: Option<String>,
+        /// Parent commit; repeat for merge commits
+        #[arg(short = 'p', long = "parent")]
+        parent_hashes: Vec<String>,
+
+        /// Commit message; multiple messages are joined as separate paragraphs
+        #[arg(short = 'm', long = "message")]
+        messages: Vec<String>,
+
+        /// Read the commit message from a file, or from stdin with "-"; it is appended after the
+        /// messages given with -m
+        #[arg(short = 'F', long)]
+        file: Option<String>,
+    },
 
+    Commit {
+        /// Commit message; multiple messages are joined as separate paragraphs. Required unless
+        /// a merge is being concluded
         #[arg(short = 'm', long)]
-        message: String,
+        message: Vec<String>,
+
+        /// Allow recording a commit with the same tree as its parent
+        #[arg(long)]
+        allow_empty: bool,
     },
 
-    LsFiles,
+    Status {
+        /// Give the output in the short format
+        #[arg(short, long)]
+        short: bool,
+
+        /// Give the output in a stable, machine readable format
+        #[arg(long, value_name = "VERSION", num_args = 0..=1, default_missing_value = "v1")]
+        porcelain: Option<String>,
+    },
+
+    LsFiles {
+        /// Show the mode, object ID and stage of each entry
+        #[arg(short, long)]
+        stage: bool,
+
+        /// Show only the entries of conflicted files, as with --stage
+        #[arg(short, long)]
+        unmerged: bool,
+    },
+
+    UpdateIndex {
+        /// Write the index in the given format version, 2, 3 or 4
+        #[arg(long, value_name = "N")]
+        index_version: Option<u32>,
+
+        /// Re-read the stat data of the files, and report the files that changed
+        #[arg(long)]
+        refresh: bool,
+
+        /// Assume that the given files are unchanged, without looking at the working tree
+        #[arg(long, conflicts_with = "no_assume_unchanged")]
+        assume_unchanged: bool,
+
+        /// Look at the working tree again for the given files
+        #[arg(long)]
+        no_assume_unchanged: bool,
+
+        /// Mark the given files as not checked out in the working tree
+        #[arg(long, conflicts_with = "no_skip_worktree")]
+        skip_worktree: bool,
+
+        /// Mark the given files as checked out in the working tree again
+        #[arg(long)]
+        no_skip_worktree: bool,
+
+        /// Files to mark
+        paths: Vec<String>,
+    },
 
     Add {
-        path: String,
+        /// Add, modify and remove files to match the whole working tree
+        #[arg(short = 'A', long, conflicts_with = "update")]
+        all: bool,
+
+        /// Only update files that are already in the index
+        #[arg(short, long)]
+        update: bool,
+
+        /// Allow adding otherwise ignored files
+        #[arg(short, long)]
+        force: bool,
+
+        /// Only show what would be added and removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Files, directories or globs to add
+        pathspecs: Vec<String>,
+    },
+
+    Rm {
+        /// Only remove the files from the index, and keep them in the working tree
+        #[arg(long)]
+        cached: bool,
+
+        /// Allow removing the files of directories
+        #[arg(short)]
+        r: bool,
+
+        /// Remove files even if they have staged or unstaged changes
+        #[arg(short, long)]
+        force: bool,
+
+        /// Only show what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Do not print the removed files
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Files, directories or globs to remove
+        pathspecs: Vec<String>,
+    },
+
+    Mv {
+        /// Overwrite the files that are in the way
+        #[arg(short, long)]
+        force: bool,
+
+        /// Only show what would be moved
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Files, directories or globs to move, followed by the destination
+        #[arg(required = true, num_args = 2..)]
+        paths: Vec<String>,
+    },
+
+    CheckIgnore {
+        /// Print the pattern that matched each path
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Also print paths that match no pattern (requires --verbose)
+        #[arg(short, long, requires = "verbose")]
+        non_matching: bool,
+
+        /// Paths to check
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    Log {
+        #[command(flatten)]
+        walk: WalkOptions,
+
+        #[command(flatten)]
+        changes: ChangeFormat,
+
+        #[command(flatten)]
+        renames: RenameArgs,
+
+        /// Print each commit on a single line, shorthand for --format=oneline
+        #[arg(long)]
+        oneline: bool,
+
+        /// Format of each commit: oneline, short, medium, full, format:<string> or
+        /// tformat:<string>, with placeholders such as %H, %an or %s
+        #[arg(long, alias = "pretty", conflicts_with = "oneline")]
+        format: Option<String>,
+
+        /// Revisions and ranges to show (A..B, A...B, ^A), and paths to limit the history to
+        args: Vec<String>,
+
+        /// Paths to limit the history to
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+
+    Diff {
+        #[command(flatten)]
+        diff: DiffArgs,
+
+        /// Compare the index with HEAD or the given commit, instead of the working tree with the
+        /// index
+        #[arg(long, alias = "staged")]
+        cached: bool,
+
+        /// Commits or trees to compare (A, A B, A..B, A...B), and paths to limit the diff to
+        args: Vec<String>,
+
+        /// Paths to limit the diff to
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+
+    DiffTree {
+        /// Compare the subtrees instead of showing them as changed entries
+        #[arg(short)]
+        recursive: bool,
+
+        #[command(flatten)]
+        format: ChangeFormat,
+
+        #[command(flatten)]
+        renames: RenameArgs,
+
+        /// Compare a root commit with the empty tree
+        #[arg(long)]
+        root: bool,
+
+        /// Do not print the ID of the commit before its changes
+        #[arg(long)]
+        no_commit_id: bool,
+
+        /// A commit to compare with its parent, or two trees to compare, and paths to limit the
+        /// comparison to
+        #[arg(required = true)]
+        args: Vec<String>,
+
+        /// Paths to limit the comparison to
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+
+    Branch {
+        /// List the branches matching the given patterns
+        #[arg(short, long)]
+        list: bool,
+
+        /// Show the commit of each branch and how far it is from its upstream; twice to show the
+        /// name of the upstream as well
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+
+        #[command(flatten)]
+        action: BranchAction,
+
+        /// Set the start point as the upstream of the new branch
+        #[arg(short, long)]
+        track: bool,
+
+        /// Reset the branch if it exists, or delete branches even if they are not merged
+        #[arg(short, long)]
+        force: bool,
+
+        /// Name and start point of the new branch, branches to act on, or patterns to list
+        args: Vec<String>,
+    },
+
+    Checkout {
+        /// Create a branch with the given name and check it out
+        #[arg(short = 'b', value_name = "NEW_BRANCH")]
+        new_branch: Option<String>,
+
+        /// Detach HEAD at the commit, even if the target is a branch
+        #[arg(long, conflicts_with = "new_branch")]
+        detach: bool,
+
+        /// Discard local changes instead of refusing to overwrite them
+        #[arg(short, long)]
+        force: bool,
+
+        /// Branch or commit to check out, or the start point of the new branch
+        target: Option<String>,
+    },
+
+    Switch {
+        /// Create a branch with the given name and switch to it
+        #[arg(short, long, value_name = "NEW_BRANCH")]
+        create: Option<String>,
+
+        /// Detach HEAD at the commit instead of switching to a branch
+        #[arg(short, long, conflicts_with = "create")]
+        detach: bool,
+
+        /// Discard local changes instead of refusing to overwrite them
+        #[arg(short, long, alias = "discard-changes")]
+        force: bool,
+
+        /// Branch to switch to, commit to detach HEAD at, or the start point of the new branch
+        target: Option<String>,
+    },
+
+    Merge {
+        /// Message of the merge commit
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Create a merge commit even when the branch can be fast-forwarded
+        #[arg(long, conflicts_with = "ff_only")]
+        no_ff: bool,
+
+        /// Refuse to merge unless the branch can be fast-forwarded
+        #[arg(long)]
+        ff_only: bool,
+
+        /// Style of the conflicts in the merged files, "merge" or "diff3"
+        #[arg(long, value_name = "STYLE")]
+        conflict: Option<String>,
+
+        /// Commit to merge into the current branch
+        commit: String,
+    },
+
+    MergeBase {
+        /// Print all the merge bases instead of one
+        #[arg(short, long)]
+        all: bool,
+
+        /// Check whether the first commit is an ancestor of the second one
+        #[arg(long, conflicts_with = "all")]
+        is_ancestor: bool,
+
+        /// First commit
+        one: String,
+
+        /// Second commit
+        two: String,
+    },
+
+    RevParse {
+        /// Require exactly one revision naming an existing object
+        #[arg(long)]
+        verify: bool,
+
+        /// With --verify, exit with status 1 instead of printing an error
+        #[arg(short, long, requires = "verify")]
+        quiet: bool,
+
+        /// Abbreviate object IDs to a unique prefix of at least the given length
+        #[arg(long, value_name = "LENGTH", num_args = 0..=1, require_equals = true,
+              default_missing_value = "7")]
+        short: Option<usize>,
+
+        /// Revisions to resolve, e.g. HEAD~2, main^2, v1.0^{tree} or HEAD:path
+        revs: Vec<String>,
+    },
+
+    Config {
+        #[command(flatten)]
+        scope: ConfigScope,
+
+        #[command(flatten)]
+        action: ConfigAction,
+    },
+
+    Tag {
+        /// List the tags matching the given patterns
+        #[arg(short, long)]
+        list: bool,
+
+        /// Delete the given tags
+        #[arg(short, long, conflicts_with_all = ["list", "annotate", "messages", "file"])]
+        delete: bool,
+
+        /// Make an annotated tag
+        #[arg(short, long)]
+        annotate: bool,
+
+        /// Tag message; multiple messages are joined as separate paragraphs
+        #[arg(short = 'm', long = "message")]
+        messages: Vec<String>,
+
+        /// Read the tag message from a file, or from stdin with "-"
+        #[arg(short = 'F', long, conflicts_with = "messages")]
+        file: Option<String>,
+
+        /// Replace an existing tag
+        #[arg(short, long)]
+        force: bool,
+
+        /// Tag name and object, tags to delete, or patterns to list
+        args: Vec<String>,
+    },
+
+    PackObjects {
+        /// Prefix of the written pack and index files
+        base_name: String,
+    },
+
+    Repack {
+        /// Pack everything into a single pack, including objects of existing packs
+        #[arg(short = 'a')]
+        all: bool,
+
+        /// Delete redundant packs and loose objects after packing
+        #[arg(short = 'd')]
+        delete: bool,
+    },
+
+    UpdateRef {
+        /// Delete the ref instead of updating it
+        #[arg(short = 'd')]
+        delete: bool,
+
+        /// Update the ref itself rather than the ref it points to
+        #[arg(long)]
+        no_deref: bool,
+
+        /// Reason of the update, recorded in the reflog
+        #[arg(short = 'm')]
+        message: Option<String>,
+
+        /// Full name of the ref to update
+        ref_name: String,
+
+        /// New value and optional expected old value (only the old value with -d)
+        values: Vec<String>,
+    },
+
+    SymbolicRef {
+        /// Do not print an error if the ref is not a symbolic ref
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Print the shortened name of the ref it points to
+        #[arg(long)]
+        short: bool,
+
+        /// Delete the symbolic ref
+        #[arg(short, long)]
+        delete: bool,
+
+        /// Name of the symbolic ref
+        name: String,
+
+        /// Full name of the ref to point to
+        target: Option<String>,
+    },
+
+    ShowRef {
+        /// Show HEAD as well
+        #[arg(long)]
+        head: bool,
+
+        /// Only show branches
+        #[arg(long)]
+        heads: bool,
+
+        /// Only show tags
+        #[arg(long)]
+        tags: bool,
+
+        /// Require the patterns to be exact names of existing refs
+        #[arg(long)]
+        verify: bool,
+
+        /// Only print the object IDs
+        #[arg(short = 's', long = "hash")]
+        hash_only: bool,
+
+        /// Patterns the ref names must end with
+        patterns: Vec<String>,
     },
 }
+
+// The config file the `config` subcommand reads or writes.
+#[derive(clap::Args)]
+#[group(multiple = false)]
+pub(crate) struct ConfigScope {
+    /// Use the global config file, ~/.gitconfig
+    #[arg(long)]
+    pub(crate) global: bool,
+
+    /// Use the system config file, /etc/gitconfig
+    #[arg(long)]
+    pub(crate) system: bool,
+
+    /// Use the repository config file, .git/config
+    #[arg(long)]
+    pub(crate) local: bool,
+
+    /// Use the given config file
+    #[arg(short, long)]
+    pub(crate) file: Option<String>,
+}
+
+// What the `config` subcommand does. Exactly one action must be given.
+#[derive(clap::Args)]
+#[group(required = true, multiple = false)]
+pub(crate) struct ConfigAction {
+    /// Print the last value of a variable
+    #[arg(long, value_name = "NAME")]
+    pub(crate) get: Option<String>,
+
+    /// Print all the values of a multi-valued variable
+    #[arg(long, value_name = "NAME")]
+    pub(crate) get_all: Option<String>,
+
+    /// Set a variable, which must have at most one value
+    #[arg(long, num_args = 2, value_names = ["NAME", "VALUE"])]
+    pub(crate) set: Option<Vec<String>>,
+
+    /// Replace all the values of a variable with a single value
+    #[arg(long, num_args = 2, value_names = ["NAME", "VALUE"])]
+    pub(crate) replace_all: Option<Vec<String>>,
+
+    /// Add a value to a variable, keeping its existing values
+    #[arg(long, num_args = 2, value_names = ["NAME", "VALUE"])]
+    pub(crate) add: Option<Vec<String>>,
+
+    /// Remove a variable, which must have a single value
+    #[arg(long, value_name = "NAME")]
+    pub(crate) unset: Option<String>,
+
+    /// Remove all the values of a variable
+    #[arg(long, value_name = "NAME")]
+    pub(crate) unset_all: Option<String>,
+
+    /// List all the variables
+    #[arg(short, long)]
+    pub(crate) list: bool,
+}
+
+// What the `branch` subcommand does instead of creating a branch. At most one action can be given.
+#[derive(clap::Args)]
+#[group(multiple = false)]
+pub(crate) struct BranchAction {
+    /// Delete branches that are merged into their upstream, or into HEAD
+    #[arg(short, long)]
+    pub(crate) delete: bool,
+
+    /// Delete branches even if they are not merged
+    #[arg(short = 'D')]
+    pub(crate) force_delete: bool,
+
+    /// Rename a branch, or the current branch if only the new name is given
+    #[arg(short = 'm', long = "move")]
+    pub(crate) rename: bool,
+
+    /// Rename a branch even if a branch with the new name exists
+    #[arg(short = 'M')]
+    pub(crate) force_rename: bool,
+
+    /// Set the upstream of a branch, or of the current branch
+    #[arg(short = 'u', long, value_name = "UPSTREAM")]
+    pub(crate) set_upstream_to: Option<String>,
+
+    /// Remove the upstream of a branch, or of the current branch
+    #[arg(long)]
+    pub(crate) unset_upstream: bool,
+}
+
+// How the commit history is walked.
+#[derive(clap::Args)]
+pub(crate) struct WalkOptions {
+    /// Show at most this many commits
+    #[arg(short = 'n', long, value_name = "NUMBER")]
+    pub(crate) max_count: Option<usize>,
+
+    /// Follow only the first parent of merge commits
+    #[arg(long)]
+    pub(crate) first_parent: bool,
+
+    /// Show no parent before all of its children, otherwise by commit date
+    #[arg(long, conflicts_with = "topo_order")]
+    pub(crate) date_order: bool,
+
+    /// Show no parent before all of its children, without interleaving lines of history
+    #[arg(long)]
+    pub(crate) topo_order: bool,
+}
+
+// How differences between files are computed and shown.
+#[derive(clap::Args)]
+pub(crate) struct DiffArgs {
+    /// Show this many lines of context around the changes
+    #[arg(
+        short = 'U',
+        long = "unified",
+        value_name = "LINES",
+        default_value_t = 3
+    )]
+    pub(crate) context: usize,
+
+    /// Spend extra time to make sure the smallest possible diff is produced
+    #[arg(long, conflicts_with_all = ["patience", "histogram", "diff_algorithm"])]
+    pub(crate) minimal: bool,
+
+    /// Use the patience diff algorithm
+    #[arg(long, conflicts_with_all = ["histogram", "diff_algorithm"])]
+    pub(crate) patience: bool,
+
+    /// Use the histogram diff algorithm
+    #[arg(long, conflicts_with = "diff_algorithm")]
+    pub(crate) histogram: bool,
+
+    /// Diff algorithm to use: myers (default), minimal, patience or histogram
+    #[arg(long, value_name = "ALGORITHM")]
+    pub(crate) diff_algorithm: Option<String>,
+}
+
+// How the files changed between two trees are listed.
+#[derive(clap::Args)]
+pub(crate) struct ChangeFormat {
+    /// Show only the names of the changed files
+    #[arg(long, conflicts_with = "name_status")]
+    pub(crate) name_only: bool,
+
+    /// Show the names and the kind of change of the changed files
+    #[arg(long)]
+    pub(crate) name_status: bool,
+}
+
+// How renamed and copied files are detected.
+//
+// The options structs that subcommands flatten are described with plain comments: clap would
+// show a doc comment as the description of the subcommands.
+#[derive(clap::Args)]
+pub(crate) struct RenameArgs {
+    /// Detect renames, between files at least this similar, e.g. 50%, 0.5 or 5 (default 50%)
+    #[arg(short = 'M', long, value_name = "SIMILARITY", num_args = 0..=1,
+          require_equals = true, default_missing_value = "")]
+    pub(crate) find_renames: Option<String>,
+
+    /// Detect copies as well as renames, between files at least this similar (default 50%)
+    #[arg(short = 'C', long, value_name = "SIMILARITY", num_args = 0..=1,
+          require_equals = true, default_missing_value = "")]
+    pub(crate) find_copies: Option<String>,
+
+    /// Detect copies of unchanged files too, not only of the changed ones
+    #[arg(long)]
+    pub(crate) find_copies_harder: bool,
+
+    /// Do not detect renames
+    #[arg(long, conflicts_with_all = ["find_renames", "find_copies", "find_copies_harder"])]
+    pub(crate) no_renames: bool,
+}
+
+/// Attaches the similarity given to `-M` and `-C` the way clap expects it, so that git's
+/// `-M90%` and `-C75%` are read as `-M=90%` and `-C=75%`. The options take an optional value,
+/// which clap only reads after `=`. Only the arguments of the subcommands that detect renames are
+/// changed, up to the `--` that starts the paths.
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, starting with the name of the program.
+///
+/// # Returns
+///
+/// Returns the arguments to parse.
+pub(crate) fn attach_similarities(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut args = args.into_iter().collect::<Vec<_>>();
+    let detects_renames = args
+        .get(1)
+        .is_some_and(|command| command == "log" || command == "diff-tree");
+    if !detects_renames {
+        return args;
+    }
+    for arg in args.iter_mut().skip(2) {
+        let Some(text) = arg.to_str() else {
+            continue;
+        };
+        if text == "--" {
+            break;
+        }
+        let similarity = text.strip_prefix("-M").or_else(|| text.strip_prefix("-C"));
+        if let Some(similarity) = similarity.filter(|s| !s.is_empty() && !s.starts_with('=')) {
+            *arg = format!("{}={}", &text[..2], similarity).into();
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// Parses a command line the way `main` does, and returns its subcommand.
+    fn parse(args: &[&str]) -> Commands {
+        let args = std::iter::once("bran").chain(args.iter().copied());
+        let cli = GitCLI::try_parse_from(attach_similarities(args.map(OsString::from)));
+        cli.unwrap().command
+    }
+
+    #[test]
+    fn reads_similarities_attached_to_short_options() {
+        let Commands::Log { renames, paths, .. } = parse(&["log", "-M90%", "-C", "--", "-C5"])
+        else {
+            panic!("Expected log.");
+        };
+        assert_eq!(renames.find_renames.as_deref(), Some("90%"));
+        assert_eq!(renames.find_copies.as_deref(), Some(""));
+        assert_eq!(paths, ["-C5"]);
+
+        let Commands::DiffTree { renames, .. } = parse(&["diff-tree", "-C75%", "-M=60", "a", "b"])
+        else {
+            panic!("Expected diff-tree.");
+        };
+        assert_eq!(renames.find_copies.as_deref(), Some("75%"));
+        assert_eq!(renames.find_renames.as_deref(), Some("60"));
+
+        // Other subcommands keep their own -M.
+        let Commands::Branch { action, .. } = parse(&["branch", "-M", "new"]) else {
+            panic!("Expected branch.");
+        };
+        assert!(action.force_rename);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::commands::merge::{self, MERGE_HEAD, MERGE_MODE, MERGE_MSG};
+use crate::config;
+use crate::index;
+use crate::objects;
+use crate::objects::signature::Signature;
+use crate::objects::Object;
+use crate::refs;
+use crate::refs::Expected;
+use anyhow::Context;
+
+/// Invokes the `commit` subcommand.
+///
+/// Writes the trees of the staged files, creates a commit on top of the current HEAD commit,
+/// and moves the current branch (or the detached HEAD) to it.
+///
+/// While a merge is in progress, the commit concludes it: the merged commit is its second
+/// parent, its message defaults to the one prepared by the merge, and all conflicts must have
+/// been resolved.
+///
+/// # Arguments
+///
+/// * `messages` - The paragraphs of the commit message.
+/// * `allow_empty` - If true, allow a commit with the same tree as its parent.
+pub(crate) fn invoke(messages: &[String], allow_empty: bool) -> anyhow::Result<()> {
+    let merge_head = refs::resolve(MERGE_HEAD)?;
+    let message = match (messages, &merge_head) {
+        ([], Some(_)) => read_merge_message()?,
+        _ => messages.join("\n\n"),
+    };
+    anyhow::ensure!(
+        !message.trim().is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+
+    let mut index = index::Index::lock(".git/index")?;
+    anyhow::ensure!(
+        !index.has_conflicts(),
+        "Committing is not possible because you have unmerged files."
+    );
+    let tree = index
+        .write_tree()
+        .context("Failed to write tree from index.")?;
+
+    let parent = refs::resolve(refs::HEAD)?;
+    // A merge commit may keep the tree of its first parent.
+    if !allow_empty && merge_head.is_none() {
+        let unchanged = match &parent {
+            Some(parent) => objects::commit::Commit::read(&parent.hash())?.tree == tree,
+            None => index.entries().is_empty(),
+        };
+        anyhow::ensure!(
+            !unchanged,
+            "nothing to commit (use --allow-empty to create an empty commit)"
+        );
+    }
+
+    let config = config::Config::load()?;
+    let mut commit = objects::commit::Commit::new(
+        tree,
+        parent
+            .clone()
+            .into_iter()
+            .chain(merge_head.clone())
+            .collect(),
+        Signature::from_env("AUTHOR", &config)?,
+        Signature::from_env("COMMITTER", &config)?,
+        format!("{}\n", message),
+    );
+    let oid = commit.write()?;
+
+    // Move the current branch, making sure nobody else moved it in the meantime.
+    let subject = commit.subject();
+    let (expected, reflog_message) = match parent {
+        Some(parent) if merge_head.is_some() => (
+            Expected::Value(parent),
+            format!("commit (merge): {}", subject),
+        ),
+        Some(parent) => (Expected::Value(parent), format!("commit: {}", subject)),
+        None => (Expected::Missing, format!("commit (initial): {}", subject)),
+    };
+    let root = matches!(expected, Expected::Missing);
+    refs::update_ref(refs::HEAD, &oid, expected, &reflog_message, false)
+        .context("Failed to update HEAD")?;
+    // Keep the trees that were written in the cache tree of the index.
+    index.write().context("Failed to write index")?;
+    if merge_head.is_some() {
+        for name in [MERGE_HEAD, MERGE_MSG, MERGE_MODE] {
+            let path = merge::merge_file_path(name);
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+        }
+    }
+
+    let branch = match refs::current_branch()? {
+        Some(branch) => refs::shorten(&branch).to_string(),
+        None => String::from("detached HEAD"),
+    };
+    let root = if root { " (root-commit)" } else { "" };
+    println!("[{}{} {}] {}", branch, root, &oid.hash()[..7], subject);
+
+    Ok(())
+}
+
+/// Reads the message prepared by a merge, without its comment lines and trailing blank lines.
+fn read_merge_message() -> anyhow::Result<String> {
+    let path = merge::merge_file_path(MERGE_MSG);
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let lines = content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(str::trim_end)
+        .collect::<Vec<_>>();
+    Ok(lines.join("\n").trim_end().to_string())
+}
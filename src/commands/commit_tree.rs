@@ -1,30 +1,61 @@
 use crate::config;
 use crate::objects;
+use crate::objects::kind::ObjectKind;
+use crate::objects::signature::Signature;
 use crate::objects::Object;
+use crate::revision;
 use anyhow::Context;
+use std::io::Read;
 
+/// Invokes the `commit-tree` subcommand.
+///
+/// Creates a commit object for a tree and prints its ID. The message is made of the `-m` options,
+/// each one a paragraph, followed by the contents of a file as another paragraph, or is read from
+/// stdin if neither is given. The dates of the author and the committer can be set with
+/// `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE`.
+///
+/// # Arguments
+///
+/// * `tree_hash` - The tree of the commit, as a revision expression.
+/// * `parent_hashes` - The parents, as revision expressions. Duplicates are ignored.
+/// * `messages` - The paragraphs of the commit message.
+/// * `file` - The file to read the commit message from, or `-` for stdin.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the commit was written, or an `anyhow::Error` if an object
+/// is missing or has the wrong type, or the message cannot be read.
 pub(crate) fn invoke(
-    tree_hash: String,
-    parent_hash: Option<String>,
-    message: String,
+    tree_hash: &str,
+    parent_hashes: &[String],
+    messages: &[String],
+    file: Option<&str>,
 ) -> anyhow::Result<()> {
-    let config = config::Config::load();
+    let tree = revision::resolve(tree_hash)?;
+    ensure_kind(&tree.hash(), ObjectKind::Tree)?;
+
+    let mut parents = Vec::new();
+    for hash in parent_hashes {
+        let parent = revision::resolve(hash)?;
+        ensure_kind(&parent.hash(), ObjectKind::Commit)?;
+        if parents.contains(&parent) {
+            eprintln!("error: duplicate parent {} ignored", parent);
+            continue;
+        }
+        parents.push(parent);
+    }
+
+    let message = read_message(messages, file)?;
 
     // Create a new commit object
-    let parent_hash = match parent_hash {
-        Some(hash) => Some(objects::id::ObjectID::from_hash(&hash)),
-        None => None,
-    };
+    let config = config::Config::load()?;
     let mut commit = objects::commit::Commit::new(
-        objects::id::ObjectID::from_hash(&tree_hash),
-        parent_hash,
-        config.author_name.clone(),
-        config.author_email.clone(),
-        config.author_name,
-        config.author_email,
+        tree,
+        parents,
+        Signature::from_env("AUTHOR", &config)?,
+        Signature::from_env("COMMITTER", &config)?,
         message,
-    )
-    .context("Failed to create commit")?;
+    );
 
     // Write the commit object to the database
     let oid = commit.write()?;
@@ -32,3 +63,79 @@ pub(crate) fn invoke(
 
     Ok(())
 }
+
+/// Builds the commit message, like git: each message is a paragraph ending with a newline, and
+/// the file is appended after a blank line, unchanged.
+///
+/// # Arguments
+///
+/// * `messages` - The paragraphs of the commit message.
+/// * `file` - The file to read the rest of the message from, or `-` for stdin. Without messages
+///   or a file, the message is read from stdin.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the commit message, or an `anyhow::Error` if the file cannot be
+/// read.
+fn read_message(messages: &[String], file: Option<&str>) -> anyhow::Result<String> {
+    let mut message = messages
+        .iter()
+        .map(|message| format!("{}\n", message))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let file = match file {
+        Some(path) => path,
+        None if messages.is_empty() => "-",
+        None => return Ok(message),
+    };
+    if !message.is_empty() {
+        message.push('\n');
+    }
+    if file == "-" {
+        std::io::stdin()
+            .read_to_string(&mut message)
+            .context("Failed to read commit message from stdin.")?;
+    } else {
+        let mut reader = std::fs::File::open(file)
+            .with_context(|| format!("Failed to read commit message from {}", file))?;
+        reader
+            .read_to_string(&mut message)
+            .with_context(|| format!("Failed to read commit message from {}", file))?;
+    }
+    Ok(message)
+}
+
+/// Checks that an object exists and has the expected kind.
+fn ensure_kind(hash: &str, expected: ObjectKind) -> anyhow::Result<()> {
+    let (kind, _, _) = objects::read_object(hash)?;
+    anyhow::ensure!(
+        kind == expected,
+        "{} is not a valid '{}' object",
+        hash,
+        expected
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn appends_the_message_file_as_a_paragraph() {
+        let repo = TestRepo::new();
+        let path = repo.path().join("message");
+        std::fs::write(&path, "file").unwrap();
+        let path = path.to_str().unwrap();
+        let messages = ["a".to_string(), "b".to_string()];
+
+        assert_eq!(read_message(&messages, None).unwrap(), "a\n\nb\n");
+        assert_eq!(read_message(&[], Some(path)).unwrap(), "file");
+        assert_eq!(
+            read_message(&messages, Some(path)).unwrap(),
+            "a\n\nb\n\nfile"
+        );
+        assert!(read_message(&messages, Some("missing")).is_err());
+    }
+}
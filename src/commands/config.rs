@@ -0,0 +1,101 @@
+use crate::commands::cli::{ConfigAction, ConfigScope};
+use crate::config;
+use crate::config::file::ConfigFile;
+use std::path::PathBuf;
+
+/// Invokes the `config` subcommand.
+///
+/// Reads use the merged configuration of all the config files and the environment, unless a
+/// scope is given, in which case only that file is read. Writes go to the repository config file
+/// `.git/config` unless a scope is given.
+///
+/// Exits with status 1 if a variable to get is not set, and with status 5 if a variable to unset
+/// is not set.
+///
+/// # Arguments
+///
+/// * `scope` - The config file to read or write.
+/// * `action` - What to do: get, set, replace, add, unset or list variables.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if a config file cannot be read or
+/// written, or a key is invalid.
+pub(crate) fn invoke(scope: &ConfigScope, action: &ConfigAction) -> anyhow::Result<()> {
+    let path = scope_path(scope)?;
+
+    if let Some(key) = action.get.as_ref().or(action.get_all.as_ref()) {
+        let values = match &path {
+            Some(path) => {
+                let key = config::normalize_key(key)?;
+                ConfigFile::read(path)?
+                    .entries()
+                    .iter()
+                    .filter(|entry| entry.key() == key)
+                    .map(|entry| entry.value.clone().unwrap_or_default())
+                    .collect()
+            }
+            None => config::Config::load()?
+                .get_all(key)
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        };
+        if values.is_empty() {
+            std::process::exit(1);
+        }
+        if action.get_all.is_some() {
+            values.iter().for_each(|value| println!("{}", value));
+        } else if let Some(value) = values.last() {
+            println!("{}", value);
+        }
+        return Ok(());
+    }
+
+    if action.list {
+        let entries = match &path {
+            Some(path) => ConfigFile::read(path)?
+                .entries()
+                .iter()
+                .map(|entry| (entry.key(), entry.value.clone()))
+                .collect(),
+            None => config::Config::load()?.entries().to_vec(),
+        };
+        for (key, value) in entries {
+            match value {
+                Some(value) => println!("{}={}", key, value),
+                None => println!("{}", key),
+            }
+        }
+        return Ok(());
+    }
+
+    // The remaining actions write to a config file.
+    let path = path.unwrap_or_else(|| PathBuf::from(config::LOCAL_CONFIG));
+    let mut file = ConfigFile::read(&path)?;
+    if let [key, value] = action.set.as_deref().unwrap_or_default() {
+        file.set(key, value, false)?;
+    } else if let [key, value] = action.replace_all.as_deref().unwrap_or_default() {
+        file.set(key, value, true)?;
+    } else if let [key, value] = action.add.as_deref().unwrap_or_default() {
+        file.add(key, value)?;
+    } else if let Some(key) = action.unset.as_ref().or(action.unset_all.as_ref()) {
+        if file.unset(key, action.unset_all.is_some())? == 0 {
+            std::process::exit(5);
+        }
+    }
+    file.write()
+}
+
+/// Returns the config file selected by the scope options, or `None` if there is none.
+fn scope_path(scope: &ConfigScope) -> anyhow::Result<Option<PathBuf>> {
+    Ok(if scope.global {
+        Some(config::global_path()?)
+    } else if scope.system {
+        Some(config::system_path())
+    } else if scope.local {
+        Some(PathBuf::from(config::LOCAL_CONFIG))
+    } else {
+        scope.file.as_ref().map(PathBuf::from)
+    })
+}
@@ -0,0 +1,185 @@
+use crate::commands::cli::DiffArgs;
+use crate::commands::log::normalize_path;
+use crate::diff::tree::Files;
+use crate::diff::unified::{self, FileVersion};
+use crate::diff::{Algorithm, DiffOptions};
+use crate::index;
+use crate::objects::blob::Blob;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::tree::Tree;
+use crate::objects::Object;
+use crate::refs;
+use crate::rev_walk::CommitGraph;
+use crate::revision;
+use crate::status::{compare_paths, head_files, index_files};
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Invokes the `diff` subcommand.
+///
+/// Without revisions, shows the changes of the working tree relative to the index. With
+/// `cached`, shows the changes of the index relative to the given commit, or to HEAD. With one
+/// revision, shows the changes of the working tree relative to it, and with two, or a range
+/// `A..B`, the changes between them. `A...B` shows the changes of `B` since the merge base of `A`
+/// and `B`. Only the files tracked in the index are compared with the working tree.
+///
+/// # Arguments
+///
+/// * `args` - The revisions to compare, and paths to limit the diff to.
+/// * `paths` - The paths given after `--`, to limit the diff to.
+/// * `cached` - If true, compare the index instead of the working tree.
+/// * `diff` - The number of context lines and the diff algorithm.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if a revision cannot be resolved or
+/// a file cannot be read.
+pub(crate) fn invoke(
+    args: &[String],
+    paths: &[String],
+    cached: bool,
+    diff: &DiffArgs,
+) -> anyhow::Result<()> {
+    let options = diff_options(diff)?;
+    let mut pathspecs: Vec<PathBuf> = paths.iter().map(normalize_path).collect();
+    let mut revs = Vec::new();
+    for arg in args {
+        match resolve_trees(arg) {
+            Ok(trees) => revs.extend(trees),
+            Err(_) if Path::new(arg).exists() => pathspecs.push(normalize_path(arg)),
+            Err(err) => return Err(err),
+        }
+    }
+
+    let index = index::Index::init(".git/index")?;
+    let (old, new, worktree) = match (revs.as_slice(), cached) {
+        ([], false) => (index_files(index.entries()), worktree_files(&index)?, true),
+        ([], true) => (head_files()?, index_files(index.entries()), false),
+        ([tree], true) => (
+            Tree::flatten(&tree.hash())?,
+            index_files(index.entries()),
+            false,
+        ),
+        ([tree], false) => (Tree::flatten(&tree.hash())?, worktree_files(&index)?, true),
+        ([old, new], false) => (
+            Tree::flatten(&old.hash())?,
+            Tree::flatten(&new.hash())?,
+            false,
+        ),
+        (_, true) => anyhow::bail!("--cached takes at most one commit"),
+        _ => anyhow::bail!("Too many revisions."),
+    };
+
+    let mut paths: Vec<&PathBuf> = old
+        .keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|path| pathspecs.is_empty() || pathspecs.iter().any(|spec| path.starts_with(spec)))
+        .collect();
+    paths.sort_by(|a, b| compare_paths(a, b));
+
+    let mut stdout = std::io::stdout().lock();
+    for path in paths {
+        let (old, new) = (old.get(path), new.get(path));
+        if old == new {
+            continue;
+        }
+        let old = match old {
+            Some((mode, oid)) => Some(FileVersion::from_blob(oid, *mode)?),
+            None => None,
+        };
+        let new = match new {
+            Some(_) if worktree => Some(FileVersion::from_worktree(path)?),
+            Some((mode, oid)) => Some(FileVersion::from_blob(oid, *mode)?),
+            None => None,
+        };
+
+        let mut patch = Vec::new();
+        unified::write_file_diff(&mut patch, path, old.as_ref(), new.as_ref(), &options)?;
+        // Stop quietly when the reader of the output went away, e.g. `bran diff | head`.
+        match stdout.write_all(&patch) {
+            Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result.context("Failed to write to stdout.")?,
+        }
+    }
+    Ok(())
+}
+
+/// Converts the diff options of the command line: the algorithm is given by
+/// `--diff-algorithm=<name>`, or the shorthands `--patience` and `--histogram`, and defaults to
+/// Myers' algorithm.
+pub(crate) fn diff_options(args: &DiffArgs) -> anyhow::Result<DiffOptions> {
+    let algorithm = match &args.diff_algorithm {
+        Some(name) => Algorithm::parse(name)?,
+        None if args.minimal => Algorithm::Minimal,
+        None if args.patience => Algorithm::Patience,
+        None if args.histogram => Algorithm::Histogram,
+        None => Algorithm::Myers,
+    };
+    Ok(DiffOptions {
+        context: args.context,
+        algorithm,
+    })
+}
+
+/// Resolves a revision argument to the trees to compare: one tree for a revision, and two for a
+/// range `A..B` or `A...B`, where an empty side means HEAD.
+fn resolve_trees(arg: &str) -> anyhow::Result<Vec<ObjectID>> {
+    let tree = |rev: &str| {
+        let rev = if rev.is_empty() { refs::HEAD } else { rev };
+        revision::peel(&revision::resolve(rev)?, ObjectKind::Tree)
+    };
+    if let Some((one, two)) = arg.split_once("...") {
+        let commit = |rev: &str| {
+            let rev = if rev.is_empty() { refs::HEAD } else { rev };
+            revision::peel(&revision::resolve(rev)?, ObjectKind::Commit)
+        };
+        let (one, two) = (commit(one)?, commit(two)?);
+        let base = CommitGraph::default()
+            .merge_bases(&one, &two)?
+            .into_iter()
+            .next()
+            .with_context(|| format!("{}: no merge base", arg))?;
+        Ok(vec![tree(&base.hash())?, tree(&two.hash())?])
+    } else if let Some((one, two)) = arg.split_once("..") {
+        Ok(vec![tree(one)?, tree(two)?])
+    } else {
+        Ok(vec![tree(arg)?])
+    }
+}
+
+/// Returns the files of the working tree that are tracked in the index. Files whose stat data
+/// matches their index entry are not hashed again, and files marked as unchanged are taken from
+/// the index.
+fn worktree_files(index: &index::Index) -> anyhow::Result<Files> {
+    let mut files = Files::new();
+    for entry in index.entries() {
+        let path = entry.path();
+        if entry.is_assumed_unchanged() {
+            if let Some(mode) = EntryMode::from_index_mode(entry.mode) {
+                files.insert(path.to_path_buf(), (mode, entry.oid.clone()));
+            }
+            continue;
+        }
+        let Ok(meta) = std::fs::symlink_metadata(path) else {
+            continue;
+        };
+        if meta.is_dir() {
+            continue;
+        }
+        let oid = if index.stat_matches(entry, &meta) {
+            entry.oid.clone()
+        } else {
+            Blob::from_worktree(path)?
+                .hash()
+                .with_context(|| format!("Failed to hash {}", path.display()))?
+        };
+        files.insert(path.to_path_buf(), (EntryMode::from_metadata(&meta), oid));
+    }
+    Ok(files)
+}
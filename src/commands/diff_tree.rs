@@ -0,0 +1,179 @@
+use crate::commands::cli::{ChangeFormat, RenameArgs};
+use crate::commands::log::normalize_path;
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::tree::{self, FileChange, FileState};
+use crate::objects::commit::Commit;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::objects::tree::Tree;
+use crate::revision;
+use anyhow::Context;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Invokes the `diff-tree` subcommand.
+///
+/// With two trees, lists the files that changed between them. With a commit, lists the files it
+/// changed compared to its parent, after its ID. Merge commits are not compared, and root commits
+/// only with `root`, against the empty tree.
+///
+/// # Arguments
+///
+/// * `args` - A commit, or two trees, followed by paths to limit the comparison to.
+/// * `paths` - The paths given after `--`, to limit the comparison to.
+/// * `recursive` - If true, list the changed files of the subtrees, instead of the subtrees.
+/// * `format` - How the changed files are listed, see `format_change`.
+/// * `renames` - How renamed and copied files are detected. By default, they are not.
+/// * `root` - If true, compare a root commit with the empty tree.
+/// * `no_commit_id` - If true, do not print the ID of the commit.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if a revision cannot be resolved or
+/// a tree cannot be read.
+pub(crate) fn invoke(
+    args: &[String],
+    paths: &[String],
+    recursive: bool,
+    format: &ChangeFormat,
+    renames: &RenameArgs,
+    root: bool,
+    no_commit_id: bool,
+) -> anyhow::Result<()> {
+    let first = revision::resolve(&args[0])?;
+    let second = match args.get(1) {
+        Some(arg) => revision::resolve(arg)
+            .and_then(|oid| revision::peel(&oid, ObjectKind::Tree))
+            .ok(),
+        None => None,
+    };
+    let rest = if second.is_some() { 2 } else { 1 };
+    let pathspecs: Vec<PathBuf> = args[rest..]
+        .iter()
+        .chain(paths)
+        .map(normalize_path)
+        .collect();
+
+    let (commit, old, new) = match second {
+        Some(new) => (None, Some(revision::peel(&first, ObjectKind::Tree)?), new),
+        None => {
+            let commit = revision::peel(&first, ObjectKind::Commit)
+                .with_context(|| format!("{}: not a commit", args[0]))?;
+            let data = Commit::read(&commit.hash())?;
+            let old = match data.parents.as_slice() {
+                [] if root => None,
+                [parent] => Some(Commit::read(&parent.hash())?.tree),
+                _ => return Ok(()),
+            };
+            (Some(commit), old, data.tree)
+        }
+    };
+
+    let mut changes = tree::diff_trees(old.as_ref(), Some(&new), recursive)?;
+    changes.retain(|change| {
+        pathspecs.is_empty() || pathspecs.iter().any(|spec| change.path().starts_with(spec))
+    });
+    if let Some(options) = rename_options(renames, false)? {
+        let unchanged = match &old {
+            Some(old) if renames.find_copies_harder => unchanged_files(old, &changes)?,
+            _ => Vec::new(),
+        };
+        changes = rename::detect_renames(changes, &unchanged, &options)?;
+    }
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut text = String::new();
+    if let Some(commit) = commit.filter(|_| !no_commit_id) {
+        text.push_str(&format!("{}\n", commit));
+    }
+    for change in &changes {
+        text.push_str(&format_change(change, format));
+    }
+    // Stop quietly when the reader of the output went away.
+    match std::io::stdout().lock().write_all(text.as_bytes()) {
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.context("Failed to write to stdout."),
+    }
+}
+
+/// Converts the rename options of the command line: `-C` detects copies as well as renames, and
+/// the similarity given to `-C`, or else to `-M`, is the minimum one.
+///
+/// # Arguments
+///
+/// * `args` - The rename options of the command line.
+/// * `default` - Whether renames are detected when no option asks for it.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the options, `None` if renames are not detected, or an
+/// `anyhow::Error` if a similarity is invalid.
+pub(crate) fn rename_options(
+    args: &RenameArgs,
+    default: bool,
+) -> anyhow::Result<Option<RenameOptions>> {
+    if args.no_renames {
+        return Ok(None);
+    }
+    let copies = args.find_copies.is_some() || args.find_copies_harder;
+    let minimum_score = match (&args.find_copies, &args.find_renames) {
+        (Some(score), _) if !score.is_empty() => rename::parse_score(score)?,
+        (_, Some(score)) => rename::parse_score(score)?,
+        _ if copies || default => rename::DEFAULT_RENAME_SCORE,
+        _ => return Ok(None),
+    };
+    Ok(Some(RenameOptions {
+        minimum_score,
+        copies,
+    }))
+}
+
+/// Returns the files of a tree that are not changed, to detect copies of them.
+fn unchanged_files(tree: &ObjectID, changes: &[FileChange]) -> anyhow::Result<Vec<FileState>> {
+    let changed: HashSet<&PathBuf> = changes
+        .iter()
+        .filter_map(|change| change.old.as_ref().map(|old| &old.path))
+        .collect();
+    Ok(Tree::flatten(&tree.hash())?
+        .into_iter()
+        .filter(|(path, _)| !changed.contains(path))
+        .map(|(path, (mode, oid))| FileState { path, mode, oid })
+        .collect())
+}
+
+/// Formats a changed file, followed by a newline.
+///
+/// By default, in the raw format of git, `:<old mode> <new mode> <old ID> <new ID> <status>\t`
+/// followed by the path, or the old and new paths of a rename or copy, where a missing side has
+/// a zero mode and ID. With `--name-status`, only the status and the paths, and with
+/// `--name-only`, only the new path.
+pub(crate) fn format_change(change: &FileChange, format: &ChangeFormat) -> String {
+    let paths = match (&change.old, &change.new) {
+        (Some(old), Some(new)) if old.path != new.path => {
+            format!("{}\t{}", old.path.display(), new.path.display())
+        }
+        _ => change.path().display().to_string(),
+    };
+    if format.name_only {
+        return format!("{}\n", change.path().display());
+    }
+    if format.name_status {
+        return format!("{}\t{}\n", change.change, paths);
+    }
+
+    let mode = |state: &Option<FileState>| state.as_ref().map_or(0, |state| state.mode.to_octal());
+    let zero = ObjectID::zero();
+    let oid = |state: &Option<FileState>| state.as_ref().map_or(&zero, |state| &state.oid).clone();
+    format!(
+        ":{:06o} {:06o} {} {} {}\t{}\n",
+        mode(&change.old),
+        mode(&change.new),
+        oid(&change.old),
+        oid(&change.new),
+        change.change,
+        paths
+    )
+}
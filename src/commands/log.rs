@@ -0,0 +1,371 @@
+use crate::commands::cli::{ChangeFormat, RenameArgs, WalkOptions};
+use crate::commands::diff_tree::{format_change, rename_options};
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::tree;
+use crate::objects::commit::Commit;
+use crate::objects::id::ObjectID;
+use crate::objects::signature::Signature;
+use crate::refs;
+use crate::rev_walk::{Order, RevWalk};
+use crate::revision;
+use anyhow::Context;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Format of the dates in the default output, e.g. `Mon Jan 1 12:00:00 2024 +0100`.
+const DEFAULT_DATE_FORMAT: &str = "%a %b %-d %H:%M:%S %Y %z";
+
+/// Represents how each commit is printed.
+enum Format {
+    /// `<abbreviated hash> <subject>`.
+    Oneline,
+    /// The hash, the author and the subject.
+    Short,
+    /// The hash, the author, the author date and the message.
+    Medium,
+    /// The hash, the author, the committer and the message.
+    Full,
+    /// A format string with placeholders, followed by a newline if `terminated`, and otherwise
+    /// separated from the next commit by a newline.
+    Custom { format: String, terminated: bool },
+}
+
+impl Format {
+    /// Parses the value of `--format` or `--pretty`: one of the names `oneline`, `short`,
+    /// `medium` and `full`, `format:<string>`, `tformat:<string>`, or a string with placeholders.
+    fn parse(format: &str) -> anyhow::Result<Format> {
+        Ok(match format {
+            "oneline" => Format::Oneline,
+            "short" => Format::Short,
+            "medium" => Format::Medium,
+            "full" => Format::Full,
+            _ => match format.split_once(':') {
+                Some(("format", format)) => Format::Custom {
+                    format: format.to_string(),
+                    terminated: false,
+                },
+                Some(("tformat", format)) => Format::Custom {
+                    format: format.to_string(),
+                    terminated: true,
+                },
+                _ if format.contains('%') => Format::Custom {
+                    format: format.to_string(),
+                    terminated: true,
+                },
+                _ => anyhow::bail!("invalid --pretty format: {}", format),
+            },
+        })
+    }
+}
+
+/// Invokes the `log` subcommand.
+///
+/// Prints the commits reachable from the given revisions, or from HEAD if there are none, see
+/// `RevWalk::push` for the revision ranges. Arguments that are not revisions but exist in the
+/// working tree are taken as paths.
+///
+/// # Arguments
+///
+/// * `args` - The revisions, revision ranges and paths.
+/// * `paths` - The paths given after `--`. Only commits that change them are printed.
+/// * `options` - How to walk the history.
+/// * `oneline` - If true, print each commit on one line.
+/// * `format` - The format of each commit, see `Format::parse`.
+/// * `changes` - How the files changed by each commit are listed after it, if they are. The
+///   changes of merge commits are not listed.
+/// * `renames` - How renamed and copied files are detected. By default, renames are.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if a revision cannot be resolved or
+/// a commit cannot be read.
+pub(crate) fn invoke(
+    args: &[String],
+    paths: &[String],
+    options: &WalkOptions,
+    oneline: bool,
+    format: Option<&str>,
+    changes: &ChangeFormat,
+    renames: &RenameArgs,
+) -> anyhow::Result<()> {
+    let format = match format {
+        Some(format) => Format::parse(format)?,
+        None if oneline => Format::Oneline,
+        None => Format::Medium,
+    };
+
+    let mut walk = RevWalk::new();
+    walk.first_parent = options.first_parent;
+    walk.max_count = options.max_count;
+    walk.order = match (options.topo_order, options.date_order) {
+        (true, _) => Order::Topo,
+        (_, true) => Order::Date,
+        _ => Order::Default,
+    };
+    walk.paths = paths.iter().map(normalize_path).collect();
+
+    let mut has_revs = false;
+    for arg in args {
+        match walk.push(arg) {
+            Ok(()) => has_revs = true,
+            Err(_) if Path::new(arg).exists() => walk.paths.push(normalize_path(arg)),
+            Err(err) => return Err(err),
+        }
+    }
+    if !has_revs {
+        if refs::resolve(refs::HEAD)?.is_none() {
+            let branch = refs::resolve_name(refs::HEAD)?;
+            anyhow::bail!(
+                "your current branch '{}' does not have any commits yet",
+                refs::shorten(&branch)
+            );
+        }
+        walk.push(refs::HEAD)?;
+    }
+
+    let show_changes = changes.name_only || changes.name_status;
+    let renames = rename_options(renames, true)?;
+
+    let oids = walk.walk()?;
+    let mut stdout = std::io::stdout().lock();
+    for (i, oid) in oids.iter().enumerate() {
+        let commit = walk.graph.get(oid)?;
+        let mut text = format_commit(&format, oid, commit)?;
+        if show_changes && commit.parents.len() <= 1 {
+            let changed = format_changes(commit, &walk.paths, changes, renames.as_ref())?;
+            if !changed.is_empty() {
+                // Like git, the changes follow an empty line, except with the oneline format,
+                // and on the next line with a format string that is not terminated.
+                if !matches!(format, Format::Oneline) {
+                    text.push('\n');
+                }
+                text.push_str(&changed);
+            }
+        }
+        let separator = match &format {
+            Format::Oneline
+            | Format::Custom {
+                terminated: true, ..
+            } => "",
+            _ if i == 0 => "",
+            _ => "\n",
+        };
+        let result = write!(stdout, "{}{}", separator, text);
+        // Stop quietly when the reader of the output went away, e.g. `bran log | head`.
+        match result {
+            Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result.context("Failed to write to stdout.")?,
+        }
+    }
+    Ok(())
+}
+
+/// Lists the files changed by a commit compared to its parent, or all of its files for a root
+/// commit, limited to `paths` if there are any.
+fn format_changes(
+    commit: &Commit,
+    paths: &[PathBuf],
+    format: &ChangeFormat,
+    renames: Option<&RenameOptions>,
+) -> anyhow::Result<String> {
+    let parent = match commit.parents.first() {
+        Some(parent) => Some(Commit::read(&parent.hash())?.tree),
+        None => None,
+    };
+    let mut changes = tree::diff_trees(parent.as_ref(), Some(&commit.tree), true)?;
+    changes.retain(|change| {
+        paths.is_empty() || paths.iter().any(|path| change.path().starts_with(path))
+    });
+    if let Some(options) = renames {
+        changes = rename::detect_renames(changes, &[], options)?;
+    }
+    Ok(changes
+        .iter()
+        .map(|change| format_change(change, format))
+        .collect())
+}
+
+/// Formats a commit, including the newline that ends its last line, except for a format string
+/// that is not terminated.
+fn format_commit(format: &Format, oid: &ObjectID, commit: &Commit) -> anyhow::Result<String> {
+    let mut text = String::new();
+    match format {
+        Format::Oneline => {
+            let abbrev = revision::abbreviate(oid, revision::DEFAULT_ABBREV)?;
+            text.push_str(&format!("{} {}\n", abbrev, commit.subject()));
+        }
+        Format::Custom { format, terminated } => {
+            text.push_str(&expand_placeholders(format, oid, commit)?);
+            if *terminated {
+                text.push('\n');
+            }
+        }
+        _ => {
+            text.push_str(&format!("commit {}\n", oid));
+            if commit.parents.len() > 1 {
+                let mut parents = Vec::new();
+                for parent in &commit.parents {
+                    parents.push(revision::abbreviate(parent, revision::DEFAULT_ABBREV)?);
+                }
+                text.push_str(&format!("Merge: {}\n", parents.join(" ")));
+            }
+            let author = &commit.author;
+            text.push_str(&format!(
+                "Author: {} <{}>\n",
+                author.display_name(),
+                author.display_email()
+            ));
+            match format {
+                Format::Medium => {
+                    let date = author.date().format(DEFAULT_DATE_FORMAT);
+                    text.push_str(&format!("Date:   {}\n", date));
+                }
+                Format::Full => {
+                    let committer = &commit.committer;
+                    text.push_str(&format!(
+                        "Commit: {} <{}>\n",
+                        committer.display_name(),
+                        committer.display_email()
+                    ));
+                }
+                _ => {}
+            }
+            let message = commit.display_message();
+            let lines = message
+                .trim_end()
+                .lines()
+                .skip_while(|line| line.trim().is_empty());
+            // The short format shows only the first paragraph.
+            let short = matches!(format, Format::Short);
+            text.push('\n');
+            for line in lines.take_while(|line| !short || !line.trim().is_empty()) {
+                text.push_str(&format!("    {}\n", line));
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// Expands the placeholders of a format string for a commit.
+///
+/// Supported placeholders are `%H`, `%h`, `%T`, `%t`, `%P` and `%p` for the hashes of the commit,
+/// its tree and its parents, `%an`, `%ae`, `%ad`, `%at`, `%ai`, `%aI` and `%ar` for the author
+/// name, email and date (default, timestamp, ISO-like, strict ISO and relative), the same with
+/// `%c` for the committer, `%s`, `%b` and `%B` for the subject, the body and the raw message,
+/// `%n` for a newline and `%%` for a `%`. Other placeholders are kept as is.
+fn expand_placeholders(format: &str, oid: &ObjectID, commit: &Commit) -> anyhow::Result<String> {
+    let abbrev = |oid: &ObjectID| revision::abbreviate(oid, revision::DEFAULT_ABBREV);
+    let mut text = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let mut chars = rest.chars();
+        let (expansion, len) = match chars.next() {
+            Some('H') => (oid.hash(), 1),
+            Some('h') => (abbrev(oid)?, 1),
+            Some('T') => (commit.tree.hash(), 1),
+            Some('t') => (abbrev(&commit.tree)?, 1),
+            Some('P') => {
+                let parents = commit.parents.iter().map(ObjectID::hash);
+                (parents.collect::<Vec<_>>().join(" "), 1)
+            }
+            Some('p') => {
+                let mut parents = Vec::new();
+                for parent in &commit.parents {
+                    parents.push(abbrev(parent)?);
+                }
+                (parents.join(" "), 1)
+            }
+            Some('s') => (commit.subject(), 1),
+            Some('b') => (commit.body(), 1),
+            Some('B') => (commit.display_message().into_owned(), 1),
+            Some('n') => ("\n".to_string(), 1),
+            Some('%') => ("%".to_string(), 1),
+            Some(role @ ('a' | 'c')) => {
+                let signature = if role == 'a' {
+                    &commit.author
+                } else {
+                    &commit.committer
+                };
+                match chars
+                    .next()
+                    .and_then(|field| format_signature(signature, field))
+                {
+                    Some(expansion) => (expansion, 2),
+                    None => ("%".to_string(), 0),
+                }
+            }
+            _ => ("%".to_string(), 0),
+        };
+        text.push_str(&expansion);
+        rest = &rest[len..];
+    }
+    text.push_str(rest);
+    Ok(text)
+}
+
+/// Formats a field of a signature for the placeholders `%a<field>` and `%c<field>`.
+fn format_signature(signature: &Signature, field: char) -> Option<String> {
+    let date = signature.date();
+    Some(match field {
+        'n' => signature.display_name().into_owned(),
+        'e' => signature.display_email().into_owned(),
+        'd' => date.format(DEFAULT_DATE_FORMAT).to_string(),
+        't' => signature.timestamp.to_string(),
+        'i' => date.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+        'I' => date.to_rfc3339(),
+        'r' => relative_date(signature.timestamp),
+        _ => return None,
+    })
+}
+
+/// Formats a date relative to now, e.g. `3 hours ago`, rounding the way git does.
+fn relative_date(timestamp: i64) -> String {
+    let seconds = chrono::Local::now().timestamp() - timestamp;
+    if seconds < 0 {
+        return "in the future".to_string();
+    }
+    let plural =
+        |n: i64, unit: &str| format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" });
+    if seconds < 90 {
+        return plural(seconds, "second");
+    }
+    let minutes = (seconds + 30) / 60;
+    if minutes < 90 {
+        return plural(minutes, "minute");
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return plural(hours, "hour");
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return plural(days, "day");
+    }
+    if days < 70 {
+        return plural((days + 3) / 7, "week");
+    }
+    if days < 365 {
+        return plural((days + 15) / 30, "month");
+    }
+    if days < 1825 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        if months > 0 {
+            let years = format!("{} year{}", years, if years == 1 { "" } else { "s" });
+            return format!("{}, {}", years, plural(months, "month"));
+        }
+        return plural(years, "year");
+    }
+    plural((days + 183) / 365, "year")
+}
+
+/// Normalizes a path given on the command line, e.g. `./src/` to `src`.
+pub(crate) fn normalize_path(path: impl AsRef<Path>) -> PathBuf {
+    path.as_ref()
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
@@ -1,9 +1,40 @@
 use crate::index;
 
-pub(crate) fn invoke(path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+/// Invokes the `ls-files` subcommand.
+///
+/// Prints the path of each entry in the index. With `stage`, each path is preceded by the mode,
+/// the object ID and the stage of the entry, so the versions of a conflicted file are told apart.
+///
+/// # Arguments
+///
+/// * `path` - The path to the index file.
+/// * `stage` - If true, print the mode, object ID and stage of the entries.
+/// * `unmerged` - If true, only print the entries of conflicted files, as with `stage`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if the index cannot be read.
+pub(crate) fn invoke(
+    path: impl AsRef<std::path::Path>,
+    stage: bool,
+    unmerged: bool,
+) -> anyhow::Result<()> {
     let index = index::Index::init(path)?;
     for entry in index.entries() {
-        println!("{}", entry.path().display());
+        if unmerged && entry.stage() == 0 {
+            continue;
+        }
+        if stage || unmerged {
+            println!(
+                "{:06o} {} {}\t{}",
+                entry.mode,
+                entry.oid,
+                entry.stage(),
+                entry.path().display()
+            );
+        } else {
+            println!("{}", entry.path().display());
+        }
     }
     Ok(())
 }
@@ -1,16 +1,19 @@
 use anyhow::Context;
 
 use crate::objects::kind::ObjectKind;
-use crate::objects::read_object;
 use crate::objects::tree::Tree;
+use crate::revision;
 
-pub(crate) fn invoke(hash: &str, name_only: bool) -> anyhow::Result<()> {
-    let (kind, _, _) = read_object(hash).context("Failed to read object")?;
-
-    let tree_entries = match kind {
-        ObjectKind::Tree => Tree::from_hash(hash).context("Failed to read tree")?,
-        _ => anyhow::bail!("Object is not a tree."),
-    };
+/// Invokes the `ls-tree` subcommand.
+///
+/// # Arguments
+///
+/// * `tree_ish` - The tree to list, or a commit or tag pointing to it, as a revision expression.
+/// * `name_only` - If true, print only the names of the entries.
+pub(crate) fn invoke(tree_ish: &str, name_only: bool) -> anyhow::Result<()> {
+    let tree = revision::peel(&revision::resolve(tree_ish)?, ObjectKind::Tree)
+        .with_context(|| format!("Not a valid object name {}", tree_ish))?;
+    let tree_entries = Tree::from_hash(&tree.hash()).context("Failed to read tree")?;
 
     for entry in tree_entries {
         if name_only {
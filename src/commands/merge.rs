@@ -0,0 +1,253 @@
+use crate::checkout::{self, Operation};
+use crate::config;
+use crate::diff::tree;
+use crate::index::entry::IndexEntry;
+use crate::index::Index;
+use crate::merge::file::ConflictStyle;
+use crate::merge::{self, MergeOptions};
+use crate::objects::commit::Commit;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::objects::signature::Signature;
+use crate::objects::tree::Tree;
+use crate::objects::Object;
+use crate::refs::{self, Expected};
+use crate::rev_walk::CommitGraph;
+use crate::revision;
+use crate::status;
+use crate::workspace::Workspace;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// The ref holding the commit being merged while conflicts are resolved.
+pub(crate) const MERGE_HEAD: &str = "MERGE_HEAD";
+/// The file holding the message of the merge commit while conflicts are resolved.
+pub(crate) const MERGE_MSG: &str = "MERGE_MSG";
+/// The file holding the options of the merge while conflicts are resolved.
+pub(crate) const MERGE_MODE: &str = "MERGE_MODE";
+/// The ref holding the commit HEAD pointed to before a merge.
+const ORIG_HEAD: &str = "ORIG_HEAD";
+/// The name of our side in conflict markers and messages.
+const OURS_LABEL: &str = "HEAD";
+/// The message printed after a merge commit is created.
+const MERGE_MADE: &str = "Merge made by the 'recursive' strategy.";
+
+/// Invokes the `merge` subcommand.
+///
+/// Merges a commit into the current branch. If the commit is already contained in HEAD, nothing
+/// happens; if it contains HEAD, the branch is fast-forwarded to it. Otherwise the trees are
+/// merged with their merge base, see `merge::merge_commits`, and the working tree and the index
+/// are updated. A merge without conflicts is committed with both commits as parents. On
+/// conflicts, the conflicted files are left with conflict markers and recorded in the index as
+/// stages 1 to 3, and the merge is concluded by `commit` once they are resolved.
+///
+/// # Arguments
+///
+/// * `commit` - The commit to merge, usually a branch.
+/// * `message` - The message of the merge commit, instead of the default one.
+/// * `no_ff` - If true, create a merge commit even when the branch could be fast-forwarded.
+/// * `ff_only` - If true, refuse to merge unless the branch can be fast-forwarded.
+/// * `conflict` - The conflict style, `merge` or `diff3`, instead of `merge.conflictStyle`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if the commit cannot be merged, local
+/// changes would be overwritten or the result cannot be written.
+pub(crate) fn invoke(
+    commit: &str,
+    message: Option<&str>,
+    no_ff: bool,
+    ff_only: bool,
+    conflict: Option<&str>,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        refs::read_ref(MERGE_HEAD)?.is_none(),
+        "You have not concluded your merge (MERGE_HEAD exists).\n\
+         Please, commit your changes before you merge."
+    );
+    let config = config::Config::load()?;
+    let style = match conflict.or(config.get("merge.conflictStyle")) {
+        Some(style) => ConflictStyle::parse(style)?,
+        None => ConflictStyle::default(),
+    };
+    let theirs = revision::resolve(commit)
+        .and_then(|oid| revision::peel(&oid, ObjectKind::Commit))
+        .map_err(|_| anyhow::anyhow!("merge: {} - not something we can merge", commit))?;
+
+    let workspace = Workspace::new(Path::new("."))?;
+    let mut index = Index::lock(".git/index")?;
+    let Some(head) = refs::resolve(refs::HEAD)? else {
+        // Merging into a branch without commits only checks the commit out.
+        let files = Tree::flatten(&Commit::read(&theirs.hash())?.tree.hash())?;
+        checkout::checkout_files(&workspace, &mut index, &files, false, Operation::Merge)?;
+        index.write().context("Failed to write index")?;
+        let reason = format!("merge {}: Fast-forward", commit);
+        return refs::update_ref(refs::HEAD, &theirs, Expected::Missing, &reason, false);
+    };
+
+    let mut graph = CommitGraph::default();
+    let bases = graph.merge_bases(&head, &theirs)?;
+    if bases.contains(&theirs) {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    if bases.contains(&head) && !no_ff {
+        return fast_forward(&workspace, index, commit, &head, &theirs);
+    }
+    anyhow::ensure!(!ff_only, "Not possible to fast-forward, aborting.");
+    anyhow::ensure!(!bases.is_empty(), "refusing to merge unrelated histories");
+
+    // The merge is written to the index, which must not have changes of its own.
+    let staged = tree::diff_files(
+        &status::head_files()?,
+        &status::index_files(index.entries()),
+    );
+    if !staged.is_empty() || index.has_conflicts() {
+        let mut paths = staged
+            .iter()
+            .map(|change| change.path().to_path_buf())
+            .chain(index.conflicts().keys().map(|path| path.to_path_buf()))
+            .collect::<Vec<_>>();
+        paths.sort_by(|a, b| status::compare_paths(a, b));
+        paths.dedup();
+        anyhow::bail!(
+            "Your local changes to the following files would be overwritten by merge:\n{}\
+             Please commit your changes or stash them before you merge.\nAborting",
+            paths
+                .iter()
+                .map(|path| format!("\t{}\n", path.display()))
+                .collect::<String>()
+        );
+    }
+
+    let options = MergeOptions {
+        ours: OURS_LABEL,
+        theirs: commit,
+        style,
+    };
+    let result = merge::merge_commits(&mut graph, &head, &theirs, &bases, &options)?;
+    checkout::checkout_files(
+        &workspace,
+        &mut index,
+        &result.files,
+        false,
+        Operation::Merge,
+    )?;
+    for (path, versions) in &result.conflicts {
+        let stages = versions
+            .iter()
+            .zip(1..)
+            .filter_map(|(version, stage)| {
+                let (mode, oid) = version.clone()?;
+                Some(IndexEntry::unmerged(path.clone(), mode, oid, stage))
+            })
+            .collect();
+        index.add_conflict(path, stages);
+    }
+    refs::update_ref(ORIG_HEAD, &head, Expected::Any, "", true)?;
+    for line in &result.messages {
+        println!("{}", line);
+    }
+
+    let message = match message {
+        Some(message) => message.to_string(),
+        None => default_message(commit)?,
+    };
+    if !result.conflicts.is_empty() {
+        index.write().context("Failed to write index")?;
+        refs::update_ref(MERGE_HEAD, &theirs, Expected::Any, "", true)?;
+        let conflicts = result
+            .conflicts
+            .keys()
+            .map(|path| format!("#\t{}\n", path.display()))
+            .collect::<String>();
+        write_merge_file(
+            MERGE_MSG,
+            &format!("{}\n\n# Conflicts:\n{}", message, conflicts),
+        )?;
+        write_merge_file(MERGE_MODE, if no_ff { "no-ff" } else { "" })?;
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        std::process::exit(1);
+    }
+
+    let tree = index
+        .write_tree()
+        .context("Failed to write tree from index.")?;
+    index.write().context("Failed to write index")?;
+    let mut merge_commit = Commit::new(
+        tree,
+        vec![head.clone(), theirs],
+        Signature::from_env("AUTHOR", &config)?,
+        Signature::from_env("COMMITTER", &config)?,
+        format!("{}\n", message),
+    );
+    let oid = merge_commit.write()?;
+    let reason = format!("merge {}: {}", commit, MERGE_MADE);
+    refs::update_ref(refs::HEAD, &oid, Expected::Value(head), &reason, false)
+        .context("Failed to update HEAD")?;
+    println!("{}", MERGE_MADE);
+    Ok(())
+}
+
+/// Fast-forwards the current branch to a commit that contains HEAD, updating the working tree
+/// and the index the way a checkout does.
+fn fast_forward(
+    workspace: &Workspace,
+    mut index: Index,
+    commit: &str,
+    head: &ObjectID,
+    theirs: &ObjectID,
+) -> anyhow::Result<()> {
+    println!(
+        "Updating {}..{}",
+        revision::abbreviate(head, revision::DEFAULT_ABBREV)?,
+        revision::abbreviate(theirs, revision::DEFAULT_ABBREV)?
+    );
+    let files = Tree::flatten(&Commit::read(&theirs.hash())?.tree.hash())?;
+    checkout::checkout_files(workspace, &mut index, &files, false, Operation::Merge)?;
+    index.write().context("Failed to write index")?;
+
+    refs::update_ref(ORIG_HEAD, head, Expected::Any, "", true)?;
+    let reason = format!("merge {}: Fast-forward", commit);
+    refs::update_ref(
+        refs::HEAD,
+        theirs,
+        Expected::Value(head.clone()),
+        &reason,
+        false,
+    )
+    .context("Failed to update HEAD")?;
+    println!("Fast-forward");
+    Ok(())
+}
+
+/// Returns the default message of a merge commit, naming what was merged the way git does, e.g.
+/// `Merge branch 'topic'`. The current branch is named as well, unless it is `main` or `master`.
+fn default_message(commit: &str) -> anyhow::Result<String> {
+    let kind = if refs::read_ref(&format!("refs/heads/{}", commit))?.is_some() {
+        "branch"
+    } else if refs::read_ref(&format!("refs/remotes/{}", commit))?.is_some() {
+        "remote-tracking branch"
+    } else if refs::read_ref(&format!("refs/tags/{}", commit))?.is_some() {
+        "tag"
+    } else {
+        "commit"
+    };
+    let into = match refs::current_branch()? {
+        Some(branch) if matches!(refs::shorten(&branch), "main" | "master") => String::new(),
+        Some(branch) => format!(" into {}", refs::shorten(&branch)),
+        None => format!(" into {}", refs::HEAD),
+    };
+    Ok(format!("Merge {} '{}'{}", kind, commit, into))
+}
+
+/// Returns the path of a file describing the merge in progress.
+pub(crate) fn merge_file_path(name: &str) -> PathBuf {
+    Path::new(refs::GIT_DIR).join(name)
+}
+
+/// Writes a file describing the merge in progress.
+fn write_merge_file(name: &str, content: &str) -> anyhow::Result<()> {
+    let path = merge_file_path(name);
+    std::fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,46 @@
+use crate::objects::kind::ObjectKind;
+use crate::rev_walk::CommitGraph;
+use crate::revision;
+
+/// Invokes the `merge-base` subcommand.
+///
+/// Prints the best common ancestor of two commits, or all of them, see
+/// `CommitGraph::merge_bases`. Exits with status 1 if the commits have no common history.
+///
+/// # Arguments
+///
+/// * `one` - The first commit.
+/// * `two` - The second commit.
+/// * `all` - If true, print all the merge bases instead of one.
+/// * `is_ancestor` - If true, print nothing and exit with status 0 if the first commit is an
+///   ancestor of the second one, and 1 otherwise.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if a commit cannot be resolved or read.
+pub(crate) fn invoke(one: &str, two: &str, all: bool, is_ancestor: bool) -> anyhow::Result<()> {
+    let resolve = |rev: &str| {
+        revision::resolve(rev)
+            .and_then(|oid| revision::peel(&oid, ObjectKind::Commit))
+            .map_err(|_| anyhow::anyhow!("Not a valid object name {}", rev))
+    };
+    let (one, two) = (resolve(one)?, resolve(two)?);
+    let mut graph = CommitGraph::default();
+
+    if is_ancestor {
+        if !graph.is_ancestor(&one, &two)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let bases = graph.merge_bases(&one, &two)?;
+    if bases.is_empty() {
+        std::process::exit(1);
+    }
+    let count = if all { bases.len() } else { 1 };
+    for base in bases.iter().take(count) {
+        println!("{}", base);
+    }
+    Ok(())
+}
@@ -1,9 +1,31 @@
 pub(crate) mod add;
+pub(crate) mod branch;
 pub(crate) mod cat_file;
+pub(crate) mod check_ignore;
+pub(crate) mod checkout;
 pub(crate) mod cli;
+pub(crate) mod commit;
 pub(crate) mod commit_tree;
+pub(crate) mod config;
+pub(crate) mod diff;
+pub(crate) mod diff_tree;
 pub(crate) mod hash_object;
 pub(crate) mod init;
+pub(crate) mod log;
 pub(crate) mod ls_files;
 pub(crate) mod ls_tree;
+pub(crate) mod merge;
+pub(crate) mod merge_base;
+pub(crate) mod mv;
+pub(crate) mod pack_objects;
+pub(crate) mod repack;
+pub(crate) mod rev_parse;
+pub(crate) mod rm;
+pub(crate) mod show_ref;
+pub(crate) mod status;
+pub(crate) mod switch;
+pub(crate) mod symbolic_ref;
+pub(crate) mod tag;
+pub(crate) mod update_index;
+pub(crate) mod update_ref;
 pub(crate) mod write_tree;
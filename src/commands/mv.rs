@@ -0,0 +1,136 @@
+use crate::commands::add::{is_glob, normalize};
+use crate::ignore;
+use crate::index::Index;
+use crate::workspace::Workspace;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Invokes the `mv` subcommand.
+///
+/// Moves files and directories in the working tree, and their entries in the index, which keep
+/// their stat data. The last path is the destination. If it is an existing directory, the sources
+/// are moved into it, otherwise the single source is renamed to it. A source is either a path or
+/// a glob such as `'*.rs'`, matched against the files of the index. Nothing is moved unless all
+/// the sources can be moved.
+///
+/// # Arguments
+///
+/// * `paths` - The sources, followed by the destination.
+/// * `force` - If true, overwrite the files that are in the way.
+/// * `dry_run` - If true, print what would be moved without moving anything.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the files were moved, or an `anyhow::Error` if a source
+/// is not tracked or conflicted, the destination exists, or the index cannot be updated.
+pub(crate) fn invoke(paths: &[String], force: bool, dry_run: bool) -> anyhow::Result<()> {
+    let mut index = Index::lock(".git/index")?;
+    let workspace = Workspace::new(Path::new("."))?;
+    let Some((destination, pathspecs)) = paths.split_last() else {
+        anyhow::bail!("No destination was given.");
+    };
+    anyhow::ensure!(!pathspecs.is_empty(), "No source was given.");
+    let destination = normalize(destination)?;
+
+    // Collect the sources.
+    let mut sources = Vec::new();
+    for pathspec in pathspecs {
+        if is_glob(pathspec) {
+            let matches = index
+                .entries()
+                .iter()
+                .filter(|entry| ignore::fnmatch(pathspec, entry.path()))
+                .map(|entry| entry.path().to_path_buf())
+                .collect::<BTreeSet<_>>();
+            anyhow::ensure!(
+                !matches.is_empty(),
+                "pathspec '{}' did not match any files",
+                pathspec
+            );
+            sources.extend(matches);
+        } else {
+            sources.push(normalize(pathspec)?);
+        }
+    }
+
+    let into_dir =
+        std::fs::symlink_metadata(workspace.dir.join(&destination)).is_ok_and(|meta| meta.is_dir());
+    anyhow::ensure!(
+        into_dir || sources.len() == 1,
+        "destination '{}' is not a directory",
+        destination.display()
+    );
+
+    // Check every move before moving anything.
+    let mut moves = Vec::<(PathBuf, PathBuf)>::new();
+    for source in sources {
+        let target = match source.file_name() {
+            Some(name) if into_dir => destination.join(name),
+            _ => destination.clone(),
+        };
+        let error = |reason: &str| {
+            anyhow::anyhow!(
+                "{}, source={}, destination={}",
+                reason,
+                source.display(),
+                target.display()
+            )
+        };
+
+        let Ok(meta) = std::fs::symlink_metadata(workspace.dir.join(&source)) else {
+            return Err(error("bad source"));
+        };
+        if meta.is_dir() {
+            if target.starts_with(&source) {
+                return Err(error("can not move directory into itself"));
+            }
+            let mut entries = index
+                .entries()
+                .iter()
+                .filter(|entry| entry.path().starts_with(&source))
+                .peekable();
+            if entries.peek().is_none() {
+                return Err(error("source directory is empty"));
+            }
+            if entries.any(|entry| entry.stage() > 0) {
+                return Err(error("conflicted"));
+            }
+        } else {
+            match index.entry(&source) {
+                None => return Err(error("not under version control")),
+                Some(entry) if entry.stage() > 0 => return Err(error("conflicted")),
+                Some(_) => {}
+            }
+        }
+
+        match std::fs::symlink_metadata(workspace.dir.join(&target)) {
+            Ok(existing) if !force || existing.is_dir() || meta.is_dir() => {
+                return Err(error("destination exists"));
+            }
+            _ => {}
+        }
+        let parent = target.parent().unwrap_or(Path::new(""));
+        if !parent.as_os_str().is_empty() && !workspace.dir.join(parent).is_dir() {
+            return Err(error("destination directory does not exist"));
+        }
+        if moves.iter().any(|(_, other)| *other == target) {
+            return Err(error("multiple sources for the same target"));
+        }
+        moves.push((source, target));
+    }
+
+    for (source, target) in &moves {
+        if dry_run {
+            println!("Renaming {} to {}", source.display(), target.display());
+            continue;
+        }
+        std::fs::rename(workspace.dir.join(source), workspace.dir.join(target))
+            .with_context(|| format!("renaming '{}' failed", source.display()))?;
+        index.rename(source, target);
+    }
+    if !dry_run {
+        index.write()?;
+    }
+    Ok(())
+}
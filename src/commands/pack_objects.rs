@@ -0,0 +1,31 @@
+use crate::objects::id::ObjectID;
+use crate::objects::pack::write::write_pack;
+use anyhow::Context;
+use std::io::BufRead;
+
+/// Invokes the `pack-objects` subcommand.
+///
+/// Reads object IDs from the standard input, one per line, writes them in a new packfile and
+/// prints the checksum of the pack.
+///
+/// # Arguments
+///
+/// * `base_name` - The prefix of the paths of the written `.pack` and `.idx` files.
+pub(crate) fn invoke(base_name: &str) -> anyhow::Result<()> {
+    let mut oids = Vec::new();
+    for line in std::io::stdin().lock().lines() {
+        let line = line.context("Failed to read object ID from stdin.")?;
+        // Like git, anything after the object ID (e.g. a path) is ignored.
+        let Some(hash) = line.split_whitespace().next() else {
+            continue;
+        };
+        let mut bytes = [0u8; 20];
+        hex::decode_to_slice(hash, &mut bytes)
+            .with_context(|| format!("Invalid object ID: {}", hash))?;
+        oids.push(ObjectID::from_bytes(bytes));
+    }
+
+    let checksum = write_pack(base_name, oids).context("Failed to write pack.")?;
+    println!("{}", checksum);
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use crate::objects;
+use crate::objects::pack::write::write_objects;
+use crate::objects::pack::{self, PACK_DIR};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Invokes the `repack` subcommand.
+///
+/// Packs all loose objects in a new packfile. With `all`, the objects of the existing packs are
+/// included too, so that everything ends up in a single pack. Packed objects are read straight
+/// from their packs, entry by entry, rather than looked up one by one.
+///
+/// # Arguments
+///
+/// * `all` - If true, repack the objects of existing packs as well.
+/// * `delete` - If true, delete the loose objects and packs that became redundant.
+pub(crate) fn invoke(all: bool, delete: bool) -> anyhow::Result<()> {
+    let loose = objects::list_loose_objects()?;
+    let packs = if all {
+        pack::packs()?
+    } else {
+        Default::default()
+    };
+
+    let mut objects = BTreeMap::new();
+    for oid in &loose {
+        let (kind, _, mut reader) = objects::read_object(&oid.hash())?;
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read object {}", oid))?;
+        objects.insert(oid.clone(), (kind, data));
+    }
+    for pack in packs.iter() {
+        for (oid, kind, data) in pack.read_all()? {
+            objects.entry(oid).or_insert((kind, data));
+        }
+    }
+    if objects.is_empty() {
+        println!("Nothing new to pack.");
+        return Ok(());
+    }
+
+    let count = objects.len();
+    let objects = objects
+        .into_iter()
+        .map(|(oid, (kind, data))| (oid, kind, data));
+    let checksum = write_objects(Path::new(PACK_DIR).join("pack"), objects)
+        .context("Failed to write pack.")?;
+    println!("Packed {} objects in pack-{}", count, checksum);
+
+    if delete {
+        // Remove the old packs, unless the new pack happens to be identical to one of them.
+        let new_pack_path = Path::new(PACK_DIR).join(format!("pack-{}.pack", checksum));
+        for pack in packs.iter() {
+            if pack.path() == new_pack_path {
+                continue;
+            }
+            std::fs::remove_file(pack.path().with_extension("idx"))
+                .with_context(|| format!("Failed to remove {}", pack.path().display()))?;
+            std::fs::remove_file(pack.path())
+                .with_context(|| format!("Failed to remove {}", pack.path().display()))?;
+        }
+
+        // Remove the loose objects, and their directories once they are empty.
+        for oid in &loose {
+            let hash = oid.hash();
+            let object_path = format!(".git/objects/{}/{}", &hash[..2], &hash[2..]);
+            std::fs::remove_file(&object_path)
+                .with_context(|| format!("Failed to remove {}", object_path))?;
+            let _ = std::fs::remove_dir(format!(".git/objects/{}", &hash[..2]));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use crate::objects;
+use crate::revision;
+
+/// Invokes the `rev-parse` subcommand.
+///
+/// Prints the object ID named by each revision expression, see `revision::resolve`.
+///
+/// # Arguments
+///
+/// * `revs` - The revision expressions.
+/// * `verify` - If true, require exactly one revision naming an object that exists.
+/// * `quiet` - If true, with `verify`, exit with status 1 instead of printing an error.
+/// * `short` - If set, abbreviate the object IDs to at least this many hex digits, keeping them
+///   unique.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if a revision cannot be resolved.
+pub(crate) fn invoke(
+    revs: &[String],
+    verify: bool,
+    quiet: bool,
+    short: Option<usize>,
+) -> anyhow::Result<()> {
+    if verify {
+        let oid = match revs {
+            [rev] => revision::resolve(rev)
+                .and_then(|oid| objects::read_object(&oid.hash()).map(|_| oid))
+                .ok(),
+            _ => None,
+        };
+        return match (oid, quiet) {
+            (Some(oid), _) => print_oid(&oid, short),
+            (None, true) => std::process::exit(1),
+            (None, false) => anyhow::bail!("Needed a single revision"),
+        };
+    }
+
+    for rev in revs {
+        print_oid(&revision::resolve(rev)?, short)?;
+    }
+    Ok(())
+}
+
+/// Prints an object ID, abbreviated if `short` is set.
+fn print_oid(oid: &objects::id::ObjectID, short: Option<usize>) -> anyhow::Result<()> {
+    match short {
+        Some(len) => println!("{}", revision::abbreviate(oid, len)?),
+        None => println!("{}", oid),
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use crate::checkout;
+use crate::commands::add::{is_glob, normalize};
+use crate::ignore;
+use crate::index::entry::IndexEntry;
+use crate::index::Index;
+use crate::objects::blob::Blob;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::Object;
+use crate::status;
+use crate::workspace::Workspace;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Invokes the `rm` subcommand.
+///
+/// Removes the files matching the pathspecs from the index and from the working tree. Each
+/// pathspec is either a path, which must be given with `recursive` if it is a directory, or a
+/// glob such as `'*.rs'`, matched against the files of the index. Unless `force` is set, files
+/// whose content in the index differs from HEAD or from the working tree are not removed, since
+/// their changes would be lost. With `cached`, the files are kept in the working tree, and only
+/// files whose index content matches neither are refused.
+///
+/// # Arguments
+///
+/// * `pathspecs` - The paths or globs of the files to remove.
+/// * `cached` - If true, only remove the files from the index.
+/// * `recursive` - If true, allow removing the files of a directory.
+/// * `force` - If true, remove the files even if they have changes.
+/// * `dry_run` - If true, print what would be removed without removing anything.
+/// * `quiet` - If true, do not print the removed files.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the files were removed, or an `anyhow::Error` if a
+/// pathspec matches no file, a directory is given without `recursive`, a file has changes, or
+/// the index cannot be updated.
+pub(crate) fn invoke(
+    pathspecs: &[String],
+    cached: bool,
+    recursive: bool,
+    force: bool,
+    dry_run: bool,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    let mut index = Index::lock(".git/index")?;
+    let workspace = Workspace::new(Path::new("."))?;
+    anyhow::ensure!(
+        !pathspecs.is_empty(),
+        "No pathspec was given. Which files should I remove?"
+    );
+
+    // Collect the files to remove.
+    let mut paths = BTreeSet::new();
+    for pathspec in pathspecs {
+        let mut matched = false;
+        if is_glob(pathspec) {
+            for entry in index.entries() {
+                if ignore::fnmatch(pathspec, entry.path()) {
+                    paths.insert(entry.path().to_path_buf());
+                    matched = true;
+                }
+            }
+        } else {
+            let path = normalize(pathspec)?;
+            for entry in index.entries() {
+                if entry.path().starts_with(&path) {
+                    anyhow::ensure!(
+                        recursive || entry.path() == path,
+                        "not removing '{}' recursively without -r",
+                        pathspec
+                    );
+                    paths.insert(entry.path().to_path_buf());
+                    matched = true;
+                }
+            }
+        }
+        anyhow::ensure!(matched, "pathspec '{}' did not match any files", pathspec);
+    }
+
+    if !force {
+        check_changes(&workspace, &index, &paths, cached)?;
+    }
+
+    for path in &paths {
+        if !quiet {
+            println!("rm '{}'", path.display());
+        }
+        if dry_run {
+            continue;
+        }
+        index.remove(path);
+        if !cached {
+            checkout::remove_file(&workspace, path)?;
+        }
+    }
+    if !dry_run {
+        index.write()?;
+    }
+    Ok(())
+}
+
+/// Refuses to remove files whose changes would be lost, the way git does: a file whose index
+/// content differs from both HEAD and the working tree, and unless only the index is changed, a
+/// file with staged changes or with local modifications. Conflicted files and files missing from
+/// the working tree are never refused.
+///
+/// # Arguments
+///
+/// * `workspace` - The working tree.
+/// * `index` - The index.
+/// * `paths` - The files to remove.
+/// * `cached` - If true, the files are only removed from the index.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the files can be removed, or an `anyhow::Error` listing
+/// the files that have changes.
+fn check_changes(
+    workspace: &Workspace,
+    index: &Index,
+    paths: &BTreeSet<PathBuf>,
+    cached: bool,
+) -> anyhow::Result<()> {
+    let head = status::head_files()?;
+    let mut both = Vec::new();
+    let mut staged = Vec::new();
+    let mut local = Vec::new();
+    for path in paths {
+        let Some(entry) = index.entry_at(path, 0) else {
+            continue;
+        };
+        let Ok(meta) = std::fs::symlink_metadata(workspace.dir.join(path)) else {
+            continue;
+        };
+        if meta.is_dir() {
+            continue;
+        }
+        let in_index = EntryMode::from_index_mode(entry.mode).map(|mode| (mode, entry.oid.clone()));
+        let staged_changes = head.get(path) != in_index.as_ref();
+        let local_changes = is_modified(workspace, index, entry, &meta)?;
+        if staged_changes && local_changes {
+            both.push(path);
+        } else if !cached {
+            if staged_changes {
+                staged.push(path);
+            }
+            if local_changes {
+                local.push(path);
+            }
+        }
+    }
+
+    let mut errors = Vec::new();
+    let groups = [
+        (
+            both,
+            "staged content different from both the\nfile and the HEAD:",
+            "(use -f to force removal)",
+        ),
+        (
+            staged,
+            "changes staged in the index:",
+            "(use --cached to keep the file, or -f to force removal)",
+        ),
+        (
+            local,
+            "local modifications:",
+            "(use --cached to keep the file, or -f to force removal)",
+        ),
+    ];
+    for (files, description, hint) in groups {
+        if files.is_empty() {
+            continue;
+        }
+        let mut error = match files.len() {
+            1 => format!("the following file has {}\n", description),
+            _ => format!("the following files have {}\n", description),
+        };
+        for path in files {
+            error.push_str(&format!("    {}\n", path.display()));
+        }
+        error.push_str(hint);
+        errors.push(error);
+    }
+    anyhow::ensure!(errors.is_empty(), "{}", errors.join("\n"));
+    Ok(())
+}
+
+/// Returns true if the file of an index entry differs from the entry in the working tree. Files
+/// marked as unchanged are not looked at.
+fn is_modified(
+    workspace: &Workspace,
+    index: &Index,
+    entry: &IndexEntry,
+    meta: &std::fs::Metadata,
+) -> anyhow::Result<bool> {
+    if entry.is_assumed_unchanged() || index.stat_matches(entry, meta) {
+        return Ok(false);
+    }
+    let path = workspace.dir.join(entry.path());
+    let oid = Blob::from_worktree(&path)?
+        .hash()
+        .with_context(|| format!("Failed to hash {}", path.display()))?;
+    Ok(oid != entry.oid
+        || EntryMode::from_index_mode(entry.mode) != Some(EntryMode::from_metadata(meta)))
+}
@@ -0,0 +1,72 @@
+use crate::refs;
+
+/// Invokes the `show-ref` subcommand.
+///
+/// Lists the refs together with the objects they point to. Patterns match the end of a ref name
+/// on a component boundary, e.g. `main` matches `refs/heads/main` and `refs/remotes/origin/main`.
+///
+/// # Arguments
+///
+/// * `patterns` - Only show refs matching any of these patterns.
+/// * `head` - If true, show `HEAD` too.
+/// * `heads` - If true, only show branches.
+/// * `tags` - If true, only show tags.
+/// * `verify` - If true, the patterns must be full ref names that exist.
+/// * `hash_only` - If true, only print the object IDs.
+pub(crate) fn invoke(
+    patterns: &[String],
+    head: bool,
+    heads: bool,
+    tags: bool,
+    verify: bool,
+    hash_only: bool,
+) -> anyhow::Result<()> {
+    let print = |name: &str, oid: &crate::objects::id::ObjectID| {
+        if hash_only {
+            println!("{}", oid);
+        } else {
+            println!("{} {}", oid, name);
+        }
+    };
+
+    if verify {
+        for name in patterns {
+            match refs::resolve(name)? {
+                Some(oid) if name == refs::HEAD || name.starts_with("refs/") => print(name, &oid),
+                _ => anyhow::bail!("'{}' - not a valid ref", name),
+            }
+        }
+        return Ok(());
+    }
+
+    let mut found = false;
+    if head {
+        if let Some(oid) = refs::resolve(refs::HEAD)? {
+            print(refs::HEAD, &oid);
+            found = true;
+        }
+    }
+
+    for (name, oid) in refs::list_refs("refs/")? {
+        let kind_matches = match (heads, tags) {
+            (false, false) => true,
+            _ => {
+                (heads && name.starts_with("refs/heads/"))
+                    || (tags && name.starts_with("refs/tags/"))
+            }
+        };
+        let pattern_matches = patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|pattern| name == *pattern || name.ends_with(&format!("/{}", pattern)));
+        if kind_matches && pattern_matches {
+            print(&name, &oid);
+            found = true;
+        }
+    }
+
+    if !found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
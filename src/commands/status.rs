@@ -0,0 +1,182 @@
+use crate::commands::branch;
+use crate::diff::tree::FileChange;
+use crate::index;
+use crate::refs;
+use crate::status::{compare_paths, Status};
+use crate::workspace::Workspace;
+use std::path::Path;
+
+/// Invokes the `status` subcommand.
+///
+/// # Arguments
+///
+/// * `short` - If true, print the status in the short format.
+/// * `porcelain` - The version of the porcelain format to print the status in, if any.
+pub(crate) fn invoke(short: bool, porcelain: Option<String>) -> anyhow::Result<()> {
+    if let Some(version) = &porcelain {
+        anyhow::ensure!(
+            version == "v1" || version == "1",
+            "Unsupported porcelain format: {}",
+            version
+        );
+    }
+
+    let workspace = Workspace::new(Path::new("."))?;
+    let index = index::Index::init(".git/index")?;
+    let status = Status::compute(&workspace, &index)?;
+
+    if short || porcelain.is_some() {
+        print_short(&status);
+    } else {
+        print_long(&status)?;
+    }
+    Ok(())
+}
+
+/// Prints the status in the short format, `XY path`, where `X` is the status of the index and `Y`
+/// is the status of the working tree. Conflicted files are printed with the versions they have,
+/// e.g. `UU path`, and untracked files as `?? path`.
+fn print_short(status: &Status) {
+    let mut paths = status
+        .staged
+        .iter()
+        .map(FileChange::path)
+        .chain(status.unstaged.iter().map(|(path, _)| path.as_path()))
+        .chain(status.unmerged.iter().map(|(path, _)| path.as_path()))
+        .collect::<Vec<&Path>>();
+    paths.sort_by(|a, b| compare_paths(a, b));
+    paths.dedup();
+
+    for path in paths {
+        if let Some((_, conflict)) = status
+            .unmerged
+            .iter()
+            .find(|(unmerged, _)| unmerged == path)
+        {
+            println!("{} {}", conflict.code(), path.display());
+            continue;
+        }
+        let staged = status.staged.iter().find(|change| change.path() == path);
+        let unstaged = status.unstaged.iter().find(|(changed, _)| changed == path);
+        println!(
+            "{}{} {}",
+            staged.map_or(' ', |change| change.change.letter()),
+            unstaged.map_or(' ', |(_, change)| change.letter()),
+            staged.map_or(path.display().to_string(), format_path)
+        );
+    }
+    for path in &status.untracked {
+        println!("?? {}", path);
+    }
+}
+
+/// Prints the status in the long, human readable format.
+fn print_long(status: &Status) -> anyhow::Result<()> {
+    match refs::current_branch()? {
+        Some(branch) => {
+            println!("On branch {}", refs::shorten(&branch));
+            print_tracking(refs::shorten(&branch))?;
+        }
+        None => println!("HEAD detached"),
+    }
+    if !status.unmerged.is_empty() {
+        println!("You have unmerged paths.");
+    }
+    if refs::resolve(refs::HEAD)?.is_none() {
+        println!("\nNo commits yet");
+    }
+
+    if !status.staged.is_empty() {
+        println!("\nChanges to be committed:");
+        for change in &status.staged {
+            println!(
+                "\t{:<12}{}",
+                format!("{}:", change.change.description()),
+                format_path(change)
+            );
+        }
+    }
+    if !status.unmerged.is_empty() {
+        println!("\nUnmerged paths:");
+        for (path, conflict) in &status.unmerged {
+            println!(
+                "\t{:<17}{}",
+                format!("{}:", conflict.description()),
+                path.display()
+            );
+        }
+    }
+    if !status.unstaged.is_empty() {
+        println!("\nChanges not staged for commit:");
+        for (path, change) in &status.unstaged {
+            println!(
+                "\t{:<12}{}",
+                format!("{}:", change.description()),
+                path.display()
+            );
+        }
+    }
+    if !status.untracked.is_empty() {
+        println!("\nUntracked files:");
+        for path in &status.untracked {
+            println!("\t{}", path);
+        }
+    }
+
+    if status.is_clean() {
+        println!("\nnothing to commit, working tree clean");
+    } else if status.staged.is_empty() {
+        println!("\nno changes added to commit");
+    }
+    Ok(())
+}
+
+/// Prints how far the current branch is from its upstream, if it has one.
+fn print_tracking(branch: &str) -> anyhow::Result<()> {
+    let Some(upstream) = branch::upstream(branch)? else {
+        return Ok(());
+    };
+    let name = refs::shorten(&upstream);
+    let Some(oid) = refs::resolve(refs::HEAD)? else {
+        return Ok(());
+    };
+    let Some(upstream) = refs::resolve(&upstream)? else {
+        println!(
+            "Your branch is based on '{}', but the upstream is gone.",
+            name
+        );
+        return Ok(());
+    };
+    let commits = |count: usize| if count == 1 { "commit" } else { "commits" };
+    match branch::ahead_behind(&oid, &upstream)? {
+        (0, 0) => println!("Your branch is up to date with '{}'.", name),
+        (ahead, 0) => println!(
+            "Your branch is ahead of '{}' by {} {}.",
+            name,
+            ahead,
+            commits(ahead)
+        ),
+        (0, behind) => println!(
+            "Your branch is behind '{}' by {} {}, and can be fast-forwarded.",
+            name,
+            behind,
+            commits(behind)
+        ),
+        (ahead, behind) => println!(
+            "Your branch and '{}' have diverged,\nand have {} and {} different commits each, \
+             respectively.",
+            name, ahead, behind
+        ),
+    }
+    Ok(())
+}
+
+/// Formats the path of a staged change, `old -> new` for renamed and copied files.
+fn format_path(change: &FileChange) -> String {
+    match (&change.old, &change.new) {
+        (Some(old), Some(new)) if old.path != new.path => {
+            format!("{} -> {}", old.path.display(), new.path.display())
+        }
+        _ => change.path().display().to_string(),
+    }
+}
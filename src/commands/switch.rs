@@ -0,0 +1,50 @@
+use crate::commands::checkout::{self, Target};
+use crate::refs;
+use crate::revision;
+
+/// Invokes the `switch` subcommand.
+///
+/// Like `checkout`, but the target must be a branch unless `detach` is given, so a commit is
+/// never checked out with a detached HEAD by mistake.
+///
+/// # Arguments
+///
+/// * `target` - The branch to switch to, the commit to detach HEAD at, or the start point of the
+///   new branch.
+/// * `create` - The name of a branch to create and switch to.
+/// * `detach` - If true, detach HEAD at the target commit.
+/// * `force` - If true, discard the local changes instead of refusing to overwrite them.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if the target is not a branch or
+/// local changes would be overwritten.
+pub(crate) fn invoke(
+    target: Option<&str>,
+    create: Option<&str>,
+    detach: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    let target = match (create, target) {
+        (Some(name), start) => Target::NewBranch(name.to_string(), start.unwrap_or("HEAD").into()),
+        (None, target) if detach => Target::Detached(target.unwrap_or("HEAD").to_string()),
+        (None, Some(target)) if checkout::branch_exists(target)? => {
+            Target::Branch(target.to_string())
+        }
+        (None, Some(target)) => {
+            anyhow::ensure!(
+                revision::resolve(target).is_ok(),
+                "invalid reference: {}",
+                target
+            );
+            let kind = if refs::read_ref(&format!("refs/tags/{}", target))?.is_some() {
+                "tag"
+            } else {
+                "commit"
+            };
+            anyhow::bail!("a branch is expected, got {} '{}'", kind, target)
+        }
+        (None, None) => anyhow::bail!("missing branch or commit argument"),
+    };
+    checkout::switch_to(&target, force)
+}
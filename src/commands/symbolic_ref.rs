@@ -0,0 +1,48 @@
+use crate::refs;
+use crate::refs::{Expected, Ref};
+
+/// Invokes the `symbolic-ref` subcommand.
+///
+/// Without a target, prints the ref that the symbolic ref points to. With a target, points the
+/// symbolic ref to it.
+///
+/// # Arguments
+///
+/// * `name` - The name of the symbolic ref, e.g. `HEAD`.
+/// * `target` - The full name of the ref to point to.
+/// * `short` - If true, print the shortened name of the ref, e.g. `main`.
+/// * `quiet` - If true, do not print an error if the ref is not a symbolic ref.
+/// * `delete` - If true, delete the symbolic ref.
+pub(crate) fn invoke(
+    name: &str,
+    target: Option<String>,
+    short: bool,
+    quiet: bool,
+    delete: bool,
+) -> anyhow::Result<()> {
+    if delete {
+        anyhow::ensure!(
+            matches!(refs::read_ref(name)?, Some(Ref::Symbolic(_))),
+            "Cannot delete {}, not a symbolic ref.",
+            name
+        );
+        return refs::delete_ref(name, Expected::Any, true);
+    }
+
+    if let Some(target) = target {
+        return refs::write_symbolic_ref(name, &target);
+    }
+
+    match refs::read_ref(name)? {
+        Some(Ref::Symbolic(target)) => {
+            if short {
+                println!("{}", refs::shorten(&target));
+            } else {
+                println!("{}", target);
+            }
+            Ok(())
+        }
+        _ if quiet => std::process::exit(1),
+        _ => anyhow::bail!("ref {} is not a symbolic ref", name),
+    }
+}
@@ -0,0 +1,139 @@
+use crate::config;
+use crate::ignore;
+use crate::objects;
+use crate::objects::signature::Signature;
+use crate::objects::tag::Tag;
+use crate::objects::Object;
+use crate::refs;
+use crate::refs::Expected;
+use crate::revision;
+use anyhow::Context;
+use std::path::Path;
+
+/// Prefix of the refs holding tags.
+const TAGS_PREFIX: &str = "refs/tags/";
+
+/// Invokes the `tag` subcommand.
+///
+/// Without arguments, or with `list`, prints the names of the tags matching any of the patterns.
+/// With `delete`, deletes the named tags. Otherwise creates a tag named by the first argument,
+/// pointing to the object named by the second argument or to HEAD. The tag is annotated if
+/// `annotate` is set or a message is given, and lightweight otherwise.
+///
+/// # Arguments
+///
+/// * `args` - The patterns to list, the tags to delete, or the name and object of the new tag.
+/// * `list` - If true, list the tags.
+/// * `delete` - If true, delete the tags.
+/// * `annotate` - If true, create an annotated tag.
+/// * `messages` - The paragraphs of the tag message.
+/// * `file` - The file to read the tag message from, or `-` for stdin.
+/// * `force` - If true, replace an existing tag.
+pub(crate) fn invoke(
+    args: &[String],
+    list: bool,
+    delete: bool,
+    annotate: bool,
+    messages: &[String],
+    file: Option<&str>,
+    force: bool,
+) -> anyhow::Result<()> {
+    let annotate = annotate || !messages.is_empty() || file.is_some();
+    if delete {
+        return delete_tags(args);
+    }
+    if list || (args.is_empty() && !annotate) {
+        return list_tags(args);
+    }
+
+    let (name, target) = match args {
+        [name] => (name, refs::HEAD),
+        [name, target] => (name, target.as_str()),
+        _ => anyhow::bail!("Too many arguments."),
+    };
+    let ref_name = format!("{}{}", TAGS_PREFIX, name);
+    refs::check_name(&ref_name).with_context(|| format!("'{}' is not a valid tag name.", name))?;
+    let old = refs::resolve(&ref_name)?;
+    if old.is_some() && !force {
+        anyhow::bail!("tag '{}' already exists", name);
+    }
+
+    let target = revision::resolve(target)?;
+    let (kind, _, _) = objects::read_object(&target.hash())?;
+    let oid = if annotate {
+        let message = read_message(messages, file)?;
+        anyhow::ensure!(
+            !message.trim().is_empty(),
+            "no tag message given (use -m or -F)"
+        );
+        let config = config::Config::load()?;
+        let tagger = Signature::from_env("COMMITTER", &config)?;
+        Tag::new(target, kind, name.clone(), tagger, message).write()?
+    } else {
+        target
+    };
+
+    let expected = if force {
+        Expected::Any
+    } else {
+        Expected::Missing
+    };
+    refs::update_ref(&ref_name, &oid, expected, "", false)?;
+    if let Some(old) = old.filter(|old| *old != oid) {
+        println!("Updated tag '{}' (was {})", name, &old.hash()[..7]);
+    }
+    Ok(())
+}
+
+/// Prints the names of the tags matching any of the patterns, or of all tags if there are none.
+fn list_tags(patterns: &[String]) -> anyhow::Result<()> {
+    for (name, _) in refs::list_refs(TAGS_PREFIX)? {
+        let name = &name[TAGS_PREFIX.len()..];
+        if patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|pattern| ignore::fnmatch(pattern, Path::new(name)))
+        {
+            println!("{}", name);
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the named tags. Tags that do not exist are reported, and the others are still deleted.
+fn delete_tags(names: &[String]) -> anyhow::Result<()> {
+    let mut missing = 0;
+    for name in names {
+        let ref_name = format!("{}{}", TAGS_PREFIX, name);
+        match refs::resolve(&ref_name)? {
+            Some(oid) => {
+                refs::delete_ref(&ref_name, Expected::Value(oid.clone()), true)?;
+                println!("Deleted tag '{}' (was {})", name, &oid.hash()[..7]);
+            }
+            None => {
+                eprintln!("error: tag '{}' not found.", name);
+                missing += 1;
+            }
+        }
+    }
+    if missing > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Reads the tag message from the `-m` options, each one a paragraph, or from a file, or from
+/// stdin if the file is `-`. The message always ends with a newline.
+fn read_message(messages: &[String], file: Option<&str>) -> anyhow::Result<String> {
+    let mut message = match file {
+        Some("-") => std::io::read_to_string(std::io::stdin())
+            .context("Failed to read tag message from stdin.")?,
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tag message from {}", path))?,
+        None => messages.join("\n\n"),
+    };
+    if !message.is_empty() && !message.ends_with('\n') {
+        message.push('\n');
+    }
+    Ok(message)
+}
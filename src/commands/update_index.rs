@@ -0,0 +1,62 @@
+use crate::index::Index;
+use std::path::Path;
+
+/// Invokes the `update-index` subcommand.
+///
+/// Changes the index directly. The given files are marked first, then the stat data of the files
+/// is refreshed, which prints the files that need to be updated or merged and exits with status
+/// 1 if there are any, once the index is written.
+///
+/// # Arguments
+///
+/// * `paths` - The files to mark, relative to the root of the working tree.
+/// * `index_version` - The format version to write the index in, e.g. 4 to compress the paths
+///   of the entries, or 2 to convert the index back.
+/// * `refresh` - If true, re-read the stat data of the files in the index, see `Index::refresh`.
+/// * `assume_unchanged` - If set, mark the files as unchanged, or clear the mark.
+/// * `skip_worktree` - If set, mark the files as not checked out, or clear the mark.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the index was updated, or an `anyhow::Error` if the
+/// version is not supported, a file to mark is not in the index, or the index cannot be read or
+/// written.
+pub(crate) fn invoke(
+    paths: &[String],
+    index_version: Option<u32>,
+    refresh: bool,
+    assume_unchanged: Option<bool>,
+    skip_worktree: Option<bool>,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        paths.is_empty() || assume_unchanged.is_some() || skip_worktree.is_some(),
+        "Updating the content of files is not supported, use add instead."
+    );
+    let mut index = Index::lock(".git/index")?;
+    if let Some(version) = index_version {
+        index.set_version(version)?;
+    }
+    for path in paths {
+        let path = Path::new(path);
+        if let Some(value) = assume_unchanged {
+            index.set_assume_unchanged(path, value)?;
+        }
+        if let Some(value) = skip_worktree {
+            index.set_skip_worktree(path, value)?;
+        }
+    }
+
+    let messages = if refresh {
+        index.refresh()?
+    } else {
+        Vec::new()
+    };
+    index.write()?;
+    for message in &messages {
+        println!("{}", message);
+    }
+    if !messages.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use crate::objects::id::ObjectID;
+use crate::refs;
+use crate::refs::Expected;
+use anyhow::Context;
+
+/// Invokes the `update-ref` subcommand.
+///
+/// Updates a ref to a new value, or deletes it, optionally checking that it currently has the
+/// given old value. An old value of all zeros means the ref must not exist yet.
+///
+/// # Arguments
+///
+/// * `ref_name` - The full name of the ref, e.g. `refs/heads/main` or `HEAD`.
+/// * `values` - The new value followed by the optional old value, or with `delete` just the
+///   optional old value.
+/// * `delete` - If true, delete the ref instead of updating it.
+/// * `no_deref` - If true, update the ref itself even if it is a symbolic ref.
+/// * `message` - The reason of the update, recorded in the reflog.
+pub(crate) fn invoke(
+    ref_name: &str,
+    values: &[String],
+    delete: bool,
+    no_deref: bool,
+    message: Option<String>,
+) -> anyhow::Result<()> {
+    if delete {
+        anyhow::ensure!(
+            values.len() <= 1,
+            "Usage: update-ref -d <ref> [<old-value>]"
+        );
+        let expected = parse_expected(values.first())?;
+        return refs::delete_ref(ref_name, expected, no_deref)
+            .with_context(|| format!("Failed to delete ref {}", ref_name));
+    }
+
+    anyhow::ensure!(
+        !values.is_empty() && values.len() <= 2,
+        "Usage: update-ref <ref> <new-value> [<old-value>]"
+    );
+    let new = ObjectID::from_hash(&values[0])?;
+    let expected = parse_expected(values.get(1))?;
+    let message = message.unwrap_or_default();
+    refs::update_ref(ref_name, &new, expected, &message, no_deref)
+        .with_context(|| format!("Failed to update ref {}", ref_name))
+}
+
+/// Parses the optional old value given on the command line.
+fn parse_expected(value: Option<&String>) -> anyhow::Result<Expected> {
+    match value {
+        None => Ok(Expected::Any),
+        Some(value) if value.is_empty() || value.bytes().all(|b| b == b'0') => {
+            Ok(Expected::Missing)
+        }
+        Some(value) => Ok(Expected::Value(ObjectID::from_hash(value)?)),
+    }
+}
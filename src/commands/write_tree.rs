@@ -1,8 +1,40 @@
+use crate::index::Index;
 use crate::objects::tree::Tree;
+use crate::workspace::Workspace;
 use anyhow::Context;
 use std::path::Path;
 
+/// Writes the tree objects of the staged files to the database, and prints the ID of the root tree.
+///
+/// # Arguments
+///
+/// * `index_path` - The path to the index file.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the trees were written, or an `anyhow::Error` if an error
+/// occurs.
+pub(crate) fn invoke(index_path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let mut index = Index::lock(index_path)?;
+    if index.has_conflicts() {
+        let unmerged = index
+            .conflicts()
+            .values()
+            .flatten()
+            .flatten()
+            .map(|entry| format!("{}: unmerged ({})\n", entry.path().display(), entry.oid))
+            .collect::<String>();
+        anyhow::bail!("{}error building trees", unmerged);
+    }
+    let oid = index.write_tree().context("Failed to write tree.")?;
+    // Keep the trees that were written in the cache tree of the index.
+    index.write()?;
+    println!("{}", oid.hash());
+    Ok(())
+}
+
 /// Writes a tree object from the given path to the database, and prints the resulting object ID.
+/// Files ignored by the ignore rules of the working tree are left out.
 ///
 /// # Arguments
 ///
@@ -11,8 +43,9 @@ use std::path::Path;
 /// # Returns
 ///
 /// Returns a `Result` containing the resulting object ID, or an `anyhow::Error` if an error occurs.
-pub(crate) fn invoke(path: &Path) -> anyhow::Result<()> {
-    let oid = Tree::write_for_dir(path)?.context("Failed to write tree.")?;
+pub(crate) fn invoke_for_dir(path: &Path) -> anyhow::Result<()> {
+    let workspace = Workspace::new(Path::new("."))?;
+    let oid = Tree::write_for_dir(path, &workspace.ignore)?.context("Failed to write tree.")?;
     println!("{}", oid.hash());
     Ok(())
 }
@@ -0,0 +1,747 @@
+use crate::lockfile::LockFile;
+use anyhow::Context;
+use std::io::prelude::*;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Represents a variable of a config file.
+pub(crate) struct Entry {
+    /// The section name, lowercased.
+    pub(crate) section: String,
+    /// The subsection name, which is case sensitive.
+    pub(crate) subsection: Option<String>,
+    /// The variable name, lowercased.
+    pub(crate) name: String,
+    /// The value, or `None` for a variable written without `=`, which stands for `true`.
+    pub(crate) value: Option<String>,
+    /// The lines of the file the variable spans.
+    lines: Range<usize>,
+}
+
+impl Entry {
+    /// Returns the full name of the variable, `section.subsection.name` or `section.name`.
+    pub(crate) fn key(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.section, subsection, self.name),
+            None => format!("{}.{}", self.section, self.name),
+        }
+    }
+
+    /// Returns true if the entry is the given variable.
+    fn matches(&self, section: &str, subsection: Option<&str>, name: &str) -> bool {
+        self.section == section && self.subsection.as_deref() == subsection && self.name == name
+    }
+}
+
+/// Represents a section header of a config file.
+struct Section {
+    section: String,
+    subsection: Option<String>,
+    /// The line of the header.
+    line: usize,
+}
+
+/// Represents a config file in git's INI-like format.
+///
+/// The file is kept as text next to its parsed variables, so that variables can be set and unset
+/// without losing the comments and the formatting of the rest of the file. Like git, the file is
+/// read as bytes: it does not need to be UTF-8, and values that are not are decoded lossily.
+pub(crate) struct ConfigFile {
+    path: PathBuf,
+    lines: Vec<Vec<u8>>,
+    sections: Vec<Section>,
+    entries: Vec<Entry>,
+}
+
+impl ConfigFile {
+    /// Reads and parses a config file. A missing file is read as an empty one.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the config file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `ConfigFile`, or an `anyhow::Error` if the file cannot be
+    /// read or is malformed.
+    pub(crate) fn read(path: impl AsRef<Path>) -> anyhow::Result<ConfigFile> {
+        let path = path.as_ref();
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read config file: {}", path.display()))
+            }
+        };
+        ConfigFile::parse(path, &data)
+    }
+
+    /// Parses the content of a config file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the config file, used in error messages.
+    /// * `data` - The content of the config file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `ConfigFile`, or an `anyhow::Error` if the file is
+    /// malformed.
+    pub(crate) fn parse(path: &Path, data: &[u8]) -> anyhow::Result<ConfigFile> {
+        let mut parser = Parser {
+            bytes: data,
+            pos: 0,
+            line: 0,
+        };
+        let mut sections = Vec::new();
+        let mut entries = Vec::new();
+        let bad_line =
+            |line: usize| format!("bad config line {} in file {}", line + 1, path.display());
+
+        loop {
+            parser.skip_blanks();
+            let Some(c) = parser.peek() else {
+                break;
+            };
+            match c {
+                b'\n' => parser.next_line(),
+                b'#' | b';' => parser.skip_comment(),
+                b'[' => {
+                    let line = parser.line;
+                    let (section, subsection) =
+                        parser.parse_header().with_context(|| bad_line(line))?;
+                    sections.push(Section {
+                        section,
+                        subsection,
+                        line,
+                    });
+                }
+                c if c.is_ascii_alphabetic() => {
+                    let start = parser.line;
+                    let current = sections.last().with_context(|| bad_line(start))?;
+                    let (name, value) = parser.parse_entry().with_context(|| bad_line(start))?;
+                    entries.push(Entry {
+                        section: current.section.clone(),
+                        subsection: current.subsection.clone(),
+                        name,
+                        value,
+                        lines: start..parser.line + 1,
+                    });
+                }
+                _ => anyhow::bail!(bad_line(parser.line)),
+            }
+        }
+
+        Ok(ConfigFile {
+            path: path.to_path_buf(),
+            lines: data
+                .split_inclusive(|&b| b == b'\n')
+                .map(<[u8]>::to_vec)
+                .collect(),
+            sections,
+            entries,
+        })
+    }
+
+    /// Returns the variables of the config file, in order.
+    pub(crate) fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Sets a variable, replacing its value if it has one, and adding it otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The full name of the variable, e.g. `user.name` or `remote.origin.url`.
+    /// * `value` - The new value.
+    /// * `all` - If true, all the values of the variable are replaced by the new one, which takes
+    ///   the place of the last one. Otherwise, the variable must have at most one value.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()`, or an `anyhow::Error` if the key is invalid or the
+    /// variable has several values and `all` is false.
+    pub(crate) fn set(&mut self, key: &str, value: &str, all: bool) -> anyhow::Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        let mut ranges = self
+            .entries
+            .iter()
+            .filter(|entry| entry.matches(&section, subsection.as_deref(), &name))
+            .map(|entry| entry.lines.clone())
+            .collect::<Vec<_>>();
+        anyhow::ensure!(
+            all || ranges.len() <= 1,
+            "cannot overwrite multiple values with a single value\n       \
+             Use --add or --replace-all to change {}.",
+            key
+        );
+        let Some(last) = ranges.pop() else {
+            return self.add(key, value);
+        };
+
+        // A variable on the same line as its section header keeps the header.
+        let mut line = match self.header_at(last.start) {
+            Some(mut header) => {
+                header.push(b'\n');
+                header
+            }
+            None => Vec::new(),
+        };
+        line.extend(format_entry(&name, value).as_bytes());
+        self.lines.splice(last, [line]);
+        self.remove_lines(&ranges);
+        self.reparse()
+    }
+
+    /// Adds a value to a variable, keeping its existing values.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The full name of the variable.
+    /// * `value` - The value to add.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()`, or an `anyhow::Error` if the key is invalid.
+    pub(crate) fn add(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        let line = format_entry(&name, value);
+
+        // Add the variable after the last variable of the last matching section, or right after
+        // its header. Without a matching section, add a new one at the end of the file.
+        let last_section = self
+            .sections
+            .iter()
+            .rposition(|s| s.section == section && s.subsection == subsection);
+        match last_section {
+            Some(index) => {
+                let header_line = self.sections[index].line;
+                let end = self
+                    .sections
+                    .get(index + 1)
+                    .map_or(self.lines.len(), |next| next.line);
+                let position = self
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.lines.start >= header_line && entry.lines.end <= end)
+                    .map(|entry| entry.lines.end)
+                    .max()
+                    .unwrap_or(header_line + 1);
+                self.ensure_newline(position);
+                self.lines.insert(position, line.into_bytes());
+            }
+            None => {
+                self.ensure_newline(self.lines.len());
+                self.lines
+                    .push(format_header(&section, subsection.as_deref()).into_bytes());
+                self.lines.push(line.into_bytes());
+            }
+        }
+        self.reparse()
+    }
+
+    /// Removes the values of a variable.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The full name of the variable.
+    /// * `all` - If false, the variable must have a single value.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the number of removed values, or an `anyhow::Error` if the
+    /// key is invalid or the variable has several values and `all` is false.
+    pub(crate) fn unset(&mut self, key: &str, all: bool) -> anyhow::Result<usize> {
+        let (section, subsection, name) = split_key(key)?;
+        let ranges = self
+            .entries
+            .iter()
+            .filter(|entry| entry.matches(&section, subsection.as_deref(), &name))
+            .map(|entry| entry.lines.clone())
+            .collect::<Vec<_>>();
+        anyhow::ensure!(all || ranges.len() <= 1, "{} has multiple values", key);
+
+        self.remove_lines(&ranges);
+        self.reparse()?;
+        Ok(ranges.len())
+    }
+
+    /// Removes the lines of variables, keeping the section headers written on them.
+    ///
+    /// # Arguments
+    ///
+    /// * `ranges` - The lines of the variables, in order.
+    fn remove_lines(&mut self, ranges: &[Range<usize>]) {
+        for range in ranges.iter().rev() {
+            let replacement = self.header_at(range.start).map(|mut header| {
+                header.push(b'\n');
+                header
+            });
+            self.lines.splice(range.clone(), replacement);
+        }
+    }
+
+    /// Removes all the sections with the given name, along with their variables and comments.
+    ///
+    /// # Arguments
+    ///
+    /// * `section` - The section name, lowercased.
+    /// * `subsection` - The subsection name, if any.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing true if a section was removed, or an `anyhow::Error` if the
+    /// edited file cannot be parsed again.
+    pub(crate) fn remove_section(
+        &mut self,
+        section: &str,
+        subsection: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let ranges = self.section_ranges(section, subsection);
+        for range in ranges.iter().rev() {
+            self.lines.drain(range.clone());
+        }
+        self.reparse()?;
+        Ok(!ranges.is_empty())
+    }
+
+    /// Renames all the sections with the given name, keeping their variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `section` - The section name, lowercased.
+    /// * `old` - The current subsection name.
+    /// * `new` - The new subsection name.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing true if a section was renamed, or an `anyhow::Error` if the
+    /// edited file cannot be parsed again.
+    pub(crate) fn rename_section(
+        &mut self,
+        section: &str,
+        old: &str,
+        new: &str,
+    ) -> anyhow::Result<bool> {
+        let ranges = self.section_ranges(section, Some(old));
+        for range in &ranges {
+            // A variable on the same line as the header stays after the new header.
+            let header = self.header_at(range.start).unwrap_or_default();
+            let rest = self.lines[range.start][header.len()..].to_vec();
+            let new_header = format_header(section, Some(new));
+            self.lines[range.start] = match rest.as_slice() {
+                b"\n" | b"" => new_header.into_bytes(),
+                rest => [new_header.trim_end().as_bytes(), rest].concat(),
+            };
+        }
+        self.reparse()?;
+        Ok(!ranges.is_empty())
+    }
+
+    /// Returns the lines of the sections with the given name, from their header up to the next
+    /// header.
+    fn section_ranges(&self, section: &str, subsection: Option<&str>) -> Vec<Range<usize>> {
+        self.sections
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.section == section && s.subsection.as_deref() == subsection)
+            .map(|(i, s)| {
+                let end = self
+                    .sections
+                    .get(i + 1)
+                    .map_or(self.lines.len(), |next| next.line);
+                s.line..end
+            })
+            .collect()
+    }
+
+    /// Writes the config file, replacing it atomically.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()`, or an `anyhow::Error` if the file cannot be locked or
+    /// written.
+    pub(crate) fn write(&self) -> anyhow::Result<()> {
+        let mut lock = LockFile::acquire(&self.path)?;
+        for line in &self.lines {
+            lock.write_all(line)
+                .with_context(|| format!("Failed to write config file: {}", self.path.display()))?;
+        }
+        lock.commit()
+    }
+
+    /// Returns the section header written on a line, if the line starts with one.
+    fn header_at(&self, line: usize) -> Option<Vec<u8>> {
+        self.sections.iter().find(|s| s.line == line).map(|_| {
+            let text = &self.lines[line];
+            let end = text
+                .iter()
+                .position(|&b| b == b']')
+                .map_or(text.len(), |end| end + 1);
+            text[..end].to_vec()
+        })
+    }
+
+    /// Makes sure the line before the given position ends with a newline.
+    fn ensure_newline(&mut self, position: usize) {
+        if let Some(line) = position.checked_sub(1).and_then(|i| self.lines.get_mut(i)) {
+            if !line.ends_with(b"\n") {
+                line.push(b'\n');
+            }
+        }
+    }
+
+    /// Parses the lines again after they were edited.
+    fn reparse(&mut self) -> anyhow::Result<()> {
+        *self = ConfigFile::parse(&self.path, &self.lines.concat())?;
+        Ok(())
+    }
+}
+
+/// Splits the full name of a variable into its section, subsection and name. The section and the
+/// name are lowercased, since they are case insensitive.
+///
+/// # Arguments
+///
+/// * `key` - The full name of the variable, e.g. `remote.origin.url`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the section, the subsection and the name, or an `anyhow::Error`
+/// if the key has no section or its name is invalid.
+pub(crate) fn split_key(key: &str) -> anyhow::Result<(String, Option<String>, String)> {
+    let (section, rest) = key
+        .split_once('.')
+        .with_context(|| format!("key does not contain a section: {}", key))?;
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection.to_string()), name),
+        None => (None, rest),
+    };
+    let valid_section = !section.is_empty()
+        && section
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    anyhow::ensure!(valid_section && valid_name, "invalid key: {}", key);
+    Ok((section.to_lowercase(), subsection, name.to_lowercase()))
+}
+
+/// Formats a section header, escaping the subsection.
+fn format_header(section: &str, subsection: Option<&str>) -> String {
+    match subsection {
+        Some(subsection) => {
+            let subsection = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("[{} \"{}\"]\n", section, subsection)
+        }
+        None => format!("[{}]\n", section),
+    }
+}
+
+/// Formats a variable line, quoting and escaping the value when needed.
+fn format_entry(name: &str, value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    let needs_quotes = value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']);
+    if needs_quotes {
+        format!("\t{} = \"{}\"\n", name, escaped)
+    } else {
+        format!("\t{} = {}\n", name, escaped)
+    }
+}
+
+/// Parses the content of a config file, byte by byte.
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    /// Skips spaces and tabs, and carriage returns before a newline.
+    fn skip_blanks(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == b' '
+                || c == b'\t'
+                || (c == b'\r' && self.bytes.get(self.pos + 1) == Some(&b'\n'))
+            {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Consumes a newline.
+    fn next_line(&mut self) {
+        self.pos += 1;
+        self.line += 1;
+    }
+
+    /// Skips a comment up to, but not including, the end of the line.
+    fn skip_comment(&mut self) {
+        while self.peek().is_some_and(|c| c != b'\n') {
+            self.pos += 1;
+        }
+    }
+
+    /// Parses a section header: `[section]`, `[section "subsection"]`, or the deprecated
+    /// `[section.subsection]`, whose subsection is lowercased.
+    fn parse_header(&mut self) -> anyhow::Result<(String, Option<String>)> {
+        self.pos += 1;
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == b'-' || c == b'.' {
+                name.push(char::from(c.to_ascii_lowercase()));
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        anyhow::ensure!(!name.is_empty(), "Empty section name.");
+
+        let mut subsection = None;
+        self.skip_blanks();
+        if self.peek() == Some(b'"') {
+            anyhow::ensure!(!name.contains('.'), "Invalid section name.");
+            self.pos += 1;
+            let mut value = Vec::new();
+            loop {
+                match self.peek() {
+                    Some(b'"') => break,
+                    Some(b'\\') => {
+                        self.pos += 1;
+                        match self.peek() {
+                            Some(c) if c != b'\n' => value.push(c),
+                            _ => anyhow::bail!("Unterminated subsection."),
+                        }
+                    }
+                    Some(c) if c != b'\n' => value.push(c),
+                    _ => anyhow::bail!("Unterminated subsection."),
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            subsection = Some(String::from_utf8_lossy(&value).into_owned());
+        } else if let Some((section, legacy)) = name.split_once('.') {
+            subsection = Some(legacy.to_string());
+            name = section.to_string();
+        }
+        anyhow::ensure!(self.peek() == Some(b']'), "Unterminated section header.");
+        self.pos += 1;
+        Ok((name, subsection))
+    }
+
+    /// Parses a variable, `name = value` or just `name`, up to the end of its last line.
+    fn parse_entry(&mut self) -> anyhow::Result<(String, Option<String>)> {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == b'-' {
+                name.push(char::from(c.to_ascii_lowercase()));
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        self.skip_blanks();
+        match self.peek() {
+            None | Some(b'\n') => Ok((name, None)),
+            Some(b'#') | Some(b';') => {
+                self.skip_comment();
+                Ok((name, None))
+            }
+            Some(b'=') => {
+                self.pos += 1;
+                Ok((name, Some(self.parse_value()?)))
+            }
+            Some(_) => anyhow::bail!("Invalid variable name."),
+        }
+    }
+
+    /// Parses a value: surrounding whitespace is dropped, double quotes preserve whitespace and
+    /// comment characters, backslashes escape `\n`, `\t`, `\b`, `"` and `\`, and a backslash at the
+    /// end of a line continues the value on the next line.
+    fn parse_value(&mut self) -> anyhow::Result<String> {
+        self.skip_blanks();
+        let mut value = Vec::new();
+        let mut pending = Vec::new();
+        let mut quoted = false;
+        while let Some(c) = self.peek() {
+            match c {
+                b'\n' => {
+                    anyhow::ensure!(!quoted, "Unterminated quoted value.");
+                    break;
+                }
+                b'\r' if self.bytes.get(self.pos + 1) == Some(&b'\n') => {}
+                b'#' | b';' if !quoted => {
+                    self.skip_comment();
+                    break;
+                }
+                b' ' | b'\t' if !quoted => pending.push(c),
+                b'\\' => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'\n') => {
+                            self.line += 1;
+                            self.pos += 1;
+                            continue;
+                        }
+                        Some(b'\r') if self.bytes.get(self.pos + 1) == Some(&b'\n') => {
+                            self.line += 1;
+                            self.pos += 2;
+                            continue;
+                        }
+                        Some(b'n') => b'\n',
+                        Some(b't') => b'\t',
+                        Some(b'b') => b'\x08',
+                        Some(b'"') => b'"',
+                        Some(b'\\') => b'\\',
+                        _ => anyhow::bail!("Invalid escape sequence in value."),
+                    };
+                    value.append(&mut pending);
+                    value.push(escaped);
+                }
+                b'"' => {
+                    value.append(&mut pending);
+                    quoted = !quoted;
+                }
+                c => {
+                    value.append(&mut pending);
+                    value.push(c);
+                }
+            }
+            self.pos += 1;
+        }
+        anyhow::ensure!(!quoted, "Unterminated quoted value.");
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> ConfigFile {
+        ConfigFile::parse(Path::new("config"), data).unwrap()
+    }
+
+    fn values(file: &ConfigFile) -> Vec<(String, Option<String>)> {
+        file.entries()
+            .iter()
+            .map(|entry| (entry.key(), entry.value.clone()))
+            .collect()
+    }
+
+    fn text(file: &ConfigFile) -> String {
+        String::from_utf8(file.lines.concat()).unwrap()
+    }
+
+    #[test]
+    fn parses_values() {
+        let file = parse(
+            b"# comment\n\
+              [Core]\n\
+              \tBare = false ; comment\n\
+              \tflag\n\
+              [remote \"Origin\"] url = a\\\"b\n\
+              [branch.Main]\n\
+              \tquoted = \" a # b \" c\n\
+              \tescapes = a\\tb\\\\c\n\
+              \tcontinued = one \\\n  two\r\n",
+        );
+        assert_eq!(
+            values(&file),
+            [
+                ("core.bare", Some("false")),
+                ("core.flag", None),
+                ("remote.Origin.url", Some("a\"b")),
+                ("branch.main.quoted", Some(" a # b  c")),
+                ("branch.main.escapes", Some("a\tb\\c")),
+                ("branch.main.continued", Some("one   two")),
+            ]
+            .map(|(key, value)| (key.to_string(), value.map(String::from)))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let bad = |data: &[u8]| ConfigFile::parse(Path::new("config"), data).is_err();
+        assert!(bad(b"name = value\n"));
+        assert!(bad(b"[section\n"));
+        assert!(bad(b"[section \"sub]\n"));
+        assert!(bad(b"[section]\n\tname = \"value\n"));
+        assert!(bad(b"[section]\n\tname = \\q\n"));
+        assert!(bad(b"[section]\n\tna_me = value\n"));
+    }
+
+    #[test]
+    fn reads_files_that_are_not_utf8() {
+        let mut file = parse(b"# caf\xe9\n[user]\n\tname = Ren\xe9\n");
+        assert_eq!(file.entries()[0].value.as_deref(), Some("Ren\u{fffd}"));
+        file.set("user.email", "r@example.com", false).unwrap();
+        assert_eq!(
+            file.lines.concat(),
+            b"# caf\xe9\n[user]\n\tname = Ren\xe9\n\temail = r@example.com\n"
+        );
+    }
+
+    #[test]
+    fn sets_and_adds_variables() {
+        let mut file = parse(b"; top\n[core]\n\tbare = false # keep\n[user] name = A\n");
+        file.set("core.bare", "true", false).unwrap();
+        file.set("user.name", "B", false).unwrap();
+        file.add("core.editor", "vim").unwrap();
+        file.set("remote.origin.url", " x ", false).unwrap();
+        assert_eq!(
+            text(&file),
+            "; top\n[core]\n\tbare = true\n\teditor = vim\n[user]\n\tname = B\n\
+             [remote \"origin\"]\n\turl = \" x \"\n"
+        );
+        assert!(file.set("user", "x", false).is_err());
+    }
+
+    #[test]
+    fn refuses_to_overwrite_multiple_values() {
+        let mut file = parse(b"[remote \"o\"]\n\tfetch = a\n\tfetch = b\n[x]\n\ty = 1\n");
+        let error = file.set("remote.o.fetch", "c", false).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("cannot overwrite multiple values"));
+        assert_eq!(file.entries().len(), 3);
+
+        file.set("remote.o.fetch", "c", true).unwrap();
+        assert_eq!(text(&file), "[remote \"o\"]\n\tfetch = c\n[x]\n\ty = 1\n");
+    }
+
+    #[test]
+    fn unsets_variables_and_sections() {
+        let mut file = parse(b"[a]\n\tx = 1\n\tx = 2\n[b \"c\"] y = 1\n\tz = 2\n");
+        assert!(file.unset("a.x", false).is_err());
+        assert_eq!(file.unset("a.x", true).unwrap(), 2);
+        assert_eq!(file.unset("b.c.y", false).unwrap(), 1);
+        assert_eq!(file.unset("b.c.missing", false).unwrap(), 0);
+        assert_eq!(text(&file), "[a]\n[b \"c\"]\n\tz = 2\n");
+
+        assert!(file.rename_section("b", "c", "d").unwrap());
+        assert_eq!(
+            values(&file),
+            [("b.d.z".to_string(), Some("2".to_string()))]
+        );
+        assert!(file.remove_section("a", None).unwrap());
+        assert!(!file.remove_section("a", None).unwrap());
+        assert_eq!(text(&file), "[b \"d\"]\n\tz = 2\n");
+    }
+}
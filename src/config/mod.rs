@@ -0,0 +1,416 @@
+pub(crate) mod file;
+
+use crate::config::file::ConfigFile;
+use crate::ignore;
+use crate::refs;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Path of the config file of the repository.
+pub(crate) const LOCAL_CONFIG: &str = ".git/config";
+/// Default path of the system config file.
+const SYSTEM_CONFIG: &str = "/etc/gitconfig";
+/// Maximum depth of nested `include.path` directives.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Represents the configuration of the repository, merged from all the config files.
+///
+/// The files are read in increasing order of precedence: the system file, the global files
+/// (`$XDG_CONFIG_HOME/git/config` then `~/.gitconfig`), the repository file `.git/config`, and
+/// finally the variables given in the environment with `GIT_CONFIG_COUNT`, `GIT_CONFIG_KEY_<n>`
+/// and `GIT_CONFIG_VALUE_<n>`. Files included with `include.path`, or with `includeIf.<condition>.path`
+/// when the condition holds, are read in place of the directive. When a variable is set several
+/// times, the last value wins.
+pub(crate) struct Config {
+    /// The full names of the variables, with lowercased section and name, and their values.
+    entries: Vec<(String, Option<String>)>,
+}
+
+impl Config {
+    /// Loads the configuration from all the config files and the environment.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `Config`, or an `anyhow::Error` if a config file cannot be
+    /// read or is malformed.
+    pub(crate) fn load() -> anyhow::Result<Config> {
+        let mut config = Config {
+            entries: Vec::new(),
+        };
+        if !env_bool("GIT_CONFIG_NOSYSTEM") {
+            config.read_file(&system_path(), 0)?;
+        }
+        for path in global_paths() {
+            config.read_file(&path, 0)?;
+        }
+        if Path::new(refs::GIT_DIR).is_dir() {
+            config.read_file(Path::new(LOCAL_CONFIG), 0)?;
+        }
+        config.read_env()?;
+        Ok(config)
+    }
+
+    /// Returns the last value of a variable. A variable without a value, which stands for
+    /// `true`, has an empty value.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The full name of the variable, e.g. `user.name`.
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).pop()
+    }
+
+    /// Returns all the values of a variable, in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The full name of the variable.
+    pub(crate) fn get_all(&self, key: &str) -> Vec<&str> {
+        let Ok(key) = normalize_key(key) else {
+            return Vec::new();
+        };
+        self.entries
+            .iter()
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_deref().unwrap_or_default())
+            .collect()
+    }
+
+    /// Returns the value of a path variable, with a leading `~/` expanded to the home directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The full name of the variable.
+    pub(crate) fn get_path(&self, key: &str) -> Option<PathBuf> {
+        self.get(key).map(expand_home)
+    }
+
+    /// Returns the name and the email of the author or the committer.
+    ///
+    /// The environment variables `GIT_<ROLE>_NAME` and `GIT_<ROLE>_EMAIL` take precedence over
+    /// `user.name` and `user.email`. The email falls back to the `EMAIL` environment variable.
+    ///
+    /// # Arguments
+    ///
+    /// * `role` - `AUTHOR` or `COMMITTER`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the name and the email, or an `anyhow::Error` if either is
+    /// not configured.
+    pub(crate) fn identity(&self, role: &str) -> anyhow::Result<(String, String)> {
+        let name = std::env::var(format!("GIT_{}_NAME", role))
+            .ok()
+            .or_else(|| self.get("user.name").map(String::from));
+        let email = std::env::var(format!("GIT_{}_EMAIL", role))
+            .ok()
+            .or_else(|| self.get("user.email").map(String::from))
+            .or_else(|| std::env::var("EMAIL").ok());
+        match (name, email) {
+            (Some(name), Some(email)) if !name.is_empty() => Ok((name, email)),
+            _ => {
+                let mut role = role.to_lowercase();
+                role[..1].make_ascii_uppercase();
+                anyhow::bail!(
+                    "{} identity unknown\n\n*** Please tell me who you are.\n\nRun\n\n  \
+                     bran config --global --set user.email \"you@example.com\"\n  \
+                     bran config --global --set user.name \"Your Name\"\n\n\
+                     to set your account's default identity.",
+                    role
+                )
+            }
+        }
+    }
+
+    /// Returns the global ignore file: `core.excludesFile`, or `$XDG_CONFIG_HOME/git/ignore` if it
+    /// is not set.
+    pub(crate) fn excludes_file(&self) -> Option<PathBuf> {
+        self.get_path("core.excludesfile")
+            .or_else(|| xdg_config_dir().map(|dir| dir.join("ignore")))
+    }
+
+    /// Returns all the variables, in the order they were read, for `config --list`.
+    pub(crate) fn entries(&self) -> &[(String, Option<String>)] {
+        &self.entries
+    }
+
+    /// Reads the variables of a config file, and of the files it includes.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the config file. A missing file is skipped.
+    /// * `depth` - The number of include directives that led to this file.
+    fn read_file(&mut self, path: &Path, depth: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            depth <= MAX_INCLUDE_DEPTH,
+            "exceeded maximum include depth ({}) while including {}",
+            MAX_INCLUDE_DEPTH,
+            path.display()
+        );
+        let file = ConfigFile::read(path)?;
+        for entry in file.entries() {
+            let key = entry.key();
+            self.entries.push((key.clone(), entry.value.clone()));
+
+            let include = match (entry.section.as_str(), &entry.subsection) {
+                ("include", None) => true,
+                ("includeif", Some(condition)) => include_condition_holds(condition, path)?,
+                _ => false,
+            };
+            if include && entry.name == "path" {
+                if let Some(value) = &entry.value {
+                    let included = resolve_include(value, path);
+                    self.read_file(&included, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the variables given in the environment with `GIT_CONFIG_COUNT`.
+    fn read_env(&mut self) -> anyhow::Result<()> {
+        let Ok(count) = std::env::var("GIT_CONFIG_COUNT") else {
+            return Ok(());
+        };
+        let count = count
+            .parse::<usize>()
+            .with_context(|| format!("bogus count in GIT_CONFIG_COUNT: {}", count))?;
+        for i in 0..count {
+            let key = std::env::var(format!("GIT_CONFIG_KEY_{}", i))
+                .with_context(|| format!("missing config key GIT_CONFIG_KEY_{}", i))?;
+            let value = std::env::var(format!("GIT_CONFIG_VALUE_{}", i))
+                .with_context(|| format!("missing config value GIT_CONFIG_VALUE_{}", i))?;
+            self.entries.push((normalize_key(&key)?, Some(value)));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the path of the system config file, `GIT_CONFIG_SYSTEM` or `/etc/gitconfig`.
+pub(crate) fn system_path() -> PathBuf {
+    std::env::var_os("GIT_CONFIG_SYSTEM")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(SYSTEM_CONFIG))
+}
+
+/// Returns the path of the global config file that is written to: `GIT_CONFIG_GLOBAL`, or
+/// `~/.gitconfig`, unless only the XDG config file exists.
+pub(crate) fn global_path() -> anyhow::Result<PathBuf> {
+    if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return Ok(PathBuf::from(path));
+    }
+    let home = home_dir().context("$HOME is not set.")?;
+    let gitconfig = home.join(".gitconfig");
+    match xdg_config_dir().map(|dir| dir.join("config")) {
+        Some(xdg) if xdg.is_file() && !gitconfig.exists() => Ok(xdg),
+        _ => Ok(gitconfig),
+    }
+}
+
+/// Returns the global config files that are read, in order.
+fn global_paths() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(path)];
+    }
+    let xdg = xdg_config_dir().map(|dir| dir.join("config"));
+    let home = home_dir().map(|home| home.join(".gitconfig"));
+    xdg.into_iter().chain(home).collect()
+}
+
+/// Returns the git directory under `$XDG_CONFIG_HOME`, or under `~/.config` if it is not set.
+fn xdg_config_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("git")),
+        None => home_dir().map(|home| home.join(".config/git")),
+    }
+}
+
+/// Returns the home directory.
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Expands a leading `~/` to the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Resolves the path of an included file. Relative paths are relative to the directory of the
+/// including file.
+fn resolve_include(value: &str, including: &Path) -> PathBuf {
+    let path = expand_home(value);
+    if path.is_absolute() {
+        return path;
+    }
+    match including.parent() {
+        Some(dir) => dir.join(path),
+        None => path,
+    }
+}
+
+/// Checks the condition of an `includeIf` section.
+///
+/// Supported conditions are `gitdir:<pattern>`, `gitdir/i:<pattern>` and `onbranch:<pattern>`.
+/// A `gitdir` pattern that starts with `./` is relative to the directory of the including file,
+/// and a pattern that is not anchored matches at any depth. A pattern ending with `/` matches
+/// everything inside. Unknown conditions never hold.
+///
+/// # Arguments
+///
+/// * `condition` - The subsection of the `includeIf` section.
+/// * `including` - The path of the including file.
+fn include_condition_holds(condition: &str, including: &Path) -> anyhow::Result<bool> {
+    let (kind, pattern) = match condition.split_once(':') {
+        Some(parts) => parts,
+        None => return Ok(false),
+    };
+    match kind {
+        "gitdir" | "gitdir/i" => {
+            let Ok(git_dir) = std::fs::canonicalize(refs::GIT_DIR) else {
+                return Ok(false);
+            };
+            let mut pattern = if let Some(rest) = pattern.strip_prefix("./") {
+                let dir = including.parent().unwrap_or(Path::new(""));
+                std::fs::canonicalize(dir)
+                    .unwrap_or_else(|_| dir.to_path_buf())
+                    .join(rest)
+                    .to_string_lossy()
+                    .into_owned()
+            } else {
+                expand_home(pattern).to_string_lossy().into_owned()
+            };
+            if !pattern.starts_with('/') {
+                pattern = format!("**/{}", pattern);
+            }
+            if pattern.ends_with('/') {
+                pattern.push_str("**");
+            }
+            let mut git_dir = git_dir.to_string_lossy().into_owned();
+            if kind == "gitdir/i" {
+                pattern = pattern.to_lowercase();
+                git_dir = git_dir.to_lowercase();
+            }
+            Ok(ignore::wildmatch(pattern.as_bytes(), git_dir.as_bytes()))
+        }
+        "onbranch" => {
+            let Some(branch) = refs::current_branch()? else {
+                return Ok(false);
+            };
+            let mut pattern = pattern.to_string();
+            if pattern.ends_with('/') {
+                pattern.push_str("**");
+            }
+            Ok(ignore::wildmatch(
+                pattern.as_bytes(),
+                refs::shorten(&branch).as_bytes(),
+            ))
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Normalizes the full name of a variable, lowercasing its section and name.
+pub(crate) fn normalize_key(key: &str) -> anyhow::Result<String> {
+    let (section, subsection, name) = file::split_key(key)?;
+    Ok(match subsection {
+        Some(subsection) => format!("{}.{}.{}", section, subsection, name),
+        None => format!("{}.{}", section, name),
+    })
+}
+
+/// Parses a boolean value the way git does.
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => value.parse::<i64>().ok().map(|n| n != 0),
+    }
+}
+
+/// Returns true if a boolean environment variable is set to a true value.
+fn env_bool(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| parse_bool(&value).unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, text: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn read(paths: &[PathBuf]) -> anyhow::Result<Config> {
+        let mut config = Config {
+            entries: Vec::new(),
+        };
+        for path in paths {
+            config.read_file(path, 0)?;
+        }
+        Ok(config)
+    }
+
+    #[test]
+    fn later_files_take_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = write(dir.path(), "system", "[user]\n\tname = S\n\temail = s@x\n");
+        let global = write(dir.path(), "global", "[user]\n\tname = G\n[a]\n\tb = 1\n");
+        let local = write(dir.path(), "local", "[A]\n\tB = 2\n\tb = 3\n");
+        let missing = dir.path().join("missing");
+        let config = read(&[system, global, missing, local]).unwrap();
+        assert_eq!(config.get("user.name"), Some("G"));
+        assert_eq!(config.get("USER.EMAIL"), Some("s@x"));
+        assert_eq!(config.get("a.b"), Some("3"));
+        assert_eq!(config.get_all("a.b"), ["1", "2", "3"]);
+        assert_eq!(config.get("a.c"), None);
+    }
+
+    #[test]
+    fn reads_included_files_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "sub/included",
+            "[a]\n\tb = included\n\tc = included\n",
+        );
+        write(dir.path(), "sub/nested", "[include]\n\tpath = included\n");
+        let main = write(
+            dir.path(),
+            "main",
+            "[a]\n\tb = before\n\tc = before\n\
+             [include]\n\tpath = sub/nested\n\
+             [a]\n\tc = after\n\
+             [includeIf \"unknown:x\"]\n\tpath = sub/included\n",
+        );
+        let config = read(&[main]).unwrap();
+        assert_eq!(config.get_all("a.b"), ["before", "included"]);
+        assert_eq!(config.get_all("a.c"), ["before", "included", "after"]);
+    }
+
+    #[test]
+    fn stops_include_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(dir.path(), "main", "[include]\n\tpath = main\n");
+        let error = read(&[main]).err().unwrap();
+        assert!(format!("{:#}", error).contains("exceeded maximum include depth"));
+    }
+
+    #[test]
+    fn parses_booleans() {
+        for value in ["true", "Yes", "on", "1", "-2"] {
+            assert_eq!(parse_bool(value), Some(true), "{}", value);
+        }
+        for value in ["false", "NO", "off", "0", ""] {
+            assert_eq!(parse_bool(value), Some(false), "{}", value);
+        }
+        assert_eq!(parse_bool("maybe"), None);
+    }
+}
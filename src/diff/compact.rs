@@ -0,0 +1,174 @@
+use crate::diff::{Changes, Edit};
+
+/// Represents a group of changed lines of one version, `start..end`. A group is empty when there
+/// are no changed lines between two kept lines.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+/// The line classes of one version, with a flag telling whether each line is changed.
+struct Version<'a> {
+    lines: &'a [usize],
+    changed: Vec<bool>,
+}
+
+impl Version<'_> {
+    /// Returns the first group.
+    fn first_group(&self) -> Group {
+        let end = self.changed.iter().take_while(|&&changed| changed).count();
+        Group { start: 0, end }
+    }
+
+    /// Moves to the next group. Returns false if the group is the last one.
+    fn next_group(&self, group: &mut Group) -> bool {
+        if group.end == self.lines.len() {
+            return false;
+        }
+        group.start = group.end + 1;
+        group.end = group.start;
+        while group.end < self.lines.len() && self.changed[group.end] {
+            group.end += 1;
+        }
+        true
+    }
+
+    /// Moves to the previous group. Returns false if the group is the first one.
+    fn previous_group(&self, group: &mut Group) -> bool {
+        if group.start == 0 {
+            return false;
+        }
+        group.end = group.start - 1;
+        group.start = group.end;
+        while group.start > 0 && self.changed[group.start - 1] {
+            group.start -= 1;
+        }
+        true
+    }
+
+    /// Slides a group down by one line if the line after it equals its first line, merging it
+    /// with the next group if they become adjacent. Returns false if the group cannot slide.
+    fn slide_down(&mut self, group: &mut Group) -> bool {
+        if group.end == self.lines.len() || self.lines[group.start] != self.lines[group.end] {
+            return false;
+        }
+        self.changed[group.start] = false;
+        self.changed[group.end] = true;
+        group.start += 1;
+        group.end += 1;
+        while group.end < self.lines.len() && self.changed[group.end] {
+            group.end += 1;
+        }
+        true
+    }
+
+    /// Slides a group up by one line if the line before it equals its last line, merging it with
+    /// the previous group if they become adjacent. Returns false if the group cannot slide.
+    fn slide_up(&mut self, group: &mut Group) -> bool {
+        if group.start == 0 || self.lines[group.start - 1] != self.lines[group.end - 1] {
+            return false;
+        }
+        group.start -= 1;
+        group.end -= 1;
+        self.changed[group.start] = true;
+        self.changed[group.end] = false;
+        while group.start > 0 && self.changed[group.start - 1] {
+            group.start -= 1;
+        }
+        true
+    }
+}
+
+/// Moves the changed lines to the positions git shows them at, when the same change can be made
+/// at several positions, e.g. when a line is inserted next to an identical one, and builds the
+/// edit script.
+///
+/// Like git's `xdl_change_compact`, the changed lines of each version are grouped, and each group
+/// is slid as far down as possible, merging groups that meet. If the group faced changed lines
+/// of the other version at some position, it is slid back up to the last such position, so that
+/// deletions and insertions stay together.
+///
+/// # Arguments
+///
+/// * `old` - The line classes of the old version.
+/// * `new` - The line classes of the new version.
+/// * `changes` - The changed lines found by the algorithm.
+///
+/// # Returns
+///
+/// The edit script, where the deleted lines of each run of changes come before the inserted
+/// ones.
+pub(super) fn compact(old: &[usize], new: &[usize], changes: Changes) -> Vec<Edit> {
+    let mut old = Version {
+        lines: old,
+        changed: changes.old,
+    };
+    let mut new = Version {
+        lines: new,
+        changed: changes.new,
+    };
+    compact_version(&mut old, &new);
+    compact_version(&mut new, &old);
+
+    let mut edits = Vec::with_capacity(old.lines.len().max(new.lines.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.lines.len() || j < new.lines.len() {
+        if i < old.lines.len() && old.changed[i] {
+            edits.push(Edit::Delete(i));
+            i += 1;
+        } else if j < new.lines.len() && new.changed[j] {
+            edits.push(Edit::Insert(j));
+            j += 1;
+        } else {
+            edits.push(Edit::Equal(i, j));
+            i += 1;
+            j += 1;
+        }
+    }
+    edits
+}
+
+/// Compacts the groups of changed lines of one version. The groups of the other version are
+/// walked along, since each group of one version faces a group of the other one.
+fn compact_version(version: &mut Version, other: &Version) {
+    let mut group = version.first_group();
+    let mut other_group = other.first_group();
+    loop {
+        if group.end != group.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let size = group.end - group.start;
+                end_matching_other = None;
+                while version.slide_up(&mut group) {
+                    other.previous_group(&mut other_group);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+                while version.slide_down(&mut group) {
+                    other.next_group(&mut other_group);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+                // Sliding merged groups: slide the merged group again.
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+            if group.end != earliest_end && end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    version.slide_up(&mut group);
+                    other.previous_group(&mut other_group);
+                }
+            }
+        }
+        if !version.next_group(&mut group) {
+            break;
+        }
+        other.next_group(&mut other_group);
+    }
+}
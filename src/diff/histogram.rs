@@ -0,0 +1,196 @@
+use crate::diff::{myers, Changes};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Lines occurring more often than this in the old range are not used as anchors, like git does.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// Represents the common region of the two ranges chosen as anchor.
+struct Region {
+    old: Range<usize>,
+    new: Range<usize>,
+}
+
+/// Represents the occurrences of the lines of the old range.
+struct Histogram {
+    /// For each line class, its first occurrence and its number of occurrences.
+    first: HashMap<usize, (usize, usize)>,
+    /// For each line, the index of the next occurrence of the same class, relative to the start
+    /// of the range.
+    next: Vec<Option<usize>>,
+    /// The lowest number of occurrences of the lines of the best region so far.
+    count: usize,
+    /// Whether a line of the new range occurs in the old range.
+    has_common: bool,
+}
+
+/// Computes an edit script between two ranges of lines with the histogram algorithm, the way
+/// git does.
+///
+/// This extends the patience algorithm to lines that are not unique: the longest common region
+/// around the lines that occur the least often in the old range is taken as anchor, and the
+/// ranges before and after it are diffed recursively. When no line occurs in both ranges, all
+/// lines are changed, and when the common lines occur too often, the ranges are diffed with
+/// Myers' algorithm instead.
+///
+/// # Arguments
+///
+/// * `old` - The line classes of the old version.
+/// * `new` - The line classes of the new version.
+/// * `old_range` - The range of old lines to diff.
+/// * `new_range` - The range of new lines to diff.
+/// * `changes` - The changed lines, which the changes of the ranges are marked in.
+pub(super) fn diff(
+    old: &[usize],
+    new: &[usize],
+    mut old_range: Range<usize>,
+    mut new_range: Range<usize>,
+    changes: &mut Changes,
+) {
+    loop {
+        if old_range.is_empty() || new_range.is_empty() {
+            changes.mark(old_range, new_range);
+            return;
+        }
+        match find_region(old, new, &old_range, &new_range) {
+            None => {
+                myers::diff(old, new, old_range, new_range, false, changes);
+                return;
+            }
+            Some(None) => {
+                changes.mark(old_range, new_range);
+                return;
+            }
+            Some(Some(region)) => {
+                diff(
+                    old,
+                    new,
+                    old_range.start..region.old.start,
+                    new_range.start..region.new.start,
+                    changes,
+                );
+                old_range.start = region.old.end;
+                new_range.start = region.new.end;
+            }
+        }
+    }
+}
+
+/// Finds the common region of the two ranges to use as anchor, like git's `find_lcs`: among the
+/// regions around the lines of the new range, the longest one, or the one whose rarest line is
+/// rarer in the old range, found first.
+///
+/// # Returns
+///
+/// `None` if every common line occurs too often, `Some(None)` if there is no common line, and the
+/// region otherwise.
+fn find_region(
+    old: &[usize],
+    new: &[usize],
+    old_range: &Range<usize>,
+    new_range: &Range<usize>,
+) -> Option<Option<Region>> {
+    let mut histogram = Histogram {
+        first: HashMap::new(),
+        next: vec![None; old_range.len()],
+        count: MAX_CHAIN_LENGTH + 1,
+        has_common: false,
+    };
+    for i in old_range.clone().rev() {
+        let entry = histogram.first.entry(old[i]).or_insert((i, 0));
+        if entry.0 != i {
+            histogram.next[i - old_range.start] = Some(entry.0);
+            entry.0 = i;
+        }
+        entry.1 += 1;
+    }
+
+    let mut region: Option<Region> = None;
+    let mut j = new_range.start;
+    while j < new_range.end {
+        j = try_region(
+            old,
+            new,
+            old_range,
+            new_range,
+            j,
+            &mut histogram,
+            &mut region,
+        );
+    }
+    if histogram.has_common && histogram.count > MAX_CHAIN_LENGTH {
+        None
+    } else {
+        Some(region)
+    }
+}
+
+/// Tries the regions around the occurrences in the old range of the new line at `j`, and keeps
+/// the best one, like git's `try_lcs`.
+///
+/// # Returns
+///
+/// The next line of the new range to try, after the regions found.
+fn try_region(
+    old: &[usize],
+    new: &[usize],
+    old_range: &Range<usize>,
+    new_range: &Range<usize>,
+    j: usize,
+    histogram: &mut Histogram,
+    region: &mut Option<Region>,
+) -> usize {
+    let mut next_j = j + 1;
+    let Some(&(first, count)) = histogram.first.get(&new[j]) else {
+        return next_j;
+    };
+    histogram.has_common = true;
+    if count > histogram.count {
+        return next_j;
+    }
+
+    let mut i = first;
+    loop {
+        let (mut old_start, mut new_start) = (i, j);
+        let (mut old_end, mut new_end) = (i + 1, j + 1);
+        let mut lowest = count;
+        while old_start > old_range.start
+            && new_start > new_range.start
+            && old[old_start - 1] == new[new_start - 1]
+        {
+            old_start -= 1;
+            new_start -= 1;
+            if lowest > 1 {
+                lowest = lowest.min(histogram.first[&old[old_start]].1);
+            }
+        }
+        while old_end < old_range.end && new_end < new_range.end && old[old_end] == new[new_end] {
+            if lowest > 1 {
+                lowest = lowest.min(histogram.first[&old[old_end]].1);
+            }
+            old_end += 1;
+            new_end += 1;
+        }
+
+        next_j = next_j.max(new_end);
+        // Like git, a region of one line is not longer than no region at all.
+        let best_len = region.as_ref().map_or(0, |region| region.old.len() - 1);
+        if best_len < old_end - old_start - 1 || lowest < histogram.count {
+            *region = Some(Region {
+                old: old_start..old_end,
+                new: new_start..new_end,
+            });
+            histogram.count = lowest;
+        }
+
+        // Continue with the next occurrence after the region.
+        let mut next = histogram.next[i - old_range.start];
+        while let Some(k) = next.filter(|&k| k < old_end) {
+            next = histogram.next[k - old_range.start];
+        }
+        match next {
+            Some(k) => i = k,
+            None => return next_j,
+        }
+    }
+}
@@ -0,0 +1,313 @@
+/// This module computes line-based differences between two versions of a file, and writes them
+/// as unified diffs.
+mod compact;
+mod histogram;
+mod myers;
+mod patience;
+pub(crate) mod rename;
+pub(crate) mod tree;
+pub(crate) mod unified;
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Number of bytes looked at to decide whether a file is binary, like git does.
+const BINARY_CHECK_SIZE: usize = 8000;
+
+/// Represents one step of the edit script that turns the old lines into the new lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Edit {
+    /// The old line at the first index is kept as the new line at the second index.
+    Equal(usize, usize),
+    /// The old line at the index is deleted.
+    Delete(usize),
+    /// The new line at the index is inserted.
+    Insert(usize),
+}
+
+/// Represents the algorithm used to compute the edit script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    /// Myers' O(ND) algorithm, with the heuristics of git that trade the shortest edit script for
+    /// speed on large inputs.
+    Myers,
+    /// Myers' algorithm, always finding the shortest edit script.
+    Minimal,
+    /// Patience diff, which aligns the lines that are unique in both versions first.
+    Patience,
+    /// Histogram diff, which aligns the lines that occur the least often first.
+    Histogram,
+}
+
+impl Algorithm {
+    /// Parses the name of an algorithm, as given to `--diff-algorithm`.
+    pub(crate) fn parse(name: &str) -> anyhow::Result<Algorithm> {
+        match name {
+            "myers" | "default" => Ok(Algorithm::Myers),
+            "minimal" => Ok(Algorithm::Minimal),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => anyhow::bail!("unknown diff algorithm: {}", name),
+        }
+    }
+}
+
+/// Represents the options that control how differences are computed and shown.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DiffOptions {
+    /// The number of unchanged lines shown around the changes.
+    pub(crate) context: usize,
+    /// The algorithm used to compute the edit script.
+    pub(crate) algorithm: Algorithm,
+}
+
+/// Splits data into lines, each one keeping its terminating newline. The last line has no
+/// newline if the data does not end with one.
+pub(crate) fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Returns true if the data looks binary: like git, if its first bytes contain a NUL byte.
+pub(crate) fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_SIZE)].contains(&0)
+}
+
+/// Computes the edit script that turns the old lines into the new lines.
+///
+/// The lines are first numbered so that equal lines get the same number, which the algorithms
+/// compare instead of the lines. The changes found by the algorithm are then moved to the
+/// positions git shows them at when they can be placed at several positions, see
+/// `compact::compact`.
+///
+/// # Arguments
+///
+/// * `old` - The lines of the old version.
+/// * `new` - The lines of the new version.
+/// * `algorithm` - The algorithm to use.
+///
+/// # Returns
+///
+/// The edit script, in the order of the lines, where the deleted lines of each run of changes
+/// come before the inserted ones.
+pub(crate) fn diff_lines(old: &[&[u8]], new: &[&[u8]], algorithm: Algorithm) -> Vec<Edit> {
+    let mut classes = HashMap::new();
+    let mut classify = |lines: &[&[u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|line| {
+                let next = classes.len();
+                *classes.entry(line.to_vec()).or_insert(next)
+            })
+            .collect()
+    };
+    let old = classify(old);
+    let new = classify(new);
+
+    let mut changes = Changes {
+        old: vec![false; old.len()],
+        new: vec![false; new.len()],
+    };
+    let old_range = 0..old.len();
+    let new_range = 0..new.len();
+    match algorithm {
+        Algorithm::Myers | Algorithm::Minimal => {
+            let minimal = algorithm == Algorithm::Minimal;
+            myers::diff(&old, &new, old_range, new_range, minimal, &mut changes)
+        }
+        Algorithm::Patience => patience::diff(&old, &new, old_range, new_range, &mut changes),
+        Algorithm::Histogram => histogram::diff(&old, &new, old_range, new_range, &mut changes),
+    }
+    compact::compact(&old, &new, changes)
+}
+
+/// Represents the lines found changed by an algorithm: deleted from the old version, or inserted
+/// in the new version.
+struct Changes {
+    old: Vec<bool>,
+    new: Vec<bool>,
+}
+
+impl Changes {
+    /// Marks all lines of the ranges as changed.
+    fn mark(&mut self, old_range: Range<usize>, new_range: Range<usize>) {
+        self.old[old_range].fill(true);
+        self.new[new_range].fill(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [Algorithm; 4] = [
+        Algorithm::Myers,
+        Algorithm::Minimal,
+        Algorithm::Patience,
+        Algorithm::Histogram,
+    ];
+
+    /// Diffs two texts, checks that the edit script turns the old lines into the new lines, and
+    /// returns the hunks with all lines of context.
+    fn diff(old: &str, new: &str, algorithm: Algorithm) -> String {
+        let old = split_lines(old.as_bytes());
+        let new = split_lines(new.as_bytes());
+        let edits = diff_lines(&old, &new, algorithm);
+
+        let (mut old_pos, mut new_pos) = (0, 0);
+        for edit in &edits {
+            match *edit {
+                Edit::Equal(i, j) => {
+                    assert_eq!((i, j), (old_pos, new_pos));
+                    assert_eq!(old[i], new[j]);
+                    (old_pos, new_pos) = (i + 1, j + 1);
+                }
+                Edit::Delete(i) => {
+                    assert_eq!(i, old_pos);
+                    old_pos += 1;
+                }
+                Edit::Insert(j) => {
+                    assert_eq!(j, new_pos);
+                    new_pos += 1;
+                }
+            }
+        }
+        assert_eq!((old_pos, new_pos), (old.len(), new.len()));
+
+        let mut out = Vec::new();
+        unified::write_hunks(&mut out, &old, &new, &edits, usize::MAX / 4).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        match out.split_once('\n') {
+            Some((_, hunk)) => hunk.to_string(),
+            None => out,
+        }
+    }
+
+    /// Diffs two texts and returns the ranges of the hunks without context, as git shows them.
+    fn ranges(old: &str, new: &str, algorithm: Algorithm) -> Vec<String> {
+        let old = split_lines(old.as_bytes());
+        let new = split_lines(new.as_bytes());
+        let edits = diff_lines(&old, &new, algorithm);
+        let mut out = Vec::new();
+        unified::write_hunks(&mut out, &old, &new, &edits, 0).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("@@ "))
+            .map(|header| header.split(" @@").next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn handles_empty_and_equal_versions() {
+        for algorithm in ALGORITHMS {
+            assert_eq!(diff("", "", algorithm), "");
+            assert_eq!(diff("a\nb\n", "a\nb\n", algorithm), "");
+            assert_eq!(diff("", "a\nb\n", algorithm), "+a\n+b\n");
+            assert_eq!(diff("a\nb\n", "", algorithm), "-a\n-b\n");
+            assert_eq!(diff("a\nb\n", "c\nd\n", algorithm), "-a\n-b\n+c\n+d\n");
+        }
+    }
+
+    #[test]
+    fn shows_missing_newlines_at_end_of_file() {
+        for algorithm in ALGORITHMS {
+            assert_eq!(
+                diff("one\ntwo\nthree", "one\ntwo\nthree\n", algorithm),
+                " one\n two\n-three\n\\ No newline at end of file\n+three\n"
+            );
+        }
+    }
+
+    #[test]
+    fn slides_changes_like_git() {
+        // The inserted lines could also be placed after any of the first three lines.
+        for algorithm in ALGORITHMS {
+            assert_eq!(
+                diff("}\n}\nx\n}\n", "}\n}\nx\n}\n}\ny\n}\n", algorithm),
+                " }\n }\n x\n }\n+}\n+y\n+}\n"
+            );
+            assert_eq!(
+                diff("a\n\nb\n", "a\n\nnew\n\nb\n", algorithm),
+                " a\n \n+new\n+\n b\n"
+            );
+        }
+    }
+
+    #[test]
+    fn aligns_repeated_lines_like_git() {
+        let (old, new) = ("a\nb\nc\na\nb\nb\na\n", "c\nb\na\nb\na\nc\n");
+        for algorithm in [Algorithm::Myers, Algorithm::Minimal, Algorithm::Patience] {
+            assert_eq!(
+                diff(old, new, algorithm),
+                "-a\n-b\n c\n-a\n b\n+a\n b\n a\n+c\n"
+            );
+        }
+        assert_eq!(
+            diff(old, new, Algorithm::Histogram),
+            "-a\n-b\n c\n-a\n-b\n b\n a\n+b\n+a\n+c\n"
+        );
+    }
+
+    #[test]
+    fn aligns_unique_lines_with_patience_and_histogram() {
+        let old = "#include <stdio.h>\n\n// Frobs foo heartily\nint frobnitz(int foo)\n{\n    \
+                   int i;\n    for(i = 0; i < 10; i++)\n    {\n        \
+                   printf(\"Your answer is: \");\n        printf(\"%d\\n\", foo);\n    }\n}\n\n\
+                   int fact(int n)\n{\n    if(n > 1)\n    {\n        \
+                   return fact(n-1) * n;\n    }\n    return 1;\n}\n\n\
+                   int main(int argc, char **argv)\n{\n    frobnitz(fact(10));\n}\n";
+        let new = "#include <stdio.h>\n\nint fib(int n)\n{\n    if(n > 2)\n    {\n        \
+                   return fib(n-1) + fib(n-2);\n    }\n    return 1;\n}\n\n\
+                   // Frobs foo heartily\nint frobnitz(int foo)\n{\n    int i;\n    \
+                   for(i = 0; i < 10; i++)\n    {\n        printf(\"%d\\n\", foo);\n    }\n}\n\n\
+                   int main(int argc, char **argv)\n{\n    frobnitz(fib(10));\n}\n";
+        let myers = [
+            "-3,2 +3",
+            "-6,2 +5",
+            "-9,2 +7",
+            "-11,0 +9",
+            "-14 +12,2",
+            "-16 +15,2",
+            "-18 +18",
+            "-20 +19,0",
+            "-25 +24",
+        ];
+        let patience = ["-2,0 +3,9", "-9 +17,0", "-14,9 +21,0", "-25 +24"];
+        assert_eq!(ranges(old, new, Algorithm::Myers), myers);
+        assert_eq!(ranges(old, new, Algorithm::Minimal), myers);
+        assert_eq!(ranges(old, new, Algorithm::Patience), patience);
+        assert_eq!(ranges(old, new, Algorithm::Histogram), patience);
+    }
+
+    #[test]
+    fn finds_valid_edit_scripts_for_large_inputs() {
+        // Large inputs with many changes go through the heuristics that cut the search short.
+        let mut state = 1u64;
+        let mut random_lines = |len: usize, alphabet: u64| {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    format!("{}\n", (state >> 33) % alphabet)
+                })
+                .collect::<String>()
+        };
+        for alphabet in [2, 20, 2000] {
+            let old = random_lines(1500, alphabet);
+            let new = random_lines(1200, alphabet);
+            let changed = |hunk: &str| hunk.lines().filter(|line| !line.starts_with(' ')).count();
+            let minimal = changed(&diff(&old, &new, Algorithm::Minimal));
+            for algorithm in ALGORITHMS {
+                assert!(changed(&diff(&old, &new, algorithm)) >= minimal);
+            }
+        }
+    }
+
+    #[test]
+    fn detects_binary_data() {
+        assert!(is_binary(b"a\0b"));
+        assert!(!is_binary(b"text\n"));
+        let mut late = vec![b'a'; BINARY_CHECK_SIZE];
+        late.push(0);
+        assert!(!is_binary(&late));
+    }
+}
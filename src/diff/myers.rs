@@ -0,0 +1,503 @@
+use crate::diff::Changes;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Edit cost above which a split point may be taken before the searches meet, if it follows a
+/// long enough snake.
+const HEUR_MIN_COST: isize = 256;
+/// Lower bound of the edit cost above which the furthest reaching point is taken as split point.
+const MAX_COST_MIN: isize = 256;
+/// Length of the snakes that make a split point interesting for the heuristic.
+const SNAKE_CNT: isize = 20;
+/// Factor of the edit cost a split point must reach to be taken by the heuristic.
+const K_HEUR: isize = 4;
+/// Upper bound of the number of matches above which a line counts as matching many lines.
+const MAX_EQLIMIT: isize = 1024;
+/// Number of lines around a line that are looked at to decide whether to discard it.
+const SIMSCAN_WINDOW: usize = 100;
+/// Ratio of lines without matches to lines with many matches above which the latter are
+/// discarded.
+const KPDIS_RUN: usize = 4;
+
+/// How a line is treated before running the algorithm, depending on its matches in the other
+/// version.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Matches {
+    /// The line matches no line of the other version: it is changed.
+    None,
+    /// The line matches a few lines of the other version.
+    Few,
+    /// The line matches many lines of the other version: it is discarded if it sits among lines
+    /// without matches.
+    Many,
+}
+
+/// Represents a point to split the edit graph at, and whether the shortest edit script must be
+/// found before and after it.
+struct Split {
+    old: isize,
+    new: isize,
+    minimal_before: bool,
+    minimal_after: bool,
+}
+
+/// Represents the state of the searches: the furthest reaching positions on each diagonal, from
+/// the start and from the end, and the cost above which the furthest reaching point is taken.
+struct Search {
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    /// Index of the diagonal 0 in `forward` and `backward`.
+    offset: isize,
+    max_cost: isize,
+}
+
+/// Computes an edit script between two ranges of lines with Myers' algorithm, the way git's
+/// xdiff does.
+///
+/// The lines the ranges have in common at their start and at their end are left out. Lines that
+/// have no match in the other range are changed, and are removed before running the algorithm,
+/// together with the lines that match many lines and sit among them. The algorithm then splits
+/// the edit graph where the searches from the start and from the end meet, in the middle of the
+/// shortest path, and recurses on both sides. Unless `minimal` is set, a split point is taken
+/// earlier when the edit cost grows large, so that the edit script may not be the shortest.
+///
+/// # Arguments
+///
+/// * `old` - The line classes of the old version.
+/// * `new` - The line classes of the new version.
+/// * `old_range` - The range of old lines to diff.
+/// * `new_range` - The range of new lines to diff.
+/// * `minimal` - If true, always find the shortest edit script.
+/// * `changes` - The changed lines, which the changes of the ranges are marked in.
+pub(super) fn diff(
+    old: &[usize],
+    new: &[usize],
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+    minimal: bool,
+    changes: &mut Changes,
+) {
+    let prefix = old[old_range.clone()]
+        .iter()
+        .zip(&new[new_range.clone()])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[old_range.clone()]
+        .iter()
+        .rev()
+        .zip(new[new_range.clone()].iter().rev())
+        .take(old_range.len().min(new_range.len()) - prefix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = old_range.start + prefix..old_range.end - suffix;
+    let new_middle = new_range.start + prefix..new_range.end - suffix;
+
+    let count = |lines: &[usize]| {
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        for &line in lines {
+            *counts.entry(line).or_default() += 1;
+        }
+        counts
+    };
+    let old_counts = count(&old[old_range.clone()]);
+    let new_counts = count(&new[new_range.clone()]);
+    let (old_kept, old_index) = discard(
+        old,
+        old_middle,
+        &new_counts,
+        old_range.len(),
+        minimal,
+        &mut changes.old,
+    );
+    let (new_kept, new_index) = discard(
+        new,
+        new_middle,
+        &old_counts,
+        new_range.len(),
+        minimal,
+        &mut changes.new,
+    );
+
+    let size = old_kept.len() + new_kept.len() + 3;
+    let mut search = Search {
+        forward: vec![0; size],
+        backward: vec![0; size],
+        offset: new_kept.len() as isize + 1,
+        max_cost: bogosqrt(size).max(MAX_COST_MIN),
+    };
+    let mut old_changed = vec![false; old_kept.len()];
+    let mut new_changed = vec![false; new_kept.len()];
+    compare(
+        (&old_kept, &mut old_changed, 0, old_kept.len() as isize),
+        (&new_kept, &mut new_changed, 0, new_kept.len() as isize),
+        minimal,
+        &mut search,
+    );
+    for (i, _) in old_changed
+        .iter()
+        .enumerate()
+        .filter(|(_, &changed)| changed)
+    {
+        changes.old[old_index[i]] = true;
+    }
+    for (j, _) in new_changed
+        .iter()
+        .enumerate()
+        .filter(|(_, &changed)| changed)
+    {
+        changes.new[new_index[j]] = true;
+    }
+}
+
+/// Marks the lines of a range that have no match in the other version as changed, as well as the
+/// lines with many matches among them, see `keep_many_matches`.
+///
+/// # Returns
+///
+/// The classes of the remaining lines, and their indexes.
+fn discard(
+    lines: &[usize],
+    range: Range<usize>,
+    other_counts: &HashMap<usize, usize>,
+    len: usize,
+    minimal: bool,
+    changed: &mut [bool],
+) -> (Vec<usize>, Vec<usize>) {
+    let limit = bogosqrt(len).min(MAX_EQLIMIT) as usize;
+    let matches: Vec<Matches> = range
+        .clone()
+        .map(
+            |i| match other_counts.get(&lines[i]).copied().unwrap_or(0) {
+                0 => Matches::None,
+                n if n >= limit && !minimal => Matches::Many,
+                _ => Matches::Few,
+            },
+        )
+        .collect();
+
+    let mut kept = Vec::new();
+    let mut index = Vec::new();
+    for (k, i) in range.enumerate() {
+        let keep = match matches[k] {
+            Matches::None => false,
+            Matches::Few => true,
+            Matches::Many => keep_many_matches(&matches, k),
+        };
+        if keep {
+            kept.push(lines[i]);
+            index.push(i);
+        } else {
+            changed[i] = true;
+        }
+    }
+    (kept, index)
+}
+
+/// Decides whether to keep a line with many matches, like xdiff's `xdl_clean_mmatch`: it is
+/// discarded if it sits in a run of lines with no matches or many matches, that has lines without
+/// matches on both sides of it and mostly lines without matches.
+fn keep_many_matches(matches: &[Matches], i: usize) -> bool {
+    let start = i.saturating_sub(SIMSCAN_WINDOW);
+    let end = (i + SIMSCAN_WINDOW).min(matches.len() - 1);
+    let scan = |lines: &mut dyn Iterator<Item = &Matches>| {
+        let (mut none, mut many) = (0, 0);
+        for line in lines {
+            match line {
+                Matches::None => none += 1,
+                Matches::Many => many += 1,
+                Matches::Few => break,
+            }
+        }
+        (none, many)
+    };
+    let (none_before, many_before) = scan(&mut matches[start..i].iter().rev());
+    if none_before == 0 {
+        return true;
+    }
+    let (none_after, many_after) = scan(&mut matches[i + 1..=end].iter());
+    if none_after == 0 {
+        return true;
+    }
+    // The line itself is counted on both sides.
+    let many = many_before + many_after + 2;
+    let none = none_before + none_after;
+    many * KPDIS_RUN >= many + none
+}
+
+/// Returns a rough square root of `n`, as a power of two.
+fn bogosqrt(mut n: usize) -> isize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// Marks the changed lines between two ranges of lines, recursing on both sides of the split
+/// point, like xdiff's `xdl_recs_cmp`. Each side is given as its lines, their changed flags and
+/// the range to compare.
+fn compare(
+    (old, old_changed, mut old_start, mut old_end): (&[usize], &mut [bool], isize, isize),
+    (new, new_changed, mut new_start, mut new_end): (&[usize], &mut [bool], isize, isize),
+    minimal: bool,
+    search: &mut Search,
+) {
+    while old_start < old_end
+        && new_start < new_end
+        && old[old_start as usize] == new[new_start as usize]
+    {
+        old_start += 1;
+        new_start += 1;
+    }
+    while old_start < old_end
+        && new_start < new_end
+        && old[old_end as usize - 1] == new[new_end as usize - 1]
+    {
+        old_end -= 1;
+        new_end -= 1;
+    }
+
+    if old_start == old_end {
+        new_changed[new_start as usize..new_end as usize].fill(true);
+    } else if new_start == new_end {
+        old_changed[old_start as usize..old_end as usize].fill(true);
+    } else {
+        let split = split(
+            (old, old_start, old_end),
+            (new, new_start, new_end),
+            minimal,
+            search,
+        );
+        compare(
+            (old, &mut *old_changed, old_start, split.old),
+            (new, &mut *new_changed, new_start, split.new),
+            split.minimal_before,
+            search,
+        );
+        compare(
+            (old, old_changed, split.old, old_end),
+            (new, new_changed, split.new, new_end),
+            split.minimal_after,
+            search,
+        );
+    }
+}
+
+/// Finds the point to split the edit graph between two non-empty ranges at, like xdiff's
+/// `xdl_split`: where the searches from the start and from the end meet or, unless `minimal` is
+/// set, a point reached by a long snake or the furthest reaching point once the cost is high.
+fn split(
+    (old, old_start, old_end): (&[usize], isize, isize),
+    (new, new_start, new_end): (&[usize], isize, isize),
+    minimal: bool,
+    search: &mut Search,
+) -> Split {
+    let offset = search.offset;
+    let at = |d: isize| (d + offset) as usize;
+    let forward = &mut search.forward;
+    let backward = &mut search.backward;
+
+    let (diagonal_min, diagonal_max) = (old_start - new_end, old_end - new_start);
+    let (forward_mid, backward_mid) = (old_start - new_start, old_end - new_end);
+    let odd = (forward_mid - backward_mid) & 1 != 0;
+    let (mut forward_min, mut forward_max) = (forward_mid, forward_mid);
+    let (mut backward_min, mut backward_max) = (backward_mid, backward_mid);
+
+    forward[at(forward_mid)] = old_start;
+    backward[at(backward_mid)] = old_end;
+
+    let mut cost = 1;
+    loop {
+        let mut got_snake = false;
+
+        // Extend the searched diagonals by one, or shrink them where they would leave the graph.
+        // The diagonals just outside are set so that they are never taken.
+        if forward_min > diagonal_min {
+            forward_min -= 1;
+            forward[at(forward_min - 1)] = -1;
+        } else {
+            forward_min += 1;
+        }
+        if forward_max < diagonal_max {
+            forward_max += 1;
+            forward[at(forward_max + 1)] = -1;
+        } else {
+            forward_max -= 1;
+        }
+
+        for d in (forward_min..=forward_max).rev().step_by(2) {
+            let mut i = if forward[at(d - 1)] >= forward[at(d + 1)] {
+                forward[at(d - 1)] + 1
+            } else {
+                forward[at(d + 1)]
+            };
+            let previous = i;
+            let mut j = i - d;
+            while i < old_end && j < new_end && old[i as usize] == new[j as usize] {
+                i += 1;
+                j += 1;
+            }
+            if i - previous > SNAKE_CNT {
+                got_snake = true;
+            }
+            forward[at(d)] = i;
+            if odd && (backward_min..=backward_max).contains(&d) && backward[at(d)] <= i {
+                return Split {
+                    old: i,
+                    new: j,
+                    minimal_before: true,
+                    minimal_after: true,
+                };
+            }
+        }
+
+        if backward_min > diagonal_min {
+            backward_min -= 1;
+            backward[at(backward_min - 1)] = isize::MAX;
+        } else {
+            backward_min += 1;
+        }
+        if backward_max < diagonal_max {
+            backward_max += 1;
+            backward[at(backward_max + 1)] = isize::MAX;
+        } else {
+            backward_max -= 1;
+        }
+
+        for d in (backward_min..=backward_max).rev().step_by(2) {
+            let mut i = if backward[at(d - 1)] < backward[at(d + 1)] {
+                backward[at(d - 1)]
+            } else {
+                backward[at(d + 1)] - 1
+            };
+            let previous = i;
+            let mut j = i - d;
+            while i > old_start && j > new_start && old[i as usize - 1] == new[j as usize - 1] {
+                i -= 1;
+                j -= 1;
+            }
+            if previous - i > SNAKE_CNT {
+                got_snake = true;
+            }
+            backward[at(d)] = i;
+            if !odd && (forward_min..=forward_max).contains(&d) && i <= forward[at(d)] {
+                return Split {
+                    old: i,
+                    new: j,
+                    minimal_before: true,
+                    minimal_after: true,
+                };
+            }
+        }
+
+        if minimal {
+            cost += 1;
+            continue;
+        }
+
+        // Past some cost, take a point at the end of a long snake that went far, measured by its
+        // distance from the corner penalized by its distance from the middle diagonal.
+        if got_snake && cost > HEUR_MIN_COST {
+            let mut best = 0;
+            let mut point = None;
+            for d in (forward_min..=forward_max).rev().step_by(2) {
+                let i = forward[at(d)];
+                let j = i - d;
+                let value = (i - old_start) + (j - new_start) - (d - forward_mid).abs();
+                if value > K_HEUR * cost
+                    && value > best
+                    && old_start + SNAKE_CNT <= i
+                    && i < old_end
+                    && new_start + SNAKE_CNT <= j
+                    && j < new_end
+                    && (1..=SNAKE_CNT).all(|k| old[(i - k) as usize] == new[(j - k) as usize])
+                {
+                    best = value;
+                    point = Some((i, j));
+                }
+            }
+            if let Some((i, j)) = point {
+                return Split {
+                    old: i,
+                    new: j,
+                    minimal_before: true,
+                    minimal_after: false,
+                };
+            }
+
+            for d in (backward_min..=backward_max).rev().step_by(2) {
+                let i = backward[at(d)];
+                let j = i - d;
+                let value = (old_end - i) + (new_end - j) - (d - backward_mid).abs();
+                if value > K_HEUR * cost
+                    && value > best
+                    && old_start < i
+                    && i <= old_end - SNAKE_CNT
+                    && new_start < j
+                    && j <= new_end - SNAKE_CNT
+                    && (0..SNAKE_CNT).all(|k| old[(i + k) as usize] == new[(j + k) as usize])
+                {
+                    best = value;
+                    point = Some((i, j));
+                }
+            }
+            if let Some((i, j)) = point {
+                return Split {
+                    old: i,
+                    new: j,
+                    minimal_before: false,
+                    minimal_after: true,
+                };
+            }
+        }
+
+        // Enough is enough: take the furthest reaching point of either search.
+        if cost >= search.max_cost {
+            let (mut forward_best, mut forward_best_old) = (-1, -1);
+            for d in (forward_min..=forward_max).rev().step_by(2) {
+                let mut i = forward[at(d)].min(old_end);
+                let mut j = i - d;
+                if new_end < j {
+                    i = new_end + d;
+                    j = new_end;
+                }
+                if forward_best < i + j {
+                    forward_best = i + j;
+                    forward_best_old = i;
+                }
+            }
+            let (mut backward_best, mut backward_best_old) = (isize::MAX, isize::MAX);
+            for d in (backward_min..=backward_max).rev().step_by(2) {
+                let mut i = backward[at(d)].max(old_start);
+                let mut j = i - d;
+                if j < new_start {
+                    i = new_start + d;
+                    j = new_start;
+                }
+                if i + j < backward_best {
+                    backward_best = i + j;
+                    backward_best_old = i;
+                }
+            }
+            let forward_reach = forward_best - (old_start + new_start);
+            let backward_reach = (old_end + new_end) - backward_best;
+            return if backward_reach < forward_reach {
+                Split {
+                    old: forward_best_old,
+                    new: forward_best - forward_best_old,
+                    minimal_before: true,
+                    minimal_after: false,
+                }
+            } else {
+                Split {
+                    old: backward_best_old,
+                    new: backward_best - backward_best_old,
+                    minimal_before: false,
+                    minimal_after: true,
+                }
+            };
+        }
+        cost += 1;
+    }
+}
@@ -0,0 +1,151 @@
+use crate::diff::{myers, Changes};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Represents a line of the old range and where it occurs in both ranges.
+struct Occurrences {
+    /// The index of the first occurrence in the old range.
+    old: usize,
+    /// The number of occurrences in the old range.
+    old_count: usize,
+    /// The index of the first occurrence in the new range, if any.
+    new: Option<usize>,
+    /// The number of occurrences in the new range.
+    new_count: usize,
+}
+
+/// Computes an edit script between two ranges of lines with the patience algorithm, the way git
+/// does.
+///
+/// The lines that occur exactly once in both ranges are matched, and the longest sequence of
+/// them that is in the same order in both ranges is kept as anchors. The matches are extended
+/// around each anchor, and the ranges between them are diffed recursively. When no line occurs
+/// in both ranges, all lines are changed, and when no line is unique in both ranges, the ranges
+/// are diffed with Myers' algorithm instead.
+///
+/// # Arguments
+///
+/// * `old` - The line classes of the old version.
+/// * `new` - The line classes of the new version.
+/// * `old_range` - The range of old lines to diff.
+/// * `new_range` - The range of new lines to diff.
+/// * `changes` - The changed lines, which the changes of the ranges are marked in.
+pub(super) fn diff(
+    old: &[usize],
+    new: &[usize],
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+    changes: &mut Changes,
+) {
+    if old_range.is_empty() || new_range.is_empty() {
+        changes.mark(old_range, new_range);
+        return;
+    }
+
+    // The lines of the old range, in the order of their first occurrence.
+    let mut lines: Vec<Occurrences> = Vec::new();
+    let mut positions: HashMap<usize, usize> = HashMap::new();
+    for i in old_range.clone() {
+        let position = *positions.entry(old[i]).or_insert_with(|| {
+            lines.push(Occurrences {
+                old: i,
+                old_count: 0,
+                new: None,
+                new_count: 0,
+            });
+            lines.len() - 1
+        });
+        lines[position].old_count += 1;
+    }
+    let mut has_matches = false;
+    for j in new_range.clone() {
+        if let Some(&position) = positions.get(&new[j]) {
+            let line = &mut lines[position];
+            line.new.get_or_insert(j);
+            line.new_count += 1;
+            has_matches = true;
+        }
+    }
+    if !has_matches {
+        changes.mark(old_range, new_range);
+        return;
+    }
+
+    let anchors = longest_common_sequence(&lines);
+    if anchors.is_empty() {
+        myers::diff(old, new, old_range, new_range, false, changes);
+        return;
+    }
+
+    let (mut old_start, mut new_start) = (old_range.start, new_range.start);
+    let mut k = 0;
+    loop {
+        // Extend the matches backwards from the next anchor and forwards from the previous one.
+        let (mut old_next, mut new_next) = match anchors.get(k) {
+            Some(&(i, j)) => (i, j),
+            None => (old_range.end, new_range.end),
+        };
+        if k < anchors.len() {
+            while old_next > old_start
+                && new_next > new_start
+                && old[old_next - 1] == new[new_next - 1]
+            {
+                old_next -= 1;
+                new_next -= 1;
+            }
+        }
+        while old_start < old_next && new_start < new_next && old[old_start] == new[new_start] {
+            old_start += 1;
+            new_start += 1;
+        }
+        if old_next > old_start || new_next > new_start {
+            diff(old, new, old_start..old_next, new_start..new_next, changes);
+        }
+        if k == anchors.len() {
+            break;
+        }
+
+        // Skip the anchors that directly follow each other.
+        while k + 1 < anchors.len()
+            && anchors[k + 1].0 == anchors[k].0 + 1
+            && anchors[k + 1].1 == anchors[k].1 + 1
+        {
+            k += 1;
+        }
+        (old_start, new_start) = (anchors[k].0 + 1, anchors[k].1 + 1);
+        k += 1;
+    }
+}
+
+/// Finds the longest sequence of lines that occur exactly once in both ranges and are in the
+/// same order in both, as pairs of old and new indexes, with patience sorting.
+fn longest_common_sequence(lines: &[Occurrences]) -> Vec<(usize, usize)> {
+    let unique: Vec<(usize, usize)> = lines
+        .iter()
+        .filter(|line| line.old_count == 1 && line.new_count == 1)
+        .filter_map(|line| Some((line.old, line.new?)))
+        .collect();
+
+    // Each pile keeps the index of its top card, and each card the index of the top of the
+    // previous pile when it was placed, to rebuild the longest sequence.
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = Vec::with_capacity(unique.len());
+    for (card, &(_, j)) in unique.iter().enumerate() {
+        let pile = piles.partition_point(|&top| unique[top].1 < j);
+        previous.push(pile.checked_sub(1).map(|pile| piles[pile]));
+        if pile == piles.len() {
+            piles.push(card);
+        } else {
+            piles[pile] = card;
+        }
+    }
+
+    let mut sequence = Vec::with_capacity(piles.len());
+    let mut card = piles.last().copied();
+    while let Some(current) = card {
+        sequence.push(unique[current]);
+        card = previous[current];
+    }
+    sequence.reverse();
+    sequence
+}
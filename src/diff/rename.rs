@@ -0,0 +1,665 @@
+use crate::diff::is_binary;
+use crate::diff::tree::{Change, FileChange, FileState};
+use crate::diff::unified::FileVersion;
+use crate::objects::id::ObjectID;
+use crate::objects::tree::mode::EntryMode;
+use crate::status::compare_paths;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// The similarity score of two identical files. Scores are fractions of it, like in git.
+pub(crate) const MAX_SCORE: u32 = 60000;
+
+/// The default minimum similarity score of a rename or copy, 50%.
+pub(crate) const DEFAULT_RENAME_SCORE: u32 = 30000;
+
+/// The number of most similar sources remembered for each destination.
+const CANDIDATES_PER_DESTINATION: usize = 4;
+
+/// Files are split into chunks that end at a newline or after this many bytes, to estimate their
+/// similarity.
+const MAX_CHUNK_LEN: usize = 64;
+
+/// The chunks of files are hashed modulo this prime, like git does.
+const HASH_BASE: u32 = 107927;
+
+/// Represents how renamed and copied files are detected.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RenameOptions {
+    /// The minimum similarity score for a pair of files to be a rename or a copy.
+    pub(crate) minimum_score: u32,
+    /// If true, also detect copies of the files that are modified or deleted.
+    pub(crate) copies: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        RenameOptions {
+            minimum_score: DEFAULT_RENAME_SCORE,
+            copies: false,
+        }
+    }
+}
+
+/// Parses the similarity given to `-M` or `-C`, like git: the digits are a fraction, e.g. `5` and
+/// `0.5` are 50%, unless they are followed by `%`. An empty value is the default, 50%.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the minimum score, or an `anyhow::Error` if the value is not a
+/// similarity.
+pub(crate) fn parse_score(value: &str) -> anyhow::Result<u32> {
+    if value.is_empty() {
+        return Ok(DEFAULT_RENAME_SCORE);
+    }
+    let (mut number, mut scale) = (0u64, 1u64);
+    let mut dot = false;
+    let mut chars = value.chars();
+    for c in chars.by_ref() {
+        match c {
+            '.' if !dot => (scale, dot) = (1, true),
+            '%' => {
+                scale = if dot { scale * 100 } else { 100 };
+                break;
+            }
+            // Digits past the precision of the score are ignored.
+            '0'..='9' if scale < 100000 => {
+                scale *= 10;
+                number = number * 10 + u64::from(c as u8 - b'0');
+            }
+            '0'..='9' => {}
+            _ => anyhow::bail!("invalid similarity: {}", value),
+        }
+    }
+    anyhow::ensure!(chars.next().is_none(), "invalid similarity: {}", value);
+    Ok(if number >= scale {
+        MAX_SCORE
+    } else {
+        (u64::from(MAX_SCORE) * number / scale) as u32
+    })
+}
+
+/// Converts a similarity score to a percentage, as shown after `R` and `C`.
+pub(crate) fn percent(score: u32) -> u32 {
+    score * 100 / MAX_SCORE
+}
+
+/// Pairs deleted files with added files to find the renamed files, and, with `options.copies`,
+/// modified files with added files to find the copied files, the way git does.
+///
+/// Files with the same contents are paired first, preferring sources that are not used yet and
+/// have the same base name. With only renames, the remaining files with a base name that no
+/// other remaining source or destination has are paired next if they are similar enough. Then
+/// the similarity of every other pair is estimated, and pairs are taken from the most similar
+/// down to `options.minimum_score`. A source used several times is a copy, except for its last
+/// use if it is deleted, which is its rename.
+///
+/// # Arguments
+///
+/// * `changes` - The changes of files, in the order of their paths.
+/// * `unchanged` - Files that did not change, also used as sources of copies, like git's
+///   `--find-copies-harder`.
+/// * `options` - The minimum similarity and whether to detect copies.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the changes, where each paired added file is replaced by a
+/// rename or copy and the deleted files that were renamed are removed, or an `anyhow::Error` if
+/// a blob cannot be read.
+pub(crate) fn detect_renames(
+    changes: Vec<FileChange>,
+    unchanged: &[FileState],
+    options: &RenameOptions,
+) -> anyhow::Result<Vec<FileChange>> {
+    let mut detector = Detector::new(&changes, unchanged, options);
+    if detector.destinations.is_empty() || detector.sources.is_empty() {
+        return Ok(changes);
+    }
+    detector.find_exact_renames();
+    if options.minimum_score < MAX_SCORE {
+        if !options.copies {
+            detector.drop_used_sources();
+            let basename_score = options.minimum_score + (MAX_SCORE - options.minimum_score) / 2;
+            detector.find_basename_renames(basename_score)?;
+            detector.drop_used_sources();
+        }
+        detector.find_inexact_renames()?;
+    }
+
+    // Replace the added files by the renames and copies, and drop the renamed deleted files. The
+    // last destination of a deleted source is its rename, and the other ones are copies.
+    let renamed: HashSet<usize> = detector
+        .sources
+        .iter()
+        .filter(|source| !source.kept && source.uses > 0)
+        .map(|source| source.change)
+        .collect();
+    let paired: HashMap<usize, (usize, u32)> = detector
+        .destinations
+        .iter()
+        .filter_map(|destination| Some((destination.change, destination.source?)))
+        .collect();
+    let mut result = Vec::with_capacity(changes.len());
+    for (k, change) in changes.into_iter().enumerate() {
+        if renamed.contains(&k) {
+            continue;
+        }
+        let Some(&(s, score)) = paired.get(&k) else {
+            result.push(change);
+            continue;
+        };
+        let source = &mut detector.sources[s];
+        source.uses -= 1;
+        result.push(FileChange {
+            change: if source.uses > 0 || source.kept {
+                Change::Copied(score)
+            } else {
+                Change::Renamed(score)
+            },
+            old: Some(source.state.clone()),
+            new: change.new,
+        });
+    }
+    Ok(result)
+}
+
+/// Represents a file the renamed or copied files may come from.
+struct Source {
+    /// The index of the change of the source, if it changed.
+    change: usize,
+    /// The state of the file before the changes.
+    state: FileState,
+    /// Whether the file still exists after the changes, in which case it is only copied.
+    kept: bool,
+    /// The number of destinations paired with this source, plus one if it is kept, like git.
+    uses: usize,
+}
+
+/// Represents an added file, that may be a rename or copy.
+struct Destination {
+    /// The index of the change adding the file.
+    change: usize,
+    /// The state of the added file.
+    state: FileState,
+    /// The index of the source paired with this file, and their similarity.
+    source: Option<(usize, u32)>,
+}
+
+/// Represents a candidate pair of a source and a destination.
+#[derive(Clone, Copy)]
+struct Candidate {
+    /// The similarity score.
+    score: u32,
+    /// Whether the source and the destination have the same base name.
+    same_name: bool,
+    /// The index of the destination.
+    destination: usize,
+    /// The index of the source.
+    source: usize,
+}
+
+/// Represents the chunks of a file, to estimate its similarity with other files: the number of
+/// bytes in the chunks with each hash.
+struct Chunks {
+    size: u64,
+    counts: HashMap<u32, u64>,
+}
+
+/// Holds the sources and destinations of renames while they are paired.
+struct Detector<'a> {
+    sources: Vec<Source>,
+    /// The indexes of the sources that may still be paired.
+    active: Vec<usize>,
+    destinations: Vec<Destination>,
+    options: &'a RenameOptions,
+    /// The chunks of the blobs read so far.
+    chunks: HashMap<ObjectID, Chunks>,
+}
+
+impl<'a> Detector<'a> {
+    /// Collects the sources and destinations of renames, in the order of their paths. Modified
+    /// and unchanged files are sources only when detecting copies.
+    fn new(
+        changes: &[FileChange],
+        unchanged: &[FileState],
+        options: &'a RenameOptions,
+    ) -> Detector<'a> {
+        let mut sources = Vec::new();
+        let mut destinations = Vec::new();
+        let source = |change: usize, state: &FileState, kept: bool| Source {
+            change,
+            state: state.clone(),
+            kept,
+            uses: usize::from(kept),
+        };
+        for (k, change) in changes.iter().enumerate() {
+            match (&change.old, &change.new, change.change) {
+                (None, Some(new), _) => destinations.push(Destination {
+                    change: k,
+                    state: new.clone(),
+                    source: None,
+                }),
+                (Some(old), None, _) => sources.push(source(k, old, false)),
+                (Some(old), Some(_), Change::Modified) if options.copies => {
+                    sources.push(source(k, old, true))
+                }
+                _ => {}
+            }
+        }
+        if options.copies && !unchanged.is_empty() {
+            sources.extend(
+                unchanged
+                    .iter()
+                    .map(|state| source(usize::MAX, state, true)),
+            );
+            sources.sort_by(|a, b| compare_paths(&a.state.path, &b.state.path));
+        }
+        Detector {
+            active: (0..sources.len()).collect(),
+            sources,
+            destinations,
+            options,
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Records that a destination comes from a source.
+    fn pair(&mut self, destination: usize, source: usize, score: u32) {
+        self.sources[source].uses += 1;
+        self.destinations[destination].source = Some((source, score));
+    }
+
+    /// Stops pairing the sources that are already renamed.
+    fn drop_used_sources(&mut self) {
+        let sources = &self.sources;
+        self.active.retain(|&s| sources[s].uses == 0);
+    }
+
+    /// Pairs the destinations with sources that have the same object ID, like git's
+    /// `find_exact_renames`.
+    fn find_exact_renames(&mut self) {
+        let mut by_oid: HashMap<ObjectID, Vec<usize>> = HashMap::new();
+        for (s, source) in self.sources.iter().enumerate() {
+            by_oid.entry(source.state.oid.clone()).or_default().push(s);
+        }
+        for d in 0..self.destinations.len() {
+            let destination = &self.destinations[d].state;
+            let Some(candidates) = by_oid.get(&destination.oid) else {
+                continue;
+            };
+            let mut best = None;
+            for &s in candidates {
+                let source = &self.sources[s];
+                // Files that are not regular must have the same mode.
+                if (!is_regular(source.state.mode) || !is_regular(destination.mode))
+                    && source.state.mode != destination.mode
+                {
+                    continue;
+                }
+                if source.uses > 0 && !self.options.copies {
+                    continue;
+                }
+                // Prefer the sources that are not used yet, and then the ones with the same
+                // base name.
+                let score = usize::from(source.uses == 0)
+                    + usize::from(same_name(&source.state.path, &destination.path));
+                if best.is_none_or(|(_, best_score)| score > best_score) {
+                    best = Some((s, score));
+                    if score == 2 {
+                        break;
+                    }
+                }
+            }
+            if let Some((s, _)) = best {
+                self.pair(d, s, MAX_SCORE);
+            }
+        }
+    }
+
+    /// Pairs the remaining sources and destinations whose base name no other remaining source or
+    /// destination has, if they are similar enough, like git's `find_basename_matches`.
+    fn find_basename_renames(&mut self, minimum_score: u32) -> anyhow::Result<()> {
+        let mut sources: HashMap<Vec<u8>, Option<usize>> = HashMap::new();
+        for &s in &self.active {
+            sources
+                .entry(basename(&self.sources[s].state.path).to_vec())
+                .and_modify(|unique| *unique = None)
+                .or_insert(Some(s));
+        }
+        let mut destinations: HashMap<Vec<u8>, Option<usize>> = HashMap::new();
+        for (d, destination) in self.destinations.iter().enumerate() {
+            if destination.source.is_none() {
+                destinations
+                    .entry(basename(&destination.state.path).to_vec())
+                    .and_modify(|unique| *unique = None)
+                    .or_insert(Some(d));
+            }
+        }
+
+        let mut pairs: Vec<(usize, usize)> = sources
+            .iter()
+            .filter_map(|(name, &s)| Some((s?, (*destinations.get(name)?)?)))
+            .collect();
+        pairs.sort_unstable();
+        for (s, d) in pairs {
+            let source = self.sources[s].state.clone();
+            let destination = self.destinations[d].state.clone();
+            let score = self.similarity(&source, &destination, minimum_score)?;
+            if score >= minimum_score {
+                self.pair(d, s, score);
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimates the similarity of every remaining pair, keeps the most similar sources of each
+    /// destination, and pairs them from the most similar down, like git's `find_renames`.
+    fn find_inexact_renames(&mut self) -> anyhow::Result<()> {
+        let mut candidates = Vec::new();
+        for d in 0..self.destinations.len() {
+            if self.destinations[d].source.is_some() {
+                continue;
+            }
+            let destination = self.destinations[d].state.clone();
+            let mut best: Vec<Candidate> = Vec::with_capacity(CANDIDATES_PER_DESTINATION);
+            for k in 0..self.active.len() {
+                let s = self.active[k];
+                let source = self.sources[s].state.clone();
+                let candidate = Candidate {
+                    score: self.similarity(&source, &destination, self.options.minimum_score)?,
+                    same_name: same_name(&source.path, &destination.path),
+                    destination: d,
+                    source: s,
+                };
+                if best.len() < CANDIDATES_PER_DESTINATION {
+                    best.push(candidate);
+                    continue;
+                }
+                // Replace the first of the least similar candidates, if the new one is better.
+                let worst = (1..best.len()).fold(0, |worst, k| {
+                    if is_better(&best[worst], &best[k]) {
+                        k
+                    } else {
+                        worst
+                    }
+                });
+                if is_better(&candidate, &best[worst]) {
+                    best[worst] = candidate;
+                }
+            }
+            candidates.extend(best);
+        }
+        candidates
+            .sort_by_key(|candidate| std::cmp::Reverse((candidate.score, candidate.same_name)));
+
+        self.take_candidates(&candidates, false);
+        if self.options.copies {
+            self.take_candidates(&candidates, true);
+        }
+        Ok(())
+    }
+
+    /// Pairs the destinations with the candidates, from the most similar down to the minimum
+    /// score. Unless `copies`, a source is used only once.
+    fn take_candidates(&mut self, candidates: &[Candidate], copies: bool) {
+        for candidate in candidates {
+            if candidate.score < self.options.minimum_score {
+                break;
+            }
+            if self.destinations[candidate.destination].source.is_some() {
+                continue;
+            }
+            if !copies && self.sources[candidate.source].uses > 0 {
+                continue;
+            }
+            self.pair(candidate.destination, candidate.source, candidate.score);
+        }
+    }
+
+    /// Estimates the similarity of two files, like git's `estimate_similarity`: the number of
+    /// bytes of the destination found in the source, as a fraction of the larger file. Only
+    /// regular files are similar, and files whose sizes differ too much to reach
+    /// `minimum_score` are not compared.
+    fn similarity(
+        &mut self,
+        source: &FileState,
+        destination: &FileState,
+        minimum_score: u32,
+    ) -> anyhow::Result<u32> {
+        if !is_regular(source.mode) || !is_regular(destination.mode) {
+            return Ok(0);
+        }
+        let source_size = self.chunks(source)?.size;
+        let destination_size = self.chunks(destination)?.size;
+        let max_size = source_size.max(destination_size);
+        let delta_size = max_size - source_size.min(destination_size);
+        if max_size * u64::from(MAX_SCORE - minimum_score) < delta_size * u64::from(MAX_SCORE) {
+            return Ok(0);
+        }
+        if destination_size == 0 {
+            return Ok(0);
+        }
+
+        let source_chunks = &self.chunks[&source.oid];
+        let destination_chunks = &self.chunks[&destination.oid];
+        let copied: u64 = source_chunks
+            .counts
+            .iter()
+            .map(|(hash, &count)| count.min(*destination_chunks.counts.get(hash).unwrap_or(&0)))
+            .sum();
+        Ok((copied * u64::from(MAX_SCORE) / max_size) as u32)
+    }
+
+    /// Returns the chunks of a blob, reading it the first time.
+    fn chunks(&mut self, file: &FileState) -> anyhow::Result<&Chunks> {
+        if !self.chunks.contains_key(&file.oid) {
+            let version = FileVersion::from_blob(&file.oid, file.mode)?;
+            self.chunks
+                .insert(file.oid.clone(), hash_chunks(&version.data));
+        }
+        Ok(&self.chunks[&file.oid])
+    }
+}
+
+/// Returns true if the first candidate is better than the second one: more similar, or as
+/// similar and with the same base name.
+fn is_better(a: &Candidate, b: &Candidate) -> bool {
+    (a.score, a.same_name) > (b.score, b.same_name)
+}
+
+/// Splits data into chunks that end at a newline or after `MAX_CHUNK_LEN` bytes, and counts the
+/// bytes of the chunks by hash, like git's `hash_chars`. In text, the carriage return of a CRLF
+/// line ending is ignored.
+fn hash_chunks(data: &[u8]) -> Chunks {
+    let text = !is_binary(data);
+    let mut counts = HashMap::new();
+    let mut add = |accum1: u32, accum2: u32, len: usize| {
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE;
+        *counts.entry(hash).or_insert(0) += len as u64;
+    };
+    let (mut accum1, mut accum2, mut len) = (0u32, 0u32, 0);
+    for (k, &c) in data.iter().enumerate() {
+        if text && c == b'\r' && data.get(k + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old1 >> 25);
+        accum1 = accum1.wrapping_add(u32::from(c));
+        len += 1;
+        if len < MAX_CHUNK_LEN && c != b'\n' {
+            continue;
+        }
+        add(accum1, accum2, len);
+        (accum1, accum2, len) = (0, 0, 0);
+    }
+    if len > 0 {
+        add(accum1, accum2, len);
+    }
+    Chunks {
+        size: data.len() as u64,
+        counts,
+    }
+}
+
+/// Returns true if the mode is the one of a regular file, executable or not.
+fn is_regular(mode: EntryMode) -> bool {
+    matches!(mode, EntryMode::Executable | EntryMode::NonExecutable)
+}
+
+/// Returns the base name of a path, as bytes.
+fn basename(path: &Path) -> &[u8] {
+    path.file_name().map_or(&[], |name| name.as_encoded_bytes())
+}
+
+/// Returns true if two paths have the same base name.
+fn same_name(a: &Path, b: &Path) -> bool {
+    basename(a) == basename(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::tree;
+    use crate::testing::TestRepo;
+
+    /// The ten lines of `a.txt` in the first snapshot of the tests.
+    const LINES: &str =
+        "line 1\nline 2\nline 3\nline 4\nline 5\nline 6\nline 7\nline 8\nline 9\nline 10\n";
+
+    /// Detects the renames between two snapshots, and returns the changes in the
+    /// `--name-status` format.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The path and the contents of each file before the changes.
+    /// * `new` - The path and the contents of each file after the changes.
+    /// * `unchanged` - The paths of the unchanged files passed as sources of copies.
+    /// * `options` - The minimum similarity and whether to detect copies.
+    fn name_status(
+        old: &[(&str, &str)],
+        new: &[(&str, &str)],
+        unchanged: &[&str],
+        options: &RenameOptions,
+    ) -> Vec<String> {
+        let repo = TestRepo::new();
+        let unchanged: Vec<FileState> = old
+            .iter()
+            .filter(|(path, _)| unchanged.contains(path))
+            .map(|(path, data)| FileState {
+                path: path.into(),
+                mode: EntryMode::NonExecutable,
+                oid: repo.blob(data),
+            })
+            .collect();
+        let changes = tree::diff_trees(Some(&repo.tree(old)), Some(&repo.tree(new)), true).unwrap();
+        detect_renames(changes, &unchanged, options)
+            .unwrap()
+            .iter()
+            .map(|change| match &change.old {
+                Some(old) if old.path != change.path() => format!(
+                    "{} {} {}",
+                    change.change,
+                    old.path.display(),
+                    change.path().display()
+                ),
+                _ => format!("{} {}", change.change, change.path().display()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_similarities() {
+        assert_eq!(parse_score("").unwrap(), DEFAULT_RENAME_SCORE);
+        assert_eq!(parse_score("5").unwrap(), 30000);
+        assert_eq!(parse_score("0.5").unwrap(), 30000);
+        assert_eq!(parse_score("90%").unwrap(), 54000);
+        assert_eq!(parse_score("7.5%").unwrap(), 4500);
+        assert_eq!(parse_score("100%").unwrap(), MAX_SCORE);
+        assert!(parse_score("5x").is_err());
+        assert!(parse_score("50%%").is_err());
+    }
+
+    #[test]
+    fn finds_exact_renames() {
+        let changes = name_status(
+            &[("a.txt", LINES), ("dir/b.txt", "kept\nfile\n")],
+            &[("moved.txt", LINES), ("dir/b.txt", "kept\nfile\n")],
+            &[],
+            &RenameOptions::default(),
+        );
+        assert_eq!(changes, ["R100 a.txt moved.txt"]);
+    }
+
+    #[test]
+    fn finds_renames_as_similar_as_the_minimum_score() {
+        // Three of the ten lines change, which git scores 63%.
+        let moved = LINES
+            .replace("line 2\n", "line two\n")
+            .replace("line 5\n", "line five\n")
+            .replace("line 9\n", "line nine\n");
+        let old = [("a.txt", LINES)];
+        let new = [("moved.txt", moved.as_str())];
+        let options = |score| RenameOptions {
+            minimum_score: parse_score(score).unwrap(),
+            copies: false,
+        };
+        assert_eq!(
+            name_status(&old, &new, &[], &RenameOptions::default()),
+            ["R063 a.txt moved.txt"]
+        );
+        assert_eq!(
+            name_status(&old, &new, &[], &options("60%")),
+            ["R063 a.txt moved.txt"]
+        );
+        assert_eq!(
+            name_status(&old, &new, &[], &options("80%")),
+            ["D a.txt", "A moved.txt"]
+        );
+    }
+
+    #[test]
+    fn finds_copies_of_modified_files() {
+        let old = [("a.txt", LINES), ("b.txt", "kept\nfile\n")];
+        let changed = format!("{}line 11\n", LINES);
+        let new = [
+            ("a.txt", changed.as_str()),
+            ("b.txt", "kept\nfile\n"),
+            ("c.txt", LINES),
+            ("d.txt", "kept\nfile\n"),
+        ];
+        assert_eq!(
+            name_status(&old, &new, &[], &RenameOptions::default()),
+            ["M a.txt", "A c.txt", "A d.txt"]
+        );
+        let copies = RenameOptions {
+            copies: true,
+            ..RenameOptions::default()
+        };
+        assert_eq!(
+            name_status(&old, &new, &[], &copies),
+            ["M a.txt", "C100 a.txt c.txt", "A d.txt"]
+        );
+    }
+
+    #[test]
+    fn finds_copies_of_unchanged_files_when_asked() {
+        let old = [("a.txt", LINES), ("b.txt", "kept\nfile\n")];
+        let new = [
+            ("a.txt", LINES),
+            ("b.txt", "kept\nfile\n"),
+            ("d.txt", "kept\nfile\n"),
+        ];
+        let copies = RenameOptions {
+            copies: true,
+            ..RenameOptions::default()
+        };
+        assert_eq!(name_status(&old, &new, &[], &copies), ["A d.txt"]);
+
+        // Like `--find-copies-harder`, which passes the unchanged files.
+        assert_eq!(
+            name_status(&old, &new, &["a.txt", "b.txt"], &copies),
+            ["C100 b.txt d.txt"]
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::cmp::compare_base_name;
+use crate::diff::rename;
+use crate::objects::id::ObjectID;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::tree::Tree;
+use crate::status::compare_paths;
+use anyhow::Context;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// The files of a snapshot, with their modes and object IDs, keyed by path.
+pub(crate) type Files = BTreeMap<PathBuf, (EntryMode, ObjectID)>;
+
+/// An entry of a tree: its name, mode and object ID.
+type TreeItem = (OsString, EntryMode, ObjectID);
+
+/// Represents the way a file changed between two snapshots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Change {
+    Added,
+    Modified,
+    Deleted,
+    TypeChanged,
+    /// The file was moved, with the given similarity score between its two versions, see
+    /// `rename::MAX_SCORE`.
+    Renamed(u32),
+    /// The file was copied from another file, with the given similarity score.
+    Copied(u32),
+}
+
+impl Change {
+    /// Returns the letter used for the change in the short status format.
+    pub(crate) fn letter(&self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+            Change::Renamed(_) => 'R',
+            Change::Copied(_) => 'C',
+        }
+    }
+
+    /// Returns the description used for the change in the long status format.
+    pub(crate) fn description(&self) -> &'static str {
+        match self {
+            Change::Added => "new file",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+            Change::TypeChanged => "typechange",
+            Change::Renamed(_) => "renamed",
+            Change::Copied(_) => "copied",
+        }
+    }
+}
+
+impl std::fmt::Display for Change {
+    /// Formats the change the way `--name-status` and the raw format show it: its letter, followed
+    /// by the similarity in percent for renames and copies, e.g. `R087`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Renamed(score) | Change::Copied(score) => {
+                write!(f, "{}{:03}", self.letter(), rename::percent(*score))
+            }
+            _ => write!(f, "{}", self.letter()),
+        }
+    }
+}
+
+/// Represents one side of a changed file: its path, mode and object ID.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FileState {
+    pub(crate) path: PathBuf,
+    pub(crate) mode: EntryMode,
+    pub(crate) oid: ObjectID,
+}
+
+/// Represents a changed file. The old side is missing for added files and the new side for
+/// deleted files; renamed and copied files have different paths on both sides.
+#[derive(Clone, Debug)]
+pub(crate) struct FileChange {
+    pub(crate) change: Change,
+    pub(crate) old: Option<FileState>,
+    pub(crate) new: Option<FileState>,
+}
+
+impl FileChange {
+    /// Returns the path the change is listed under: the new path, or the old one for deletions.
+    pub(crate) fn path(&self) -> &Path {
+        match (&self.new, &self.old) {
+            (Some(new), _) => &new.path,
+            (None, Some(old)) => &old.path,
+            (None, None) => Path::new(""),
+        }
+    }
+
+    /// Creates the change between two versions of a file, or `None` if they are the same.
+    fn between(old: Option<FileState>, new: Option<FileState>) -> Option<FileChange> {
+        let change = match (&old, &new) {
+            (None, None) => return None,
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Deleted,
+            (Some(old), Some(new)) => compare(old.mode, &old.oid, Some(new.mode), &new.oid)?,
+        };
+        Some(FileChange { change, old, new })
+    }
+}
+
+/// Compares two versions of a file and returns how the file changed, if it did.
+///
+/// # Arguments
+///
+/// * `old_mode` - The mode of the old version.
+/// * `old_oid` - The object ID of the old version.
+/// * `new_mode` - The mode of the new version, `None` if it is not a file git can track.
+/// * `new_oid` - The object ID of the new version.
+pub(crate) fn compare(
+    old_mode: EntryMode,
+    old_oid: &ObjectID,
+    new_mode: Option<EntryMode>,
+    new_oid: &ObjectID,
+) -> Option<Change> {
+    let is_link = |mode: Option<EntryMode>| mode == Some(EntryMode::Symlink);
+    if is_link(Some(old_mode)) != is_link(new_mode) {
+        Some(Change::TypeChanged)
+    } else if Some(old_mode) != new_mode || old_oid != new_oid {
+        Some(Change::Modified)
+    } else {
+        None
+    }
+}
+
+/// Computes the changes between two trees.
+///
+/// The entries of both trees are compared by mode and object ID, so subtrees with the same ID are
+/// skipped without being read. When `recursive`, changed subtrees are compared in turn and only
+/// files are reported; otherwise a changed subtree is reported as a modified entry. An entry that
+/// changes between a file and a directory is reported as deleted and added.
+///
+/// # Arguments
+///
+/// * `old` - The ID of the old tree, or `None` for an empty tree.
+/// * `new` - The ID of the new tree, or `None` for an empty tree.
+/// * `recursive` - If true, compare the subtrees instead of reporting them.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the changes in the order of the trees, or an `anyhow::Error` if
+/// a tree cannot be read.
+pub(crate) fn diff_trees(
+    old: Option<&ObjectID>,
+    new: Option<&ObjectID>,
+    recursive: bool,
+) -> anyhow::Result<Vec<FileChange>> {
+    let mut changes = Vec::new();
+    diff_trees_into(old, new, Path::new(""), recursive, &mut changes)?;
+    Ok(changes)
+}
+
+/// Adds the changes between two trees to `changes`, prefixing their paths with `prefix`.
+fn diff_trees_into(
+    old: Option<&ObjectID>,
+    new: Option<&ObjectID>,
+    prefix: &Path,
+    recursive: bool,
+    changes: &mut Vec<FileChange>,
+) -> anyhow::Result<()> {
+    let read = |oid: Option<&ObjectID>| -> anyhow::Result<Vec<TreeItem>> {
+        let Some(oid) = oid else {
+            return Ok(Vec::new());
+        };
+        let mut entries = Vec::new();
+        for entry in
+            Tree::from_hash(&oid.hash()).with_context(|| format!("Failed to read tree {}", oid))?
+        {
+            let entry_oid = entry
+                .oid()
+                .clone()
+                .context("Tree entry without object ID.")?;
+            entries.push((entry.name().to_os_string(), *entry.mode(), entry_oid));
+        }
+        Ok(entries)
+    };
+    let old_entries = read(old)?;
+    let new_entries = read(new)?;
+
+    // Both lists are in tree order, so they are merged like sorted lists.
+    let (mut i, mut j) = (0, 0);
+    while i < old_entries.len() || j < new_entries.len() {
+        let order = match (old_entries.get(i), new_entries.get(j)) {
+            (Some(old), Some(new)) => compare_base_name(&old.0, &old.1, &new.0, &new.1),
+            (Some(_), None) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        let (old_entry, new_entry) = match order {
+            Ordering::Less => (old_entries.get(i), None),
+            Ordering::Greater => (None, new_entries.get(j)),
+            Ordering::Equal => (old_entries.get(i), new_entries.get(j)),
+        };
+        if order != Ordering::Greater {
+            i += 1;
+        }
+        if order != Ordering::Less {
+            j += 1;
+        }
+
+        match (old_entry, new_entry) {
+            // A file replaced by a directory, or the other way around, is deleted and added. Like
+            // in git, the file comes first, as if the name of the directory ended with a slash.
+            (Some(old), Some(new))
+                if (old.1 == EntryMode::Directory) != (new.1 == EntryMode::Directory) =>
+            {
+                let (old, new) = (Some(old), Some(new));
+                if old_entry.is_some_and(|entry| entry.1 == EntryMode::Directory) {
+                    diff_entries(None, new, prefix, recursive, changes)?;
+                    diff_entries(old, None, prefix, recursive, changes)?;
+                } else {
+                    diff_entries(old, None, prefix, recursive, changes)?;
+                    diff_entries(None, new, prefix, recursive, changes)?;
+                }
+            }
+            _ => diff_entries(old_entry, new_entry, prefix, recursive, changes)?,
+        }
+    }
+    Ok(())
+}
+
+/// Adds the changes between two versions of an entry of a tree to `changes`: the entries
+/// themselves, or with `recursive` the files of the subtrees. Both versions are files or
+/// directories.
+fn diff_entries(
+    old: Option<&TreeItem>,
+    new: Option<&TreeItem>,
+    prefix: &Path,
+    recursive: bool,
+    changes: &mut Vec<FileChange>,
+) -> anyhow::Result<()> {
+    if old.map(|entry| (entry.1, &entry.2)) == new.map(|entry| (entry.1, &entry.2)) {
+        return Ok(());
+    }
+    let Some((name, mode, _)) = old.or(new) else {
+        return Ok(());
+    };
+    let path = prefix.join(name);
+    if recursive && *mode == EntryMode::Directory {
+        let (old, new) = (old.map(|entry| &entry.2), new.map(|entry| &entry.2));
+        return diff_trees_into(old, new, &path, recursive, changes);
+    }
+
+    let state = |entry: Option<&TreeItem>| {
+        entry.map(|entry| FileState {
+            path: path.clone(),
+            mode: entry.1,
+            oid: entry.2.clone(),
+        })
+    };
+    changes.extend(FileChange::between(state(old), state(new)));
+    Ok(())
+}
+
+/// Computes the changes between two snapshots given as lists of files, e.g. the files of a tree
+/// and the entries of the index.
+///
+/// # Returns
+///
+/// The changes, in the order of their paths.
+pub(crate) fn diff_files(old: &Files, new: &Files) -> Vec<FileChange> {
+    let state = |files: &Files, path: &PathBuf| {
+        files.get(path).map(|(mode, oid)| FileState {
+            path: path.clone(),
+            mode: *mode,
+            oid: oid.clone(),
+        })
+    };
+    let mut changes: Vec<FileChange> = old
+        .keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|path| FileChange::between(state(old, path), state(new, path)))
+        .collect();
+    changes.sort_by(|a, b| compare_paths(a.path(), b.path()));
+    changes
+}
@@ -0,0 +1,267 @@
+use crate::diff::{diff_lines, is_binary, split_lines, DiffOptions, Edit};
+use crate::objects;
+use crate::objects::blob::Blob;
+use crate::objects::id::ObjectID;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::Object;
+use crate::revision;
+use anyhow::Context;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Maximum length of the function name shown after a hunk header, like git.
+const MAX_FUNCNAME_LEN: usize = 80;
+
+/// Represents one side of the diff of a file: its object ID, its mode and its contents.
+pub(crate) struct FileVersion {
+    pub(crate) oid: ObjectID,
+    pub(crate) mode: EntryMode,
+    pub(crate) data: Vec<u8>,
+}
+
+impl FileVersion {
+    /// Reads a version of a file from a blob of the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `oid` - The ID of the blob.
+    /// * `mode` - The mode of the file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `FileVersion`, or an `anyhow::Error` if the blob cannot
+    /// be read.
+    pub(crate) fn from_blob(oid: &ObjectID, mode: EntryMode) -> anyhow::Result<FileVersion> {
+        let (_, _, mut reader) = objects::read_object(&oid.hash())?;
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read blob {}", oid))?;
+        Ok(FileVersion {
+            oid: oid.clone(),
+            mode,
+            data,
+        })
+    }
+
+    /// Reads a version of a file from the working tree. The contents of a symbolic link are the
+    /// path it points to, and the object ID is the one the contents would have as a blob.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `FileVersion`, or an `anyhow::Error` if the file cannot
+    /// be read.
+    pub(crate) fn from_worktree(path: &Path) -> anyhow::Result<FileVersion> {
+        let metadata = std::fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read metadata for {}", path.display()))?;
+        let mut data = Vec::new();
+        Blob::from_worktree(path)?
+            .content()
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let oid = Blob::new(data.len() as u64, data.as_slice())
+            .hash()
+            .with_context(|| format!("Failed to hash {}", path.display()))?;
+        Ok(FileVersion {
+            oid,
+            mode: EntryMode::from_metadata(&metadata),
+            data,
+        })
+    }
+}
+
+/// Writes the diff of a file in the unified format of git: a `diff --git` header, the extended
+/// headers describing the change of mode and of object ID, and the hunks of changed lines with
+/// their context. Binary files are only reported as differing.
+///
+/// A file that changes between a symbolic link and a regular file is written as a deletion
+/// followed by an addition. Nothing is written if both versions are the same.
+///
+/// # Arguments
+///
+/// * `out` - The writer to write the diff into.
+/// * `path` - The path of the file.
+/// * `old` - The old version of the file, or `None` if the file is added.
+/// * `new` - The new version of the file, or `None` if the file is deleted.
+/// * `options` - The number of context lines and the diff algorithm.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if writing fails or an object ID
+/// cannot be abbreviated.
+pub(crate) fn write_file_diff(
+    out: &mut impl Write,
+    path: &Path,
+    old: Option<&FileVersion>,
+    new: Option<&FileVersion>,
+    options: &DiffOptions,
+) -> anyhow::Result<()> {
+    if let (Some(old), Some(new)) = (old, new) {
+        let is_link = |version: &FileVersion| version.mode == EntryMode::Symlink;
+        if is_link(old) != is_link(new) {
+            write_file_diff(out, path, Some(old), None, options)?;
+            return write_file_diff(out, path, None, Some(new), options);
+        }
+        if old.oid == new.oid && old.mode == new.mode {
+            return Ok(());
+        }
+    }
+
+    let path = path.display();
+    let mut header = format!("diff --git a/{} b/{}\n", path, path);
+    let zero = ObjectID::zero();
+    let (old_oid, new_oid) = (
+        old.map_or(&zero, |old| &old.oid),
+        new.map_or(&zero, |new| &new.oid),
+    );
+    let mut index = format!(
+        "index {}..{}",
+        revision::abbreviate(old_oid, revision::DEFAULT_ABBREV)?,
+        revision::abbreviate(new_oid, revision::DEFAULT_ABBREV)?
+    );
+    match (old, new) {
+        (None, Some(new)) => header.push_str(&format!("new file mode {}\n", new.mode)),
+        (Some(old), None) => header.push_str(&format!("deleted file mode {}\n", old.mode)),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header.push_str(&format!("old mode {}\nnew mode {}\n", old.mode, new.mode));
+        }
+        (Some(old), Some(_)) => index.push_str(&format!(" {}", old.mode)),
+        (None, None) => return Ok(()),
+    }
+    if old_oid != new_oid {
+        header.push_str(&index);
+        header.push('\n');
+    }
+    out.write_all(header.as_bytes())?;
+    if old_oid == new_oid {
+        return Ok(());
+    }
+
+    let old_name = old.map_or("/dev/null".to_string(), |_| format!("a/{}", path));
+    let new_name = new.map_or("/dev/null".to_string(), |_| format!("b/{}", path));
+    let old_data = old.map_or(&[][..], |old| &old.data);
+    let new_data = new.map_or(&[][..], |new| &new.data);
+    if is_binary(old_data) || is_binary(new_data) {
+        writeln!(out, "Binary files {} and {} differ", old_name, new_name)?;
+        return Ok(());
+    }
+
+    let old_lines = split_lines(old_data);
+    let new_lines = split_lines(new_data);
+    let edits = diff_lines(&old_lines, &new_lines, options.algorithm);
+    if edits.iter().all(|edit| matches!(edit, Edit::Equal(..))) {
+        return Ok(());
+    }
+    write!(out, "--- {}\n+++ {}\n", old_name, new_name)?;
+    write_hunks(out, &old_lines, &new_lines, &edits, options.context)
+}
+
+/// Writes the hunks of an edit script: the changed lines, surrounded by up to `context` unchanged
+/// lines. Changes separated by at most twice that many unchanged lines share a hunk.
+///
+/// # Arguments
+///
+/// * `out` - The writer to write the hunks into.
+/// * `old` - The lines of the old version.
+/// * `new` - The lines of the new version.
+/// * `edits` - The edit script between the old and the new lines.
+/// * `context` - The number of unchanged lines shown around the changes.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()`, or an `anyhow::Error` if writing fails.
+pub(crate) fn write_hunks(
+    out: &mut impl Write,
+    old: &[&[u8]],
+    new: &[&[u8]],
+    edits: &[Edit],
+    context: usize,
+) -> anyhow::Result<()> {
+    // The number of old and new lines before each edit.
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    positions.push((0, 0));
+    for edit in edits {
+        match edit {
+            Edit::Equal(..) => (old_pos, new_pos) = (old_pos + 1, new_pos + 1),
+            Edit::Delete(_) => old_pos += 1,
+            Edit::Insert(_) => new_pos += 1,
+        }
+        positions.push((old_pos, new_pos));
+    }
+
+    let changes: Vec<usize> = (0..edits.len())
+        .filter(|&k| !matches!(edits[k], Edit::Equal(..)))
+        .collect();
+    let mut i = 0;
+    while i < changes.len() {
+        let first = changes[i];
+        while i + 1 < changes.len() && changes[i + 1] - changes[i] - 1 <= 2 * context {
+            i += 1;
+        }
+        let last = changes[i];
+        i += 1;
+
+        let start = first.saturating_sub(context);
+        let end = (last + 1 + context).min(edits.len());
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let mut header = format!(
+            "@@ -{} +{} @@",
+            format_range(old_start, old_end - old_start),
+            format_range(new_start, new_end - new_start)
+        )
+        .into_bytes();
+        if let Some(funcname) = find_funcname(old, old_start) {
+            header.push(b' ');
+            header.extend_from_slice(funcname);
+        }
+        header.push(b'\n');
+        out.write_all(&header)?;
+
+        for edit in &edits[start..end] {
+            let (prefix, line) = match *edit {
+                Edit::Equal(i, _) => (b' ', old[i]),
+                Edit::Delete(i) => (b'-', old[i]),
+                Edit::Insert(j) => (b'+', new[j]),
+            };
+            out.write_all(&[prefix])?;
+            out.write_all(line)?;
+            if !line.ends_with(b"\n") {
+                out.write_all(b"\n\\ No newline at end of file\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Formats the range of lines of a hunk header: the first line and the number of lines, which is
+/// omitted when it is one. An empty range starts at the line before it.
+fn format_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Finds the function name shown after a hunk header: like git's default, the closest line before
+/// the hunk that starts with a letter, `_` or `$`, truncated and without trailing whitespace.
+fn find_funcname<'a>(old: &[&'a [u8]], start: usize) -> Option<&'a [u8]> {
+    old[..start]
+        .iter()
+        .rev()
+        .find(|line| matches!(line.first(), Some(c) if c.is_ascii_alphabetic() || *c == b'_' || *c == b'$'))
+        .map(|line| {
+            let line = &line[..line.len().min(MAX_FUNCNAME_LEN)];
+            let len = line
+                .iter()
+                .rposition(|c| !c.is_ascii_whitespace())
+                .map_or(0, |i| i + 1);
+            &line[..len]
+        })
+}
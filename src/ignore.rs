@@ -0,0 +1,626 @@
+use crate::config;
+use anyhow::Context;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the per-directory ignore files.
+const IGNORE_FILENAME: &str = ".gitignore";
+/// Repository-wide ignore file that is not shared with other clones.
+const INFO_EXCLUDE: &str = ".git/info/exclude";
+/// Name of the repository directory, which is always ignored.
+const REPO_DIRNAME: &str = ".git";
+
+/// Represents a single pattern of an ignore file.
+#[derive(Debug)]
+pub(crate) struct Pattern {
+    /// The glob the path is matched against, without the `!` prefix and trailing `/`.
+    glob: String,
+    /// If true, a match re-includes a path that a previous pattern excluded.
+    negated: bool,
+    /// If true, the pattern only matches directories.
+    dir_only: bool,
+    /// If true, the pattern is matched against the path relative to `base`. Otherwise it is
+    /// matched against the file name only, at any depth.
+    anchored: bool,
+    /// The directory of the ignore file, relative to the root of the working tree.
+    base: PathBuf,
+    /// The file the pattern was read from, and its line number.
+    source: PathBuf,
+    line: usize,
+    /// The pattern as written in the file.
+    original: String,
+}
+
+impl Pattern {
+    /// Parses a line of an ignore file.
+    ///
+    /// Blank lines and lines starting with `#` hold no pattern. Trailing spaces are removed unless
+    /// escaped with a backslash, and a leading `\#` or `\!` stands for a literal `#` or `!`.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line of the ignore file.
+    /// * `base` - The directory of the ignore file, relative to the root of the working tree.
+    /// * `source` - The path of the ignore file.
+    /// * `line_number` - The line number, starting from 1.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `Pattern`, or `None` if the line holds no pattern.
+    fn parse(line: &str, base: &Path, source: &Path, line_number: usize) -> Option<Pattern> {
+        let mut pattern = line.trim_end_matches(['\n', '\r']);
+        while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return None;
+        }
+        let original = pattern.to_string();
+
+        let negated = pattern.starts_with('!');
+        // Drop the `!`, or the backslash escaping a leading `!` or `#`.
+        if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern = &pattern[1..];
+        }
+
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.is_empty() {
+            return None;
+        }
+        // A slash anywhere but at the end anchors the pattern to the directory of the file.
+        let anchored = pattern.contains('/');
+        let glob = pattern.trim_start_matches('/').to_string();
+
+        Some(Pattern {
+            glob,
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_path_buf(),
+            source: source.to_path_buf(),
+            line: line_number,
+            original,
+        })
+    }
+
+    /// Checks whether the pattern matches a path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to the root of the working tree.
+    /// * `is_dir` - Whether the path is a directory.
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        if self.anchored {
+            wildmatch(
+                self.glob.as_bytes(),
+                relative.as_os_str().as_encoded_bytes(),
+            )
+        } else {
+            match relative.file_name() {
+                Some(name) => wildmatch(self.glob.as_bytes(), name.as_encoded_bytes()),
+                None => false,
+            }
+        }
+    }
+
+    /// Returns true if a match re-includes the path instead of ignoring it.
+    pub(crate) fn is_negated(&self) -> bool {
+        self.negated
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    /// Formats the pattern the way `check-ignore -v` prints it: `<source>:<line>:<pattern>`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.source.display(),
+            self.line,
+            self.original
+        )
+    }
+}
+
+/// Represents the ignore rules of a working tree.
+///
+/// The rules come from, in increasing order of precedence: the file configured with
+/// `core.excludesFile`, `.git/info/exclude`, and the `.gitignore` files of the directories
+/// containing the path, deeper directories taking precedence. Within a file, the last matching
+/// pattern wins. The `.gitignore` files are read lazily, the first time a path in their
+/// directory is checked.
+pub(crate) struct IgnoreRules {
+    root: PathBuf,
+    global: Vec<Pattern>,
+    info: Vec<Pattern>,
+    per_dir: RefCell<HashMap<PathBuf, Vec<Pattern>>>,
+}
+
+impl IgnoreRules {
+    /// Loads the ignore rules of a working tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The root directory of the working tree.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `IgnoreRules`, or an `anyhow::Error` if an ignore file
+    /// exists but cannot be read.
+    pub(crate) fn load(root: &Path) -> anyhow::Result<IgnoreRules> {
+        let config = config::Config::load()?;
+        let global = match config.excludes_file() {
+            Some(path) => read_patterns(&path, Path::new(""), &path)?,
+            None => Vec::new(),
+        };
+        let info = read_patterns(
+            &root.join(INFO_EXCLUDE),
+            Path::new(""),
+            Path::new(INFO_EXCLUDE),
+        )?;
+
+        Ok(IgnoreRules {
+            root: root.to_path_buf(),
+            global,
+            info,
+            per_dir: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Checks whether a path is ignored.
+    ///
+    /// A path inside an ignored directory is ignored too, whatever the patterns say about it,
+    /// since git never looks inside ignored directories. The repository directory is always
+    /// ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to the root of the working tree.
+    /// * `is_dir` - Whether the path is a directory.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `true` if the path is ignored, or an `anyhow::Error` if an
+    /// ignore file cannot be read.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> anyhow::Result<bool> {
+        if is_repo_path(path) {
+            return Ok(true);
+        }
+        self.with_match(path, is_dir, |pattern| {
+            pattern.is_some_and(|pattern| !pattern.is_negated())
+        })
+    }
+
+    /// Finds the pattern that decides whether a path is ignored, and passes it to `f`.
+    ///
+    /// If a parent directory is ignored, the pattern that matched the directory is passed.
+    /// Otherwise, the pattern with the highest precedence that matches the path is passed, which
+    /// may be a negated pattern, or `None` if no pattern matches.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to the root of the working tree.
+    /// * `is_dir` - Whether the path is a directory.
+    /// * `f` - The function receiving the deciding pattern.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the value returned by `f`, or an `anyhow::Error` if an ignore
+    /// file cannot be read.
+    pub(crate) fn with_match<T>(
+        &self,
+        path: &Path,
+        is_dir: bool,
+        f: impl FnOnce(Option<&Pattern>) -> T,
+    ) -> anyhow::Result<T> {
+        let path = normalize(path);
+        if is_repo_path(&path) {
+            return Ok(f(None));
+        }
+
+        // Load the ignore files of every directory containing the path.
+        let dirs = path
+            .ancestors()
+            .skip(1)
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        for dir in &dirs {
+            if !self.per_dir.borrow().contains_key(dir) {
+                let source = dir.join(IGNORE_FILENAME);
+                let patterns = read_patterns(&self.root.join(&source), dir, &source)?;
+                self.per_dir.borrow_mut().insert(dir.clone(), patterns);
+            }
+        }
+        let per_dir = self.per_dir.borrow();
+
+        // Parent directories are checked from the top, as git stops descending at the first
+        // ignored directory.
+        let mut candidates = dirs[..dirs.len() - 1]
+            .iter()
+            .rev()
+            .map(|dir| (dir.as_path(), true))
+            .collect::<Vec<_>>();
+        candidates.push((path.as_path(), is_dir));
+
+        for (candidate, candidate_is_dir) in candidates {
+            let pattern = dirs
+                .iter()
+                .filter(|dir| candidate.starts_with(dir) && candidate != dir.as_path())
+                .filter_map(|dir| per_dir.get(dir))
+                .chain([&self.info, &self.global])
+                .find_map(|patterns| {
+                    patterns
+                        .iter()
+                        .rev()
+                        .find(|pattern| pattern.matches(candidate, candidate_is_dir))
+                });
+            let is_last = candidate == path.as_path();
+            match pattern {
+                Some(pattern) if !pattern.is_negated() => return Ok(f(Some(pattern))),
+                pattern if is_last => return Ok(f(pattern)),
+                _ => {}
+            }
+        }
+        Ok(f(None))
+    }
+}
+
+/// Reads the patterns of an ignore file.
+///
+/// # Arguments
+///
+/// * `path` - The path of the ignore file to read.
+/// * `base` - The directory the patterns are relative to.
+/// * `source` - The path of the ignore file, as reported by `check-ignore`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the patterns, which are empty if the file does not exist, or an
+/// `anyhow::Error` if the file cannot be read.
+fn read_patterns(path: &Path, base: &Path, source: &Path) -> anyhow::Result<Vec<Pattern>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read(path)
+        .with_context(|| format!("Failed to read ignore file: {}", path.display()))?;
+    let content = String::from_utf8_lossy(&content);
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, base, source, i + 1))
+        .collect())
+}
+
+/// Returns true if the path is inside the repository directory, which no pattern applies to.
+fn is_repo_path(path: &Path) -> bool {
+    path.components()
+        .any(|component| component.as_os_str() == REPO_DIRNAME)
+}
+
+/// Removes `.` components from a path, e.g. `./src/main.rs` becomes `src/main.rs`.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, std::path::Component::CurDir))
+        .collect()
+}
+
+/// Matches a path against a glob with the semantics of git's `wildmatch` in pathname mode.
+///
+/// `?` and `*` match any character except `/`, `[...]` matches a character class, and `\`
+/// escapes the next character. A `**` between slashes (or at the start or end of the glob)
+/// matches any number of directories: `**/a` matches `a` at any depth, `a/**` matches
+/// everything inside `a`, and `a/**/b` matches `a/b`, `a/x/b`, `a/x/y/b` and so on.
+///
+/// # Arguments
+///
+/// * `glob` - The glob.
+/// * `text` - The path to match.
+///
+/// # Returns
+///
+/// `true` if the whole path matches the glob.
+pub(crate) fn wildmatch(glob: &[u8], text: &[u8]) -> bool {
+    // A leading `**` is the only one not preceded by a slash that is special.
+    match glob.strip_prefix(b"**") {
+        Some([]) => true,
+        Some([b'/', rest @ ..]) => {
+            // `**/` matches zero or more leading directories.
+            wildmatch_at(rest, text, true)
+                || text
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &c)| c == b'/')
+                    .any(|(i, _)| wildmatch_at(rest, &text[i + 1..], true))
+        }
+        _ => wildmatch_at(glob, text, true),
+    }
+}
+
+/// Matches a path against a glob the way pathspecs are matched: unlike in ignore files, `*`, `?`
+/// and `[...]` match slashes too, so `*.rs` matches `src/main.rs`.
+///
+/// # Arguments
+///
+/// * `glob` - The glob.
+/// * `path` - The path to match, relative to the root of the working tree.
+///
+/// # Returns
+///
+/// `true` if the whole path matches the glob.
+pub(crate) fn fnmatch(glob: &str, path: &Path) -> bool {
+    wildmatch_at(glob.as_bytes(), path.as_os_str().as_encoded_bytes(), false)
+}
+
+/// Matches a path against a glob. In pathname mode, wildcards do not match slashes and `**` is
+/// special only after a slash.
+fn wildmatch_at(glob: &[u8], text: &[u8], pathname: bool) -> bool {
+    let Some((&first, rest)) = glob.split_first() else {
+        return text.is_empty();
+    };
+
+    match first {
+        b'*' => {
+            let rest = &glob[glob.iter().take_while(|&&c| c == b'*').count()..];
+            // A single `*` matches anything but a slash.
+            for i in 0..=text.len() {
+                if wildmatch_at(rest, &text[i..], pathname) {
+                    return true;
+                }
+                if pathname && i < text.len() && text[i] == b'/' {
+                    break;
+                }
+            }
+            false
+        }
+        b'?' => match text.split_first() {
+            Some((&c, text)) if !pathname || c != b'/' => wildmatch_at(rest, text, pathname),
+            _ => false,
+        },
+        b'[' => match text.split_first() {
+            Some((&c, text)) if !pathname || c != b'/' => match match_class(rest, c) {
+                Some((true, rest)) => wildmatch_at(rest, text, pathname),
+                Some((false, _)) => false,
+                // Like git, a glob with an unterminated class matches nothing.
+                None => false,
+            },
+            _ => false,
+        },
+        b'\\' if !rest.is_empty() => match text.split_first() {
+            Some((&c, text)) if c == rest[0] => wildmatch_at(&rest[1..], text, pathname),
+            _ => false,
+        },
+        b'/' if pathname && rest.starts_with(b"**") && (rest.len() == 2 || rest[2] == b'/') => {
+            // `/**` at the end matches everything inside, `/**/` matches zero or more directories.
+            let rest = &rest[2..];
+            match text.split_first() {
+                Some((b'/', text)) if rest.is_empty() => !text.is_empty(),
+                Some((b'/', _)) => {
+                    let rest = &rest[1..];
+                    text.iter()
+                        .enumerate()
+                        .filter(|(_, &c)| c == b'/')
+                        .any(|(i, _)| wildmatch_at(rest, &text[i + 1..], pathname))
+                }
+                _ => false,
+            }
+        }
+        c => match text.split_first() {
+            Some((&t, text)) if t == c => wildmatch_at(rest, text, pathname),
+            _ => false,
+        },
+    }
+}
+
+/// Matches a character against a character class, e.g. `[a-z]` or `[!0-9]`.
+///
+/// # Arguments
+///
+/// * `class` - The glob right after the opening `[`.
+/// * `c` - The character to match.
+///
+/// # Returns
+///
+/// Whether the character matches and the rest of the glob after the closing `]`, or `None` if
+/// the class is not terminated.
+fn match_class(class: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut i) = match class.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        let mut start = class[i];
+        if start == b']' && !first {
+            return Some((matched != negated, &class[i + 1..]));
+        }
+        first = false;
+        if start == b'\\' && i + 1 < class.len() {
+            i += 1;
+            start = class[i];
+        }
+        if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+            let mut end = class[i + 2];
+            i += 2;
+            if end == b'\\' && i + 1 < class.len() {
+                i += 1;
+                end = class[i];
+            }
+            matched |= start <= c && c <= end;
+        } else {
+            matched |= start == c;
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn matches_globs_in_pathname_mode() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("foo", "foo", true),
+            ("foo", "fo", false),
+            ("*", "foo", true),
+            ("*", "foo/bar", false),
+            ("*.c", "a/b.c", false),
+            ("f?o", "foo", true),
+            ("a?b", "a/b", false),
+            ("**", "foo/bar", true),
+            ("**/foo", "foo", true),
+            ("**/foo", "a/b/foo", true),
+            ("**/foo", "a/b/foox", false),
+            ("foo/**", "foo/a", true),
+            ("foo/**", "foo/a/b", true),
+            ("foo/**", "foo", false),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**/b", "ab", false),
+            // A `**` that is not between slashes is a plain `*`.
+            ("a**b", "axxb", true),
+            ("a**b", "a/b", false),
+            ("[a-c]x", "bx", true),
+            ("[a-c]x", "dx", false),
+            ("[!a-c]x", "dx", true),
+            ("[^a-c]x", "ax", false),
+            ("[]]", "]", true),
+            ("[a-]", "-", true),
+            ("[\\]]", "]", true),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("[ab", "[ab", false),
+            ("[ab", "a", false),
+        ];
+        for &(glob, text, expected) in cases {
+            let matched = wildmatch(glob.as_bytes(), text.as_bytes());
+            assert_eq!(matched, expected, "{} against {}", glob, text);
+        }
+    }
+
+    #[test]
+    fn matches_pathspecs_across_directories() {
+        assert!(fnmatch("*.rs", Path::new("src/main.rs")));
+        assert!(fnmatch("src/*", Path::new("src/a/b.rs")));
+        assert!(fnmatch("src/?/b.rs", Path::new("src/a/b.rs")));
+        assert!(!fnmatch("*.rs", Path::new("src/main.c")));
+    }
+
+    #[test]
+    fn parses_lines_of_ignore_files() {
+        let parse = |line: &str| Pattern::parse(line, Path::new(""), Path::new(".gitignore"), 1);
+        assert!(parse("").is_none());
+        assert!(parse("   ").is_none());
+        assert!(parse("# comment").is_none());
+        assert!(parse("/").is_none());
+
+        let pattern = parse("!build/  ").unwrap();
+        assert!(pattern.negated && pattern.dir_only && !pattern.anchored);
+        assert_eq!(pattern.glob, "build");
+        assert_eq!(pattern.to_string(), ".gitignore:1:!build/");
+
+        let pattern = parse("/doc/*.pdf").unwrap();
+        assert!(pattern.anchored && !pattern.negated && !pattern.dir_only);
+        assert_eq!(pattern.glob, "doc/*.pdf");
+
+        assert_eq!(parse("\\#hash").unwrap().glob, "#hash");
+        let pattern = parse("\\!bang").unwrap();
+        assert!(!pattern.negated);
+        assert_eq!(pattern.glob, "!bang");
+        assert_eq!(parse("trailing\\  ").unwrap().glob, "trailing\\ ");
+    }
+
+    #[test]
+    fn follows_the_precedence_of_ignore_files() {
+        let repo = TestRepo::new();
+        let global = repo.path().join("global-excludes");
+        std::fs::write(&global, "*.tmp\n*.log\n").unwrap();
+        std::fs::write(
+            ".git/config",
+            format!("[core]\n\texcludesFile = {}\n", global.display()),
+        )
+        .unwrap();
+        std::fs::create_dir_all(".git/info").unwrap();
+        std::fs::write(".git/info/exclude", "!keep.log\nbuild/\n").unwrap();
+        std::fs::write(
+            ".gitignore",
+            "# comment\n*.o\n!important.o\n/root-only\ndoc/**/*.pdf\nlogs/\n",
+        )
+        .unwrap();
+        std::fs::create_dir("sub").unwrap();
+        std::fs::write("sub/.gitignore", "!*.tmp\nlocal.o\nnested/deep\n").unwrap();
+
+        let rules = IgnoreRules::load(repo.path()).unwrap();
+        let global = global.display();
+        // The pattern that decides whether each path is ignored, as `check-ignore -v` prints it,
+        // checked against git.
+        let cases: &[(&str, bool, Option<String>)] = &[
+            ("a.tmp", false, Some(format!("{}:1:*.tmp", global))),
+            ("sub/a.tmp", false, Some("sub/.gitignore:1:!*.tmp".into())),
+            (
+                "keep.log",
+                false,
+                Some(".git/info/exclude:1:!keep.log".into()),
+            ),
+            ("sub/x.log", false, Some(format!("{}:2:*.log", global))),
+            ("build", true, Some(".git/info/exclude:2:build/".into())),
+            ("build", false, None),
+            (
+                "sub/build/y",
+                false,
+                Some(".git/info/exclude:2:build/".into()),
+            ),
+            ("x.o", false, Some(".gitignore:2:*.o".into())),
+            (
+                "sub/important.o",
+                false,
+                Some(".gitignore:3:!important.o".into()),
+            ),
+            (
+                "sub/local.o",
+                false,
+                Some("sub/.gitignore:2:local.o".into()),
+            ),
+            ("local.o", false, Some(".gitignore:2:*.o".into())),
+            ("root-only", false, Some(".gitignore:4:/root-only".into())),
+            ("sub/root-only", false, None),
+            ("doc/a.pdf", false, Some(".gitignore:5:doc/**/*.pdf".into())),
+            (
+                "doc/x/y/a.pdf",
+                false,
+                Some(".gitignore:5:doc/**/*.pdf".into()),
+            ),
+            ("other/doc/a.pdf", false, None),
+            ("sub/logs", true, Some(".gitignore:6:logs/".into())),
+            (
+                "sub/nested/deep",
+                false,
+                Some("sub/.gitignore:3:nested/deep".into()),
+            ),
+            ("./x.o", false, Some(".gitignore:2:*.o".into())),
+        ];
+        for (path, is_dir, expected) in cases {
+            let path = Path::new(path);
+            let decided = rules
+                .with_match(path, *is_dir, |pattern| pattern.map(Pattern::to_string))
+                .unwrap();
+            assert_eq!(&decided, expected, "{}", path.display());
+            let ignored = expected
+                .as_ref()
+                .is_some_and(|pattern| !pattern.contains(":!"));
+            assert_eq!(rules.is_ignored(path, *is_dir).unwrap(), ignored);
+        }
+        assert!(rules.is_ignored(Path::new(".git/config"), false).unwrap());
+    }
+}
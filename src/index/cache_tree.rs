@@ -0,0 +1,146 @@
+use crate::objects::id::ObjectID;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStringExt;
+use std::path::Path;
+
+/// The signature of the cache tree extension of the index.
+pub(crate) const SIGNATURE: &[u8; 4] = b"TREE";
+
+/// Represents the cache tree extension of the index, which records the tree objects of the
+/// directories of the index, so that unchanged directories do not need to be written again.
+///
+/// Each node is a directory, the root being the whole index. A node is valid if it holds the ID of
+/// the tree of its directory, along with the number of index entries under the directory. Changing
+/// an entry invalidates the directories that contain it, see `invalidate`, and valid nodes are
+/// filled in again when the trees are written.
+#[derive(Debug, Default)]
+pub(crate) struct CacheTree {
+    /// The number of index entries in the directory and the ID of its tree, unless invalidated.
+    pub(crate) tree: Option<(usize, ObjectID)>,
+    /// The nodes of the subdirectories, by name.
+    pub(crate) subtrees: BTreeMap<OsString, CacheTree>,
+}
+
+impl CacheTree {
+    /// Parses the data of the cache tree extension.
+    ///
+    /// Each node is stored as its NUL-terminated name, empty for the root, the number of entries
+    /// and the number of subtrees in ASCII, separated by a space and followed by a newline, then
+    /// the 20-byte tree ID if the node is valid, i.e. if the number of entries is not `-1`. The
+    /// nodes of the subtrees follow the node of their directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data of the extension.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the root node, or an `anyhow::Error` if the data is malformed.
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<CacheTree> {
+        let mut reader = std::io::Cursor::new(data);
+        let (_, root) = CacheTree::parse_node(&mut reader)?;
+        anyhow::ensure!(
+            reader.position() == data.len() as u64,
+            "Trailing data in cache tree extension."
+        );
+        Ok(root)
+    }
+
+    /// Parses a node and its subtrees, and returns its name along with it.
+    fn parse_node<R: BufRead>(reader: &mut R) -> anyhow::Result<(OsString, CacheTree)> {
+        let mut name = Vec::new();
+        reader.read_until(0, &mut name)?;
+        anyhow::ensure!(name.pop() == Some(0), "Truncated cache tree extension.");
+        let mut counts = Vec::new();
+        reader.read_until(b'\n', &mut counts)?;
+        anyhow::ensure!(
+            counts.pop() == Some(b'\n'),
+            "Truncated cache tree extension."
+        );
+        let counts = std::str::from_utf8(&counts).context("Invalid cache tree node.")?;
+        let (entry_count, subtree_count) =
+            counts.split_once(' ').context("Invalid cache tree node.")?;
+        let entry_count = entry_count
+            .parse::<i64>()
+            .context("Invalid entry count in cache tree node.")?;
+        let subtree_count = subtree_count
+            .parse::<usize>()
+            .context("Invalid subtree count in cache tree node.")?;
+
+        let tree = if entry_count >= 0 {
+            let mut oid_bytes = [0u8; 20];
+            reader
+                .read_exact(&mut oid_bytes)
+                .context("Truncated cache tree extension.")?;
+            Some((entry_count as usize, ObjectID::from_bytes(oid_bytes)))
+        } else {
+            None
+        };
+        let mut subtrees = BTreeMap::new();
+        for _ in 0..subtree_count {
+            let (name, subtree) = CacheTree::parse_node(reader)?;
+            subtrees.insert(name, subtree);
+        }
+        Ok((OsString::from_vec(name), CacheTree { tree, subtrees }))
+    }
+
+    /// Encodes the cache tree into the data of its extension, see `parse`. Subtrees are written
+    /// ordered by the length of their name first, and then by name, the way git orders them.
+    ///
+    /// # Returns
+    ///
+    /// Returns the data of the extension.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.write_node(OsStr::new(""), &mut data);
+        data
+    }
+
+    /// Encodes a node and its subtrees.
+    fn write_node(&self, name: &OsStr, data: &mut Vec<u8>) {
+        data.extend(name.as_encoded_bytes());
+        data.push(0);
+        let entry_count = match &self.tree {
+            Some((entry_count, _)) => *entry_count as i64,
+            None => -1,
+        };
+        data.extend(format!("{} {}\n", entry_count, self.subtrees.len()).as_bytes());
+        if let Some((_, oid)) = &self.tree {
+            data.extend(oid.to_bytes());
+        }
+
+        let mut subtrees = self.subtrees.iter().collect::<Vec<_>>();
+        subtrees.sort_by(|(name1, _), (name2, _)| {
+            name1
+                .len()
+                .cmp(&name2.len())
+                .then(name1.as_encoded_bytes().cmp(name2.as_encoded_bytes()))
+        });
+        for (name, subtree) in subtrees {
+            subtree.write_node(name, data);
+        }
+    }
+
+    /// Invalidates the directories that contain a path, from the root down to the path itself in
+    /// case it is a directory too.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the entry, or the directory, that changed.
+    pub(crate) fn invalidate(&mut self, path: &Path) {
+        self.tree = None;
+        let mut node = self;
+        for component in path.iter() {
+            match node.subtrees.get_mut(component) {
+                Some(subtree) => {
+                    subtree.tree = None;
+                    node = subtree;
+                }
+                None => break,
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::objects::id::ObjectID;
+use crate::objects::tree::mode::EntryMode;
 use anyhow::Context;
 use byteorder::{BigEndian, ReadBytesExt};
 use filetime::FileTime;
@@ -7,45 +8,76 @@ use std::io::prelude::*;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 
+/// The bits of the flags that hold the length of the path. Longer paths store `0x0fff`.
+const NAME_MASK: u16 = 0x0fff;
+/// The position of the two bits of the flags that hold the stage of the entry.
+const STAGE_SHIFT: u16 = 12;
+/// The bits of the flags that hold the stage of the entry, once shifted.
+const STAGE_MASK: u16 = 0x3;
+/// The flag telling that the file in the working tree is assumed to be unchanged.
+const ASSUME_VALID: u16 = 0x8000;
+/// The flag telling that a second 16-bit word of flags follows, from version 3 of the index.
+const EXTENDED: u16 = 0x4000;
+/// The extended flag telling that the file is not checked out in the working tree.
+const SKIP_WORKTREE: u16 = 0x4000;
+/// The extended flag telling that the file is only recorded, with `add --intent-to-add`.
+const INTENT_TO_ADD: u16 = 0x2000;
+/// The size of an entry before its path: ten 4-byte numbers, a 20-byte object ID and the flags.
+const HEADER_SIZE: usize = 62;
+
 /// Represents an entry in the index.
 #[allow(dead_code)]
 #[derive(Debug)]
 pub(crate) struct IndexEntry {
-    pub(crate) ctime: u32,    // Creation time
-    pub(crate) ctime_ns: u32, // Creation time nanoseconds
-    pub(crate) mtime: u32,    // Modification time
-    pub(crate) mtime_ns: u32, // Modification time nanoseconds
-    pub(crate) dev: u32,      // Device ID
-    pub(crate) ino: u32,      // Inode number
-    pub(crate) mode: u32,     // File mode
-    pub(crate) uid: u32,      // User ID
-    pub(crate) gid: u32,      // Group ID
-    pub(crate) size: u32,     // File size
-    pub(crate) flags: u16,    // Flags
-    pub(crate) oid: ObjectID, // Object ID
-    pub(crate) path: PathBuf, // Path
+    pub(crate) ctime: u32,          // Status change time
+    pub(crate) ctime_ns: u32,       // Status change time nanoseconds
+    pub(crate) mtime: u32,          // Modification time
+    pub(crate) mtime_ns: u32,       // Modification time nanoseconds
+    pub(crate) dev: u32,            // Device ID
+    pub(crate) ino: u32,            // Inode number
+    pub(crate) mode: u32,           // File mode
+    pub(crate) uid: u32,            // User ID
+    pub(crate) gid: u32,            // Group ID
+    pub(crate) size: u32,           // File size
+    pub(crate) assume_valid: bool,  // File assumed unchanged
+    pub(crate) stage: u16,          // Merge stage
+    pub(crate) skip_worktree: bool, // File not checked out
+    pub(crate) intent_to_add: bool, // File only recorded
+    pub(crate) oid: ObjectID,       // Object ID
+    pub(crate) path: PathBuf,       // Path
 }
 
 impl IndexEntry {
     /// Parses an index entry from a reader.
     ///
-    /// The reader is expected to be at the start of the index entry.
-    /// First, we read 64 bytes from the file. This is the smallest size that an entry can be;
-    /// ten 4-byte numbers, a 20-byte object ID, two bytes of flags and then a null-terminated string,
-    /// which must be at least one character and a null byte, all of which adds up to 64 bytes.
-    /// An entry must end with a null byte and its size must be a multiple of 8 bytes.
+    /// The reader is expected to be at the start of the index entry. An entry is made of ten
+    /// 4-byte numbers, a 20-byte object ID and two bytes of flags, followed in version 3 and later
+    /// by two bytes of extended flags if the extended flag is set. Then comes the path, which is
+    /// terminated by a null byte and padded with more null bytes so that the size of the entry is a
+    /// multiple of 8 bytes. All numbers are stored in big-endian format.
+    ///
+    /// The flags hold the length of the path, capped at `0x0fff`, so longer paths are read up to
+    /// their null byte.
     ///
-    /// Until the last byte of entry is \0, we read 8-byte blocks from the file and add them to entry.
-    /// All numbers are stored in big-endian format.
+    /// In version 4, paths are compressed against the path of the previous entry: the path is
+    /// stored as the number of bytes to remove from the end of the previous path, encoded as a
+    /// variable-length integer, followed by the null-terminated bytes to append to what remains.
+    /// Entries are not padded.
     ///
     /// # Arguments
     ///
     /// * `reader` - A mutable reference to a type that implements the `BufRead` trait.
+    /// * `version` - The version of the index.
+    /// * `previous_path` - The path of the previous entry, empty for the first entry.
     ///
     /// # Returns
     ///
     /// Returns a `Result` containing the parsed `IndexEntry` if successful, or an `anyhow::Error` if parsing fails.
-    pub(crate) fn parse<R: BufRead>(reader: &mut R) -> anyhow::Result<IndexEntry> {
+    pub(crate) fn parse<R: BufRead>(
+        reader: &mut R,
+        version: u32,
+        previous_path: &Path,
+    ) -> anyhow::Result<IndexEntry> {
         let ctime = reader.read_u32::<BigEndian>()?;
         let ctime_ns = reader.read_u32::<BigEndian>()?;
         let mtime = reader.read_u32::<BigEndian>()?;
@@ -62,16 +94,48 @@ impl IndexEntry {
             ObjectID::from_bytes(oid_bytes)
         };
         let flags = reader.read_u16::<BigEndian>()?;
+        let mut header_size = HEADER_SIZE;
+        let extended_flags = if flags & EXTENDED != 0 {
+            anyhow::ensure!(
+                version >= 3,
+                "Extended flags in an index entry of version {}.",
+                version
+            );
+            header_size += 2;
+            reader.read_u16::<BigEndian>()?
+        } else {
+            0
+        };
 
         let mut path_buffer: Vec<u8> = Vec::new();
-        let _ = reader
-            .read_to_end(&mut path_buffer)
-            .context("Failed to read path bytes to end")?;
-        path_buffer.retain(|&x| x != 0);
+        if version >= 4 {
+            let previous = previous_path.as_os_str().as_encoded_bytes();
+            let strip = read_varint(reader).context("Failed to read path prefix length")?;
+            anyhow::ensure!(
+                strip <= previous.len(),
+                "Path prefix length is longer than the previous path."
+            );
+            path_buffer.extend(&previous[..previous.len() - strip]);
+        }
+        reader
+            .read_until(0, &mut path_buffer)
+            .context("Failed to read path bytes")?;
         anyhow::ensure!(
-            path_buffer.len() as u16 == flags,
+            path_buffer.pop() == Some(0),
+            "Path of index entry is not terminated."
+        );
+        anyhow::ensure!(
+            path_buffer.len().min(NAME_MASK as usize) == (flags & NAME_MASK) as usize,
             "Path length does not match flags."
         );
+        if version < 4 {
+            // The null byte that terminates the path is the first byte of the padding.
+            let padding = 8 - (header_size + path_buffer.len()) % 8;
+            let mut buffer = [0u8; 8];
+            reader
+                .read_exact(&mut buffer[..padding - 1])
+                .context("Failed to read padding of entry")?;
+        }
 
         let path = PathBuf::from(String::from_utf8(path_buffer).context("Failed to parse path.")?);
 
@@ -86,47 +150,222 @@ impl IndexEntry {
             uid,
             gid,
             size,
-            flags,
+            assume_valid: flags & ASSUME_VALID != 0,
+            stage: (flags >> STAGE_SHIFT) & STAGE_MASK,
+            skip_worktree: extended_flags & SKIP_WORKTREE != 0,
+            intent_to_add: extended_flags & INTENT_TO_ADD != 0,
             oid,
             path,
         })
     }
 
+    /// Encodes the entry into the bytes stored in the index, see `parse`.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The version of the index.
+    /// * `previous_path` - The path of the previous entry, empty for the first entry.
+    ///
+    /// # Returns
+    ///
+    /// Returns the bytes of the entry.
+    pub(crate) fn to_bytes(&self, version: u32, previous_path: &Path) -> Vec<u8> {
+        let path = self.path.to_string_lossy();
+        let mut flags = (path.len().min(NAME_MASK as usize) as u16) | (self.stage << STAGE_SHIFT);
+        if self.assume_valid {
+            flags |= ASSUME_VALID;
+        }
+        if self.is_extended() {
+            flags |= EXTENDED;
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend(&self.ctime.to_be_bytes());
+        bytes.extend(&self.ctime_ns.to_be_bytes());
+        bytes.extend(&self.mtime.to_be_bytes());
+        bytes.extend(&self.mtime_ns.to_be_bytes());
+        bytes.extend(&self.dev.to_be_bytes());
+        bytes.extend(&self.ino.to_be_bytes());
+        bytes.extend(&self.mode.to_be_bytes());
+        bytes.extend(&self.uid.to_be_bytes());
+        bytes.extend(&self.gid.to_be_bytes());
+        bytes.extend(&self.size.to_be_bytes());
+        bytes.extend(self.oid.to_bytes());
+        bytes.extend(&flags.to_be_bytes());
+        if self.is_extended() {
+            let mut extended_flags = 0u16;
+            if self.skip_worktree {
+                extended_flags |= SKIP_WORKTREE;
+            }
+            if self.intent_to_add {
+                extended_flags |= INTENT_TO_ADD;
+            }
+            bytes.extend(&extended_flags.to_be_bytes());
+        }
+        if version >= 4 {
+            let previous = previous_path.as_os_str().as_encoded_bytes();
+            let common = previous
+                .iter()
+                .zip(path.as_bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            write_varint(&mut bytes, previous.len() - common);
+            bytes.extend(&path.as_bytes()[common..]);
+            bytes.push(0);
+        } else {
+            bytes.extend(path.as_bytes());
+            let padding = 8 - (bytes.len() % 8);
+            bytes.extend(vec![0; padding]);
+        }
+        bytes
+    }
+
+    /// Returns true if the entry has extended flags, which need version 3 of the index.
+    pub(crate) fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+
     /// Returns the path of the entry.
     pub(crate) fn path(&self) -> &Path {
         &self.path
     }
 
+    /// Returns the stage of the entry: 0 for a merged file, or 1, 2 and 3 for the versions of a
+    /// conflicted file in the merge base, in our side and in their side.
+    pub(crate) fn stage(&self) -> u16 {
+        self.stage
+    }
+
+    /// Checks whether the stat data of the entry matches the metadata of the file in the
+    /// working tree.
+    ///
+    /// The fields are compared after truncating the metadata to 32 bits, the same way they are
+    /// stored. Like git, the modification and status change times, the size, the inode number,
+    /// the owner and the mode are compared, but not the device, which can change across mounts.
+    /// If they all match, the file is assumed to be unchanged and does not need to be hashed
+    /// again, unless the entry is racily clean, see `Index::stat_matches`.
+    ///
+    /// # Arguments
+    ///
+    /// * `meta` - The metadata of the file, without following symbolic links.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the stat data matches.
+    pub(crate) fn stat_matches(&self, meta: &Metadata) -> bool {
+        let mtime = FileTime::from_last_modification_time(meta);
+        self.mtime == mtime.seconds() as u32
+            && self.mtime_ns == mtime.nanoseconds()
+            && self.ctime == meta.ctime() as u32
+            && self.ctime_ns == meta.ctime_nsec() as u32
+            && self.size == meta.size() as u32
+            && self.ino == meta.ino() as u32
+            && self.uid == meta.uid()
+            && self.gid == meta.gid()
+            && self.mode == EntryMode::from_metadata(meta).to_octal()
+    }
+
+    /// Returns true if the file of the entry is assumed to be unchanged without looking at the
+    /// working tree, because it is marked with `update-index --assume-unchanged` or
+    /// `--skip-worktree`.
+    pub(crate) fn is_assumed_unchanged(&self) -> bool {
+        self.assume_valid || self.skip_worktree
+    }
+
+    /// Replaces the stat data of the entry with the metadata of its file, once the file was found
+    /// to hold the content of the entry. The mode is normalised to one of the modes git stores:
+    /// `100644`, `100755` or `120000`.
+    ///
+    /// # Arguments
+    ///
+    /// * `meta` - The metadata of the file, without following symbolic links.
+    pub(crate) fn update_stat(&mut self, meta: &Metadata) {
+        let mtime = FileTime::from_last_modification_time(meta);
+        self.ctime = meta.ctime() as u32;
+        self.ctime_ns = meta.ctime_nsec() as u32;
+        self.mtime = mtime.seconds() as u32;
+        self.mtime_ns = mtime.nanoseconds();
+        self.dev = meta.dev() as u32;
+        self.ino = meta.ino() as u32;
+        self.mode = EntryMode::from_metadata(meta).to_octal();
+        self.uid = meta.uid();
+        self.gid = meta.gid();
+        self.size = meta.size() as u32;
+    }
+
     /// Creates a new instance of the `IndexEntry` struct.
     ///
     /// # Arguments
     ///
     /// * `path` - The path to the file.
     /// * `oid` - The object ID of the file.
-    /// * `meta` - The metadata of the file.
+    /// * `meta` - The metadata of the file, see `update_stat`.
     ///
     /// # Returns
     ///
     /// Returns a new instance of the `IndexEntry` struct.
     pub(crate) fn new(path: PathBuf, oid: ObjectID, meta: &Metadata) -> Self {
-        let ctime = FileTime::from_creation_time(meta).unwrap_or_else(|| FileTime::zero());
-        let mtime = FileTime::from_last_modification_time(meta);
-        let path_len = path.to_string_lossy().len();
+        let mut entry = IndexEntry::unmerged(path, EntryMode::NonExecutable, oid, 0);
+        entry.update_stat(meta);
+        entry
+    }
 
+    /// Creates an entry for one version of a conflicted file. The entry has no stat data, since
+    /// the file in the working tree holds none of the versions.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the file.
+    /// * `mode` - The mode of the version.
+    /// * `oid` - The object ID of the version.
+    /// * `stage` - The stage of the version, from 1 to 3, or 0 for a merged file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new instance of the `IndexEntry` struct.
+    pub(crate) fn unmerged(path: PathBuf, mode: EntryMode, oid: ObjectID, stage: u16) -> Self {
         IndexEntry {
             path,
             oid,
-            ctime: ctime.seconds() as u32,
-            ctime_ns: ctime.nanoseconds() as u32,
-            mtime: mtime.seconds() as u32,
-            mtime_ns: mtime.nanoseconds() as u32,
-            dev: meta.dev() as u32,
-            ino: meta.ino() as u32,
-            mode: meta.mode() as u32,
-            uid: meta.uid() as u32,
-            gid: meta.gid() as u32,
-            size: meta.size() as u32,
-            flags: path_len as u16,
+            ctime: 0,
+            ctime_ns: 0,
+            mtime: 0,
+            mtime_ns: 0,
+            dev: 0,
+            ino: 0,
+            mode: mode.to_octal(),
+            uid: 0,
+            gid: 0,
+            size: 0,
+            assume_valid: false,
+            stage,
+            skip_worktree: false,
+            intent_to_add: false,
         }
     }
 }
+
+/// Reads a variable-length integer, the way the prefix lengths of version 4 are encoded: seven
+/// bits per byte, most significant first, where every byte with its high bit set is followed by
+/// another one and implies an extra one.
+fn read_varint<R: BufRead>(reader: &mut R) -> anyhow::Result<usize> {
+    let mut byte = reader.read_u8()?;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = reader.read_u8()?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+    Ok(value)
+}
+
+/// Writes a variable-length integer, the inverse of `read_varint`.
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    let mut encoded = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        value -= 1;
+        encoded.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.extend(encoded.iter().rev());
+}
@@ -1,95 +1,146 @@
+pub(crate) mod cache_tree;
 pub(crate) mod entry;
+pub(crate) mod resolve_undo;
 
-use crate::cmp::compare_base_name;
+use crate::index::cache_tree::CacheTree;
 use crate::index::entry::IndexEntry;
+use crate::index::resolve_undo::ResolveUndo;
+use crate::lockfile::LockFile;
+use crate::objects::blob::Blob;
+use crate::objects::id::ObjectID;
 use crate::objects::tree::mode::EntryMode;
+use crate::objects::tree::Tree;
+use crate::objects::Object;
 use anyhow::Context;
+use filetime::FileTime;
+use sha1::Digest;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::Metadata;
 use std::io::prelude::*;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 const INDEX_VERSIONS: [u32; 3] = [2, 3, 4];
+/// The size of the header of the index: the signature, the version and the entry count.
+const HEADER_SIZE: usize = 12;
+/// The size of the SHA-1 checksum that ends the index.
+const CHECKSUM_SIZE: usize = 20;
+/// The optional extensions that are dropped instead of being kept as they are, because they
+/// describe the entries or the working tree, which bran changes without updating them: the end of
+/// index entries, the index entry offset table, the file system monitor and the untracked cache.
+const DROPPED_EXTENSIONS: [&[u8; 4]; 4] = [b"EOIE", b"IEOT", b"FSMN", b"UNTR"];
+
+/// Returns the key the entries of the index are ordered by: the bytes of the path, then the stage.
+/// This is the order git writes the entries in.
+fn sort_key(path: &Path, stage: u16) -> (&[u8], u16) {
+    (path.as_os_str().as_encoded_bytes(), stage)
+}
 
 /// Represents an index used for tracking changes in a Git repository.
-pub(crate) struct Index<R> {
+pub(crate) struct Index {
+    path: PathBuf,
     version: u32,
     entries: Vec<IndexEntry>,
-    reader: std::io::BufReader<R>,
+    /// The trees of the directories of the index, if they were ever written.
+    cache_tree: Option<CacheTree>,
+    /// The versions of the conflicted files that were resolved.
+    resolve_undo: ResolveUndo,
+    /// The optional extensions that bran does not understand, by signature, kept as they are.
+    extensions: Vec<([u8; 4], Vec<u8>)>,
+    /// The modification time of the index file when it was read, to detect racily clean entries.
+    timestamp: Option<FileTime>,
+    lock: Option<LockFile>,
 }
 
-impl Index<()> {
-    /// Creates a new instance of the `Index` struct.
+impl Index {
+    /// Reads the index file, to look at the index without changing it.
     ///
     /// # Arguments
     ///
-    /// * `version` - The version of the index.
+    /// * `path` - The path to the index file. A missing or empty file is read as an empty index.
     ///
     /// # Returns
     ///
-    /// Returns a `Result` containing the newly created `Index` instance, or an `anyhow::Error`
-    /// if an error occurs.
-    pub(crate) fn init(path: impl AsRef<std::path::Path>) -> anyhow::Result<Index<std::fs::File>> {
+    /// Returns a `Result` containing the `Index` instance, or an `anyhow::Error` if the index
+    /// cannot be read or is corrupt.
+    pub(crate) fn init(path: impl AsRef<Path>) -> anyhow::Result<Index> {
         let path = path.as_ref();
-        let index_exists = path.exists();
-
-        // Open index file with read/write and create permissions
-        let file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&path)
-            .with_context(|| format!("Failed to open index file: {}", path.display()))?;
-
-        // Acquire exclusive lock on the file
-        // file.lock_exclusive().with_context(|| {
-        //     format!(
-        //         "Failed to acquire exclusive lock for file: {}",
-        //         path.display()
-        //     )
-        // })?;
-
-        // If the index exists, parse it; otherwise, create one.
-        if index_exists {
-            // Parse the index file
-            Index::parse_index(file)
-        } else {
-            // Create a new index file
-            Ok(Index::new(2, Vec::new(), file))
+        let timestamp = std::fs::metadata(path)
+            .ok()
+            .map(|meta| FileTime::from_last_modification_time(&meta));
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read index file: {}", path.display()))
+            }
+        };
+
+        // A missing or empty index file means that nothing has been staged yet.
+        let mut index = Index {
+            path: path.to_path_buf(),
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+            resolve_undo: ResolveUndo::default(),
+            extensions: Vec::new(),
+            timestamp,
+            lock: None,
+        };
+        if !data.is_empty() {
+            index
+                .parse_index(&data)
+                .with_context(|| format!("index file corrupt: {}", path.display()))?;
         }
+        Ok(index)
     }
-}
 
-impl<R: Read> Index<R> {
-    /// Creates a new instance of the `Index` struct.
+    /// Locks the index file and reads it, to change the index. The lock is held through the
+    /// `<path>.lock` file until the index is written, see `write`, or dropped, so that no other
+    /// process changes the index in the meantime.
+    ///
+    /// # Arguments
     ///
-    ///  # Arguments
+    /// * `path` - The path to the index file.
     ///
-    ///  * `version` - The version of the index.
-    ///  * `entries` - A vector of `IndexEntry` instances.
-    ///  * `reader` - A type that implements the `Read` trait.
-    ///  
     /// # Returns
     ///
-    /// Returns a new instance of the `Index` struct.
-    pub(crate) fn new(version: u32, entries: Vec<IndexEntry>, reader: R) -> Index<R> {
-        Index {
-            version,
-            entries,
-            reader: std::io::BufReader::new(reader),
-        }
+    /// Returns a `Result` containing the locked `Index` instance, or an `anyhow::Error` if another
+    /// process holds the lock, or the index cannot be read or is corrupt.
+    pub(crate) fn lock(path: impl AsRef<Path>) -> anyhow::Result<Index> {
+        let lock = LockFile::acquire(&path)?;
+        let mut index = Index::init(path)?;
+        index.lock = Some(lock);
+        Ok(index)
     }
 
-    /// Parses an existing index file.
+    /// Parses the contents of an existing index file. The SHA-1 checksum that ends the file is
+    /// verified against the rest of the contents before anything else is parsed.
+    ///
+    /// The entries are followed by extensions, each made of a 4-byte signature, the 4-byte size of
+    /// its data and the data. An extension whose signature starts with an uppercase letter is
+    /// optional, and can be ignored by implementations that do not understand it.
     ///
     /// # Arguments
     ///
-    /// * `file` - The file to parse.
+    /// * `data` - The contents of the index file.
     ///
     /// # Returns
     ///
-    /// Returns a `Result` containing the parsed `Index` instance, or an `anyhow::Error`
-    /// if an error occurs.
-    fn parse_index(file: R) -> anyhow::Result<Index<R>> {
-        let mut reader = std::io::BufReader::new(file);
-        // let mut hasher = Sha1::new();
+    /// Returns a `Result` containing `()` if the index was parsed, or an `anyhow::Error` if an
+    /// error occurs or the index has a required extension that bran does not understand.
+    fn parse_index(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            data.len() >= HEADER_SIZE + CHECKSUM_SIZE,
+            "index file smaller than expected"
+        );
+        let (content, checksum) = data.split_at(data.len() - CHECKSUM_SIZE);
+        anyhow::ensure!(
+            sha1::Sha1::digest(content).as_slice() == checksum,
+            "bad index file sha1 signature"
+        );
+        let mut reader = std::io::Cursor::new(content);
 
         // Parse signature from header and check its validity. The signature should be "DIRC".
         let mut buffer = [0u8; 4];
@@ -101,7 +152,6 @@ impl<R: Read> Index<R> {
             signature == "DIRC",
             format!("Invalid index signature: {}", signature)
         );
-        // hasher.update(buffer);
 
         // Parse version from header and check its validity. The version should be 2, 3, or 4.
         let mut buffer = [0u8; 4];
@@ -114,7 +164,6 @@ impl<R: Read> Index<R> {
             "Invalid index version: {}",
             version
         );
-        // hasher.update(buffer);
 
         // Parse entry count from header.
         let mut buffer = [0u8; 4];
@@ -122,45 +171,53 @@ impl<R: Read> Index<R> {
             .read_exact(&mut buffer)
             .context("Failed to read index entry count")?;
         let entry_count = u32::from_be_bytes(buffer);
-        // hasher.update(buffer);
 
         // Parse entries.
-        // For each entry read the 64 first bytes, which is the minimum possible entry length.
-        // If the last byte is not nul, keep reading batches of 8 bytes until a nul byte is found.
-        let mut entries = Vec::new();
-        let mut entry: Vec<u8> = Vec::new();
+        let mut entries = Vec::<IndexEntry>::new();
         for _ in 0..entry_count {
-            entry.clear();
-            let mut buffer = [0u8; 64];
+            let previous_path = entries.last().map_or(Path::new(""), IndexEntry::path);
+            let entry = IndexEntry::parse(&mut reader, version, previous_path)?;
+            entries.push(entry);
+        }
+
+        // Parse extensions, until the checksum.
+        while (reader.position() as usize) < content.len() {
+            let mut signature = [0u8; 4];
+            reader
+                .read_exact(&mut signature)
+                .context("Failed to read extension signature")?;
+            let mut buffer = [0u8; 4];
             reader
                 .read_exact(&mut buffer)
-                .context("Failed to read first 64 bytes of entry")?;
-            entry.extend(&buffer);
-
-            if entry.last().unwrap() != &0 {
-                loop {
-                    let mut buffer = [0u8; 8];
-                    reader
-                        .read_exact(&mut buffer)
-                        .context("Failed to read for entry.")?;
-                    entry.extend(&buffer);
-                    if buffer[7] == 0 {
-                        break;
-                    }
+                .context("Failed to read extension size")?;
+            let start = reader.position() as usize;
+            let end = start + u32::from_be_bytes(buffer) as usize;
+            anyhow::ensure!(end <= content.len(), "Truncated index extension.");
+            let extension = &content[start..end];
+            reader.set_position(end as u64);
+
+            match &signature {
+                cache_tree::SIGNATURE => {
+                    self.cache_tree =
+                        Some(CacheTree::parse(extension).context("Failed to parse cache tree.")?);
                 }
+                resolve_undo::SIGNATURE => {
+                    self.resolve_undo = ResolveUndo::parse(extension)
+                        .context("Failed to parse resolve undo extension.")?;
+                }
+                signature if DROPPED_EXTENSIONS.contains(&signature) => {}
+                [b'A'..=b'Z', ..] => self.extensions.push((signature, extension.to_vec())),
+                _ => anyhow::bail!(
+                    "index uses {} extension, which we do not understand",
+                    String::from_utf8_lossy(&signature)
+                ),
             }
-            // hasher.update(&entry);
-            let mut cursor = std::io::Cursor::new(&entry);
-            entries.push(IndexEntry::parse(&mut cursor)?);
         }
 
-        // Add hexsum validation
-
-        Ok(Index {
-            version,
-            entries,
-            reader,
-        })
+        self.version = version;
+        self.entries = entries;
+        self.sort_entries();
+        Ok(())
     }
 
     /// Returns a reference to the entries in the index.
@@ -168,19 +225,77 @@ impl<R: Read> Index<R> {
         &self.entries
     }
 
-    /// Orders the entries in the index by path.
-    fn sort_entries(&mut self) -> anyhow::Result<()> {
-        self.entries.sort_unstable_by(|entry1, entry2| {
-            compare_base_name(
-                &entry1.path.as_os_str(),
-                &EntryMode::from_octal(entry1.mode).unwrap(),
-                &entry2.path.as_os_str(),
-                &EntryMode::from_octal(entry2.mode).unwrap(),
-            )
-        });
+    /// Sets the format version the index is written in. Version 2 and 3 only differ by the
+    /// extended flags of the entries, so the one that is written depends on whether an entry has
+    /// extended flags, see `write`. Version 4 compresses the paths of the entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The version, from 2 to 4.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()`, or an `anyhow::Error` if the version is not supported.
+    pub(crate) fn set_version(&mut self, version: u32) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            INDEX_VERSIONS.contains(&version),
+            "index-version {} not in range: 2..4",
+            version
+        );
+        self.version = version;
         Ok(())
     }
 
+    /// Orders the entries in the index by path, and the stages of a conflicted file by stage.
+    ///
+    /// The entries are kept in this order as they are added and removed, so that they can be
+    /// found by binary search. Sorting is only needed when the order may have been lost: after
+    /// reading an index written by another tool and after renaming entries.
+    fn sort_entries(&mut self) {
+        self.entries.sort_by(|entry1, entry2| {
+            sort_key(&entry1.path, entry1.stage).cmp(&sort_key(&entry2.path, entry2.stage))
+        });
+    }
+
+    /// Returns the position of the entry of a file at a given stage, or the position where it
+    /// would be inserted.
+    fn position(&self, path: &Path, stage: u16) -> usize {
+        let key = sort_key(path, stage);
+        self.entries
+            .partition_point(|entry| sort_key(&entry.path, entry.stage) < key)
+    }
+
+    /// Returns the range of the entries of a file, one for each of its stages.
+    fn path_range(&self, path: &Path) -> Range<usize> {
+        let start = self.position(path, 0);
+        let len = self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.path == path)
+            .count();
+        start..start + len
+    }
+
+    /// Returns the range of the entries of the files inside a directory, which are next to each
+    /// other since they all start with the path of the directory followed by a slash.
+    fn directory_range(&self, path: &Path) -> Range<usize> {
+        let mut prefix = path.as_os_str().as_encoded_bytes().to_vec();
+        prefix.push(b'/');
+        let start = self
+            .entries
+            .partition_point(|entry| entry.path.as_os_str().as_encoded_bytes() < prefix.as_slice());
+        let len = self.entries[start..]
+            .iter()
+            .take_while(|entry| {
+                entry
+                    .path
+                    .as_os_str()
+                    .as_encoded_bytes()
+                    .starts_with(&prefix)
+            })
+            .count();
+        start..start + len
+    }
+
     /// Adds an entry to the index. The index is updated in the following way. If an entry with the
     /// same name is already present in the index, it is replaced by the new entry. Naturally, there
     /// cannot be an file and a directory at the same time. Therefore, if we add a directory with
@@ -188,57 +303,759 @@ impl<R: Read> Index<R> {
     /// we add a file with the name of a directory that is already present in the index, the whole
     /// directory is removed.
     ///
+    /// Entries are replaced stage by stage: adding a merged entry, at stage 0, resolves a
+    /// conflicted file by replacing all of its stages, while adding a stage of a conflicted file
+    /// only replaces the same stage and the merged entry.
+    ///
     /// # Arguments
     ///
     /// * `entry` - The entry to be added to the index.
     pub(crate) fn add(&mut self, entry: IndexEntry) {
-        self.entries.push(entry);
+        let resolved = entry.stage == 0;
+        self.invalidate(&entry.path);
+
+        // The common case: the file is already in the index, at the same stage only.
+        let range = self.path_range(&entry.path);
+        if range.len() == 1 && self.entries[range.start].stage == entry.stage {
+            self.entries[range.start] = entry;
+            return;
+        }
+
+        // Files at the paths of the parent directories, and files inside a directory at the path.
+        for ancestor in entry.path.ancestors().skip(1) {
+            if ancestor.as_os_str().is_empty() {
+                break;
+            }
+            let range = self.path_range(ancestor);
+            self.remove_range(range, resolved);
+        }
+        let range = self.directory_range(&entry.path);
+        self.remove_range(range, resolved);
+
+        // The stages of the file that the entry replaces.
+        let range = self.path_range(&entry.path);
+        for i in range.rev() {
+            let stage = self.entries[i].stage;
+            if entry.stage == 0 || stage == 0 || stage == entry.stage {
+                self.remove_range(i..i + 1, resolved);
+            }
+        }
+
+        let position = self.position(&entry.path, entry.stage);
+        self.entries.insert(position, entry);
+    }
+
+    /// Records a file as conflicted: its entries are replaced by the entries of its stages.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the conflicted file.
+    /// * `stages` - The entries of the versions of the file, see `IndexEntry::unmerged`.
+    pub(crate) fn add_conflict(&mut self, path: &Path, stages: Vec<IndexEntry>) {
+        self.remove(path);
+        for entry in stages {
+            self.add(entry);
+        }
+    }
+
+    /// Returns true if some files are conflicted, with entries at stages other than 0.
+    pub(crate) fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.stage > 0)
+    }
+
+    /// Returns the conflicted files of the index, with the entries of their stages 1 to 3. A
+    /// stage is missing if the file did not exist in the corresponding version.
+    ///
+    /// # Returns
+    ///
+    /// Returns a map from the path of each conflicted file to the entries of its stages.
+    pub(crate) fn conflicts(&self) -> BTreeMap<&Path, [Option<&IndexEntry>; 3]> {
+        let mut conflicts = BTreeMap::<&Path, [Option<&IndexEntry>; 3]>::new();
+        for entry in self.entries.iter().filter(|entry| entry.stage > 0) {
+            conflicts.entry(entry.path()).or_default()[entry.stage as usize - 1] = Some(entry);
+        }
+        conflicts
+    }
+
+    /// Removes a file, or all the files of a directory, from the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file or directory to remove.
+    pub(crate) fn remove(&mut self, path: &Path) {
+        let range = self.path_range(path);
+        self.remove_range(range, true);
+        let range = self.directory_range(path);
+        self.remove_range(range, true);
+    }
+
+    /// Moves a file, or all the files of a directory, to another path in the index. The entries
+    /// keep their object IDs, modes and stat data, since renaming a file does not change it, and
+    /// replace the entries already at the destination.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The path of the file or directory to move.
+    /// * `destination` - The new path of the file or directory.
+    pub(crate) fn rename(&mut self, source: &Path, destination: &Path) {
+        self.remove(destination);
+        for i in 0..self.entries.len() {
+            let Ok(rest) = self.entries[i].path.strip_prefix(source) else {
+                continue;
+            };
+            let path = if rest.as_os_str().is_empty() {
+                destination.to_path_buf()
+            } else {
+                destination.join(rest)
+            };
+            let old_path = std::mem::replace(&mut self.entries[i].path, path.clone());
+            self.invalidate(&old_path);
+            self.invalidate(&path);
+        }
+        self.sort_entries();
+    }
+
+    /// Removes a range of entries, and invalidates the cached trees of their directories.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The positions of the entries to remove.
+    /// * `resolved` - If true, the conflicted files whose entries are removed are resolved, and
+    ///   their versions are remembered in the resolve undo extension.
+    fn remove_range(&mut self, range: Range<usize>, resolved: bool) {
+        let removed = self.entries.drain(range).collect::<Vec<_>>();
+        for entry in &removed {
+            if resolved && entry.stage > 0 {
+                self.resolve_undo.record(entry);
+            }
+            self.invalidate(&entry.path);
+        }
+    }
+
+    /// Invalidates the cached trees of the directories that contain a path.
+    fn invalidate(&mut self, path: &Path) {
+        if let Some(cache_tree) = self.cache_tree.as_mut() {
+            cache_tree.invalidate(path);
+        }
+    }
+
+    /// Forgets the versions of the resolved files, once the index is replaced by another tree.
+    pub(crate) fn clear_resolve_undo(&mut self) {
+        self.resolve_undo.paths.clear();
+    }
+
+    /// Writes the trees of the index to the database, and returns the ID of the root tree.
+    ///
+    /// The trees of the directories that did not change since they were last written are reused
+    /// from the cache tree instead of being written again, and the cache tree is updated with the
+    /// trees that are written. The index must be written afterwards for the cache tree to be kept.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the ID of the root tree, or an `anyhow::Error` if an entry
+    /// has an invalid mode or a tree cannot be written.
+    pub(crate) fn write_tree(&mut self) -> anyhow::Result<ObjectID> {
+        let cache_tree = self.cache_tree.get_or_insert_with(CacheTree::default);
+        Tree::write_from_index(&self.entries, cache_tree)
+    }
+
+    /// Returns the entry of a file, if the file is in the index. For a conflicted file, this is
+    /// the entry of its lowest stage.
+    pub(crate) fn entry(&self, path: &Path) -> Option<&IndexEntry> {
+        self.entries
+            .get(self.path_range(path))
+            .and_then(<[_]>::first)
+    }
+
+    /// Returns the entry of a file at a given stage, 0 for a merged file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    /// * `stage` - The stage of the entry, from 0 to 3.
+    pub(crate) fn entry_at(&self, path: &Path, stage: u16) -> Option<&IndexEntry> {
+        self.entries
+            .get(self.position(path, stage))
+            .filter(|entry| entry.path == path && entry.stage == stage)
+    }
+
+    /// Returns true if an entry is racily clean: its file was modified no earlier than the index
+    /// file was written, so it may have changed again after it was staged, within the precision
+    /// of the timestamps, without any change to its stat data.
+    fn is_racy(&self, entry: &IndexEntry) -> bool {
+        self.timestamp.is_some_and(|timestamp| {
+            let index_time = (timestamp.seconds() as u32, timestamp.nanoseconds());
+            (entry.mtime, entry.mtime_ns) >= index_time
+        })
+    }
+
+    /// Checks whether the file of an entry is known to be unchanged from its stat data alone, see
+    /// `IndexEntry::stat_matches`. Racily clean entries never match, so that their files are
+    /// hashed to find out whether they changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The entry of the file.
+    /// * `meta` - The metadata of the file, without following symbolic links.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the file can be assumed to hold the content of the entry.
+    pub(crate) fn stat_matches(&self, entry: &IndexEntry, meta: &Metadata) -> bool {
+        entry.stat_matches(meta) && !self.is_racy(entry)
+    }
+
+    /// Returns the root of the working tree, which holds the directory of the index file.
+    fn worktree(&self) -> &Path {
+        self.path
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""))
+    }
+
+    /// Checks whether the file of an entry holds the content and the mode of the entry.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `true` if the file is unchanged, or an `anyhow::Error` if
+    /// the file cannot be hashed.
+    fn content_matches(&self, entry: &IndexEntry, meta: &Metadata) -> anyhow::Result<bool> {
+        let path = self.worktree().join(entry.path());
+        let oid = Blob::from_worktree(&path)?
+            .hash()
+            .with_context(|| format!("Failed to hash {}", path.display()))?;
+        Ok(oid == entry.oid
+            && EntryMode::from_index_mode(entry.mode) == Some(EntryMode::from_metadata(meta)))
+    }
+
+    /// Re-reads the stat data of the files in the index, like `git update-index --refresh`.
+    ///
+    /// The stat data of an entry whose file changed on disk but still holds the same content,
+    /// e.g. because it was touched or copied back, is updated, so that the file is not hashed
+    /// again. The content of the entries is never changed. Files marked as unchanged are skipped.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a message for each file that needs to be updated or merged,
+    /// or an `anyhow::Error` if a file cannot be hashed.
+    pub(crate) fn refresh(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut messages = Vec::new();
+        let mut unmerged = BTreeSet::new();
+        for i in 0..self.entries.len() {
+            let entry = &self.entries[i];
+            if entry.stage > 0 {
+                if unmerged.insert(entry.path.clone()) {
+                    messages.push(format!("{}: needs merge", entry.path.display()));
+                }
+                continue;
+            }
+            if entry.is_assumed_unchanged() {
+                continue;
+            }
+            let meta = match std::fs::symlink_metadata(self.worktree().join(entry.path())) {
+                Ok(meta) if !meta.is_dir() => meta,
+                _ => {
+                    messages.push(format!("{}: needs update", entry.path.display()));
+                    continue;
+                }
+            };
+            if self.stat_matches(entry, &meta) {
+                continue;
+            }
+            if self.content_matches(entry, &meta)? {
+                self.entries[i].update_stat(&meta);
+            } else {
+                messages.push(format!("{}: needs update", entry.path.display()));
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Marks a file as unchanged, or clears the mark, like `git update-index --assume-unchanged`
+    /// and `--no-assume-unchanged`. The working tree is then no longer looked at for the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    /// * `value` - Whether the file is assumed to be unchanged.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()`, or an `anyhow::Error` if the file is not in the index.
+    pub(crate) fn set_assume_unchanged(&mut self, path: &Path, value: bool) -> anyhow::Result<()> {
+        self.entry_mut(path)?.assume_valid = value;
+        Ok(())
+    }
+
+    /// Marks a file as not checked out, or clears the mark, like `git update-index
+    /// --skip-worktree` and `--no-skip-worktree`. The working tree is then no longer looked at for
+    /// the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    /// * `value` - Whether the file is skipped in the working tree.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()`, or an `anyhow::Error` if the file is not in the index.
+    pub(crate) fn set_skip_worktree(&mut self, path: &Path, value: bool) -> anyhow::Result<()> {
+        self.entry_mut(path)?.skip_worktree = value;
+        Ok(())
+    }
+
+    /// Returns the merged entry of a file, to change its flags.
+    fn entry_mut(&mut self, path: &Path) -> anyhow::Result<&mut IndexEntry> {
+        let position = self.position(path, 0);
+        self.entries
+            .get_mut(position)
+            .filter(|entry| entry.path == path && entry.stage == 0)
+            .with_context(|| format!("Unable to mark file {}", path.display()))
     }
 
-    /// Writes the index to the file.
+    /// Smudges the racily clean entries whose files changed since they were staged, by setting
+    /// their size to 0, so that their stat data no longer matches once the index is written with
+    /// a newer timestamp and they stop looking racily clean.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()`, or an `anyhow::Error` if a file cannot be hashed.
+    fn smudge_racily_clean_entries(&mut self) -> anyhow::Result<()> {
+        for i in 0..self.entries.len() {
+            let entry = &self.entries[i];
+            if entry.stage > 0 || entry.is_assumed_unchanged() || !self.is_racy(entry) {
+                continue;
+            }
+            let Ok(meta) = std::fs::symlink_metadata(self.worktree().join(entry.path())) else {
+                continue;
+            };
+            if meta.is_dir() || !entry.stat_matches(&meta) {
+                continue;
+            }
+            if !self.content_matches(entry, &meta)? {
+                self.entries[i].size = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the index to its file, ending it with the SHA-1 checksum of its contents.
+    ///
+    /// The index is written into the lock file, which then atomically replaces the index file, so
+    /// that readers never see a partially written index. If the index was not locked when it was
+    /// read, see `lock`, the lock is acquired now.
     ///
     /// # Returns
     ///
     /// Returns a `Result` containint `()` if the index was written successfully, or an
-    /// `anyhow::Error` if an error occurs.
-    pub(crate) fn write(mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
-        let mut file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path.as_ref())?;
+    /// `anyhow::Error` if another process holds the lock or an error occurs.
+    pub(crate) fn write(mut self) -> anyhow::Result<()> {
+        let mut lock = match self.lock.take() {
+            Some(lock) => lock,
+            None => LockFile::acquire(&self.path)?,
+        };
 
+        self.smudge_racily_clean_entries()?;
+        // Entries with extended flags need version 3, which is only used when they do.
+        if self.version == 2 || self.version == 3 {
+            let extended = self.entries.iter().any(IndexEntry::is_extended);
+            self.version = if extended { 3 } else { 2 };
+        }
+
+        let mut data = Vec::new();
         // Write signature
-        file.write_all(b"DIRC")?;
+        data.extend(b"DIRC");
         // Write index version
-        file.write_all(&self.version.to_be_bytes())?;
+        data.extend(&self.version.to_be_bytes());
         // Write entry count
-        file.write_all(&(self.entries.len() as u32).to_be_bytes())?;
-
+        data.extend(&(self.entries.len() as u32).to_be_bytes());
         // Write entries
-        self.sort_entries()?;
-        let mut entry_buffer = Vec::new();
+        let mut previous_path = Path::new("");
         for entry in &self.entries {
-            entry_buffer.clear();
-            entry_buffer.extend(&entry.ctime.to_be_bytes());
-            entry_buffer.extend(&entry.ctime_ns.to_be_bytes());
-            entry_buffer.extend(&entry.mtime.to_be_bytes());
-            entry_buffer.extend(&entry.mtime_ns.to_be_bytes());
-            entry_buffer.extend(&entry.dev.to_be_bytes());
-            entry_buffer.extend(&entry.ino.to_be_bytes());
-            entry_buffer.extend(&entry.mode.to_be_bytes());
-            entry_buffer.extend(&entry.uid.to_be_bytes());
-            entry_buffer.extend(&entry.gid.to_be_bytes());
-            entry_buffer.extend(&entry.size.to_be_bytes());
-            entry_buffer.extend(entry.oid.to_bytes());
-            entry_buffer.extend(&entry.flags.to_be_bytes());
-            entry_buffer.extend(entry.path.to_string_lossy().as_bytes());
-            let padding = 8 - (entry_buffer.len() % 8);
-            entry_buffer.extend(vec![0; padding]);
-            file.write_all(&entry_buffer)
-                .context("Failed to write entry in index")?;
+            data.extend(entry.to_bytes(self.version, previous_path));
+            previous_path = entry.path();
         }
+        // Write extensions
+        let mut extensions = Vec::new();
+        if let Some(cache_tree) = &self.cache_tree {
+            extensions.push((*cache_tree::SIGNATURE, cache_tree.to_bytes()));
+        }
+        if !self.resolve_undo.paths.is_empty() {
+            extensions.push((*resolve_undo::SIGNATURE, self.resolve_undo.to_bytes()));
+        }
+        extensions.append(&mut self.extensions);
+        for (signature, extension) in extensions {
+            data.extend(signature);
+            data.extend(&(extension.len() as u32).to_be_bytes());
+            data.extend(extension);
+        }
+        // Write checksum
+        let checksum = sha1::Sha1::digest(&data);
+        data.extend(checksum.as_slice());
 
-        Ok(())
+        lock.write_all(&data)
+            .with_context(|| format!("Failed to write index to: {}", self.path.display()))?;
+        lock.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    /// Returns a merged entry for a file with the given contents, whose blob is written.
+    fn file(repo: &TestRepo, path: &str, data: &str) -> IndexEntry {
+        IndexEntry::unmerged(
+            PathBuf::from(path),
+            EntryMode::NonExecutable,
+            repo.blob(data),
+            0,
+        )
+    }
+
+    /// Writes an index of a few files and returns the contents of its file.
+    fn write_files(repo: &TestRepo) -> Vec<u8> {
+        let mut index = Index::lock(".git/index").unwrap();
+        for path in ["a", "dir/b", "dir/sub/c"] {
+            index.add(file(repo, path, path));
+        }
+        index.write().unwrap();
+        std::fs::read(".git/index").unwrap()
+    }
+
+    #[test]
+    fn ends_the_file_with_its_checksum() {
+        let repo = TestRepo::new();
+        let data = write_files(&repo);
+        let (content, checksum) = data.split_at(data.len() - CHECKSUM_SIZE);
+        assert_eq!(sha1::Sha1::digest(content).as_slice(), checksum);
+        assert_eq!(&content[..4], b"DIRC");
+
+        let index = Index::init(".git/index").unwrap();
+        let paths = index
+            .entries()
+            .iter()
+            .map(IndexEntry::path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [Path::new("a"), Path::new("dir/b"), Path::new("dir/sub/c")]
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let repo = TestRepo::new();
+        let data = write_files(&repo);
+
+        let mut corrupt = data.clone();
+        corrupt[HEADER_SIZE + 1] ^= 1;
+        std::fs::write(".git/index", &corrupt).unwrap();
+        let err = format!("{:#}", Index::init(".git/index").err().unwrap());
+        assert!(err.starts_with("index file corrupt: .git/index"), "{}", err);
+        assert!(err.ends_with("bad index file sha1 signature"), "{}", err);
+
+        std::fs::write(".git/index", &data[..HEADER_SIZE]).unwrap();
+        let err = format!("{:#}", Index::init(".git/index").err().unwrap());
+        assert!(err.ends_with("index file smaller than expected"), "{}", err);
+
+        // A missing index is an empty one.
+        std::fs::remove_file(".git/index").unwrap();
+        assert!(Index::init(".git/index").unwrap().entries().is_empty());
+    }
+
+    #[test]
+    fn holds_the_lock_until_written() {
+        let repo = TestRepo::new();
+        write_files(&repo);
+
+        let mut index = Index::lock(".git/index").unwrap();
+        assert!(Path::new(".git/index.lock").exists());
+        let err = format!("{:#}", Index::lock(".git/index").err().unwrap());
+        assert!(err.starts_with("Unable to create '"), "{}", err);
+        // Readers still see the previous index until the new one is committed.
+        index.remove(Path::new("a"));
+        assert_eq!(Index::init(".git/index").unwrap().entries().len(), 3);
+        index.write().unwrap();
+        assert!(!Path::new(".git/index.lock").exists());
+        assert_eq!(Index::init(".git/index").unwrap().entries().len(), 2);
+
+        // Dropping a locked index releases the lock without writing.
+        let mut index = Index::lock(".git/index").unwrap();
+        index.remove(Path::new("dir/b"));
+        drop(index);
+        assert!(!Path::new(".git/index.lock").exists());
+        assert_eq!(Index::init(".git/index").unwrap().entries().len(), 2);
+    }
+
+    /// Returns the cached tree of a directory of the index, given by the names of its components.
+    fn cached_tree<'a>(index: &'a Index, components: &[&str]) -> Option<&'a (usize, ObjectID)> {
+        let mut node = index.cache_tree.as_ref()?;
+        for component in components {
+            node = node.subtrees.get(std::ffi::OsStr::new(component))?;
+        }
+        node.tree.as_ref()
+    }
+
+    #[test]
+    fn keeps_the_cache_tree() {
+        let repo = TestRepo::new();
+        write_files(&repo);
+        let mut index = Index::lock(".git/index").unwrap();
+        assert!(index.cache_tree.is_none());
+        let root = index.write_tree().unwrap();
+        let files = [("a", "a"), ("dir/b", "dir/b"), ("dir/sub/c", "dir/sub/c")];
+        assert_eq!(root, repo.tree(&files));
+        let cache_tree = index.cache_tree.as_ref().unwrap().to_bytes();
+        index.write().unwrap();
+
+        let mut index = Index::lock(".git/index").unwrap();
+        assert_eq!(index.cache_tree.as_ref().unwrap().to_bytes(), cache_tree);
+        assert_eq!(cached_tree(&index, &[]), Some(&(3, root)));
+        assert_eq!(cached_tree(&index, &["dir"]).unwrap().0, 2);
+        assert_eq!(
+            cached_tree(&index, &["dir", "sub"]),
+            Some(&(1, repo.tree(&[("c", "dir/sub/c")])))
+        );
+
+        // Changing a file invalidates the directories that contain it, and only them.
+        index.add(file(&repo, "dir/b", "changed"));
+        index.write().unwrap();
+        let mut index = Index::lock(".git/index").unwrap();
+        assert_eq!(cached_tree(&index, &[]), None);
+        assert_eq!(cached_tree(&index, &["dir"]), None);
+        assert!(cached_tree(&index, &["dir", "sub"]).is_some());
+        let root = index.write_tree().unwrap();
+        let files = [("a", "a"), ("dir/b", "changed"), ("dir/sub/c", "dir/sub/c")];
+        assert_eq!(root, repo.tree(&files));
+        assert_eq!(cached_tree(&index, &[]), Some(&(3, root)));
+    }
+
+    #[test]
+    fn keeps_the_versions_of_resolved_conflicts() {
+        let repo = TestRepo::new();
+        write_files(&repo);
+        let mut index = Index::lock(".git/index").unwrap();
+        let path = Path::new("dir/b");
+        let stages = (1..=3)
+            .map(|stage| {
+                let oid = repo.blob(&format!("stage {}", stage));
+                IndexEntry::unmerged(path.to_path_buf(), EntryMode::NonExecutable, oid, stage)
+            })
+            .collect::<Vec<_>>();
+        let versions = stages
+            .iter()
+            .map(|entry| Some((EntryMode::NonExecutable.to_octal(), entry.oid.clone())))
+            .collect::<Vec<_>>();
+        index.add_conflict(path, stages);
+        index.write().unwrap();
+
+        let mut index = Index::lock(".git/index").unwrap();
+        assert_eq!(index.conflicts().keys().collect::<Vec<_>>(), [&path]);
+        assert!(index.resolve_undo.paths.is_empty());
+        index.add(file(&repo, "dir/b", "resolved"));
+        index.write().unwrap();
+
+        let mut index = Index::lock(".git/index").unwrap();
+        assert!(!index.has_conflicts());
+        assert_eq!(index.resolve_undo.paths.len(), 1);
+        assert_eq!(index.resolve_undo.paths[path].as_slice(), versions);
+        index.clear_resolve_undo();
+        index.write().unwrap();
+        assert!(Index::init(".git/index")
+            .unwrap()
+            .resolve_undo
+            .paths
+            .is_empty());
+    }
+
+    /// Appends an extension to an index file, and updates its checksum.
+    fn append_extension(signature: &[u8; 4], data: &[u8]) {
+        let mut content = std::fs::read(".git/index").unwrap();
+        content.truncate(content.len() - CHECKSUM_SIZE);
+        content.extend(signature);
+        content.extend(&(data.len() as u32).to_be_bytes());
+        content.extend(data);
+        let checksum = sha1::Sha1::digest(&content);
+        content.extend(checksum.as_slice());
+        std::fs::write(".git/index", content).unwrap();
+    }
+
+    #[test]
+    fn keeps_optional_extensions_and_rejects_required_ones() {
+        let repo = TestRepo::new();
+        write_files(&repo);
+        append_extension(b"ABCD", b"kept");
+        append_extension(b"UNTR", b"dropped");
+        let mut index = Index::lock(".git/index").unwrap();
+        assert_eq!(index.extensions, [(*b"ABCD", b"kept".to_vec())]);
+        index.add(file(&repo, "a", "changed"));
+        index.write().unwrap();
+        assert_eq!(
+            Index::init(".git/index").unwrap().extensions,
+            [(*b"ABCD", b"kept".to_vec())]
+        );
+
+        append_extension(b"link", b"");
+        let err = format!("{:#}", Index::init(".git/index").err().unwrap());
+        assert!(
+            err.ends_with("index uses link extension, which we do not understand"),
+            "{}",
+            err
+        );
+    }
+
+    /// The fields of an index entry that are compared between versions.
+    type Fields = (PathBuf, u16, u32, ObjectID, u32, u32, bool, bool, bool);
+
+    /// Returns the fields of the entries of an index.
+    fn fields(index: &Index) -> Vec<Fields> {
+        index
+            .entries()
+            .iter()
+            .map(|entry| {
+                (
+                    entry.path.clone(),
+                    entry.stage,
+                    entry.mode,
+                    entry.oid.clone(),
+                    entry.mtime,
+                    entry.size,
+                    entry.assume_valid,
+                    entry.skip_worktree,
+                    entry.intent_to_add,
+                )
+            })
+            .collect()
+    }
+
+    /// Writes an index in a version, and returns the version found in the header of its file and
+    /// the contents of the file.
+    fn write_version(mut index: Index, version: u32) -> (u32, Vec<u8>) {
+        index.set_version(version).unwrap();
+        index.write().unwrap();
+        let data = std::fs::read(".git/index").unwrap();
+        let version = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        (version, data)
+    }
+
+    #[test]
+    fn round_trips_every_version() {
+        let repo = TestRepo::new();
+        let mut index = Index::lock(".git/index").unwrap();
+        // Paths that share long prefixes, for version 4 to compress.
+        for path in [
+            "src/commands/add.rs",
+            "src/commands/commit.rs",
+            "src/commands/config.rs",
+            "src/index/mod.rs",
+            "src/main.rs",
+        ] {
+            let mut entry = file(&repo, path, path);
+            entry.mtime = 1700000000;
+            entry.size = path.len() as u32;
+            index.add(entry);
+        }
+        index.add_conflict(
+            Path::new("README"),
+            vec![
+                IndexEntry::unmerged("README".into(), EntryMode::NonExecutable, repo.blob("1"), 1),
+                IndexEntry::unmerged("README".into(), EntryMode::Executable, repo.blob("3"), 3),
+            ],
+        );
+        index
+            .set_assume_unchanged(Path::new("src/main.rs"), true)
+            .unwrap();
+        index.add(file(&repo, "README", "resolved"));
+        index.write_tree().unwrap();
+        let expected = fields(&index);
+
+        // Without extended flags, version 3 is written as version 2.
+        let (version, v2) = write_version(index, 3);
+        assert_eq!(version, 2);
+        let index = Index::lock(".git/index").unwrap();
+        assert_eq!(fields(&index), expected);
+        assert_eq!(write_version(index, 2), (2, v2));
+
+        // Extended flags need version 3, whatever the version asked for.
+        let mut index = Index::lock(".git/index").unwrap();
+        index
+            .set_skip_worktree(Path::new("src/index/mod.rs"), true)
+            .unwrap();
+        index
+            .entry_mut(Path::new("src/main.rs"))
+            .unwrap()
+            .intent_to_add = true;
+        let expected = fields(&index);
+        let (version, v3) = write_version(index, 2);
+        assert_eq!(version, 3);
+        let index = Index::lock(".git/index").unwrap();
+        assert_eq!(fields(&index), expected);
+        assert_eq!(write_version(index, 3), (3, v3.clone()));
+
+        // Version 4 compresses the paths, and is kept once chosen.
+        let index = Index::lock(".git/index").unwrap();
+        let (version, v4) = write_version(index, 4);
+        assert_eq!(version, 4);
+        assert!(v4.len() < v3.len(), "{} >= {}", v4.len(), v3.len());
+        let index = Index::lock(".git/index").unwrap();
+        assert_eq!(index.version, 4);
+        assert_eq!(fields(&index), expected);
+        assert_eq!(index.resolve_undo.paths.len(), 1);
+        assert!(cached_tree(&index, &["src", "commands"]).is_some());
+        assert_eq!(write_version(index, 4), (4, v4));
+
+        // Going back from version 4 keeps the extended flags.
+        let (version, _) = write_version(Index::lock(".git/index").unwrap(), 2);
+        assert_eq!(version, 3);
+        assert_eq!(fields(&Index::init(".git/index").unwrap()), expected);
+    }
+
+    /// Returns the paths and stages of the entries of an index.
+    fn paths(index: &Index) -> Vec<(&str, u16)> {
+        index
+            .entries()
+            .iter()
+            .map(|entry| (entry.path.to_str().unwrap(), entry.stage))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_entries_sorted_as_they_are_added() {
+        let repo = TestRepo::new();
+        let mut index = Index::init(".git/index").unwrap();
+        // "a-b" sorts between "a" and "a/b", since '-' sorts before '/'.
+        for path in ["a/b", "a-b", "c", "a/c/d"] {
+            index.add(file(&repo, path, path));
+        }
+        assert_eq!(
+            paths(&index),
+            [("a-b", 0), ("a/b", 0), ("a/c/d", 0), ("c", 0)]
+        );
+
+        // A file replaces the directory at its path, and a directory the file at its path.
+        index.add(file(&repo, "a", "a"));
+        assert_eq!(paths(&index), [("a", 0), ("a-b", 0), ("c", 0)]);
+        index.add(file(&repo, "c/d", "c/d"));
+        assert_eq!(paths(&index), [("a", 0), ("a-b", 0), ("c/d", 0)]);
+
+        // Stages replace the merged entry, and the merged entry replaces every stage.
+        let stage = |stage| {
+            IndexEntry::unmerged("a".into(), EntryMode::NonExecutable, repo.blob("a"), stage)
+        };
+        index.add(stage(3));
+        index.add(stage(1));
+        assert_eq!(paths(&index), [("a", 1), ("a", 3), ("a-b", 0), ("c/d", 0)]);
+        index.add(stage(3));
+        assert_eq!(index.entry_at(Path::new("a"), 3).unwrap().stage(), 3);
+        assert!(index.entry_at(Path::new("a"), 0).is_none());
+        index.add(file(&repo, "a", "resolved"));
+        assert_eq!(paths(&index), [("a", 0), ("a-b", 0), ("c/d", 0)]);
+
+        index.remove(Path::new("a-b"));
+        assert_eq!(paths(&index), [("a", 0), ("c/d", 0)]);
+        assert!(index.entry(Path::new("c/d")).is_some());
+        assert!(index.entry(Path::new("c")).is_none());
     }
 }
@@ -0,0 +1,102 @@
+use crate::index::entry::IndexEntry;
+use crate::objects::id::ObjectID;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// The signature of the resolve undo extension of the index.
+pub(crate) const SIGNATURE: &[u8; 4] = b"REUC";
+
+/// The mode and object ID of one version of a conflicted file.
+pub(crate) type Version = Option<(u32, ObjectID)>;
+
+/// Represents the resolve undo extension of the index, which remembers the versions of the
+/// conflicted files that were resolved, so that their conflicts can be recreated.
+#[derive(Debug, Default)]
+pub(crate) struct ResolveUndo {
+    /// The versions of each resolved file, at stages 1 to 3.
+    pub(crate) paths: BTreeMap<PathBuf, [Version; 3]>,
+}
+
+impl ResolveUndo {
+    /// Parses the data of the resolve undo extension.
+    ///
+    /// Each resolved file is stored as its NUL-terminated path, followed by the modes of its three
+    /// versions as NUL-terminated octal numbers, `0` for a missing version, and then by the 20-byte
+    /// object IDs of the versions that are not missing.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data of the extension.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `ResolveUndo`, or an `anyhow::Error` if the data is
+    /// malformed.
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<ResolveUndo> {
+        let mut reader = std::io::Cursor::new(data);
+        let mut paths = BTreeMap::new();
+        while reader.position() < data.len() as u64 {
+            let mut path = Vec::new();
+            reader.read_until(0, &mut path)?;
+            anyhow::ensure!(path.pop() == Some(0), "Truncated resolve undo extension.");
+
+            let mut modes = [0u32; 3];
+            for mode in &mut modes {
+                let mut buffer = Vec::new();
+                reader.read_until(0, &mut buffer)?;
+                anyhow::ensure!(buffer.pop() == Some(0), "Truncated resolve undo extension.");
+                let octal = std::str::from_utf8(&buffer).context("Invalid resolve undo mode.")?;
+                *mode = u32::from_str_radix(octal, 8).context("Invalid resolve undo mode.")?;
+            }
+            let mut versions: [Version; 3] = Default::default();
+            for (version, mode) in versions.iter_mut().zip(modes) {
+                if mode != 0 {
+                    let mut oid_bytes = [0u8; 20];
+                    reader
+                        .read_exact(&mut oid_bytes)
+                        .context("Truncated resolve undo extension.")?;
+                    *version = Some((mode, ObjectID::from_bytes(oid_bytes)));
+                }
+            }
+            paths.insert(PathBuf::from(OsString::from_vec(path)), versions);
+        }
+        Ok(ResolveUndo { paths })
+    }
+
+    /// Encodes the resolved files into the data of the extension, see `parse`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the data of the extension.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (path, versions) in &self.paths {
+            data.extend(path.as_os_str().as_encoded_bytes());
+            data.push(0);
+            for version in versions {
+                let mode = version.as_ref().map_or(0, |(mode, _)| *mode);
+                data.extend(format!("{:o}", mode).as_bytes());
+                data.push(0);
+            }
+            for (_, oid) in versions.iter().flatten() {
+                data.extend(oid.to_bytes());
+            }
+        }
+        data
+    }
+
+    /// Remembers the version of a conflicted file held by an entry that is being removed from the
+    /// index, because the file was resolved.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The entry, at stage 1, 2 or 3.
+    pub(crate) fn record(&mut self, entry: &IndexEntry) {
+        let versions = self.paths.entry(entry.path().to_path_buf()).or_default();
+        versions[entry.stage() as usize - 1] = Some((entry.mode, entry.oid.clone()));
+    }
+}
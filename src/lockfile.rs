@@ -0,0 +1,123 @@
+use anyhow::Context;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the lock file that guards a file while it is being rewritten.
+const LOCK_SUFFIX: &str = ".lock";
+
+/// Represents an exclusive lock on a file, held through a `<path>.lock` file.
+///
+/// The lock file is created exclusively, so only one process can hold it at a time. The new
+/// contents of the locked file are written into the lock file, and `commit` atomically renames
+/// it over the original. If the lock is dropped without being committed, the lock file is removed
+/// and the original file is left untouched.
+pub(crate) struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<std::fs::File>,
+}
+
+impl LockFile {
+    /// Acquires the lock for the given file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file to lock. It does not need to exist.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `LockFile`, or an `anyhow::Error` if the lock is already
+    /// held by another process or the lock file cannot be created.
+    pub(crate) fn acquire(path: impl AsRef<Path>) -> anyhow::Result<LockFile> {
+        let path = path.as_ref().to_path_buf();
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(LOCK_SUFFIX);
+        let lock_path = PathBuf::from(lock_path);
+
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+            }
+        }
+
+        let file = match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => anyhow::bail!(
+                "Unable to create '{}': File exists.\n\nAnother process seems to be running in \
+                 this repository. If it still fails, a process may have crashed in this \
+                 repository earlier: remove the file manually to continue.",
+                lock_path.display()
+            ),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Unable to create '{}'", lock_path.display()))
+            }
+        };
+
+        Ok(LockFile {
+            path,
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// Replaces the locked file with the contents written into the lock, releasing the lock.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()` if the file was replaced, or an `anyhow::Error` if the
+    /// contents cannot be flushed or the rename fails.
+    pub(crate) fn commit(mut self) -> anyhow::Result<()> {
+        let mut file = self
+            .file
+            .take()
+            .context("Lock file was already released.")?;
+        file.flush()?;
+        file.sync_all()
+            .with_context(|| format!("Failed to sync {}", self.lock_path.display()))?;
+        drop(file);
+        if let Err(err) = std::fs::rename(&self.lock_path, &self.path) {
+            let _ = std::fs::remove_file(&self.lock_path);
+            return Err(err).with_context(|| {
+                format!(
+                    "Failed to rename {} to {}",
+                    self.lock_path.display(),
+                    self.path.display()
+                )
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Write for LockFile {
+    /// Writes the given buffer into the lock file.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.file.as_mut() {
+            Some(file) => file.write(buf),
+            None => Err(std::io::Error::other("Lock file was already released.")),
+        }
+    }
+
+    /// Flushes the lock file.
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LockFile {
+    /// Removes the lock file if the lock was never committed.
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.lock_path);
+        }
+    }
+}
@@ -1,14 +1,26 @@
+mod checkout;
 mod cmp;
 mod commands;
 mod config;
+mod diff;
+mod ignore;
 mod index;
+mod lockfile;
+mod merge;
 mod objects;
+mod refs;
+mod rev_walk;
+mod revision;
+mod status;
+#[cfg(test)]
+mod testing;
+mod workspace;
 
-use crate::commands::cli::{Commands, GitCLI};
+use crate::commands::cli::{attach_similarities, Commands, GitCLI};
 use clap::Parser;
 
 fn main() -> anyhow::Result<()> {
-    let args = GitCLI::parse();
+    let args = GitCLI::parse_from(attach_similarities(std::env::args_os()));
 
     match args.command {
         // Initialize repository
@@ -37,25 +49,261 @@ fn main() -> anyhow::Result<()> {
             object_hash,
         } => commands::ls_tree::invoke(&object_hash, name_only)?,
         // Write tree
-        Commands::WriteTree { tree_path } => match tree_path {
-            Some(path) => commands::write_tree::invoke(std::path::Path::new(&path))?,
-            None => commands::write_tree::invoke(std::path::Path::new("."))?,
+        Commands::WriteTree {
+            from_dir,
+            tree_path,
+        } => match (from_dir, tree_path) {
+            (true, Some(path)) => {
+                commands::write_tree::invoke_for_dir(std::path::Path::new(&path))?
+            }
+            (true, None) => commands::write_tree::invoke_for_dir(std::path::Path::new("."))?,
+            (false, _) => commands::write_tree::invoke(".git/index")?,
         },
 
         // Commit tree
         Commands::CommitTree {
             tree_hash,
-            parent_hash,
-            message,
+            parent_hashes,
+            messages,
+            file,
         } => {
-            commands::commit_tree::invoke(tree_hash, parent_hash, message)?;
+            commands::commit_tree::invoke(&tree_hash, &parent_hashes, &messages, file.as_deref())?;
         }
 
+        // Commit the staged changes
+        Commands::Commit {
+            message,
+            allow_empty,
+        } => commands::commit::invoke(&message, allow_empty)?,
+
+        // Show the status of the working tree
+        Commands::Status { short, porcelain } => commands::status::invoke(short, porcelain)?,
+
         // List files in index
-        Commands::LsFiles => commands::ls_files::invoke(".git/index")?,
+        Commands::LsFiles { stage, unmerged } => {
+            commands::ls_files::invoke(".git/index", stage, unmerged)?
+        }
+
+        // Change the index directly
+        Commands::UpdateIndex {
+            index_version,
+            refresh,
+            assume_unchanged,
+            no_assume_unchanged,
+            skip_worktree,
+            no_skip_worktree,
+            paths,
+        } => {
+            let assume_unchanged = match (assume_unchanged, no_assume_unchanged) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            let skip_worktree = match (skip_worktree, no_skip_worktree) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            commands::update_index::invoke(
+                &paths,
+                index_version,
+                refresh,
+                assume_unchanged,
+                skip_worktree,
+            )?
+        }
+
+        // Add files to index
+        Commands::Add {
+            all,
+            update,
+            force,
+            dry_run,
+            pathspecs,
+        } => commands::add::invoke(&pathspecs, all, update, force, dry_run)?,
+
+        // Remove files from the index and the working tree
+        Commands::Rm {
+            cached,
+            r,
+            force,
+            dry_run,
+            quiet,
+            pathspecs,
+        } => commands::rm::invoke(&pathspecs, cached, r, force, dry_run, quiet)?,
+
+        // Move or rename files in the index and the working tree
+        Commands::Mv {
+            force,
+            dry_run,
+            paths,
+        } => commands::mv::invoke(&paths, force, dry_run)?,
+
+        // Check whether paths are ignored
+        Commands::CheckIgnore {
+            verbose,
+            non_matching,
+            paths,
+        } => commands::check_ignore::invoke(&paths, verbose, non_matching)?,
+
+        // Show the commit history
+        Commands::Log {
+            walk,
+            changes,
+            renames,
+            oneline,
+            format,
+            args,
+            paths,
+        } => commands::log::invoke(
+            &args,
+            &paths,
+            &walk,
+            oneline,
+            format.as_deref(),
+            &changes,
+            &renames,
+        )?,
+
+        // Show changes between the working tree, the index and commits
+        Commands::Diff {
+            diff,
+            cached,
+            args,
+            paths,
+        } => commands::diff::invoke(&args, &paths, cached, &diff)?,
+
+        // Show the files changed between two trees, or by a commit
+        Commands::DiffTree {
+            recursive,
+            format,
+            renames,
+            root,
+            no_commit_id,
+            args,
+            paths,
+        } => commands::diff_tree::invoke(
+            &args,
+            &paths,
+            recursive,
+            &format,
+            &renames,
+            root,
+            no_commit_id,
+        )?,
+
+        // Create, list, delete or rename branches
+        Commands::Branch {
+            list,
+            verbose,
+            action,
+            track,
+            force,
+            args,
+        } => commands::branch::invoke(&args, &action, list, verbose, track, force)?,
+
+        // Check out a branch or commit
+        Commands::Checkout {
+            new_branch,
+            detach,
+            force,
+            target,
+        } => commands::checkout::invoke(target.as_deref(), new_branch.as_deref(), detach, force)?,
+
+        // Switch to a branch
+        Commands::Switch {
+            create,
+            detach,
+            force,
+            target,
+        } => commands::switch::invoke(target.as_deref(), create.as_deref(), detach, force)?,
+
+        // Merge a commit into the current branch
+        Commands::Merge {
+            message,
+            no_ff,
+            ff_only,
+            conflict,
+            commit,
+        } => commands::merge::invoke(
+            &commit,
+            message.as_deref(),
+            no_ff,
+            ff_only,
+            conflict.as_deref(),
+        )?,
+
+        // Find the best common ancestors of two commits
+        Commands::MergeBase {
+            all,
+            is_ancestor,
+            one,
+            two,
+        } => commands::merge_base::invoke(&one, &two, all, is_ancestor)?,
+
+        // Resolve revision expressions to object IDs
+        Commands::RevParse {
+            verify,
+            quiet,
+            short,
+            revs,
+        } => commands::rev_parse::invoke(&revs, verify, quiet, short)?,
+
+        // Get, set or list config variables
+        Commands::Config { scope, action } => commands::config::invoke(&scope, &action)?,
+
+        // Create, list or delete tags
+        Commands::Tag {
+            list,
+            delete,
+            annotate,
+            messages,
+            file,
+            force,
+            args,
+        } => commands::tag::invoke(
+            &args,
+            list,
+            delete,
+            annotate,
+            &messages,
+            file.as_deref(),
+            force,
+        )?,
+
+        // Write objects read from stdin in a pack
+        Commands::PackObjects { base_name } => commands::pack_objects::invoke(&base_name)?,
+
+        // Pack loose objects
+        Commands::Repack { all, delete } => commands::repack::invoke(all, delete)?,
+
+        // Update or delete a ref
+        Commands::UpdateRef {
+            delete,
+            no_deref,
+            message,
+            ref_name,
+            values,
+        } => commands::update_ref::invoke(&ref_name, &values, delete, no_deref, message)?,
+
+        // Read, write or delete a symbolic ref
+        Commands::SymbolicRef {
+            quiet,
+            short,
+            delete,
+            name,
+            target,
+        } => commands::symbolic_ref::invoke(&name, target, short, quiet, delete)?,
 
-        // Add file to index
-        Commands::Add { path } => commands::add::invoke(&path)?,
+        // List refs
+        Commands::ShowRef {
+            head,
+            heads,
+            tags,
+            verify,
+            hash_only,
+            patterns,
+        } => commands::show_ref::invoke(&patterns, head, heads, tags, verify, hash_only)?,
     }
 
     Ok(())
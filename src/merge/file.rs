@@ -0,0 +1,575 @@
+use crate::diff::{self, Algorithm, Edit};
+
+/// The default length of the conflict markers, like git.
+pub(crate) const MARKER_SIZE: usize = 7;
+
+/// The maximum number of unchanged lines between two conflicts for them to be shown as one.
+const MAX_CONFLICT_GAP: usize = 3;
+
+/// Represents the way conflicts are written in a merged file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum ConflictStyle {
+    /// Our and their versions of the conflicting lines, reduced to the lines that differ.
+    #[default]
+    Merge,
+    /// Our and their versions of the conflicting lines, with the lines of the merge base between
+    /// them.
+    Diff3,
+}
+
+impl ConflictStyle {
+    /// Parses the name of a conflict style, as given to `merge.conflictStyle`.
+    pub(crate) fn parse(name: &str) -> anyhow::Result<ConflictStyle> {
+        match name {
+            "merge" => Ok(ConflictStyle::Merge),
+            "diff3" => Ok(ConflictStyle::Diff3),
+            _ => anyhow::bail!("unknown conflict style '{}'", name),
+        }
+    }
+}
+
+/// Represents the names written after the conflict markers.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Labels<'a> {
+    /// The name of our side, after `<<<<<<<`.
+    pub(crate) ours: &'a str,
+    /// The name of the merge base, after `|||||||` in the diff3 style.
+    pub(crate) base: &'a str,
+    /// The name of their side, after `>>>>>>>`.
+    pub(crate) theirs: &'a str,
+}
+
+/// Represents the result of a three-way merge of files.
+pub(crate) struct MergedFile {
+    /// The merged contents, with conflict markers around the conflicting lines.
+    pub(crate) data: Vec<u8>,
+    /// The number of conflicts.
+    pub(crate) conflicts: usize,
+}
+
+/// Represents a change of one side: `base_len` lines of the base starting at `base` were
+/// replaced by `len` lines of the side starting at `start`.
+#[derive(Clone, Copy, Debug)]
+struct Hunk {
+    base: usize,
+    base_len: usize,
+    start: usize,
+    len: usize,
+}
+
+/// Represents how a region of the merged file is resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Resolution {
+    /// Both sides changed the region differently.
+    Conflict,
+    /// Only our side changed the region.
+    Ours,
+    /// Only their side changed the region.
+    Theirs,
+    /// Both sides changed the region the same way, which was found when refining a conflict.
+    Same,
+}
+
+/// Represents a region of the merged file, in the base and in both sides.
+#[derive(Clone, Copy, Debug)]
+struct Region {
+    resolution: Resolution,
+    base: usize,
+    base_len: usize,
+    ours: usize,
+    ours_len: usize,
+    theirs: usize,
+    theirs_len: usize,
+}
+
+/// Merges the changes of two versions of a file to their common base, line by line.
+///
+/// This follows the merge of git's xdiff. The changes of both sides to the base are walked
+/// together: a change that does not overlap or touch a change of the other side is taken as is,
+/// the same change on both sides is taken once, and other changes form a conflict, which grows
+/// to cover all the changes it overlaps. Unless the diff3 style is used, the lines of a conflict
+/// are then compared between both sides, and only the lines that differ are kept in conflict, as
+/// separate conflicts. Conflicts separated by at most three unchanged lines are joined again,
+/// since showing them apart takes as many lines.
+///
+/// # Arguments
+///
+/// * `base` - The contents of the common base.
+/// * `ours` - The contents of our side, whose lines are used outside of the changes.
+/// * `theirs` - The contents of their side.
+/// * `labels` - The names written after the conflict markers.
+/// * `style` - The way conflicts are written.
+/// * `marker_size` - The length of the conflict markers.
+///
+/// # Returns
+///
+/// The merged contents and the number of conflicts.
+pub(crate) fn merge_files(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &Labels,
+    style: ConflictStyle,
+    marker_size: usize,
+) -> MergedFile {
+    let (base_lines, our_lines, their_lines) = (
+        diff::split_lines(base),
+        diff::split_lines(ours),
+        diff::split_lines(theirs),
+    );
+    let our_hunks = hunks(&base_lines, &our_lines);
+    let their_hunks = hunks(&base_lines, &their_lines);
+    if our_hunks.is_empty() {
+        return MergedFile {
+            data: theirs.to_vec(),
+            conflicts: 0,
+        };
+    }
+    if their_hunks.is_empty() {
+        return MergedFile {
+            data: ours.to_vec(),
+            conflicts: 0,
+        };
+    }
+
+    let mut regions = merge_hunks(
+        &our_hunks,
+        &their_hunks,
+        &our_lines,
+        &their_lines,
+        base_lines.len(),
+    );
+    if style == ConflictStyle::Merge {
+        regions = refine_conflicts(regions, &our_lines, &their_lines);
+        regions = join_conflicts(regions);
+    }
+
+    let conflicts = regions
+        .iter()
+        .filter(|region| region.resolution == Resolution::Conflict)
+        .count();
+    let output = Output {
+        base: &base_lines,
+        ours: &our_lines,
+        theirs: &their_lines,
+        labels,
+        style,
+        marker_size,
+    };
+    MergedFile {
+        data: output.write(&regions),
+        conflicts,
+    }
+}
+
+/// Computes the changes that turn the base lines into the lines of a side.
+fn hunks(base: &[&[u8]], side: &[&[u8]]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut in_hunk = false;
+    for edit in diff::diff_lines(base, side, Algorithm::Myers) {
+        match edit {
+            Edit::Equal(old, new) => {
+                (i, j) = (old + 1, new + 1);
+                in_hunk = false;
+                continue;
+            }
+            Edit::Delete(_) | Edit::Insert(_) if !in_hunk => {
+                hunks.push(Hunk {
+                    base: i,
+                    base_len: 0,
+                    start: j,
+                    len: 0,
+                });
+                in_hunk = true;
+            }
+            _ => {}
+        }
+        let hunk = hunks.last_mut().expect("a hunk was started");
+        match edit {
+            Edit::Delete(_) => {
+                hunk.base_len += 1;
+                i += 1;
+            }
+            Edit::Insert(_) => {
+                hunk.len += 1;
+                j += 1;
+            }
+            Edit::Equal(..) => {}
+        }
+    }
+    hunks
+}
+
+/// Walks the changes of both sides in the order of the base, and lists the regions they form.
+fn merge_hunks(
+    ours: &[Hunk],
+    theirs: &[Hunk],
+    our_lines: &[&[u8]],
+    their_lines: &[&[u8]],
+    base_len: usize,
+) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let (mut ours, mut theirs) = (ours.iter().peekable(), theirs.iter().peekable());
+    while let (Some(&&one), Some(&&two)) = (ours.peek(), theirs.peek()) {
+        // A change that ends before the other one starts is taken as is. The other side has
+        // the same lines as the base there, at the offset of its previous changes.
+        if one.base + one.base_len < two.base {
+            append(
+                &mut regions,
+                only_ours(one, two.start + one.base - two.base),
+            );
+            ours.next();
+            continue;
+        }
+        if two.base + two.base_len < one.base {
+            append(
+                &mut regions,
+                only_theirs(two, one.start + two.base - one.base),
+            );
+            theirs.next();
+            continue;
+        }
+
+        let same = one.base == two.base
+            && one.base_len == two.base_len
+            && one.len == two.len
+            && our_lines[one.start..one.start + one.len]
+                == their_lines[two.start..two.start + two.len];
+        if !same {
+            // Both changes, extended to cover the same lines of the base.
+            let (mut base, mut our_start, mut their_start) = (one.base, one.start, two.start);
+            if one.base > two.base {
+                let offset = one.base - two.base;
+                base -= offset;
+                our_start -= offset;
+            } else {
+                their_start -= two.base - one.base;
+            }
+            let end = (one.base + one.base_len).max(two.base + two.base_len);
+            append(
+                &mut regions,
+                Region {
+                    resolution: Resolution::Conflict,
+                    base,
+                    base_len: end - base,
+                    ours: our_start,
+                    ours_len: one.start + one.len + (end - one.base - one.base_len) - our_start,
+                    theirs: their_start,
+                    theirs_len: two.start + two.len + (end - two.base - two.base_len) - their_start,
+                },
+            );
+        }
+
+        let (our_end, their_end) = (one.base + one.base_len, two.base + two.base_len);
+        if our_end >= their_end {
+            theirs.next();
+        }
+        if their_end >= our_end {
+            ours.next();
+        }
+    }
+
+    let (our_total, their_total) = (our_lines.len(), their_lines.len());
+    for &one in ours {
+        append(
+            &mut regions,
+            only_ours(one, one.base + their_total - base_len),
+        );
+    }
+    for &two in theirs {
+        append(
+            &mut regions,
+            only_theirs(two, two.base + our_total - base_len),
+        );
+    }
+    regions
+}
+
+/// Returns the region of a change of our side only, where their side starts at `theirs`.
+fn only_ours(hunk: Hunk, theirs: usize) -> Region {
+    Region {
+        resolution: Resolution::Ours,
+        base: hunk.base,
+        base_len: hunk.base_len,
+        ours: hunk.start,
+        ours_len: hunk.len,
+        theirs,
+        theirs_len: hunk.base_len,
+    }
+}
+
+/// Returns the region of a change of their side only, where our side starts at `ours`.
+fn only_theirs(hunk: Hunk, ours: usize) -> Region {
+    Region {
+        resolution: Resolution::Theirs,
+        base: hunk.base,
+        base_len: hunk.base_len,
+        ours,
+        ours_len: hunk.base_len,
+        theirs: hunk.start,
+        theirs_len: hunk.len,
+    }
+}
+
+/// Appends a region, or extends the last region with it if they overlap or touch on either side.
+/// A region that gets changes of different kinds is a conflict.
+fn append(regions: &mut Vec<Region>, region: Region) {
+    match regions.last_mut() {
+        Some(last)
+            if region.ours <= last.ours + last.ours_len
+                || region.theirs <= last.theirs + last.theirs_len =>
+        {
+            if last.resolution != region.resolution {
+                last.resolution = Resolution::Conflict;
+            }
+            last.base_len = region.base + region.base_len - last.base;
+            last.ours_len = region.ours + region.ours_len - last.ours;
+            last.theirs_len = region.theirs + region.theirs_len - last.theirs;
+        }
+        _ => regions.push(region),
+    }
+}
+
+/// Compares the lines of both sides in each conflict, and keeps only the lines that differ in
+/// conflict. A conflict where both sides have the same lines is resolved. Conflicts where one
+/// side is empty are kept as they are.
+fn refine_conflicts(regions: Vec<Region>, ours: &[&[u8]], theirs: &[&[u8]]) -> Vec<Region> {
+    let mut refined = Vec::with_capacity(regions.len());
+    for region in regions {
+        if region.resolution != Resolution::Conflict
+            || region.ours_len == 0
+            || region.theirs_len == 0
+        {
+            refined.push(region);
+            continue;
+        }
+        let our_lines = &ours[region.ours..region.ours + region.ours_len];
+        let their_lines = &theirs[region.theirs..region.theirs + region.theirs_len];
+        let hunks = hunks(our_lines, their_lines);
+        if hunks.is_empty() {
+            refined.push(Region {
+                resolution: Resolution::Same,
+                ..region
+            });
+            continue;
+        }
+        // The lines of the base cannot be split along with the sides, so they are dropped.
+        refined.extend(hunks.into_iter().map(|hunk| Region {
+            resolution: Resolution::Conflict,
+            base: region.base,
+            base_len: 0,
+            ours: region.ours + hunk.base,
+            ours_len: hunk.base_len,
+            theirs: region.theirs + hunk.start,
+            theirs_len: hunk.len,
+        }));
+    }
+    refined
+}
+
+/// Joins the conflicts separated by at most `MAX_CONFLICT_GAP` unchanged lines.
+fn join_conflicts(regions: Vec<Region>) -> Vec<Region> {
+    let mut joined: Vec<Region> = Vec::with_capacity(regions.len());
+    for region in regions {
+        match joined.last_mut() {
+            Some(last)
+                if last.resolution == Resolution::Conflict
+                    && region.resolution == Resolution::Conflict
+                    && region.ours - (last.ours + last.ours_len) <= MAX_CONFLICT_GAP =>
+            {
+                last.ours_len = region.ours + region.ours_len - last.ours;
+                last.theirs_len = region.theirs + region.theirs_len - last.theirs;
+            }
+            _ => joined.push(region),
+        }
+    }
+    joined
+}
+
+/// Writes the merged file from the lines of the three versions.
+struct Output<'a> {
+    base: &'a [&'a [u8]],
+    ours: &'a [&'a [u8]],
+    theirs: &'a [&'a [u8]],
+    labels: &'a Labels<'a>,
+    style: ConflictStyle,
+    marker_size: usize,
+}
+
+impl Output<'_> {
+    /// Writes the regions, with our lines between them.
+    fn write(&self, regions: &[Region]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut next = 0;
+        for region in regions {
+            match region.resolution {
+                // Our lines were already the same as theirs, and are written with the lines
+                // that follow.
+                Resolution::Same => continue,
+                Resolution::Conflict => {
+                    copy(&mut data, &self.ours[next..region.ours], false);
+                    self.write_conflict(&mut data, region);
+                }
+                Resolution::Ours => copy(
+                    &mut data,
+                    &self.ours[next..region.ours + region.ours_len],
+                    false,
+                ),
+                Resolution::Theirs => {
+                    copy(&mut data, &self.ours[next..region.ours], false);
+                    copy(
+                        &mut data,
+                        &self.theirs[region.theirs..region.theirs + region.theirs_len],
+                        false,
+                    );
+                }
+            }
+            next = region.ours + region.ours_len;
+        }
+        copy(&mut data, &self.ours[next..], false);
+        data
+    }
+
+    /// Writes a conflict between markers. The lines before each marker end with a newline.
+    fn write_conflict(&self, data: &mut Vec<u8>, region: &Region) {
+        self.write_marker(data, b'<', Some(self.labels.ours));
+        copy(
+            data,
+            &self.ours[region.ours..region.ours + region.ours_len],
+            true,
+        );
+        if self.style == ConflictStyle::Diff3 {
+            self.write_marker(data, b'|', Some(self.labels.base));
+            copy(
+                data,
+                &self.base[region.base..region.base + region.base_len],
+                true,
+            );
+        }
+        self.write_marker(data, b'=', None);
+        copy(
+            data,
+            &self.theirs[region.theirs..region.theirs + region.theirs_len],
+            true,
+        );
+        self.write_marker(data, b'>', Some(self.labels.theirs));
+    }
+
+    /// Writes a conflict marker line, followed by a label if there is one.
+    fn write_marker(&self, data: &mut Vec<u8>, marker: u8, label: Option<&str>) {
+        data.extend(std::iter::repeat_n(marker, self.marker_size));
+        if let Some(label) = label {
+            data.push(b' ');
+            data.extend(label.as_bytes());
+        }
+        data.push(b'\n');
+    }
+}
+
+/// Copies lines, adding a newline to the last one if it has none and `add_newline` is set.
+fn copy(data: &mut Vec<u8>, lines: &[&[u8]], add_newline: bool) {
+    for line in lines {
+        data.extend(*line);
+    }
+    if add_newline && lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        data.push(b'\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "a\nb\nc\nd\ne\n";
+    const LABELS: Labels = Labels {
+        ours: "ours",
+        base: "base",
+        theirs: "theirs",
+    };
+
+    fn merge(ours: &str, theirs: &str, style: ConflictStyle) -> (String, usize) {
+        let merged = merge_files(
+            BASE.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            &LABELS,
+            style,
+            MARKER_SIZE,
+        );
+        (String::from_utf8(merged.data).unwrap(), merged.conflicts)
+    }
+
+    #[test]
+    fn merges_separate_changes() {
+        let expected = ("a\nB\nc\nD\ne\n".to_string(), 0);
+        let (ours, theirs) = ("a\nB\nc\nd\ne\n", "a\nb\nc\nD\ne\n");
+        assert_eq!(merge(ours, theirs, ConflictStyle::Merge), expected);
+        assert_eq!(merge(theirs, ours, ConflictStyle::Merge), expected);
+        assert_eq!(
+            merge(BASE, "x\n", ConflictStyle::Merge),
+            ("x\n".to_string(), 0)
+        );
+        assert_eq!(merge("", BASE, ConflictStyle::Merge), (String::new(), 0));
+    }
+
+    #[test]
+    fn merges_identical_changes() {
+        let changed = "a\nb\nX\nd\n";
+        assert_eq!(
+            merge(changed, changed, ConflictStyle::Merge),
+            (changed.to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn writes_conflict_markers() {
+        let (ours, theirs) = ("a\nb\nX\nd\ne\n", "a\nb\nY\nd\ne\n");
+        assert_eq!(
+            merge(ours, theirs, ConflictStyle::Merge),
+            (
+                "a\nb\n<<<<<<< ours\nX\n=======\nY\n>>>>>>> theirs\nd\ne\n".to_string(),
+                1
+            )
+        );
+        assert_eq!(
+            merge(ours, theirs, ConflictStyle::Diff3),
+            (
+                "a\nb\n<<<<<<< ours\nX\n||||||| base\nc\n=======\nY\n>>>>>>> theirs\nd\ne\n"
+                    .to_string(),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn reduces_conflicts_to_the_lines_that_differ() {
+        // Both sides replace `c` by `Z` and another line, and only the other line conflicts.
+        assert_eq!(
+            merge(
+                "a\nb\nZ\nW\nd\ne\n",
+                "a\nb\nZ\nV\nd\ne\n",
+                ConflictStyle::Merge
+            ),
+            (
+                "a\nb\nZ\n<<<<<<< ours\nW\n=======\nV\n>>>>>>> theirs\nd\ne\n".to_string(),
+                1
+            )
+        );
+        // The identical change to `c` merges cleanly, next to the conflict at the end.
+        assert_eq!(
+            merge("a\nb\nX\nd\nE\n", "a\nb\nX\nd\nF\n", ConflictStyle::Merge),
+            (
+                "a\nb\nX\nd\n<<<<<<< ours\nE\n=======\nF\n>>>>>>> theirs\n".to_string(),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn parses_conflict_styles() {
+        assert_eq!(ConflictStyle::parse("diff3").unwrap(), ConflictStyle::Diff3);
+        assert_eq!(ConflictStyle::parse("merge").unwrap(), ConflictStyle::Merge);
+        assert!(ConflictStyle::parse("zdiff4").is_err());
+    }
+}
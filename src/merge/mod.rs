@@ -0,0 +1,775 @@
+/// This module merges the trees of two commits with their merge base, like the recursive
+/// strategy of `git merge`, and the versions of single files line by line.
+pub(crate) mod file;
+
+use crate::diff::is_binary;
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::tree::{self, Change, Files};
+use crate::diff::unified::FileVersion;
+use crate::merge::file::{ConflictStyle, Labels, MARKER_SIZE};
+use crate::objects::blob::Blob;
+use crate::objects::id::ObjectID;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::tree::Tree;
+use crate::objects::Object;
+use crate::rev_walk::CommitGraph;
+use crate::revision;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// The names of the sides of the merges of merge bases, in their conflict markers.
+const INNER_LABELS: [&str; 2] = ["Temporary merge branch 1", "Temporary merge branch 2"];
+
+/// The name of a merge base that was built by merging several merge bases.
+const VIRTUAL_BASE_LABEL: &str = "merged common ancestors";
+
+/// A version of a file, with its mode and object ID, or `None` if the file does not exist.
+pub(crate) type Version = Option<(EntryMode, ObjectID)>;
+
+/// Represents how two commits are merged.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MergeOptions<'a> {
+    /// The name of our side, in messages and conflict markers.
+    pub(crate) ours: &'a str,
+    /// The name of their side.
+    pub(crate) theirs: &'a str,
+    /// The way conflicts are written in the merged files.
+    pub(crate) style: ConflictStyle,
+}
+
+/// Represents the result of a merge of trees.
+pub(crate) struct TreeMerge {
+    /// The merged files. Conflicted files hold our version, or the merged contents with conflict
+    /// markers, whose blobs are written to the database.
+    pub(crate) files: Files,
+    /// The conflicted files, with their versions in the merge base, in our side and in their
+    /// side, which are recorded in the index as stages 1, 2 and 3.
+    pub(crate) conflicts: BTreeMap<PathBuf, [Version; 3]>,
+    /// The messages describing the merge, in the order of the paths they are about.
+    pub(crate) messages: Vec<String>,
+}
+
+/// Represents a file to merge, with its versions in the base and on both sides. Renamed files are
+/// merged at their new path.
+struct Item {
+    base: Version,
+    ours: Version,
+    theirs: Version,
+    /// If true, the file is in conflict whatever its versions, e.g. when it was renamed on one side
+    /// and deleted on the other.
+    conflicted: bool,
+}
+
+/// Represents a file renamed on one side only, to be moved to its new path.
+struct Rename<'a> {
+    old: &'a Path,
+    new: &'a Path,
+    /// The version of the file in the base.
+    in_base: Version,
+    /// If true, the file was renamed on our side.
+    by_us: bool,
+}
+
+/// Merges two commits.
+///
+/// The trees of the commits are merged with the tree of their merge base. When there are several
+/// merge bases, they are first merged together, recursively, and the result is used as the base:
+/// the conflicts of that merge are kept in its files, with conflict markers for their contents.
+///
+/// # Arguments
+///
+/// * `graph` - The commit graph, to read the commits and find the merge bases of merge bases.
+/// * `ours` - Our commit.
+/// * `theirs` - Their commit.
+/// * `bases` - The merge bases of both commits, see `CommitGraph::merge_bases`.
+/// * `options` - The names of both sides and the conflict style.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `TreeMerge`, or an `anyhow::Error` if an object cannot be read
+/// or written.
+pub(crate) fn merge_commits(
+    graph: &mut CommitGraph,
+    ours: &ObjectID,
+    theirs: &ObjectID,
+    bases: &[ObjectID],
+    options: &MergeOptions,
+) -> anyhow::Result<TreeMerge> {
+    let base_label = base_label(bases)?;
+    let merger = Merger {
+        labels: Labels {
+            ours: options.ours,
+            base: &base_label,
+            theirs: options.theirs,
+        },
+        style: options.style,
+        depth: 0,
+    };
+    let base = virtual_base(graph, bases, options.style, 1)?;
+    merger.merge(
+        &base,
+        &commit_files(graph, ours)?,
+        &commit_files(graph, theirs)?,
+    )
+}
+
+/// Returns the files of a commit.
+fn commit_files(graph: &mut CommitGraph, oid: &ObjectID) -> anyhow::Result<Files> {
+    let tree = graph.get(oid)?.tree.clone();
+    Tree::flatten(&tree.hash())
+}
+
+/// Returns the name of the merge base in the diff3 conflict style: its abbreviated ID, unless it
+/// was built from several merge bases.
+fn base_label(bases: &[ObjectID]) -> anyhow::Result<String> {
+    match bases {
+        [base] => revision::abbreviate(base, revision::DEFAULT_ABBREV),
+        _ => Ok(VIRTUAL_BASE_LABEL.to_string()),
+    }
+}
+
+/// Merges merge bases into a single base, one after the other from the oldest, like git. Each one
+/// is merged with the result so far, using the merge bases of the commits merged so far and the
+/// next one.
+///
+/// # Arguments
+///
+/// * `depth` - How deep the merge is nested in the merge of the two commits, which makes the
+///   conflict markers longer.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the files of the merged base, which are empty without merge bases.
+fn virtual_base(
+    graph: &mut CommitGraph,
+    bases: &[ObjectID],
+    style: ConflictStyle,
+    depth: usize,
+) -> anyhow::Result<Files> {
+    let bases = bases.iter().rev().cloned().collect::<Vec<_>>();
+    let Some((first, rest)) = bases.split_first() else {
+        return Ok(Files::new());
+    };
+    let mut merged = commit_files(graph, first)?;
+    let mut heads = vec![first.clone()];
+    for next in rest {
+        let mut inner_bases = Vec::new();
+        for head in &heads {
+            for base in graph.merge_bases(head, next)? {
+                if !inner_bases.contains(&base) {
+                    inner_bases.push(base);
+                }
+            }
+        }
+        let inner_bases = independent(graph, inner_bases)?;
+        let base_label = base_label(&inner_bases)?;
+        let merger = Merger {
+            labels: Labels {
+                ours: INNER_LABELS[0],
+                base: &base_label,
+                theirs: INNER_LABELS[1],
+            },
+            style,
+            depth,
+        };
+        let base = virtual_base(graph, &inner_bases, style, depth + 1)?;
+        merged = merger
+            .merge(&base, &merged, &commit_files(graph, next)?)?
+            .files;
+        heads.push(next.clone());
+    }
+    Ok(merged)
+}
+
+/// Drops the commits that are ancestors of another one of the commits.
+fn independent(graph: &mut CommitGraph, commits: Vec<ObjectID>) -> anyhow::Result<Vec<ObjectID>> {
+    let mut kept = Vec::new();
+    for commit in &commits {
+        let mut redundant = false;
+        for other in commits.iter().filter(|other| *other != commit) {
+            if graph.is_ancestor(commit, other)? {
+                redundant = true;
+                break;
+            }
+        }
+        if !redundant {
+            kept.push(commit.clone());
+        }
+    }
+    Ok(kept)
+}
+
+/// Merges trees, given as the files they contain.
+struct Merger<'a> {
+    labels: Labels<'a>,
+    style: ConflictStyle,
+    /// Zero for the merge of the two commits, and more for the merges of their merge bases,
+    /// whose conflicts are not recorded: their files keep the base version, or conflict markers.
+    depth: usize,
+}
+
+/// Collects the results of a merge of trees, with the messages keyed by path.
+#[derive(Default)]
+struct Outcome {
+    files: Files,
+    conflicts: BTreeMap<PathBuf, [Version; 3]>,
+    messages: BTreeMap<PathBuf, Vec<String>>,
+}
+
+impl Outcome {
+    fn message(&mut self, path: &Path, message: String) {
+        self.messages
+            .entry(path.to_path_buf())
+            .or_default()
+            .push(message);
+    }
+}
+
+impl Merger<'_> {
+    /// Merges the files of both sides with the files of the base.
+    ///
+    /// The renames of each side are detected first, and a renamed file is merged at its new path
+    /// with the versions of the other side at its old path. A file renamed on both sides to the
+    /// same path is merged there; to different paths, both are kept in conflict. A file renamed on
+    /// one side and deleted on the other is in conflict. Then each file is resolved: a file that
+    /// only one side changed takes the version of that side, and a file both sides changed is
+    /// merged line by line, which fails for binary files. A file that one side deleted and the
+    /// other changed is in conflict and kept. Finally, a file that is in the way of a directory
+    /// is moved aside, to `<path>~<side>`.
+    fn merge(&self, base: &Files, ours: &Files, theirs: &Files) -> anyhow::Result<TreeMerge> {
+        let mut outcome = Outcome::default();
+        let mut items = base
+            .keys()
+            .chain(ours.keys())
+            .chain(theirs.keys())
+            .map(|path| {
+                let item = Item {
+                    base: base.get(path).cloned(),
+                    ours: ours.get(path).cloned(),
+                    theirs: theirs.get(path).cloned(),
+                    conflicted: false,
+                };
+                (path.clone(), item)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let our_renames = renames(base, ours)?;
+        let their_renames = renames(base, theirs)?;
+        for (old, new) in &our_renames {
+            let in_base = base.get(old).cloned();
+            match their_renames.get(old) {
+                Some(their_new) if their_new == new => {
+                    items.remove(old);
+                    items.insert(
+                        new.clone(),
+                        Item {
+                            base: in_base,
+                            ours: ours.get(new).cloned(),
+                            theirs: theirs.get(new).cloned(),
+                            conflicted: false,
+                        },
+                    );
+                }
+                Some(their_new) => {
+                    if theirs.contains_key(new) || ours.contains_key(their_new) {
+                        continue;
+                    }
+                    // The base version stays at the old path, and each side at its new path.
+                    let conflicted = |base: Version, ours: Version, theirs: Version| Item {
+                        base,
+                        ours,
+                        theirs,
+                        conflicted: true,
+                    };
+                    items.insert(old.clone(), conflicted(in_base, None, None));
+                    items.insert(new.clone(), conflicted(None, ours.get(new).cloned(), None));
+                    items.insert(
+                        their_new.clone(),
+                        conflicted(None, None, theirs.get(their_new).cloned()),
+                    );
+                    outcome.message(
+                        old,
+                        format!(
+                            "CONFLICT (rename/rename): {} renamed to {} in {} and to {} in {}.",
+                            old.display(),
+                            new.display(),
+                            self.labels.ours,
+                            their_new.display(),
+                            self.labels.theirs
+                        ),
+                    );
+                }
+                None => {
+                    let rename = Rename {
+                        old,
+                        new,
+                        in_base,
+                        by_us: true,
+                    };
+                    self.follow_rename(&mut items, &mut outcome, rename, theirs)
+                }
+            }
+        }
+        for (old, new) in &their_renames {
+            if !our_renames.contains_key(old) {
+                let rename = Rename {
+                    old,
+                    new,
+                    in_base: base.get(old).cloned(),
+                    by_us: false,
+                };
+                self.follow_rename(&mut items, &mut outcome, rename, ours);
+            }
+        }
+
+        for (path, item) in items {
+            self.resolve(&mut outcome, &path, item)?;
+        }
+        self.move_files_in_the_way(&mut outcome, ours);
+
+        Ok(TreeMerge {
+            files: outcome.files,
+            conflicts: outcome.conflicts,
+            messages: outcome.messages.into_values().flatten().collect(),
+        })
+    }
+
+    /// Moves the file renamed on one side to its new path, with the version of the other side at
+    /// its old path, or in conflict if the other side deleted it. Nothing is moved if the other
+    /// side has a file at the new path.
+    ///
+    /// # Arguments
+    ///
+    /// * `rename` - The renamed file.
+    /// * `other` - The files of the side that did not rename the file.
+    fn follow_rename(
+        &self,
+        items: &mut BTreeMap<PathBuf, Item>,
+        outcome: &mut Outcome,
+        rename: Rename,
+        other: &Files,
+    ) {
+        let Rename {
+            old,
+            new,
+            in_base,
+            by_us,
+        } = rename;
+        if other.contains_key(new) {
+            return;
+        }
+        let Some(renamed) = items.get(new).map(|item| {
+            if by_us {
+                item.ours.clone()
+            } else {
+                item.theirs.clone()
+            }
+        }) else {
+            return;
+        };
+        let other_version = other.get(old).cloned();
+        let conflicted = other_version.is_none();
+        if conflicted {
+            let (renamer, deleter) = if by_us {
+                (self.labels.ours, self.labels.theirs)
+            } else {
+                (self.labels.theirs, self.labels.ours)
+            };
+            outcome.message(
+                new,
+                format!(
+                    "CONFLICT (rename/delete): {} renamed to {} in {}, but deleted in {}.",
+                    old.display(),
+                    new.display(),
+                    renamer,
+                    deleter
+                ),
+            );
+        }
+        let (ours, theirs) = if by_us {
+            (renamed, other_version)
+        } else {
+            (other_version, renamed)
+        };
+        items.remove(old);
+        items.insert(
+            new.to_path_buf(),
+            Item {
+                base: in_base,
+                ours,
+                theirs,
+                conflicted,
+            },
+        );
+    }
+
+    /// Resolves the versions of a file.
+    fn resolve(&self, outcome: &mut Outcome, path: &Path, item: Item) -> anyhow::Result<()> {
+        let Item {
+            base,
+            ours,
+            theirs,
+            conflicted,
+        } = item;
+        if conflicted {
+            if let Some(version) = ours.clone().or(theirs.clone()) {
+                outcome.files.insert(path.to_path_buf(), version);
+            }
+            self.record_conflict(outcome, path, [base, ours, theirs]);
+            return Ok(());
+        }
+
+        let resolved = if ours == theirs || base == theirs {
+            ours
+        } else if base == ours {
+            theirs
+        } else {
+            match (&base, &ours, &theirs) {
+                (_, Some(one), Some(two)) => {
+                    let merged = self.merge_contents(outcome, path, base.as_ref(), one, two)?;
+                    Some(merged)
+                }
+                (Some(_), Some(_), None) | (Some(_), None, Some(_)) => {
+                    let (deleter, modifier) = if ours.is_some() {
+                        (self.labels.theirs, self.labels.ours)
+                    } else {
+                        (self.labels.ours, self.labels.theirs)
+                    };
+                    outcome.message(
+                        path,
+                        format!(
+                            "CONFLICT (modify/delete): {path} deleted in {} and modified in {}.  \
+                             Version {} of {path} left in tree.",
+                            deleter,
+                            modifier,
+                            modifier,
+                            path = path.display()
+                        ),
+                    );
+                    let kept = if self.depth > 0 {
+                        base.clone()
+                    } else {
+                        ours.clone().or(theirs.clone())
+                    };
+                    self.record_conflict(outcome, path, [base, ours, theirs]);
+                    kept
+                }
+                _ => unreachable!("a file missing on both sides is resolved"),
+            }
+        };
+        if let Some(version) = resolved {
+            outcome.files.insert(path.to_path_buf(), version);
+        }
+        Ok(())
+    }
+
+    /// Merges the modes and contents of a file changed on both sides, and returns the merged
+    /// version. Files that cannot be merged keep our version and are in conflict.
+    fn merge_contents(
+        &self,
+        outcome: &mut Outcome,
+        path: &Path,
+        base: Option<&(EntryMode, ObjectID)>,
+        ours: &(EntryMode, ObjectID),
+        theirs: &(EntryMode, ObjectID),
+    ) -> anyhow::Result<(EntryMode, ObjectID)> {
+        let (mode, clean_mode) = merge_modes(base.map(|(mode, _)| *mode), ours.0, theirs.0);
+        let base_oid = base.map(|(_, oid)| oid);
+        let (oid, clean) = if ours.1 == theirs.1 || base_oid == Some(&theirs.1) {
+            (ours.1.clone(), true)
+        } else if base_oid == Some(&ours.1) {
+            (theirs.1.clone(), true)
+        } else if ours.0 == EntryMode::Symlink || theirs.0 == EntryMode::Symlink {
+            (ours.1.clone(), false)
+        } else {
+            outcome.message(path, format!("Auto-merging {}", path.display()));
+            let read =
+                |version: &(EntryMode, ObjectID)| FileVersion::from_blob(&version.1, version.0);
+            let base_data = match base {
+                Some(base) => read(base)?.data,
+                None => Vec::new(),
+            };
+            let (our_data, their_data) = (read(ours)?.data, read(theirs)?.data);
+            if [&base_data, &our_data, &their_data]
+                .iter()
+                .any(|data| is_binary(data))
+            {
+                eprintln!(
+                    "warning: Cannot merge binary files: {} ({} vs. {})",
+                    path.display(),
+                    self.labels.ours,
+                    self.labels.theirs
+                );
+                (ours.1.clone(), false)
+            } else {
+                let merged = file::merge_files(
+                    &base_data,
+                    &our_data,
+                    &their_data,
+                    &self.labels,
+                    self.style,
+                    MARKER_SIZE + 2 * self.depth,
+                );
+                let oid = Blob::new(merged.data.len() as u64, merged.data.as_slice()).write()?;
+                (oid, merged.conflicts == 0)
+            }
+        };
+
+        if !clean || !clean_mode {
+            let reason = if base.is_some() { "content" } else { "add/add" };
+            outcome.message(
+                path,
+                format!(
+                    "CONFLICT ({}): Merge conflict in {}",
+                    reason,
+                    path.display()
+                ),
+            );
+            self.record_conflict(
+                outcome,
+                path,
+                [base.cloned(), Some(ours.clone()), Some(theirs.clone())],
+            );
+        }
+        Ok((mode, oid))
+    }
+
+    /// Records the versions of a conflicted file, unless the merge is one of merge bases.
+    fn record_conflict(&self, outcome: &mut Outcome, path: &Path, versions: [Version; 3]) {
+        if self.depth == 0 {
+            outcome.conflicts.insert(path.to_path_buf(), versions);
+        }
+    }
+
+    /// Moves the merged files that are in the way of a directory of merged files to
+    /// `<path>~<side>`, where the side is the one the file comes from. They are in conflict.
+    fn move_files_in_the_way(&self, outcome: &mut Outcome, ours: &Files) {
+        let dirs = outcome
+            .files
+            .keys()
+            .flat_map(|path| path.ancestors().skip(1))
+            .map(Path::to_path_buf)
+            .collect::<BTreeSet<_>>();
+        let in_the_way = outcome
+            .files
+            .keys()
+            .filter(|path| dirs.contains(*path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in in_the_way {
+            // The directory comes from the side that has files in it.
+            let ours_has_dir = ours
+                .keys()
+                .any(|file| file.starts_with(&path) && *file != path);
+            let (side, slot) = if ours_has_dir {
+                (self.labels.theirs, 2)
+            } else {
+                (self.labels.ours, 1)
+            };
+            let mut new_path = path.clone().into_os_string();
+            new_path.push(format!("~{}", side.replace('/', "_")));
+            let new_path = PathBuf::from(new_path);
+            outcome.message(
+                &path,
+                format!(
+                    "CONFLICT (file/directory): directory in the way of {} from {}; moving it to \
+                     {} instead.",
+                    path.display(),
+                    side,
+                    new_path.display()
+                ),
+            );
+
+            let version = outcome.files.remove(&path).expect("the file was merged");
+            let versions = outcome.conflicts.remove(&path).unwrap_or_else(|| {
+                let mut versions = [None, None, None];
+                versions[slot] = Some(version.clone());
+                versions
+            });
+            outcome.files.insert(new_path.clone(), version);
+            self.record_conflict(outcome, &new_path, versions);
+        }
+    }
+}
+
+/// Returns the renames from the base to a side, from their old path to their new path.
+fn renames(base: &Files, side: &Files) -> anyhow::Result<BTreeMap<PathBuf, PathBuf>> {
+    let changes =
+        rename::detect_renames(tree::diff_files(base, side), &[], &RenameOptions::default())?;
+    Ok(changes
+        .into_iter()
+        .filter(|change| matches!(change.change, Change::Renamed(_)))
+        .filter_map(|change| Some((change.old?.path, change.new?.path)))
+        .collect())
+}
+
+/// Merges the modes of a file changed on both sides, and returns the merged mode and whether it
+/// merged cleanly. When both sides changed the mode differently, our mode is kept.
+fn merge_modes(base: Option<EntryMode>, ours: EntryMode, theirs: EntryMode) -> (EntryMode, bool) {
+    if ours == theirs || base == Some(theirs) {
+        (ours, true)
+    } else if base == Some(ours) {
+        (theirs, true)
+    } else {
+        (ours, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+    use std::io::Read;
+
+    const OPTIONS: MergeOptions = MergeOptions {
+        ours: "ours",
+        theirs: "theirs",
+        style: ConflictStyle::Merge,
+    };
+
+    /// A file long enough for its renames to be detected after small changes.
+    const POEM: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+
+    /// Merges two commits with their merge bases.
+    fn merge(ours: &ObjectID, theirs: &ObjectID) -> TreeMerge {
+        let mut graph = CommitGraph::default();
+        let bases = graph.merge_bases(ours, theirs).unwrap();
+        merge_commits(&mut graph, ours, theirs, &bases, &OPTIONS).unwrap()
+    }
+
+    /// Returns the merged files with their contents.
+    fn contents(merged: &TreeMerge) -> BTreeMap<String, String> {
+        merged
+            .files
+            .iter()
+            .map(|(path, (_, oid))| {
+                let (_, _, mut reader) = crate::objects::read_object(&oid.hash()).unwrap();
+                let mut data = String::new();
+                reader.read_to_string(&mut data).unwrap();
+                (path.to_string_lossy().into_owned(), data)
+            })
+            .collect()
+    }
+
+    fn files<'a>(files: &[(&'a str, &'a str)]) -> BTreeMap<String, String> {
+        files
+            .iter()
+            .map(|(path, data)| (path.to_string(), data.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn merges_separate_changes_cleanly() {
+        let repo = TestRepo::new();
+        let base = repo.commit(&[("a", "a\n"), ("b", "b\n"), ("gone", "x\n")], &[], "base");
+        let ours = repo.commit(&[("a", "A\n"), ("b", "b\n")], &[&base], "ours");
+        let theirs = repo.commit(
+            &[
+                ("a", "a\n"),
+                ("b", "B\n"),
+                ("gone", "x\n"),
+                ("dir/new", "n\n"),
+            ],
+            &[&base],
+            "theirs",
+        );
+        let merged = merge(&ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(
+            contents(&merged),
+            files(&[("a", "A\n"), ("b", "B\n"), ("dir/new", "n\n")])
+        );
+    }
+
+    #[test]
+    fn records_content_conflicts() {
+        let repo = TestRepo::new();
+        let base = repo.commit(&[("f", "a\nb\nc\n")], &[], "base");
+        let ours = repo.commit(&[("f", "a\nX\nc\n")], &[&base], "ours");
+        let theirs = repo.commit(&[("f", "a\nY\nc\n")], &[&base], "theirs");
+        let merged = merge(&ours, &theirs);
+        assert_eq!(
+            contents(&merged)["f"],
+            "a\n<<<<<<< ours\nX\n=======\nY\n>>>>>>> theirs\nc\n"
+        );
+        let [base, ours, theirs] = &merged.conflicts[Path::new("f")];
+        let blob = |data: &str| Some((EntryMode::NonExecutable, repo.blob(data)));
+        assert_eq!(base, &blob("a\nb\nc\n"));
+        assert_eq!(ours, &blob("a\nX\nc\n"));
+        assert_eq!(theirs, &blob("a\nY\nc\n"));
+        assert_eq!(
+            merged.messages,
+            ["Auto-merging f", "CONFLICT (content): Merge conflict in f"]
+        );
+    }
+
+    #[test]
+    fn records_modify_delete_conflicts() {
+        let repo = TestRepo::new();
+        let base = repo.commit(&[("f", "a\n"), ("g", "g\n")], &[], "base");
+        let ours = repo.commit(&[("g", "g\n")], &[&base], "ours");
+        let theirs = repo.commit(&[("f", "b\n"), ("g", "g\n")], &[&base], "theirs");
+        let merged = merge(&ours, &theirs);
+        let [_, ours, theirs] = &merged.conflicts[Path::new("f")];
+        assert!(ours.is_none() && theirs.is_some());
+        assert_eq!(contents(&merged)["f"], "b\n");
+        assert!(merged.messages[0].starts_with("CONFLICT (modify/delete): f deleted in ours"));
+    }
+
+    #[test]
+    fn merges_changes_into_renamed_files() {
+        let repo = TestRepo::new();
+        let base = repo.commit(&[("old", POEM)], &[], "base");
+        let ours = repo.commit(&[("new", POEM)], &[&base], "ours");
+        let changed = POEM.replace("five", "FIVE");
+        let theirs = repo.commit(&[("old", &changed)], &[&base], "theirs");
+        let merged = merge(&ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(contents(&merged), files(&[("new", &changed)]));
+
+        // The same merge the other way around.
+        let merged = merge(&theirs, &ours);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(contents(&merged), files(&[("new", &changed)]));
+    }
+
+    #[test]
+    fn records_rename_delete_conflicts() {
+        let repo = TestRepo::new();
+        let base = repo.commit(&[("old", POEM), ("other", "x\n")], &[], "base");
+        let ours = repo.commit(&[("new", POEM), ("other", "x\n")], &[&base], "ours");
+        let theirs = repo.commit(&[("other", "x\n")], &[&base], "theirs");
+        let merged = merge(&ours, &theirs);
+        assert_eq!(merged.conflicts.len(), 1);
+        let [_, ours, theirs] = &merged.conflicts[Path::new("new")];
+        assert!(ours.is_some() && theirs.is_none());
+        assert_eq!(
+            merged.messages,
+            ["CONFLICT (rename/delete): old renamed to new in ours, but deleted in theirs."]
+        );
+    }
+
+    #[test]
+    fn merges_criss_cross_histories() {
+        // Both sides merged each other once, so there are two merge bases, which are merged into
+        // a virtual base first.
+        let repo = TestRepo::new();
+        let root = repo.commit(&[("f", "1\n2\n3\n")], &[], "root");
+        let left = repo.commit(&[("f", "L\n2\n3\n")], &[&root], "left");
+        let right = repo.commit(&[("f", "1\n2\nR\n")], &[&root], "right");
+        let ours = repo.commit(&[("f", "L\n2\nR\n")], &[&left, &right], "ours");
+        let theirs = repo.commit(
+            &[("f", "L\n2\nR\n"), ("g", "g\n")],
+            &[&right, &left],
+            "theirs",
+        );
+        let mut graph = CommitGraph::default();
+        assert_eq!(graph.merge_bases(&ours, &theirs).unwrap().len(), 2);
+        let merged = merge(&ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(
+            contents(&merged),
+            files(&[("f", "L\n2\nR\n"), ("g", "g\n")])
+        );
+    }
+}
@@ -48,6 +48,44 @@ impl Blob<()> {
         let size = metadata.len();
         Ok(Blob::new(size, file))
     }
+
+    /// Creates a new `Blob` object from a file in the working tree.
+    ///
+    /// Unlike `from_file`, symbolic links are not followed: like git, the blob of a symbolic link
+    /// holds the path it points to.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the file or symbolic link.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a `Blob` with a reader, or an `anyhow::Error` if the file
+    /// or link cannot be read.
+    pub(crate) fn from_worktree(
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<Blob<Box<dyn std::io::Read>>> {
+        let path = path.as_ref();
+        let metadata = std::fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read metadata for {}", path.display()))?;
+        if metadata.is_symlink() {
+            let target = std::fs::read_link(path)
+                .with_context(|| format!("Failed to read link {}", path.display()))?;
+            let target = target.into_os_string().into_encoded_bytes();
+            Ok(Blob {
+                size: target.len() as u64,
+                content: Box::new(std::io::Cursor::new(target)),
+            })
+        } else {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            anyhow::ensure!(!metadata.is_dir(), "Path points to directory.");
+            Ok(Blob {
+                size: metadata.len(),
+                content: Box::new(file),
+            })
+        }
+    }
 }
 
 impl<R> Object for Blob<R>
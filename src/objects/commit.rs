@@ -1,85 +1,325 @@
 use crate::objects;
+use crate::objects::id::ObjectID;
+use crate::objects::signature::Signature;
 use crate::objects::Object;
 use anyhow::Context;
-use std::fmt::Write;
+use std::io::Read;
 
-pub(crate) struct Commit<R> {
-    kind: objects::kind::ObjectKind,
-    size: u64,
-    reader: R,
+/// Represents a commit object.
+///
+/// A commit is made of a list of headers, one per line, followed by an empty line and the commit
+/// message. The headers are the tree, the parents, the author, the committer and, optionally,
+/// extra headers such as `encoding`, `mergetag` or `gpgsig`. Header values that span several lines
+/// are continued on lines starting with a space.
+///
+/// Parsing a commit and serialising it again gives back the same bytes, and so the same
+/// `ObjectID`. The header values and the message are kept as bytes, since they are in the
+/// encoding named by the `encoding` header, UTF-8 by default, and are only decoded for display.
+pub(crate) struct Commit {
+    pub(crate) tree: ObjectID,
+    pub(crate) parents: Vec<ObjectID>,
+    pub(crate) author: Signature,
+    pub(crate) committer: Signature,
+    /// Headers after the committer, in order. Multi-line values are joined with `\n`.
+    pub(crate) headers: Vec<(String, Vec<u8>)>,
+    /// The message, exactly as it follows the empty line after the headers.
+    pub(crate) message: Vec<u8>,
+    reader: std::io::Cursor<Vec<u8>>,
 }
 
-impl Commit<()> {
+impl Commit {
     /// Creates new `Commit` object.
     ///
     /// # Arguments
     ///
-    /// * `tree` - The tree hash.
-    /// * `parent` - The parent hash.
-    /// * `author_name` - The author name.
-    /// * `author_email` - The author email.
-    /// * `committer_name` - The committer name.
-    /// * `committer_email` - The committer email.
-    /// * `message` - The commit message.
+    /// * `tree` - The ID of the tree.
+    /// * `parents` - The IDs of the parents: none for a root commit, several for a merge.
+    /// * `author` - The author and the date the changes were made.
+    /// * `committer` - The committer and the date of the commit.
+    /// * `message` - The commit message, written as is, so it should end with a newline.
     ///
     /// # Returns
     ///
     /// Returns a `Commit` object.
     pub(crate) fn new(
-        tree: objects::id::ObjectID,
-        parent: Option<objects::id::ObjectID>,
-        author_name: String,
-        author_email: String,
-        committer_name: String,
-        committer_email: String,
+        tree: ObjectID,
+        parents: Vec<ObjectID>,
+        author: Signature,
+        committer: Signature,
         message: String,
-    ) -> anyhow::Result<Commit<impl std::io::Read>> {
-        let mut commit = String::new();
-        // Write the tree and parent hashes (if any)
-        writeln!(commit, "tree {}", tree.hash()).context("Failed to write tree hash")?;
-        if let Some(parent) = parent {
-            writeln!(commit, "parent {}\n", parent.hash())
-                .context("Failed to write parent hash")?;
-        };
-
-        // Write the author and committer information with the current timestamp
-        let local = chrono::Local::now();
-        let timestamp = local.timestamp();
-        let timezone = format!("{}", local.offset()).replace(":", "");
-        writeln!(
-            commit,
-            "author {} <{}> {} {}",
-            author_name, author_email, timestamp, timezone
-        )
-        .context("Failed to write author information")?;
-        writeln!(
-            commit,
-            "committer {} <{}> {} {}",
-            committer_name, committer_email, timestamp, timezone
-        )
-        .context("Failed to write committer information")?;
-
-        // Write the commit message
-        writeln!(commit, "\n{}", message).context("Failed to write commit message")?;
+    ) -> Commit {
+        Commit {
+            tree,
+            parents,
+            author,
+            committer,
+            headers: Vec::new(),
+            message: message.into_bytes(),
+            reader: std::io::Cursor::new(Vec::new()),
+        }
+    }
+
+    /// Parses the content of a commit object.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The content of the commit object, without the object header.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the parsed `Commit`, or an `anyhow::Error` if the commit is
+    /// malformed.
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<Commit> {
+        let (header, message) =
+            split_message(data).context("Commit has no empty line after its headers.")?;
+
+        let mut headers = split_headers(header)?.into_iter().peekable();
+        let tree = ObjectID::from_hash(&expect_text_header(&mut headers, "tree")?)?;
+        let mut parents = Vec::new();
+        while let Some((_, value)) = headers.next_if(|(name, _)| name == "parent") {
+            let parent = std::str::from_utf8(&value).context("Invalid parent header.")?;
+            parents.push(ObjectID::from_hash(parent)?);
+        }
+        let author = Signature::parse(&expect_header(&mut headers, "author")?)?;
+        let committer = Signature::parse(&expect_header(&mut headers, "committer")?)?;
 
         Ok(Commit {
-            kind: objects::kind::ObjectKind::Commit,
-            size: commit.len() as u64,
-            reader: std::io::Cursor::new(commit),
+            tree,
+            parents,
+            author,
+            committer,
+            headers: headers.collect(),
+            message: message.to_vec(),
+            reader: std::io::Cursor::new(Vec::new()),
         })
     }
+
+    /// Reads a commit from the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The hash of the commit.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the parsed `Commit`, or an `anyhow::Error` if the object
+    /// cannot be read, is not a commit or is malformed.
+    pub(crate) fn read(hash: &str) -> anyhow::Result<Commit> {
+        let (kind, _, mut reader) = objects::read_object(hash)?;
+        anyhow::ensure!(
+            kind == objects::kind::ObjectKind::Commit,
+            "Object {} is not a commit.",
+            hash
+        );
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read commit {}", hash))?;
+        Commit::parse(&data).with_context(|| format!("Failed to parse commit {}", hash))
+    }
+
+    /// Returns the message, decoded for display. Bytes that are not UTF-8 are replaced.
+    pub(crate) fn display_message(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.message)
+    }
+
+    /// Returns the subject of the commit: the first paragraph of the message, with its lines
+    /// joined by spaces.
+    pub(crate) fn subject(&self) -> String {
+        self.display_message()
+            .lines()
+            .skip_while(|line| line.trim().is_empty())
+            .take_while(|line| !line.trim().is_empty())
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Returns the body of the commit: the message after the subject, without the empty lines
+    /// that separate them.
+    pub(crate) fn body(&self) -> String {
+        let message = self.display_message();
+        let mut rest = message.as_ref();
+        let mut in_subject = false;
+        while let Some((line, next)) = rest.split_once('\n') {
+            let blank = line.trim().is_empty();
+            if in_subject && blank {
+                break;
+            }
+            in_subject |= !blank;
+            rest = next;
+        }
+        if !in_subject {
+            return String::new();
+        }
+        rest.trim_start_matches(['\n', '\r']).to_string()
+    }
+
+    /// Serialises the commit into the content of a commit object.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut commit = format!("tree {}\n", self.tree).into_bytes();
+        for parent in &self.parents {
+            commit.extend(format!("parent {}\n", parent).as_bytes());
+        }
+        write_header(&mut commit, "author", &self.author.to_bytes());
+        write_header(&mut commit, "committer", &self.committer.to_bytes());
+        for (name, value) in &self.headers {
+            write_header(&mut commit, name, value);
+        }
+        commit.push(b'\n');
+        commit.extend(&self.message);
+        commit
+    }
+}
+
+/// Splits a commit or tag object at the first empty line, into its headers and its message.
+///
+/// # Returns
+///
+/// Returns the headers, without the newline ending the last one, and the message, or `None` if
+/// the object has no empty line.
+pub(super) fn split_message(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let position = data.windows(2).position(|window| window == b"\n\n")?;
+    Some((&data[..position], &data[position + 2..]))
+}
+
+/// Writes a header of a commit or tag object, continuing the lines of a multi-line value on
+/// lines starting with a space.
+pub(super) fn write_header(data: &mut Vec<u8>, name: &str, value: &[u8]) {
+    data.extend(name.as_bytes());
+    data.push(b' ');
+    for &byte in value {
+        data.push(byte);
+        if byte == b'\n' {
+            data.push(b' ');
+        }
+    }
+    data.push(b'\n');
+}
+
+/// Splits the headers of a commit or tag object into names and values, joining continuation
+/// lines to the value of the header they continue.
+///
+/// # Arguments
+///
+/// * `header` - The headers, up to the empty line before the message.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the names and values of the headers, in order, or an
+/// `anyhow::Error` if a header is malformed.
+pub(super) fn split_headers(header: &[u8]) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let mut headers: Vec<(String, Vec<u8>)> = Vec::new();
+    for line in header.split(|&b| b == b'\n') {
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = headers
+                .last_mut()
+                .context("Object starts with a continuation line.")?;
+            value.push(b'\n');
+            value.extend(continuation);
+        } else {
+            let malformed =
+                || format!("Malformed object header: {}", String::from_utf8_lossy(line));
+            let space = line
+                .iter()
+                .position(|&b| b == b' ')
+                .with_context(malformed)?;
+            let name = std::str::from_utf8(&line[..space]).with_context(malformed)?;
+            headers.push((name.to_string(), line[space + 1..].to_vec()));
+        }
+    }
+    Ok(headers)
 }
 
-impl<R: std::io::Read> Object for Commit<R> {
+/// Takes the next header, which must have the given name, and returns its value.
+pub(super) fn expect_header(
+    headers: &mut impl Iterator<Item = (String, Vec<u8>)>,
+    name: &str,
+) -> anyhow::Result<Vec<u8>> {
+    match headers.next() {
+        Some((header, value)) if header == name => Ok(value),
+        _ => anyhow::bail!("Object is missing the {} header.", name),
+    }
+}
+
+/// Takes the next header, which must have the given name and a UTF-8 value, such as an object
+/// ID, and returns its value.
+pub(super) fn expect_text_header(
+    headers: &mut impl Iterator<Item = (String, Vec<u8>)>,
+    name: &str,
+) -> anyhow::Result<String> {
+    String::from_utf8(expect_header(headers, name)?)
+        .with_context(|| format!("Invalid {} header.", name))
+}
+
+impl Object for Commit {
     fn kind(&self) -> &objects::kind::ObjectKind {
-        &self.kind
+        &objects::kind::ObjectKind::Commit
     }
 
     fn size(&self) -> u64 {
-        self.size
+        self.to_bytes().len() as u64
     }
 
     fn content(&mut self) -> &mut dyn std::io::Read {
+        self.reader = std::io::Cursor::new(self.to_bytes());
         &mut self.reader
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_commits_in_other_encodings() {
+        let data = b"tree aaff74984cccd156a469afa7d9ab10e4777beb24\n\
+            parent 490b9aaf7c4ff054685a947977ef77530203f500\n\
+            author J\xf6rg <j@example.com> 1700000000 +0100\n\
+            committer J\xf6rg <j@example.com> 1700000000 +0100\n\
+            encoding ISO-8859-1\n\
+            \n\
+            S\xe9curit\xe9\n\nbody \xe9\n";
+        let commit = Commit::parse(data).unwrap();
+        assert_eq!(commit.author.name, b"J\xf6rg");
+        assert_eq!(
+            commit.headers,
+            vec![("encoding".to_string(), b"ISO-8859-1".to_vec())]
+        );
+        assert_eq!(commit.subject(), "S\u{fffd}curit\u{fffd}");
+        assert_eq!(commit.body(), "body \u{fffd}\n");
+        assert_eq!(commit.to_bytes(), data);
+    }
+
+    #[test]
+    fn round_trips_multi_line_headers() {
+        let data = b"tree aaff74984cccd156a469afa7d9ab10e4777beb24\n\
+            author A <a@example.com> 1700000000 +0000\n\
+            committer A <a@example.com> 1700000000 +0000\n\
+            gpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\
+            \n\
+            subject\n";
+        let commit = Commit::parse(data).unwrap();
+        assert_eq!(
+            commit.headers[0].1,
+            b"-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----"
+        );
+        assert_eq!(commit.to_bytes(), data);
+    }
+
+    #[test]
+    fn round_trips_irregular_signatures() {
+        let data = b"tree aaff74984cccd156a469afa7d9ab10e4777beb24\n\
+            author A<a@example.com>  0123 +0100\n\
+            committer B  <b@example.com> 1700000000  -0030\n\
+            \n\
+            subject\n";
+        let commit = Commit::parse(data).unwrap();
+        assert_eq!(commit.author.name, b"A");
+        assert_eq!(commit.author.timestamp, 123);
+        assert_eq!(commit.author.timezone, "+0100");
+        assert_eq!(commit.committer.name, b"B");
+        assert_eq!(commit.committer.timezone, "-0030");
+        assert_eq!(commit.to_bytes(), data);
+    }
+}
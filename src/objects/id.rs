@@ -1,25 +1,29 @@
+use anyhow::Context;
+
 /// Represents an Object ID.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub(crate) struct ObjectID {
     bytes: [u8; 20],
 }
 
 /// The `ObjectID` struct provides methods for creating, manipulating, and converting object IDs.
 impl ObjectID {
-    /// Creates a new ObjectID with the given bytes.
     /// Creates an `ObjectID` from a hash string.
     ///
     /// # Arguments
     ///
-    /// * `hash` - A string slice that represents the hash.
+    /// * `hash` - A string slice that represents the hash, as 40 hex digits.
     ///
     /// # Returns
     ///
-    /// An `ObjectID` created from the given hash.
-    pub(crate) fn from_hash(hash: impl AsRef<str>) -> ObjectID {
+    /// Returns a `Result` containing the `ObjectID` created from the given hash, or an
+    /// `anyhow::Error` if the hash is not 40 hex digits.
+    pub(crate) fn from_hash(hash: impl AsRef<str>) -> anyhow::Result<ObjectID> {
+        let hash = hash.as_ref();
         let mut bytes = [0; 20];
-        hex::decode_to_slice(hash.as_ref(), &mut bytes).unwrap();
-        ObjectID::from_bytes(bytes)
+        hex::decode_to_slice(hash, &mut bytes)
+            .with_context(|| format!("Invalid object ID: {}", hash))?;
+        Ok(ObjectID::from_bytes(bytes))
     }
 
     /// Creates an `ObjectID` from a byte array.
@@ -35,13 +39,18 @@ impl ObjectID {
         ObjectID { bytes }
     }
 
+    /// Returns the null `ObjectID`, made of zeros, which stands for a missing object.
+    pub(crate) fn zero() -> ObjectID {
+        ObjectID::from_bytes([0; 20])
+    }
+
     /// Returns the hash string representation of the `ObjectID`.
     ///
     /// # Returns
     ///
     /// A string that represents the hash of the `ObjectID`.
     pub(crate) fn hash(&self) -> String {
-        hex::encode(&self.bytes)
+        hex::encode(self.bytes)
     }
 
     /// Returns the byte array representation of the `ObjectID`.
@@ -1,11 +1,34 @@
 use std::fmt;
 
 /// Represents the kind of object.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) enum ObjectKind {
     Blob,
     Tree,
     Commit,
+    Tag,
+}
+
+impl ObjectKind {
+    /// Parses the name of an object kind, as found in object headers.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the kind, e.g. `blob`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `ObjectKind`, or an `anyhow::Error` if the name is not one
+    /// of the known kinds.
+    pub(crate) fn parse(name: &str) -> anyhow::Result<ObjectKind> {
+        match name {
+            "blob" => Ok(ObjectKind::Blob),
+            "tree" => Ok(ObjectKind::Tree),
+            "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
+            _ => anyhow::bail!("Object kind is not one of the acceptables: {}", name),
+        }
+    }
 }
 
 /// Implements the `Display` trait for `ObjectKind`.
@@ -25,6 +48,7 @@ impl fmt::Display for ObjectKind {
             ObjectKind::Blob => write!(f, "blob"),
             ObjectKind::Tree => write!(f, "tree"),
             ObjectKind::Commit => write!(f, "commit"),
+            ObjectKind::Tag => write!(f, "tag"),
         }
     }
 }
@@ -3,6 +3,9 @@ pub mod blob;
 pub mod commit;
 pub mod id;
 pub mod kind;
+pub mod pack;
+pub mod signature;
+pub mod tag;
 pub mod tree;
 
 use crate::objects;
@@ -86,14 +89,17 @@ pub(crate) trait Object {
         // Create the final object path
         let object_path = format!("{}/{}/{}", &db_path, &hash[..2], &hash[2..]);
         let object_path = std::path::Path::new(&object_path);
-        let _ = std::fs::create_dir_all(object_path.parent().unwrap())?;
-        let _ = std::fs::rename(&temp_path, &object_path);
+        std::fs::create_dir_all(object_path.parent().unwrap())?;
+        let _ = std::fs::rename(temp_path, object_path);
         Ok(object_id)
     }
 }
 
 /// Reads an object from the database given its hash.
 ///
+/// The object is first looked up as a loose file under `.git/objects`. If there is no loose file,
+/// the packfiles under `.git/objects/pack` are searched.
+///
 /// # Arguments
 ///
 /// * `hash` - The hash of the object.
@@ -111,6 +117,16 @@ pub(crate) fn read_object(
     // Create the object path from its hash
     let path = format!(".git/objects/{}/{}", &hash[..2], &hash[2..]);
 
+    // If there is no loose object, look for the object in the packfiles.
+    if !std::path::Path::new(&path).exists() {
+        let oid = objects::id::ObjectID::from_hash(hash)?;
+        let (kind, data) = objects::pack::read_packed_object(&oid)?
+            .with_context(|| format!("Object {} does not exist in the database.", hash))?;
+        let size = data.len() as u64;
+        let reader: Box<dyn BufRead> = Box::new(std::io::Cursor::new(data));
+        return Ok((kind, size, reader));
+    }
+
     // Read the file into a buffer: Read & decompress
     let file = std::fs::File::open(&path).context("Loading raw file from the database.")?;
     let reader = flate2::read::ZlibDecoder::new(file);
@@ -143,16 +159,61 @@ pub(crate) fn read_object(
     let reader = reader; //.take(size);
 
     // Return the object depending on its type
-    let object_type = match object_type {
-        "blob" => objects::kind::ObjectKind::Blob,
-        "tree" => objects::kind::ObjectKind::Tree,
-        "commit" => objects::kind::ObjectKind::Commit,
-        _ => anyhow::bail!("Object kind is not one of the acceptables."),
-    };
+    let object_type = objects::kind::ObjectKind::parse(object_type)?;
 
+    let reader: Box<dyn BufRead> = Box::new(reader);
     Ok((object_type, size, reader))
 }
 
+/// Checks whether an object is in the database, as a loose file or in a pack, without reading it.
+///
+/// # Arguments
+///
+/// * `oid` - The ID of the object.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `true` if the object exists, or an `anyhow::Error` if the packs
+/// cannot be opened.
+pub(crate) fn has_object(oid: &objects::id::ObjectID) -> anyhow::Result<bool> {
+    let hash = oid.hash();
+    let path = format!(".git/objects/{}/{}", &hash[..2], &hash[2..]);
+    if std::path::Path::new(&path).exists() {
+        return Ok(true);
+    }
+    objects::pack::has_packed_object(oid)
+}
+
+/// Lists the IDs of all loose objects in the database.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the IDs of the loose objects, or an `anyhow::Error` if the
+/// object directories cannot be read.
+pub(crate) fn list_loose_objects() -> anyhow::Result<Vec<objects::id::ObjectID>> {
+    let mut oids = Vec::new();
+    let db_path = std::path::Path::new(".git/objects");
+    for direntry in std::fs::read_dir(db_path).context("Failed to read object database.")? {
+        let direntry = direntry.context("Bad entry in object database.")?;
+        let prefix = direntry.file_name();
+        let prefix = prefix.to_string_lossy();
+        // Loose objects live in directories named after the first two hex digits of their hash.
+        if prefix.len() != 2 || !direntry.path().is_dir() {
+            continue;
+        }
+        for object in std::fs::read_dir(direntry.path())? {
+            let name = object?.file_name();
+            let hash = format!("{}{}", prefix, name.to_string_lossy());
+            let mut bytes = [0u8; 20];
+            if hex::decode_to_slice(&hash, &mut bytes).is_ok() {
+                oids.push(objects::id::ObjectID::from_bytes(bytes));
+            }
+        }
+    }
+    oids.sort();
+    Ok(oids)
+}
+
 /// A writer that calculates the SHA-1 hash of the written data.
 pub(crate) struct HashWriter<W> {
     pub(crate) writer: W,
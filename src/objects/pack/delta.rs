@@ -0,0 +1,286 @@
+/// Length of the blocks of the base object that are indexed when creating a delta.
+const BLOCK_SIZE: usize = 16;
+/// Largest number of bytes a single copy instruction can reference.
+const MAX_COPY_SIZE: usize = 0x10000;
+/// Largest number of bytes a single insert instruction can carry.
+const MAX_INSERT_SIZE: usize = 0x7f;
+
+/// Applies a git delta to a base object and returns the reconstructed object.
+///
+/// A delta starts with the sizes of the base and of the result, each encoded as a little-endian
+/// base-128 varint. It is followed by a sequence of instructions. An instruction with the most
+/// significant bit set copies a range of the base: the lower four bits select which offset bytes
+/// follow and the next three bits select which size bytes follow (a size of zero means 0x10000).
+/// An instruction with the most significant bit clear inserts the next `n` bytes of the delta.
+///
+/// # Arguments
+///
+/// * `base` - The contents of the base object.
+/// * `delta` - The delta instructions.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the contents of the reconstructed object, or an `anyhow::Error`
+/// if the delta is malformed or does not belong to the given base.
+pub(crate) fn apply(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut position = 0;
+    let base_size = read_size(delta, &mut position)?;
+    anyhow::ensure!(
+        base_size == base.len() as u64,
+        "Delta base size mismatch. Expected size: {}. Actual size: {}",
+        base_size,
+        base.len()
+    );
+    let result_size = read_size(delta, &mut position)?;
+
+    let mut result = Vec::with_capacity(result_size as usize);
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+
+        if instruction & 0x80 != 0 {
+            // Copy a range of the base object.
+            let mut offset = 0usize;
+            for i in 0..4 {
+                if instruction & (1 << i) != 0 {
+                    let byte = *delta.get(position).ok_or_else(truncated)?;
+                    offset |= (byte as usize) << (8 * i);
+                    position += 1;
+                }
+            }
+            let mut size = 0usize;
+            for i in 0..3 {
+                if instruction & (0x10 << i) != 0 {
+                    let byte = *delta.get(position).ok_or_else(truncated)?;
+                    size |= (byte as usize) << (8 * i);
+                    position += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let range = base
+                .get(offset..offset + size)
+                .ok_or_else(|| anyhow::anyhow!("Delta copies outside of the base object."))?;
+            result.extend_from_slice(range);
+        } else if instruction != 0 {
+            // Insert the following bytes of the delta.
+            let size = instruction as usize;
+            let range = delta.get(position..position + size).ok_or_else(truncated)?;
+            result.extend_from_slice(range);
+            position += size;
+        } else {
+            anyhow::bail!("Delta contains the reserved instruction 0.");
+        }
+    }
+
+    anyhow::ensure!(
+        result.len() as u64 == result_size,
+        "Delta result size mismatch. Expected size: {}. Actual size: {}",
+        result_size,
+        result.len()
+    );
+    Ok(result)
+}
+
+/// Reads a little-endian base-128 size from the start of a delta.
+fn read_size(delta: &[u8], position: &mut usize) -> anyhow::Result<u64> {
+    let mut size = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*position).ok_or_else(truncated)?;
+        *position += 1;
+        anyhow::ensure!(shift < u64::BITS, "Delta size is too large.");
+        size |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Error returned when the delta ends in the middle of an instruction.
+fn truncated() -> anyhow::Error {
+    anyhow::anyhow!("Delta is truncated.")
+}
+
+/// Creates a delta that reconstructs `target` from `base`.
+///
+/// The base object is split into fixed size blocks which are indexed by their contents. The
+/// target is then scanned, and wherever a block of the base matches, the match is extended as
+/// far as possible and emitted as a copy instruction. Everything else is emitted as inserts.
+///
+/// # Arguments
+///
+/// * `base` - The contents of the base object.
+/// * `target` - The contents of the object to encode.
+///
+/// # Returns
+///
+/// The delta instructions, which can be passed to [`apply`] together with `base`.
+pub(crate) fn create(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_size(&mut delta, base.len() as u64);
+    write_size(&mut delta, target.len() as u64);
+
+    // Index the blocks of the base object. The first occurrence of each block is kept.
+    let mut blocks = std::collections::HashMap::new();
+    let mut offset = 0;
+    while offset + BLOCK_SIZE <= base.len() {
+        blocks
+            .entry(&base[offset..offset + BLOCK_SIZE])
+            .or_insert(offset);
+        offset += BLOCK_SIZE;
+    }
+
+    let mut pending = Vec::new();
+    let mut position = 0;
+    while position < target.len() {
+        let matched = target
+            .get(position..position + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block));
+        let Some(&base_offset) = matched else {
+            pending.push(target[position]);
+            position += 1;
+            continue;
+        };
+
+        // Extend the match forwards as far as both objects agree.
+        let mut length = BLOCK_SIZE;
+        while base_offset + length < base.len()
+            && position + length < target.len()
+            && base[base_offset + length] == target[position + length]
+        {
+            length += 1;
+        }
+
+        write_inserts(&mut delta, &pending);
+        pending.clear();
+        write_copies(&mut delta, base_offset, length);
+        position += length;
+    }
+    write_inserts(&mut delta, &pending);
+
+    delta
+}
+
+/// Writes a size as a little-endian base-128 varint.
+fn write_size(delta: &mut Vec<u8>, mut size: u64) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            delta.push(byte);
+            return;
+        }
+        delta.push(byte | 0x80);
+    }
+}
+
+/// Writes insert instructions for the given bytes, splitting them into chunks that fit.
+fn write_inserts(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT_SIZE) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+/// Writes copy instructions for a range of the base, splitting it into chunks that fit.
+///
+/// Only the non-zero bytes of the offset and size are written, and the instruction byte records
+/// which ones are present. A chunk of exactly 0x10000 bytes is written without any size bytes.
+fn write_copies(delta: &mut Vec<u8>, mut offset: usize, mut length: usize) {
+    while length > 0 {
+        let size = length.min(MAX_COPY_SIZE);
+        let mut instruction = 0x80u8;
+        let mut operands = Vec::with_capacity(7);
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                instruction |= 1 << i;
+                operands.push(byte);
+            }
+        }
+        if size != MAX_COPY_SIZE {
+            for i in 0..3 {
+                let byte = (size >> (8 * i)) as u8;
+                if byte != 0 {
+                    instruction |= 0x10 << i;
+                    operands.push(byte);
+                }
+            }
+        }
+        delta.push(instruction);
+        delta.extend_from_slice(&operands);
+
+        offset += size;
+        length -= size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns pseudo-random bytes, the same for the same seed.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let delta = create(base, target);
+        assert_eq!(apply(base, &delta).unwrap(), target);
+        delta
+    }
+
+    #[test]
+    fn round_trips_edge_cases() {
+        round_trip(b"", b"");
+        round_trip(b"", b"only inserts");
+        round_trip(b"only a base", b"");
+        round_trip(b"short", b"short");
+        let base = noise(1000, 1);
+        let delta = round_trip(&base, &base);
+        assert!(delta.len() < 16, "identical objects are copied");
+    }
+
+    #[test]
+    fn round_trips_edits() {
+        let base = noise(5000, 2);
+        let mut target = base.clone();
+        target.splice(100..110, noise(300, 3));
+        target.drain(2000..2500);
+        target.extend(&base[..700]);
+        let delta = round_trip(&base, &target);
+        assert!(delta.len() < target.len() / 4);
+    }
+
+    #[test]
+    fn splits_long_copies_and_inserts() {
+        // Copies are limited to 0x10000 bytes and inserts to 0x7f bytes per instruction.
+        let base = noise(3 * MAX_COPY_SIZE + 17, 4);
+        round_trip(&base, &base);
+        round_trip(b"", &noise(10 * MAX_INSERT_SIZE + 3, 5));
+    }
+
+    #[test]
+    fn rejects_malformed_deltas() {
+        let delta = create(b"base object", b"target object");
+        assert!(apply(b"another base", &delta).is_err());
+        assert!(apply(b"base object", &delta[..delta.len() - 1]).is_err());
+        // The reserved instruction 0.
+        assert!(apply(b"", &[0, 1, 0]).is_err());
+        // A copy outside of the base.
+        assert!(apply(b"abc", &[3, 4, 0x91, 2, 4]).is_err());
+        // A size that does not fit in 64 bits.
+        assert!(apply(b"", &[0xff; 12]).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::objects::id::ObjectID;
+use anyhow::Context;
+use std::path::Path;
+
+/// Magic number at the start of every version 2 pack index: `\377tOc`.
+pub(crate) const IDX_SIGNATURE: [u8; 4] = [0xff, b't', b'O', b'c'];
+/// The only pack index version that is supported.
+pub(crate) const IDX_VERSION: u32 = 2;
+/// Offsets with this bit set point into the table of 64-bit offsets.
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+/// Represents a version 2 pack index (`.idx`) file.
+///
+/// The index maps the object IDs contained in a packfile to the offsets of their entries. It
+/// consists of a header, a fanout table of 256 cumulative counts keyed by the first byte of the
+/// object ID, the sorted object IDs, their CRC32 checksums, their 32-bit offsets, an optional
+/// table of 64-bit offsets, and finally the checksums of the packfile and of the index itself.
+pub(crate) struct PackIndex {
+    fanout: [u32; 256],
+    oids: Vec<ObjectID>,
+    offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    /// Reads and parses a pack index file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the `.idx` file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the parsed `PackIndex`, or an `anyhow::Error` if the file
+    /// cannot be read or is not a valid version 2 index.
+    pub(crate) fn open(path: impl AsRef<Path>) -> anyhow::Result<PackIndex> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read pack index: {}", path.display()))?;
+        PackIndex::parse(&data).with_context(|| format!("Invalid pack index: {}", path.display()))
+    }
+
+    /// Parses the contents of a pack index file.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The raw bytes of the `.idx` file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the parsed `PackIndex`, or an `anyhow::Error` if parsing fails.
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<PackIndex> {
+        anyhow::ensure!(data.len() >= 8 + 256 * 4 + 40, "Pack index is truncated.");
+        anyhow::ensure!(
+            data[..4] == IDX_SIGNATURE,
+            "Pack index signature is not recognised."
+        );
+        let version = read_u32(data, 4);
+        anyhow::ensure!(
+            version == IDX_VERSION,
+            "Unsupported pack index version: {}",
+            version
+        );
+
+        // Parse the fanout table. The last entry is the total number of objects.
+        let mut fanout = [0u32; 256];
+        for (i, count) in fanout.iter_mut().enumerate() {
+            *count = read_u32(data, 8 + i * 4);
+        }
+        let count = fanout[255] as usize;
+
+        let names_start = 8 + 256 * 4;
+        let crcs_start = names_start + count * 20;
+        let offsets_start = crcs_start + count * 4;
+        let large_offsets_start = offsets_start + count * 4;
+        anyhow::ensure!(
+            data.len() >= large_offsets_start + 40,
+            "Pack index is truncated."
+        );
+
+        let mut oids = Vec::with_capacity(count);
+        let mut offsets = Vec::with_capacity(count);
+        for i in 0..count {
+            let mut bytes = [0u8; 20];
+            bytes.copy_from_slice(&data[names_start + i * 20..names_start + (i + 1) * 20]);
+            oids.push(ObjectID::from_bytes(bytes));
+
+            // Offsets that do not fit in 31 bits are stored in the 64-bit offset table.
+            let offset = read_u32(data, offsets_start + i * 4);
+            let offset = if offset & LARGE_OFFSET_FLAG != 0 {
+                let position = large_offsets_start + (offset & !LARGE_OFFSET_FLAG) as usize * 8;
+                anyhow::ensure!(
+                    position + 8 <= data.len() - 40,
+                    "Pack index large offset is out of range."
+                );
+                u64::from_be_bytes(data[position..position + 8].try_into()?)
+            } else {
+                offset as u64
+            };
+            offsets.push(offset);
+        }
+
+        Ok(PackIndex {
+            fanout,
+            oids,
+            offsets,
+        })
+    }
+
+    /// Looks up the offset of an object in the packfile.
+    ///
+    /// The fanout table narrows the search to the objects that share the first byte of the ID,
+    /// and the remaining range is binary searched.
+    ///
+    /// # Arguments
+    ///
+    /// * `oid` - The ID of the object to look up.
+    ///
+    /// # Returns
+    ///
+    /// The offset of the object's entry in the packfile, or `None` if the pack does not contain it.
+    pub(crate) fn find(&self, oid: &ObjectID) -> Option<u64> {
+        let first = oid.to_bytes()[0] as usize;
+        let start = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1] as usize
+        };
+        let end = self.fanout[first] as usize;
+        self.oids[start..end]
+            .binary_search(oid)
+            .ok()
+            .map(|i| self.offsets[start + i])
+    }
+
+    /// Returns the object IDs contained in the pack, in sorted order.
+    pub(crate) fn oids(&self) -> &[ObjectID] {
+        &self.oids
+    }
+
+    /// Returns the object IDs contained in the pack along with the offsets of their entries, in
+    /// the order of the object IDs.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&ObjectID, u64)> {
+        self.oids.iter().zip(self.offsets.iter().copied())
+    }
+}
+
+/// Reads a big-endian `u32` at the given position.
+fn read_u32(data: &[u8], position: usize) -> u32 {
+    u32::from_be_bytes([
+        data[position],
+        data[position + 1],
+        data[position + 2],
+        data[position + 3],
+    ])
+}
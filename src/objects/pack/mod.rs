@@ -0,0 +1,671 @@
+/// This module contains the implementation of packfiles and their indexes.
+pub(crate) mod delta;
+pub(crate) mod idx;
+pub(crate) mod write;
+
+use crate::objects;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::objects::pack::idx::PackIndex;
+use anyhow::Context;
+use byteorder::ReadBytesExt;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Directory holding the packfiles of the object database.
+pub(crate) const PACK_DIR: &str = ".git/objects/pack";
+/// Magic number at the start of every packfile.
+pub(crate) const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+/// Packfile versions that can be read.
+const PACK_VERSIONS: [u32; 2] = [2, 3];
+
+/// The packs of the object database, opened on first use and kept open, see `packs`.
+static PACKS: Mutex<Option<Arc<Vec<Pack>>>> = Mutex::new(None);
+
+/// The longest delta chain that is followed. git never writes chains longer than 4095 entries,
+/// and a longer chain is taken as a sign of a corrupt pack whose deltas loop.
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// Type numbers of the entries stored in a packfile.
+pub(crate) const OBJ_COMMIT: u8 = 1;
+pub(crate) const OBJ_TREE: u8 = 2;
+pub(crate) const OBJ_BLOB: u8 = 3;
+pub(crate) const OBJ_TAG: u8 = 4;
+pub(crate) const OBJ_OFS_DELTA: u8 = 6;
+pub(crate) const OBJ_REF_DELTA: u8 = 7;
+
+/// Describes how the data of a packed entry should be interpreted.
+enum EntryKind {
+    /// The entry holds the full contents of an object.
+    Object(ObjectKind),
+    /// The entry is a delta against the entry at the given offset of the same pack.
+    OfsDelta(u64),
+    /// The entry is a delta against the object with the given ID.
+    RefDelta(ObjectID),
+}
+
+/// Represents a packfile together with its index. The packfile is kept open, so that reading
+/// its entries does not open it again.
+pub(crate) struct Pack {
+    path: PathBuf,
+    file: std::fs::File,
+    index: PackIndex,
+}
+
+impl Pack {
+    /// Opens the packfile that belongs to the given index file.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx_path` - The path to the `.idx` file. The packfile is expected next to it, with the
+    ///   `.pack` extension.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the opened `Pack`, or an `anyhow::Error` if the index cannot
+    /// be parsed or the packfile has an invalid header.
+    pub(crate) fn open(idx_path: impl AsRef<Path>) -> anyhow::Result<Pack> {
+        let idx_path = idx_path.as_ref();
+        let index = PackIndex::open(idx_path)?;
+        let path = idx_path.with_extension("pack");
+
+        // Check the header of the packfile: 'PACK' 'version' 'object count'.
+        let mut file = std::fs::File::open(&path)
+            .with_context(|| format!("Failed to open packfile: {}", path.display()))?;
+        let mut header = [0u8; 12];
+        file.read_exact(&mut header)
+            .with_context(|| format!("Failed to read packfile header: {}", path.display()))?;
+        anyhow::ensure!(
+            &header[..4] == PACK_SIGNATURE,
+            "Invalid packfile signature: {}",
+            path.display()
+        );
+        let version = u32::from_be_bytes(header[4..8].try_into()?);
+        anyhow::ensure!(
+            PACK_VERSIONS.contains(&version),
+            "Unsupported packfile version: {}",
+            version
+        );
+        let count = u32::from_be_bytes(header[8..12].try_into()?);
+        anyhow::ensure!(
+            count as usize == index.oids().len(),
+            "Packfile {} holds {} objects but its index lists {}.",
+            path.display(),
+            count,
+            index.oids().len()
+        );
+
+        Ok(Pack { path, file, index })
+    }
+
+    /// Opens every pack in the object database.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the packs ordered by path, or an `anyhow::Error` if any of
+    /// them cannot be opened. If the pack directory does not exist, no packs are returned.
+    pub(crate) fn list() -> anyhow::Result<Vec<Pack>> {
+        list_indexes()?.iter().map(Pack::open).collect()
+    }
+
+    /// Returns the path of the packfile.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the index of the packfile.
+    pub(crate) fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    /// Reads an object from the pack.
+    ///
+    /// # Arguments
+    ///
+    /// * `oid` - The ID of the object to read.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the kind and contents of the object, `None` if the pack does
+    /// not contain it, or an `anyhow::Error` if the entry cannot be read.
+    pub(crate) fn read(&self, oid: &ObjectID) -> anyhow::Result<Option<(ObjectKind, Vec<u8>)>> {
+        match self.index.find(oid) {
+            Some(offset) => Ok(Some(self.read_at(offset).with_context(|| {
+                format!("Failed to read {} from {}", oid, self.path.display())
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the object whose entry starts at the given offset, resolving any delta chain.
+    ///
+    /// Deltas are collected while walking down to the base object, and then applied in reverse
+    /// order. A `REF_DELTA` whose base is not in this pack is resolved through the object database.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the entry in the packfile.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the kind and contents of the object, or an `anyhow::Error`
+    /// if the entry or any of its bases cannot be read, or the delta chain is too long.
+    pub(crate) fn read_at(&self, offset: u64) -> anyhow::Result<(ObjectKind, Vec<u8>)> {
+        self.read_chain(offset, 0)
+    }
+
+    /// Reads the object whose entry starts at the given offset, as part of a delta chain that
+    /// already holds `depth` deltas, see `read_at`.
+    fn read_chain(&self, offset: u64, depth: usize) -> anyhow::Result<(ObjectKind, Vec<u8>)> {
+        let mut deltas = Vec::new();
+        let mut offset = offset;
+        let (kind, mut data) = loop {
+            // Deltas whose bases point back at them would be followed forever.
+            anyhow::ensure!(
+                depth + deltas.len() < MAX_DELTA_DEPTH,
+                "Delta chain is longer than {} entries, or loops.",
+                MAX_DELTA_DEPTH
+            );
+            let (entry_kind, data) = read_entry(&self.file, offset)?;
+            match entry_kind {
+                EntryKind::Object(kind) => break (kind, data),
+                EntryKind::OfsDelta(base_offset) => {
+                    deltas.push(data);
+                    offset = base_offset;
+                }
+                EntryKind::RefDelta(base_oid) => {
+                    deltas.push(data);
+                    match self.index.find(&base_oid) {
+                        Some(base_offset) => offset = base_offset,
+                        None => break read_base(&base_oid, depth + deltas.len())?,
+                    }
+                }
+            }
+        };
+
+        for delta in deltas.iter().rev() {
+            data = delta::apply(&data, delta).context("Failed to apply delta.")?;
+        }
+
+        Ok((kind, data))
+    }
+
+    /// Reads every object of the pack.
+    ///
+    /// The entries are read in the order they are stored, so that the base of an `OFS_DELTA`
+    /// entry, which always comes before it, has already been read, and each delta is applied only
+    /// once. A `REF_DELTA` entry whose base has not been read yet is resolved through `read_at`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the ID, kind and contents of each object, in the order of the
+    /// entries, or an `anyhow::Error` if an entry cannot be read.
+    pub(crate) fn read_all(&self) -> anyhow::Result<Vec<(ObjectID, ObjectKind, Vec<u8>)>> {
+        let mut entries = self.index.entries().collect::<Vec<_>>();
+        entries.sort_by_key(|(_, offset)| *offset);
+
+        // The position in `objects` of the object read at each offset.
+        let mut positions = HashMap::with_capacity(entries.len());
+        let mut objects = Vec::<(ObjectID, ObjectKind, Vec<u8>)>::with_capacity(entries.len());
+        for (oid, offset) in entries {
+            let (entry_kind, data) = read_entry(&self.file, offset)
+                .with_context(|| format!("Failed to read {} from {}", oid, self.path.display()))?;
+            let base_offset = match entry_kind {
+                EntryKind::Object(kind) => {
+                    positions.insert(offset, objects.len());
+                    objects.push((oid.clone(), kind, data));
+                    continue;
+                }
+                EntryKind::OfsDelta(base_offset) => Some(base_offset),
+                EntryKind::RefDelta(base_oid) => self.index.find(&base_oid),
+            };
+            let (kind, data) = match base_offset.and_then(|base| positions.get(&base)) {
+                Some(&position) => {
+                    let (_, kind, base) = &objects[position];
+                    let object = delta::apply(base, &data).context("Failed to apply delta.")?;
+                    (kind.clone(), object)
+                }
+                None => self.read_at(offset)?,
+            };
+            positions.insert(offset, objects.len());
+            objects.push((oid.clone(), kind, data));
+        }
+        Ok(objects)
+    }
+}
+
+/// Reads the base of a `REF_DELTA` entry that is not in the pack of the entry. The base is looked
+/// up in the other packs first, where its own delta chain counts towards the same limit, so that
+/// packs whose deltas point at each other are not followed forever, and then as a loose object.
+///
+/// # Arguments
+///
+/// * `oid` - The ID of the base object.
+/// * `depth` - The number of deltas already collected above the base.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the kind and contents of the base, or an `anyhow::Error` if it
+/// cannot be read.
+fn read_base(oid: &ObjectID, depth: usize) -> anyhow::Result<(ObjectKind, Vec<u8>)> {
+    for pack in packs()?.iter() {
+        if let Some(offset) = pack.index.find(oid) {
+            return pack.read_chain(offset, depth);
+        }
+    }
+    let (kind, _, mut reader) =
+        objects::read_object(&oid.hash()).with_context(|| format!("Missing delta base {}", oid))?;
+    let mut base = Vec::new();
+    reader.read_to_end(&mut base)?;
+    Ok((kind, base))
+}
+
+/// Lists the pack indexes of the object database that have a packfile next to them.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the paths of the `.idx` files in order, or an `anyhow::Error` if
+/// the pack directory cannot be read. If it does not exist, no paths are returned.
+fn list_indexes() -> anyhow::Result<Vec<PathBuf>> {
+    let pack_dir = Path::new(PACK_DIR);
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut idx_paths = Vec::new();
+    for direntry in std::fs::read_dir(pack_dir).context("Failed to read pack directory.")? {
+        let path = direntry.context("Bad entry in pack directory.")?.path();
+        if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").exists()
+        {
+            idx_paths.push(path);
+        }
+    }
+    idx_paths.sort();
+    Ok(idx_paths)
+}
+
+/// Returns the packs of the object database. They are listed and opened on the first call, and
+/// the same packs are returned afterwards, until `reload_packs` is called.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the packs ordered by path, or an `anyhow::Error` if any of them
+/// cannot be opened.
+pub(crate) fn packs() -> anyhow::Result<Arc<Vec<Pack>>> {
+    let mut packs = PACKS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(packs) = packs.as_ref() {
+        return Ok(Arc::clone(packs));
+    }
+    let opened = Arc::new(Pack::list()?);
+    *packs = Some(Arc::clone(&opened));
+    Ok(opened)
+}
+
+/// Opens the packs of the object database again if packs were added or removed since they were
+/// opened, e.g. by another process repacking the repository.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `true` if the packs changed, or an `anyhow::Error` if the pack
+/// directory cannot be read.
+pub(crate) fn reload_packs() -> anyhow::Result<bool> {
+    let idx_paths = list_indexes()?;
+    let mut packs = PACKS.lock().unwrap_or_else(PoisonError::into_inner);
+    let unchanged = packs.as_ref().is_some_and(|packs| {
+        packs
+            .iter()
+            .map(|pack| pack.path.with_extension("idx"))
+            .eq(idx_paths.iter().cloned())
+    });
+    if unchanged {
+        return Ok(false);
+    }
+    let opened = idx_paths
+        .iter()
+        .map(Pack::open)
+        .collect::<anyhow::Result<_>>()?;
+    *packs = Some(Arc::new(opened));
+    Ok(true)
+}
+
+/// Reads an object from any of the packs in the object database. If no pack contains the
+/// object, the packs are reloaded in case they changed, and searched again.
+///
+/// # Arguments
+///
+/// * `oid` - The ID of the object to read.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the kind and contents of the object, `None` if no pack contains
+/// it, or an `anyhow::Error` if the packs cannot be read.
+pub(crate) fn read_packed_object(oid: &ObjectID) -> anyhow::Result<Option<(ObjectKind, Vec<u8>)>> {
+    for pack in packs()?.iter() {
+        if let Some(object) = pack.read(oid)? {
+            return Ok(Some(object));
+        }
+    }
+    if reload_packs()? {
+        for pack in packs()?.iter() {
+            if let Some(object) = pack.read(oid)? {
+                return Ok(Some(object));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Checks whether any of the packs in the object database contains an object, without reading
+/// the object.
+///
+/// # Arguments
+///
+/// * `oid` - The ID of the object.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `true` if a pack contains the object, or an `anyhow::Error` if
+/// the packs cannot be opened.
+pub(crate) fn has_packed_object(oid: &ObjectID) -> anyhow::Result<bool> {
+    if packs()?.iter().any(|pack| pack.index.find(oid).is_some()) {
+        return Ok(true);
+    }
+    Ok(reload_packs()? && packs()?.iter().any(|pack| pack.index.find(oid).is_some()))
+}
+
+/// Reads a packfile from a position, without moving any file cursor, so that entries of the
+/// same packfile can be read through a shared handle.
+struct PackReader<'a> {
+    file: &'a std::fs::File,
+    position: u64,
+}
+
+impl Read for PackReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.file.read_at(buf, self.position)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+/// Reads a single entry of a packfile without resolving deltas.
+///
+/// Each entry starts with a header whose first byte holds the type in bits 4-6 and the lowest
+/// four bits of the inflated size. While the most significant bit is set, the following bytes
+/// contribute seven more bits of the size. Delta entries then identify their base, either with
+/// a negative offset or with the base's object ID. The zlib-compressed data follows.
+///
+/// # Arguments
+///
+/// * `file` - The packfile.
+/// * `offset` - The offset of the entry.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the kind of the entry and its inflated data, or an
+/// `anyhow::Error` if the entry is malformed.
+fn read_entry(file: &std::fs::File, offset: u64) -> anyhow::Result<(EntryKind, Vec<u8>)> {
+    let mut reader = std::io::BufReader::new(PackReader {
+        file,
+        position: offset,
+    });
+
+    let mut byte = reader
+        .read_u8()
+        .context("Failed to read pack entry header.")?;
+    let entry_type = (byte >> 4) & 0x7;
+    let mut size = (byte & 0x0f) as u64;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = reader
+            .read_u8()
+            .context("Failed to read pack entry size.")?;
+        anyhow::ensure!(shift < u64::BITS, "Pack entry size is too large.");
+        size |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+    }
+
+    let entry_kind = match entry_type {
+        OBJ_COMMIT => EntryKind::Object(ObjectKind::Commit),
+        OBJ_TREE => EntryKind::Object(ObjectKind::Tree),
+        OBJ_BLOB => EntryKind::Object(ObjectKind::Blob),
+        OBJ_TAG => EntryKind::Object(ObjectKind::Tag),
+        OBJ_OFS_DELTA => {
+            // The base offset is relative to the start of this entry. Every continuation byte
+            // adds one before shifting, so that each encoding length covers a distinct range.
+            let mut byte = reader
+                .read_u8()
+                .context("Failed to read delta base offset.")?;
+            let mut distance = (byte & 0x7f) as u64;
+            while byte & 0x80 != 0 {
+                byte = reader
+                    .read_u8()
+                    .context("Failed to read delta base offset.")?;
+                distance = distance
+                    .checked_add(1)
+                    .and_then(|distance| distance.checked_mul(1 << 7))
+                    .context("Delta base offset is too large.")?
+                    | (byte & 0x7f) as u64;
+            }
+            // The base comes before the delta, which cannot be its own base.
+            anyhow::ensure!(
+                distance > 0,
+                "Delta base offset points at the delta itself."
+            );
+            let base_offset = offset
+                .checked_sub(distance)
+                .context("Delta base offset points before the start of the pack.")?;
+            EntryKind::OfsDelta(base_offset)
+        }
+        OBJ_REF_DELTA => {
+            let mut bytes = [0u8; 20];
+            reader
+                .read_exact(&mut bytes)
+                .context("Failed to read delta base ID.")?;
+            EntryKind::RefDelta(ObjectID::from_bytes(bytes))
+        }
+        _ => anyhow::bail!("Invalid pack entry type: {}", entry_type),
+    };
+
+    let mut data = Vec::with_capacity(size as usize);
+    flate2::read::ZlibDecoder::new(reader)
+        .take(size)
+        .read_to_end(&mut data)
+        .context("Failed to inflate pack entry.")?;
+    anyhow::ensure!(
+        data.len() as u64 == size,
+        "Pack entry did not have the expected size. Expected size: {}. Actual size: {}",
+        size,
+        data.len()
+    );
+
+    Ok((entry_kind, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+    use sha1::Digest;
+
+    /// Returns the ID of an object from its kind and contents.
+    fn hash(kind: &ObjectKind, data: &[u8]) -> ObjectID {
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(format!("{} {}\0", kind, data.len()));
+        hasher.update(data);
+        ObjectID::from_bytes(hasher.finalize().into())
+    }
+
+    /// Returns an object ID made of the same byte.
+    fn oid(byte: u8) -> ObjectID {
+        ObjectID::from_bytes([byte; 20])
+    }
+
+    /// Encodes a pack entry whose data is deflated after the header and the given base, which is
+    /// empty for full objects.
+    fn entry(entry_type: u8, base: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut entry = write::entry_header(entry_type, data.len() as u64);
+        entry.extend(base);
+        entry.extend(write::compress(data).unwrap());
+        entry
+    }
+
+    /// Writes a pack of the given entries, listed in its index under the given IDs, and opens it.
+    fn craft(dir: &Path, entries: &[(ObjectID, Vec<u8>)]) -> Pack {
+        let mut data = PACK_SIGNATURE.to_vec();
+        data.extend(2u32.to_be_bytes());
+        data.extend((entries.len() as u32).to_be_bytes());
+        let mut index = Vec::new();
+        for (oid, entry) in entries {
+            index.push((oid.clone(), 0, data.len() as u64));
+            data.extend(entry);
+        }
+        let checksum = sha1::Sha1::digest(&data);
+        data.extend(checksum);
+        std::fs::write(dir.join("pack-test.pack"), data).unwrap();
+        let idx_path = dir.join("pack-test.idx");
+        write::write_index(&idx_path, index, &hex::encode(checksum)).unwrap();
+        Pack::open(idx_path).unwrap()
+    }
+
+    /// Returns the messages of the error of reading an object.
+    fn read_error(pack: &Pack, oid: &ObjectID) -> String {
+        format!("{:#}", pack.read(oid).unwrap_err())
+    }
+
+    #[test]
+    fn reads_packs_written_by_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let packs = [
+            (
+                "ofs",
+                OBJ_OFS_DELTA,
+                include_bytes!("testdata/ofs.pack").as_slice(),
+            ),
+            (
+                "ref",
+                OBJ_REF_DELTA,
+                include_bytes!("testdata/ref.pack").as_slice(),
+            ),
+        ];
+        let indexes = [
+            include_bytes!("testdata/ofs.idx").as_slice(),
+            include_bytes!("testdata/ref.idx").as_slice(),
+        ];
+        for ((name, delta_type, pack), idx) in packs.into_iter().zip(indexes) {
+            std::fs::write(dir.path().join(format!("{}.pack", name)), pack).unwrap();
+            let idx_path = dir.path().join(format!("{}.idx", name));
+            std::fs::write(&idx_path, idx).unwrap();
+            let pack = Pack::open(&idx_path).unwrap();
+
+            // Four commits of a file that changes a little, three of whose versions are deltas.
+            let deltas = pack
+                .index
+                .entries()
+                .filter(|(_, offset)| {
+                    let mut byte = [0];
+                    pack.file.read_exact_at(&mut byte, *offset).unwrap();
+                    (byte[0] >> 4) & 0x7 == delta_type
+                })
+                .count();
+            assert_eq!(deltas, 3, "{}", name);
+            for oid in pack.index.oids() {
+                let (kind, data) = pack.read(oid).unwrap().unwrap();
+                assert_eq!(hash(&kind, &data), *oid, "{}", name);
+            }
+            let all = pack.read_all().unwrap();
+            assert_eq!(all.len(), 12);
+            for (oid, kind, data) in all {
+                assert_eq!(hash(&kind, &data), oid, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn resolves_ref_deltas_against_other_objects() {
+        let repo = TestRepo::new();
+        let text = "line\n".repeat(100);
+        let changed = format!("{}more\n", text);
+        let base = repo.blob(&text);
+        let delta = delta::create(text.as_bytes(), changed.as_bytes());
+        let target = hash(&ObjectKind::Blob, changed.as_bytes());
+
+        // The base is a loose object.
+        let dir = tempfile::tempdir().unwrap();
+        let entries = [(
+            target.clone(),
+            entry(OBJ_REF_DELTA, &base.to_bytes(), &delta),
+        )];
+        let pack = craft(dir.path(), &entries);
+        assert_eq!(
+            pack.read(&target).unwrap(),
+            Some((ObjectKind::Blob, changed.clone().into_bytes()))
+        );
+
+        // The base is in the same pack, after the delta.
+        let entries = [
+            (
+                target.clone(),
+                entry(OBJ_REF_DELTA, &base.to_bytes(), &delta),
+            ),
+            (base.clone(), entry(OBJ_BLOB, &[], text.as_bytes())),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let pack = craft(dir.path(), &entries);
+        assert_eq!(
+            pack.read(&target).unwrap(),
+            Some((ObjectKind::Blob, changed.into_bytes()))
+        );
+        assert_eq!(pack.read_all().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_deltas_that_loop() {
+        let _repo = TestRepo::new();
+        let dir = tempfile::tempdir().unwrap();
+        let delta = delta::create(b"base", b"target");
+
+        // An OFS_DELTA entry whose base is itself.
+        let pack = craft(dir.path(), &[(oid(1), entry(OBJ_OFS_DELTA, &[0], &delta))]);
+        let err = read_error(&pack, &oid(1));
+        assert!(err.contains("points at the delta itself"), "{}", err);
+
+        // REF_DELTA entries whose bases are each other.
+        let entries = [
+            (oid(1), entry(OBJ_REF_DELTA, &oid(2).to_bytes(), &delta)),
+            (oid(2), entry(OBJ_REF_DELTA, &oid(1).to_bytes(), &delta)),
+        ];
+        let pack = craft(dir.path(), &entries);
+        let err = read_error(&pack, &oid(1));
+        assert!(err.contains("Delta chain is longer than"), "{}", err);
+        assert!(pack.read_all().is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_delta_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let delta = delta::create(b"base", b"target");
+        let mut offset = vec![0xff; 12];
+        offset.push(0x7f);
+        let pack = craft(
+            dir.path(),
+            &[(oid(1), entry(OBJ_OFS_DELTA, &offset, &delta))],
+        );
+        let err = read_error(&pack, &oid(1));
+        assert!(err.contains("Delta base offset is too large."), "{}", err);
+
+        let pack = craft(
+            dir.path(),
+            &[(oid(1), entry(OBJ_OFS_DELTA, &[0x7f], &delta))],
+        );
+        let err = read_error(&pack, &oid(1));
+        assert!(
+            err.contains("points before the start of the pack"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,433 @@
+use crate::objects;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::objects::pack::{delta, idx, PACK_SIGNATURE};
+use crate::objects::pack::{OBJ_BLOB, OBJ_COMMIT, OBJ_OFS_DELTA, OBJ_TAG, OBJ_TREE};
+use crate::objects::HashWriter;
+use anyhow::Context;
+use sha1::Digest;
+use std::collections::BTreeSet;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Version of the packfiles that are written.
+const PACK_VERSION: u32 = 2;
+/// Number of preceding objects that are tried as delta bases for each object.
+const WINDOW: usize = 10;
+/// Maximum length of a delta chain.
+const MAX_DEPTH: usize = 50;
+/// Objects smaller than this are always stored whole.
+const MIN_DELTA_SIZE: usize = 32;
+
+/// An object that is about to be written in a packfile.
+struct PackObject {
+    oid: ObjectID,
+    kind: ObjectKind,
+    data: Vec<u8>,
+    /// The position of the delta base in the write order, and the delta against it.
+    delta: Option<(usize, Vec<u8>)>,
+    depth: usize,
+}
+
+/// Writes the given objects into a new packfile and its version 2 index, reading them from the
+/// object database, see `write_objects`.
+///
+/// # Arguments
+///
+/// * `base_name` - The prefix of the paths of the written files.
+/// * `oids` - The IDs of the objects to pack.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the checksum of the packfile, or an `anyhow::Error` if any of the
+/// objects cannot be read or the files cannot be written.
+pub(crate) fn write_pack(
+    base_name: impl AsRef<Path>,
+    oids: impl IntoIterator<Item = ObjectID>,
+) -> anyhow::Result<String> {
+    let oids = oids.into_iter().collect::<BTreeSet<_>>();
+    let mut objects = Vec::with_capacity(oids.len());
+    for oid in oids {
+        let (kind, _, mut reader) = objects::read_object(&oid.hash())?;
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read object {}", oid))?;
+        objects.push((oid, kind, data));
+    }
+    write_objects(base_name, objects)
+}
+
+/// Writes the given objects into a new packfile and its version 2 index.
+///
+/// The objects are ordered by kind and by decreasing size, so that similar objects end up next to
+/// each other. Each object is then compared against the objects in a sliding window before it, and
+/// if a delta against one of them is at most half the size of the object, the smallest such delta
+/// is stored as an `OFS_DELTA` entry instead of the whole object. Since bases precede the objects
+/// that use them, the entries are written in that same order.
+///
+/// The packfile and the index are named after the checksum of the packfile, i.e. the files
+/// `<base_name>-<checksum>.pack` and `<base_name>-<checksum>.idx` are created.
+///
+/// # Arguments
+///
+/// * `base_name` - The prefix of the paths of the written files.
+/// * `objects` - The ID, kind and contents of each object to pack, each object appearing once.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the checksum of the packfile, or an `anyhow::Error` if the files
+/// cannot be written. Temporary files are removed when writing fails.
+pub(crate) fn write_objects(
+    base_name: impl AsRef<Path>,
+    objects: impl IntoIterator<Item = (ObjectID, ObjectKind, Vec<u8>)>,
+) -> anyhow::Result<String> {
+    let base_name = base_name.as_ref();
+
+    // Order the objects by kind and decreasing size.
+    let mut objects = objects
+        .into_iter()
+        .map(|(oid, kind, data)| PackObject {
+            oid,
+            kind,
+            data,
+            delta: None,
+            depth: 0,
+        })
+        .collect::<Vec<_>>();
+    objects.sort_by(|a, b| {
+        type_number(&a.kind)
+            .cmp(&type_number(&b.kind))
+            .then(b.data.len().cmp(&a.data.len()))
+    });
+
+    find_deltas(&mut objects);
+
+    // Write the packfile in a temporary file, keeping track of the entries for the index.
+    let dir = base_name.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let temp_pack_path = dir.join(format!("tmp_pack_{}", uuid::Uuid::new_v4()));
+    let result = write_pack_file(base_name, &temp_pack_path, &objects);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_pack_path);
+    }
+    result
+}
+
+/// Writes the entries of the ordered objects in a temporary packfile, then writes its index and
+/// moves the packfile next to it, see `write_objects`.
+fn write_pack_file(
+    base_name: &Path,
+    temp_pack_path: &Path,
+    objects: &[PackObject],
+) -> anyhow::Result<String> {
+    let file =
+        std::fs::File::create(temp_pack_path).context("Writing packfile in temporary file.")?;
+    let mut writer = HashWriter::new(std::io::BufWriter::new(file));
+
+    writer.write_all(PACK_SIGNATURE)?;
+    writer.write_all(&PACK_VERSION.to_be_bytes())?;
+    writer.write_all(&(objects.len() as u32).to_be_bytes())?;
+
+    let mut offset = 12u64;
+    let mut offsets = Vec::with_capacity(objects.len());
+    let mut index_entries = Vec::with_capacity(objects.len());
+    for object in objects {
+        let entry = match &object.delta {
+            Some((base, delta)) => {
+                let mut entry = entry_header(OBJ_OFS_DELTA, delta.len() as u64);
+                entry.extend(encode_offset(offset - offsets[*base]));
+                entry.extend(compress(delta)?);
+                entry
+            }
+            None => {
+                let mut entry = entry_header(type_number(&object.kind), object.data.len() as u64);
+                entry.extend(compress(&object.data)?);
+                entry
+            }
+        };
+        writer
+            .write_all(&entry)
+            .context("Failed to write pack entry.")?;
+
+        index_entries.push((object.oid.clone(), crc32fast::hash(&entry), offset));
+        offsets.push(offset);
+        offset += entry.len() as u64;
+    }
+
+    // The packfile ends with the checksum of everything before it.
+    let checksum: [u8; 20] = writer.hasher.finalize().into();
+    let mut file = writer.writer;
+    file.write_all(&checksum)?;
+    file.flush()?;
+    let checksum = hex::encode(checksum);
+
+    let pack_path = PathBuf::from(format!("{}-{}.pack", base_name.display(), checksum));
+    let idx_path = pack_path.with_extension("idx");
+    write_index(&idx_path, index_entries, &checksum)?;
+    std::fs::rename(temp_pack_path, &pack_path)
+        .with_context(|| format!("Failed to move packfile to {}", pack_path.display()))?;
+
+    Ok(checksum)
+}
+
+/// Looks for delta bases in a sliding window over the ordered objects.
+///
+/// Only objects of the same kind whose sizes are within a factor of two are considered, and bases
+/// whose delta chain is already at the maximum depth are skipped.
+fn find_deltas(objects: &mut [PackObject]) {
+    for i in 0..objects.len() {
+        let target = &objects[i];
+        if target.data.len() < MIN_DELTA_SIZE {
+            continue;
+        }
+
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        let window_start = i.saturating_sub(WINDOW);
+        for (j, base) in objects[window_start..i].iter().enumerate() {
+            if base.kind != target.kind
+                || base.depth >= MAX_DEPTH
+                || base.data.len() > target.data.len() * 2
+                || base.data.len() * 2 < target.data.len()
+            {
+                continue;
+            }
+
+            let delta = delta::create(&base.data, &target.data);
+            let limit = match &best {
+                Some((_, best)) => best.len(),
+                None => target.data.len() / 2,
+            };
+            if delta.len() < limit {
+                best = Some((window_start + j, delta));
+            }
+        }
+
+        if let Some((base, delta)) = best {
+            objects[i].depth = objects[base].depth + 1;
+            objects[i].delta = Some((base, delta));
+        }
+    }
+}
+
+/// Writes a version 2 pack index.
+///
+/// # Arguments
+///
+/// * `path` - The path of the index file.
+/// * `entries` - The object ID, CRC32 checksum and offset of every packed entry.
+/// * `pack_checksum` - The hex encoded checksum of the packfile.
+pub(super) fn write_index(
+    path: &Path,
+    mut entries: Vec<(ObjectID, u32, u64)>,
+    pack_checksum: &str,
+) -> anyhow::Result<()> {
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut data = Vec::new();
+    data.extend(idx::IDX_SIGNATURE);
+    data.extend(idx::IDX_VERSION.to_be_bytes());
+
+    // Fanout table: the number of objects whose first byte is at most i.
+    let mut fanout = [0u32; 256];
+    for (oid, _, _) in &entries {
+        fanout[oid.to_bytes()[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        data.extend(total.to_be_bytes());
+    }
+
+    for (oid, _, _) in &entries {
+        data.extend(oid.to_bytes());
+    }
+    for (_, crc, _) in &entries {
+        data.extend(crc.to_be_bytes());
+    }
+
+    // Offsets that do not fit in 31 bits are moved to the table of 64-bit offsets.
+    let mut large_offsets = Vec::new();
+    for (_, _, offset) in &entries {
+        if *offset < 0x8000_0000 {
+            data.extend((*offset as u32).to_be_bytes());
+        } else {
+            data.extend((0x8000_0000 | large_offsets.len() as u32).to_be_bytes());
+            large_offsets.push(*offset);
+        }
+    }
+    for offset in large_offsets {
+        data.extend(offset.to_be_bytes());
+    }
+
+    data.extend(hex::decode(pack_checksum).context("Invalid pack checksum.")?);
+    let checksum = sha1::Sha1::digest(&data);
+    data.extend(checksum);
+
+    let temp_path = path.with_extension(format!("idx_{}", uuid::Uuid::new_v4()));
+    let result = std::fs::write(&temp_path, &data)
+        .context("Writing pack index in temporary file.")
+        .and_then(|_| {
+            std::fs::rename(&temp_path, path)
+                .with_context(|| format!("Failed to move pack index to {}", path.display()))
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Returns the packfile type number of an object kind.
+fn type_number(kind: &ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Commit => OBJ_COMMIT,
+        ObjectKind::Tree => OBJ_TREE,
+        ObjectKind::Blob => OBJ_BLOB,
+        ObjectKind::Tag => OBJ_TAG,
+    }
+}
+
+/// Encodes the header of a pack entry: the type and the inflated size of its data.
+pub(super) fn entry_header(entry_type: u8, mut size: u64) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut byte = (entry_type << 4) | (size & 0x0f) as u8;
+    size >>= 4;
+    while size > 0 {
+        header.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    header.push(byte);
+    header
+}
+
+/// Encodes the distance between an `OFS_DELTA` entry and its base.
+///
+/// This is the inverse of the decoding in the pack reader: every continuation byte implies an
+/// extra one, so one is subtracted before each shift.
+fn encode_offset(mut distance: u64) -> Vec<u8> {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance > 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+/// Compresses data with zlib.
+pub(super) fn compress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::pack::idx::PackIndex;
+    use crate::objects::pack::Pack;
+
+    /// Returns an object ID whose first byte is given, the rest being filled with `fill`.
+    fn oid(first: u8, fill: u8) -> ObjectID {
+        let mut bytes = [fill; 20];
+        bytes[0] = first;
+        ObjectID::from_bytes(bytes)
+    }
+
+    /// Returns the ID, kind and contents of a blob.
+    fn blob(data: &[u8]) -> (ObjectID, ObjectKind, Vec<u8>) {
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(format!("blob {}\0", data.len()));
+        hasher.update(data);
+        let oid = ObjectID::from_bytes(hasher.finalize().into());
+        (oid, ObjectKind::Blob, data.to_vec())
+    }
+
+    #[test]
+    fn writes_fanout_and_large_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack-test.idx");
+        let entries = vec![
+            (oid(0xff, 1), 3, 0x1_0000_0000),
+            (oid(0x00, 1), 1, 12),
+            (oid(0x7f, 2), 2, 0x8000_0000),
+            (oid(0x7f, 1), 2, 0x7fff_ffff),
+        ];
+        write_index(&path, entries.clone(), &"ab".repeat(20)).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let fanout = |i: usize| u32::from_be_bytes(data[8 + i * 4..12 + i * 4].try_into().unwrap());
+        assert_eq!(
+            [
+                fanout(0),
+                fanout(0x7e),
+                fanout(0x7f),
+                fanout(0xfe),
+                fanout(0xff)
+            ],
+            [1, 1, 3, 3, 4]
+        );
+        // Two offsets do not fit in 31 bits, and are stored in the table of 64-bit offsets.
+        assert_eq!(data.len(), 8 + 256 * 4 + 4 * (20 + 4 + 4) + 2 * 8 + 40);
+
+        let index = PackIndex::open(&path).unwrap();
+        assert!(index.oids().is_sorted());
+        for (oid, _, offset) in &entries {
+            assert_eq!(index.find(oid), Some(*offset));
+        }
+        assert_eq!(index.find(&oid(0x7f, 3)), None);
+        assert_eq!(index.find(&oid(0x80, 1)), None);
+    }
+
+    #[test]
+    fn rejects_corrupt_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack-test.idx");
+        write_index(&path, vec![(oid(1, 1), 0, 12)], &"ab".repeat(20)).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert!(PackIndex::parse(&data[..data.len() - 41]).is_err());
+        let mut version = data.clone();
+        version[7] = 1;
+        assert!(PackIndex::parse(&version).is_err());
+    }
+
+    #[test]
+    fn round_trips_objects_through_packs() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_name = dir.path().join("pack/pack");
+        let text = (0..200)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let objects = vec![
+            blob(text.as_bytes()),
+            blob(text.replace("line 100", "changed").as_bytes()),
+            blob(format!("{}more\n", text).as_bytes()),
+            blob(b"small"),
+            blob(b""),
+        ];
+        let checksum = write_objects(&base_name, objects.clone()).unwrap();
+
+        let idx_path = dir.path().join(format!("pack/pack-{}.idx", checksum));
+        let pack = Pack::open(&idx_path).unwrap();
+        for (oid, kind, data) in &objects {
+            assert_eq!(pack.read(oid).unwrap(), Some((kind.clone(), data.clone())));
+        }
+        let mut all = pack.read_all().unwrap();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut expected = objects.clone();
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(all, expected);
+
+        // No temporary file is left behind.
+        let temp_files = std::fs::read_dir(dir.path().join("pack"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("tmp_"))
+            .count();
+        assert_eq!(temp_files, 0);
+    }
+}
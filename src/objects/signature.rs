@@ -0,0 +1,271 @@
+use crate::config::Config;
+use anyhow::Context;
+
+/// Represents the identity of an author, committer or tagger, together with the time of the
+/// action, as found in the headers of commits and tags: `Name <email> <timestamp> <timezone>`.
+///
+/// The name and the email are kept as bytes, since they are in the encoding of the object, which
+/// is not necessarily UTF-8, see the `encoding` header of commits. The signature is written back
+/// exactly as it was read, since any change to its spacing or digits would change the ID of the
+/// object, so the fields are only parsed for display.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Signature {
+    pub(crate) name: Vec<u8>,
+    pub(crate) email: Vec<u8>,
+    /// Seconds since the Unix epoch.
+    pub(crate) timestamp: i64,
+    /// Offset from UTC, as written in the object, e.g. `+0100`.
+    pub(crate) timezone: String,
+    /// The signature as written in the object.
+    raw: Vec<u8>,
+}
+
+impl Signature {
+    /// Creates a new `Signature` with the current local time.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the person.
+    /// * `email` - The email of the person.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Signature`.
+    pub(crate) fn now(name: impl Into<Vec<u8>>, email: impl Into<Vec<u8>>) -> Signature {
+        let local = chrono::Local::now();
+        let timezone = format!("{}", local.offset()).replace(':', "");
+        Signature::new(name.into(), email.into(), local.timestamp(), timezone)
+    }
+
+    /// Creates a new `Signature` from its parts, in the format git writes.
+    fn new(name: Vec<u8>, email: Vec<u8>, timestamp: i64, timezone: String) -> Signature {
+        let mut raw = name.clone();
+        raw.extend(b" <");
+        raw.extend(&email);
+        raw.extend(format!("> {} {}", timestamp, timezone).as_bytes());
+        Signature {
+            name,
+            email,
+            timestamp,
+            timezone,
+            raw,
+        }
+    }
+
+    /// Creates a new `Signature` for an author or committer.
+    ///
+    /// The identity comes from the configuration, see `Config::identity`. The date is taken from
+    /// the environment variable `GIT_AUTHOR_DATE` or `GIT_COMMITTER_DATE` if it is set, and is the
+    /// current local time otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `role` - The role of the person, `AUTHOR` or `COMMITTER`.
+    /// * `config` - The configuration of the repository.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `Signature`, or an `anyhow::Error` if the identity is not
+    /// configured or the date in the environment cannot be parsed.
+    pub(crate) fn from_env(role: &str, config: &Config) -> anyhow::Result<Signature> {
+        let (name, email) = config.identity(role)?;
+        let variable = format!("GIT_{}_DATE", role);
+        match std::env::var(&variable) {
+            Ok(date) => {
+                let (timestamp, timezone) = parse_date(&date)
+                    .with_context(|| format!("Invalid date in {}: {}", variable, date))?;
+                Ok(Signature::new(
+                    name.into(),
+                    email.into(),
+                    timestamp,
+                    timezone,
+                ))
+            }
+            Err(_) => Ok(Signature::now(name, email)),
+        }
+    }
+
+    /// Parses a signature of the form `Name <email> <timestamp> <timezone>`. The parts are read
+    /// leniently, like git does: the spaces around the email may be missing or repeated.
+    ///
+    /// # Arguments
+    ///
+    /// * `signature` - The signature, without the header name.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `Signature`, or an `anyhow::Error` if the signature is
+    /// malformed.
+    pub(crate) fn parse(signature: &[u8]) -> anyhow::Result<Signature> {
+        let display = || String::from_utf8_lossy(signature);
+        let email_start = signature
+            .iter()
+            .position(|&b| b == b'<')
+            .with_context(|| format!("Missing email in signature: {}", display()))?;
+        let email_len = signature[email_start..]
+            .iter()
+            .position(|&b| b == b'>')
+            .with_context(|| format!("Unterminated email in signature: {}", display()))?;
+        let name = &signature[..email_start];
+        let email = &signature[email_start + 1..email_start + email_len];
+        let date = std::str::from_utf8(&signature[email_start + email_len + 1..])
+            .with_context(|| format!("Invalid date in signature: {}", display()))?;
+        let (timestamp, timezone) = date
+            .trim_start()
+            .split_once(' ')
+            .with_context(|| format!("Missing timezone in signature: {}", display()))?;
+        let timestamp = timestamp
+            .parse()
+            .with_context(|| format!("Invalid timestamp in signature: {}", display()))?;
+
+        Ok(Signature {
+            name: name.trim_ascii_end().to_vec(),
+            email: email.to_vec(),
+            timestamp,
+            timezone: timezone.trim_start().to_string(),
+            raw: signature.to_vec(),
+        })
+    }
+
+    /// Serialises the signature the way it is written in objects: unchanged if it was parsed.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        self.raw.clone()
+    }
+
+    /// Returns the name, decoded for display. Bytes that are not UTF-8 are replaced.
+    pub(crate) fn display_name(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    /// Returns the email, decoded for display. Bytes that are not UTF-8 are replaced.
+    pub(crate) fn display_email(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.email)
+    }
+
+    /// Returns the date of the signature, in the timezone of the signature.
+    pub(crate) fn date(&self) -> chrono::DateTime<chrono::FixedOffset> {
+        let digits = self
+            .timezone
+            .get(1..)
+            .and_then(|digits| digits.parse::<i32>().ok());
+        let offset = digits.unwrap_or(0);
+        let seconds = (offset / 100 * 3600 + offset % 100 * 60)
+            * if self.timezone.starts_with('-') {
+                -1
+            } else {
+                1
+            };
+        let timezone = chrono::FixedOffset::east_opt(seconds)
+            .unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap());
+        chrono::DateTime::from_timestamp(self.timestamp, 0)
+            .unwrap_or_default()
+            .with_timezone(&timezone)
+    }
+}
+
+/// Parses a date in one of the formats git accepts in `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE`:
+///
+/// * the internal format, `<timestamp> <timezone>` or `@<timestamp> <timezone>`, where the
+///   timezone defaults to `+0000`;
+/// * RFC 2822, e.g. `Thu, 07 Apr 2005 22:13:13 +0200`;
+/// * ISO 8601, e.g. `2005-04-07T22:13:13+02:00` or `2005-04-07 22:13:13 +0200`.
+///
+/// # Arguments
+///
+/// * `date` - The date to parse.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the timestamp and the timezone, or an `anyhow::Error` if the date
+/// is in none of the supported formats.
+fn parse_date(date: &str) -> anyhow::Result<(i64, String)> {
+    let date = date.trim();
+    let mut parts = date.trim_start_matches('@').split_whitespace();
+    if let Some(Ok(timestamp)) = parts.next().map(str::parse::<i64>) {
+        let timezone = parts.next().unwrap_or("+0000");
+        let valid_timezone = timezone.len() == 5
+            && timezone.starts_with(['+', '-'])
+            && timezone[1..].bytes().all(|b| b.is_ascii_digit());
+        anyhow::ensure!(
+            valid_timezone && parts.next().is_none(),
+            "Invalid timezone: {}",
+            timezone
+        );
+        return Ok((timestamp, timezone.to_string()));
+    }
+
+    let parsed = chrono::DateTime::parse_from_rfc2822(date)
+        .or_else(|_| chrono::DateTime::parse_from_rfc3339(date))
+        .or_else(|_| chrono::DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z"))
+        .or_else(|_| chrono::DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"))
+        .context("Unsupported date format.")?;
+    Ok((
+        parsed.timestamp(),
+        parsed.offset().to_string().replace(':', ""),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_signatures_back_unchanged() {
+        for raw in [
+            b"A U Thor <author@example.com> 1700000000 +0000".as_slice(),
+            b"A<a@b> 1700000000 +0000",
+            b"A <a@b>   1700000000 +0000",
+            b"A <a@b> 0123 -0130",
+            b"  <> 0 +0000",
+        ] {
+            assert_eq!(Signature::parse(raw).unwrap().to_bytes(), raw);
+        }
+
+        let signature = Signature::parse(b"A  U Thor<a@b>   0123 -0130").unwrap();
+        assert_eq!(signature.display_name(), "A  U Thor");
+        assert_eq!(signature.display_email(), "a@b");
+        assert_eq!(signature.timestamp, 123);
+        assert_eq!(signature.timezone, "-0130");
+        assert_eq!(signature.date().to_rfc3339(), "1969-12-31T22:32:03-01:30");
+    }
+
+    #[test]
+    fn writes_new_signatures_in_the_usual_format() {
+        let signature = Signature::new(b"A".to_vec(), b"a@b".to_vec(), 5, "+0100".to_string());
+        assert_eq!(signature.to_bytes(), b"A <a@b> 5 +0100");
+        assert_eq!(Signature::parse(&signature.to_bytes()).unwrap(), signature);
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        assert!(Signature::parse(b"A a@b 0 +0000").is_err());
+        assert!(Signature::parse(b"A <a@b 0 +0000").is_err());
+        assert!(Signature::parse(b"A <a@b> 0").is_err());
+        assert!(Signature::parse(b"A <a@b> x +0000").is_err());
+    }
+
+    #[test]
+    fn parses_dates_like_git() {
+        let date = |date| parse_date(date).unwrap();
+        let expected = (1112904793, "+0200".to_string());
+        assert_eq!(date("Thu, 07 Apr 2005 22:13:13 +0200"), expected);
+        assert_eq!(date("2005-04-07T22:13:13+02:00"), expected);
+        assert_eq!(date("2005-04-07T22:13:13+0200"), expected);
+        assert_eq!(date("@1112904793 +0200"), expected);
+        assert_eq!(date(" 1112904793 +0200\n"), expected);
+        assert_eq!(
+            date("2005-04-07 22:13:13 -0130"),
+            (1112917393, "-0130".to_string())
+        );
+        assert_eq!(date("@1112904793"), (1112904793, "+0000".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        assert!(parse_date("1112904793 +02").is_err());
+        assert!(parse_date("1112904793 0200").is_err());
+        assert!(parse_date("1112904793 +0200 extra").is_err());
+        assert!(parse_date("07/04/2005").is_err());
+        assert!(parse_date("").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::objects;
+use crate::objects::commit::{expect_text_header, split_headers, split_message, write_header};
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::objects::signature::Signature;
+use crate::objects::Object;
+use anyhow::Context;
+use std::io::Read;
+
+/// Represents an annotated tag object.
+///
+/// A tag is made of the `object` it points to, the `type` of that object, the `tag` name and,
+/// except for very old tags, the `tagger`, followed by an empty line and the tag message. A signed
+/// tag carries its signature at the end of the message.
+///
+/// Parsing a tag and serialising it again gives back the same bytes, and so the same `ObjectID`.
+pub(crate) struct Tag {
+    pub(crate) object: ObjectID,
+    pub(crate) object_kind: ObjectKind,
+    pub(crate) name: String,
+    pub(crate) tagger: Option<Signature>,
+    /// The message, exactly as it follows the empty line after the headers.
+    pub(crate) message: Vec<u8>,
+    reader: std::io::Cursor<Vec<u8>>,
+}
+
+impl Tag {
+    /// Creates a new `Tag` object.
+    ///
+    /// # Arguments
+    ///
+    /// * `object` - The ID of the tagged object.
+    /// * `object_kind` - The kind of the tagged object.
+    /// * `name` - The name of the tag, without `refs/tags/`.
+    /// * `tagger` - The person creating the tag and the date.
+    /// * `message` - The tag message, written as is, so it should end with a newline.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Tag` object.
+    pub(crate) fn new(
+        object: ObjectID,
+        object_kind: ObjectKind,
+        name: String,
+        tagger: Signature,
+        message: String,
+    ) -> Tag {
+        Tag {
+            object,
+            object_kind,
+            name,
+            tagger: Some(tagger),
+            message: message.into_bytes(),
+            reader: std::io::Cursor::new(Vec::new()),
+        }
+    }
+
+    /// Parses the content of a tag object.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The content of the tag object, without the object header.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the parsed `Tag`, or an `anyhow::Error` if the tag is
+    /// malformed.
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<Tag> {
+        let (header, message) =
+            split_message(data).context("Tag has no empty line after its headers.")?;
+
+        let mut headers = split_headers(header)?.into_iter().peekable();
+        let object = ObjectID::from_hash(&expect_text_header(&mut headers, "object")?)?;
+        let object_kind = ObjectKind::parse(&expect_text_header(&mut headers, "type")?)?;
+        let name = expect_text_header(&mut headers, "tag")?;
+        let tagger = match headers.next_if(|(name, _)| name == "tagger") {
+            Some((_, tagger)) => Some(Signature::parse(&tagger)?),
+            None => None,
+        };
+        if let Some((name, _)) = headers.next() {
+            anyhow::bail!("Unexpected tag header: {}", name);
+        }
+
+        Ok(Tag {
+            object,
+            object_kind,
+            name,
+            tagger,
+            message: message.to_vec(),
+            reader: std::io::Cursor::new(Vec::new()),
+        })
+    }
+
+    /// Reads a tag from the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The hash of the tag.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the parsed `Tag`, or an `anyhow::Error` if the object cannot
+    /// be read, is not a tag or is malformed.
+    pub(crate) fn read(hash: &str) -> anyhow::Result<Tag> {
+        let (kind, _, mut reader) = objects::read_object(hash)?;
+        anyhow::ensure!(kind == ObjectKind::Tag, "Object {} is not a tag.", hash);
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read tag {}", hash))?;
+        Tag::parse(&data).with_context(|| format!("Failed to parse tag {}", hash))
+    }
+
+    /// Serialises the tag into the content of a tag object.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut tag = format!(
+            "object {}\ntype {}\ntag {}\n",
+            self.object, self.object_kind, self.name
+        )
+        .into_bytes();
+        if let Some(tagger) = &self.tagger {
+            write_header(&mut tag, "tagger", &tagger.to_bytes());
+        }
+        tag.push(b'\n');
+        tag.extend(&self.message);
+        tag
+    }
+}
+
+impl Object for Tag {
+    fn kind(&self) -> &ObjectKind {
+        &ObjectKind::Tag
+    }
+
+    fn size(&self) -> u64 {
+        self.to_bytes().len() as u64
+    }
+
+    fn content(&mut self) -> &mut dyn std::io::Read {
+        self.reader = std::io::Cursor::new(self.to_bytes());
+        &mut self.reader
+    }
+}
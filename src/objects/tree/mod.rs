@@ -1,22 +1,32 @@
 use anyhow::Context;
+use std::collections::BTreeMap;
 use std::ffi::CStr;
 use std::ffi::OsString;
 use std::io::BufRead;
 use std::io::Cursor;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub(crate) mod entry;
 pub(crate) mod mode;
 
 use crate::cmp::compare_base_name;
+use crate::ignore::IgnoreRules;
+use crate::index::cache_tree::CacheTree;
+use crate::index::entry::IndexEntry;
 use crate::objects::blob::Blob;
 use crate::objects::id::ObjectID;
 use crate::objects::kind::ObjectKind;
-use crate::objects::read_object;
 use crate::objects::tree::entry::TreeEntry;
 use crate::objects::tree::mode::EntryMode;
 use crate::objects::Object;
+use crate::objects::{has_object, read_object};
+
+/// A node of the directory hierarchy that is built from the index before writing trees.
+enum TreeNode {
+    File(EntryMode, ObjectID),
+    Directory(BTreeMap<OsString, TreeNode>),
+}
 
 #[allow(dead_code)]
 pub(crate) struct Tree<R> {
@@ -39,23 +49,38 @@ impl Tree<()> {
         Tree { size, reader }
     }
 
-    pub(crate) fn write_for_dir(path: impl AsRef<Path>) -> anyhow::Result<Option<ObjectID>> {
+    /// Writes the tree objects of a directory and of its subdirectories to the database.
+    ///
+    /// Ignored files and directories are left out, and so are directories that contain no files.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the directory, relative to the root of the working tree.
+    /// * `ignore` - The ignore rules of the working tree.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the ID of the tree, or `None` if the directory contains no
+    /// files, or an `anyhow::Error` if the directory cannot be read or written.
+    pub(crate) fn write_for_dir(
+        path: impl AsRef<Path>,
+        ignore: &IgnoreRules,
+    ) -> anyhow::Result<Option<ObjectID>> {
         // Read the directory
         let path = path.as_ref();
-        let mut dir = std::fs::read_dir(path)
+        let dir = std::fs::read_dir(path)
             .with_context(|| format!("Failed to read directory: {}", path.display()))?;
 
         // Read tree entries
         let mut tree_entries = Vec::new();
-        while let Some(direntry) = dir.next() {
+        for direntry in dir {
             let direntry = direntry.with_context(|| format!("Bad entry in {}", path.display()))?;
             let entry_path = direntry.path();
             let filename = direntry.file_name();
             let metadata = direntry
                 .metadata()
                 .with_context(|| format!("Failed to read metadata for {}", entry_path.display()))?;
-            // Ignore entries here
-            if direntry.file_name() == ".git" {
+            if ignore.is_ignored(&entry_path, metadata.is_dir())? {
                 continue;
             }
 
@@ -73,7 +98,7 @@ impl Tree<()> {
             let oid = match mode {
                 EntryMode::Directory => {
                     // If the entry is a directory, get the hash recursively.
-                    let Some(oid) = Tree::write_for_dir(entry_path)
+                    let Some(oid) = Tree::write_for_dir(entry_path, ignore)
                         .context(format!("Failed to write tree {}", entry_path.display()))?
                     else {
                         // If the directory is empty, skip it.
@@ -109,6 +134,122 @@ impl Tree<()> {
         }
     }
 
+    /// Writes the hierarchy of tree objects described by the entries of the index, and returns the
+    /// ID of the root tree.
+    ///
+    /// The paths of the entries are split into their components to build the directory hierarchy.
+    /// Every directory is then written as a tree, bottom-up, with its entries ordered by
+    /// `compare_base_name`, and with the executable and symlink bits of the mode of the entries.
+    /// Unlike `write_for_dir`, an empty index results in the empty tree.
+    ///
+    /// A directory whose node in the cache tree is valid is not written again: the tree of the
+    /// node is reused, as long as it is still in the database. The nodes of the other directories
+    /// are filled in with the trees that are written.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The entries of the index.
+    /// * `cache_tree` - The cache tree of the index.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the ID of the root tree, or an `anyhow::Error` if an entry
+    /// has an invalid mode or a tree cannot be written.
+    pub(crate) fn write_from_index(
+        entries: &[IndexEntry],
+        cache_tree: &mut CacheTree,
+    ) -> anyhow::Result<ObjectID> {
+        let mut root = BTreeMap::new();
+        for entry in entries {
+            let mode = EntryMode::from_index_mode(entry.mode).with_context(|| {
+                format!(
+                    "Invalid mode {:o} for index entry {}",
+                    entry.mode,
+                    entry.path().display()
+                )
+            })?;
+            let components = entry
+                .path()
+                .iter()
+                .map(|component| component.to_os_string())
+                .collect::<Vec<_>>();
+            let Some((name, parents)) = components.split_last() else {
+                anyhow::bail!("Index entry with empty path.");
+            };
+
+            // Walk down to the directory of the entry, creating directories on the way.
+            let mut dir = &mut root;
+            for parent in parents {
+                let node = dir
+                    .entry(parent.clone())
+                    .or_insert_with(|| TreeNode::Directory(BTreeMap::new()));
+                dir = match node {
+                    TreeNode::Directory(children) => children,
+                    TreeNode::File(_, _) => anyhow::bail!(
+                        "Index entry {} is both a file and a directory.",
+                        Path::new(parent).display()
+                    ),
+                };
+            }
+            dir.insert(name.clone(), TreeNode::File(mode, entry.oid.clone()));
+        }
+
+        Tree::write_node(&root, cache_tree)
+    }
+
+    /// Writes a directory of the hierarchy built from the index, after writing its subdirectories,
+    /// unless its node in the cache tree is valid, and updates the node.
+    fn write_node(
+        children: &BTreeMap<OsString, TreeNode>,
+        cache_tree: &mut CacheTree,
+    ) -> anyhow::Result<ObjectID> {
+        if let Some((_, oid)) = &cache_tree.tree {
+            if has_object(oid)? {
+                return Ok(oid.clone());
+            }
+        }
+
+        let mut subtrees = BTreeMap::new();
+        let mut entry_count = 0;
+        let mut tree_entries = Vec::with_capacity(children.len());
+        for (name, node) in children {
+            match node {
+                TreeNode::File(mode, oid) => {
+                    entry_count += 1;
+                    tree_entries.push((name, *mode, oid.clone()));
+                }
+                TreeNode::Directory(grandchildren) => {
+                    let mut subtree = cache_tree.subtrees.remove(name).unwrap_or_default();
+                    let oid = Tree::write_node(grandchildren, &mut subtree)?;
+                    entry_count += subtree.tree.as_ref().map_or(0, |(count, _)| *count);
+                    subtrees.insert(name.clone(), subtree);
+                    tree_entries.push((name, EntryMode::Directory, oid));
+                }
+            }
+        }
+
+        // Sort entries by base name.
+        tree_entries.sort_unstable_by(|entry1, entry2| {
+            compare_base_name(entry1.0, &entry1.1, entry2.0, &entry2.1)
+        });
+
+        let mut tree_object = Vec::new();
+        for (name, mode, oid) in &tree_entries {
+            tree_object.extend(format!("{}", mode).as_bytes());
+            tree_object.push(b' ');
+            tree_object.extend(name.as_encoded_bytes());
+            tree_object.push(0);
+            tree_object.extend(oid.to_bytes());
+        }
+
+        let oid = Tree::new(tree_object.len() as u64, Cursor::new(tree_object))
+            .write()
+            .context("Failed to write tree in database.")?;
+        cache_tree.tree = Some((entry_count, oid.clone()));
+        cache_tree.subtrees = subtrees;
+        Ok(oid)
+    }
+
     /// Creates a `Tree` object from a given hash.
     ///
     /// # Arguments
@@ -168,6 +309,46 @@ impl Tree<()> {
 
         Ok(entries)
     }
+
+    /// Reads a tree and all of its subtrees, and returns the files they contain.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - A string representing the hash of the tree object.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the mode and object ID of every file, keyed by its path
+    /// relative to the tree, or an `anyhow::Error` if a tree cannot be read.
+    pub(crate) fn flatten(hash: &str) -> anyhow::Result<BTreeMap<PathBuf, (EntryMode, ObjectID)>> {
+        let mut files = BTreeMap::new();
+        Tree::flatten_into(hash, Path::new(""), &mut files)?;
+        Ok(files)
+    }
+
+    /// Adds the files of a tree to `files`, prefixing their paths with `prefix`.
+    fn flatten_into(
+        hash: &str,
+        prefix: &Path,
+        files: &mut BTreeMap<PathBuf, (EntryMode, ObjectID)>,
+    ) -> anyhow::Result<()> {
+        for entry in
+            Tree::from_hash(hash).with_context(|| format!("Failed to read tree {}", hash))?
+        {
+            let path = prefix.join(entry.name());
+            let oid = entry
+                .oid()
+                .clone()
+                .context("Tree entry without object ID.")?;
+            match entry.mode() {
+                EntryMode::Directory => Tree::flatten_into(&oid.hash(), &path, files)?,
+                mode => {
+                    files.insert(path, (*mode, oid));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<R> Object for Tree<R>
@@ -1,7 +1,7 @@
 use std::os::unix::fs::PermissionsExt;
 
 /// Represents the mode of an entry in a tree object.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum EntryMode {
     Directory,
     Symlink,
@@ -50,24 +50,38 @@ impl EntryMode {
         }
     }
 
-    /// Creates an `EntryMode` from the given octal mode representation.
+    /// Creates an `EntryMode` from the mode of an index entry.
+    ///
+    /// Any file mode is accepted: only the file type bits and the owner's
+    /// executable bit are taken into account, like git does. This way, a file staged with mode
+    /// `100664` is still a regular, non-executable file.
     ///
     /// # Arguments
     ///
-    /// * `mode` - The octal representation of the mode as an integer.
+    /// * `mode` - The mode of the index entry, e.g. as returned by `stat`.
     ///
     /// # Returns
     ///
-    /// The corresponding `EntryMode` for the given octal representation, or `None` if the mode is invalid.
-    pub(crate) fn from_octal(mode: u32) -> Option<Self> {
-        match mode {
-            0o40000 => Some(EntryMode::Directory),
+    /// The corresponding `EntryMode`, or `None` if the mode is not a regular file, symlink or directory.
+    pub(crate) fn from_index_mode(mode: u32) -> Option<Self> {
+        match mode & 0o170000 {
+            0o040000 => Some(EntryMode::Directory),
             0o120000 => Some(EntryMode::Symlink),
-            0o100755 => Some(EntryMode::Executable),
-            0o100644 => Some(EntryMode::NonExecutable),
+            0o100000 if mode & 0o100 != 0 => Some(EntryMode::Executable),
+            0o100000 => Some(EntryMode::NonExecutable),
             _ => None,
         }
     }
+
+    /// Returns the octal representation of the mode, as stored in the index.
+    pub(crate) fn to_octal(self) -> u32 {
+        match self {
+            EntryMode::Directory => 0o40000,
+            EntryMode::Symlink => 0o120000,
+            EntryMode::Executable => 0o100755,
+            EntryMode::NonExecutable => 0o100644,
+        }
+    }
 }
 
 impl std::fmt::Display for EntryMode {
@@ -0,0 +1,625 @@
+/// This module contains the implementation of references: HEAD, branches, tags and symbolic refs.
+pub(crate) mod reflog;
+
+use crate::lockfile::LockFile;
+use crate::objects::id::ObjectID;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Directory of the repository, in which all refs are stored.
+pub(crate) const GIT_DIR: &str = ".git";
+/// The name of the ref that points to the current branch or commit.
+pub(crate) const HEAD: &str = "HEAD";
+/// File containing refs that were packed together by `git pack-refs` or `git gc`.
+const PACKED_REFS: &str = "packed-refs";
+/// Prefix of the contents of a symbolic ref.
+const SYMREF_PREFIX: &str = "ref: ";
+/// Maximum number of symbolic refs followed before giving up.
+const MAX_SYMREF_DEPTH: usize = 5;
+/// Name under which the reflog of a renamed ref is kept while the ref is renamed.
+const RENAMED_LOG: &str = "refs/.tmp-renamed-log";
+
+/// Represents the value stored in a ref.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Ref {
+    /// The ref points directly to an object.
+    Direct(ObjectID),
+    /// The ref points to another ref, e.g. `HEAD` pointing to `refs/heads/main`.
+    Symbolic(String),
+}
+
+/// Represents the value a ref is expected to have before it is updated or deleted.
+pub(crate) enum Expected {
+    /// The ref may have any value, or not exist.
+    Any,
+    /// The ref must not exist.
+    Missing,
+    /// The ref must point to the given object.
+    Value(ObjectID),
+}
+
+/// Returns the path of a loose ref.
+fn ref_path(name: &str) -> PathBuf {
+    Path::new(GIT_DIR).join(name)
+}
+
+/// Checks that a ref name is well formed, following the rules of `git check-ref-format`.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the ref, e.g. `refs/heads/main`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the name is valid, or an `anyhow::Error` describing
+/// why it is not.
+pub(crate) fn check_name(name: &str) -> anyhow::Result<()> {
+    let valid = !name.is_empty()
+        && name != "@"
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+        && name
+            .split('/')
+            .all(|component| !component.starts_with('.') && !component.ends_with(".lock"));
+    anyhow::ensure!(valid, "'{}' is not a valid ref name.", name);
+    Ok(())
+}
+
+/// Reads the value of a ref without following symbolic refs.
+///
+/// Loose refs take precedence over the entries of `packed-refs`.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the ref, e.g. `HEAD` or `refs/heads/main`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the value of the ref, `None` if the ref does not exist, or an
+/// `anyhow::Error` if the ref cannot be read or is malformed.
+pub(crate) fn read_ref(name: &str) -> anyhow::Result<Option<Ref>> {
+    let path = ref_path(name);
+    if path.is_file() {
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read ref: {}", path.display()))?;
+        let content = content.trim_end();
+        return match content.strip_prefix(SYMREF_PREFIX) {
+            Some(target) => Ok(Some(Ref::Symbolic(target.trim().to_string()))),
+            None => Ok(Some(Ref::Direct(
+                ObjectID::from_hash(content)
+                    .with_context(|| format!("Ref {} does not contain a valid object ID.", name))?,
+            ))),
+        };
+    }
+
+    Ok(read_packed_refs()?.remove(name).map(Ref::Direct))
+}
+
+/// Follows a chain of symbolic refs down to the ref that holds an object ID.
+///
+/// The final ref does not need to exist, e.g. `HEAD` on a branch without commits resolves to the
+/// name of that branch.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the ref to start from.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the name of the final ref, or an `anyhow::Error` if the chain is
+/// too long or a ref cannot be read.
+pub(crate) fn resolve_name(name: &str) -> anyhow::Result<String> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read_ref(&name)? {
+            Some(Ref::Symbolic(target)) => name = target,
+            _ => return Ok(name),
+        }
+    }
+    anyhow::bail!("Symbolic ref {} is nested too deeply.", name)
+}
+
+/// Resolves a ref to the object it points to, following symbolic refs.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the ref, e.g. `HEAD` or `refs/heads/main`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the object ID, `None` if the ref (or the ref it points to) does
+/// not exist, or an `anyhow::Error` if a ref cannot be read.
+pub(crate) fn resolve(name: &str) -> anyhow::Result<Option<ObjectID>> {
+    match read_ref(&resolve_name(name)?)? {
+        Some(Ref::Direct(oid)) => Ok(Some(oid)),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the name of the branch HEAD points to, or `None` if HEAD is detached.
+pub(crate) fn current_branch() -> anyhow::Result<Option<String>> {
+    match read_ref(HEAD)? {
+        Some(Ref::Symbolic(_)) => Ok(Some(resolve_name(HEAD)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Reads the refs stored in the `packed-refs` file.
+///
+/// Lines starting with `#` are comments and lines starting with `^` hold the peeled value of the
+/// annotated tag on the previous line; both are skipped.
+///
+/// # Returns
+///
+/// Returns a `Result` containing a map from ref names to object IDs, which is empty if the file
+/// does not exist, or an `anyhow::Error` if the file is malformed.
+pub(crate) fn read_packed_refs() -> anyhow::Result<BTreeMap<String, ObjectID>> {
+    let path = Path::new(GIT_DIR).join(PACKED_REFS);
+    let mut refs = BTreeMap::new();
+    if !path.exists() {
+        return Ok(refs);
+    }
+
+    let content = std::fs::read_to_string(&path).context("Failed to read packed-refs.")?;
+    for line in content.lines() {
+        if line.is_empty() || line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        let (hash, name) = line
+            .split_once(' ')
+            .with_context(|| format!("Malformed line in packed-refs: {}", line))?;
+        refs.insert(name.to_string(), ObjectID::from_hash(hash)?);
+    }
+    Ok(refs)
+}
+
+/// Lists the refs that hold object IDs under the given prefix, e.g. `refs/heads/`.
+///
+/// Both loose refs and packed refs are listed, with loose refs taking precedence. Symbolic refs
+/// under `refs/` (such as `refs/remotes/origin/HEAD`) are resolved.
+///
+/// # Arguments
+///
+/// * `prefix` - The prefix the full ref names must start with.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the refs sorted by name, or an `anyhow::Error` if the refs
+/// cannot be read.
+pub(crate) fn list_refs(prefix: &str) -> anyhow::Result<Vec<(String, ObjectID)>> {
+    let mut refs = read_packed_refs()?;
+    refs.retain(|name, _| name.starts_with(prefix));
+
+    let refs_dir = ref_path("refs");
+    if refs_dir.is_dir() {
+        for direntry in walkdir::WalkDir::new(&refs_dir) {
+            let direntry = direntry.context("Failed to walk the refs directory.")?;
+            if !direntry.file_type().is_file() {
+                continue;
+            }
+            let name = direntry
+                .path()
+                .strip_prefix(GIT_DIR)?
+                .to_string_lossy()
+                .to_string();
+            if !name.starts_with(prefix) || name.ends_with(".lock") {
+                continue;
+            }
+            if let Some(oid) = resolve(&name)? {
+                refs.insert(name, oid);
+            }
+        }
+    }
+
+    Ok(refs.into_iter().collect())
+}
+
+/// Atomically updates a ref to point to an object.
+///
+/// Symbolic refs are followed unless `no_deref` is set, so that updating `HEAD` moves the current
+/// branch. The ref is locked through a `.lock` file while its current value is compared with the
+/// expected one and the new value is written. The update is recorded in the reflog of the ref,
+/// and in the reflog of `HEAD` when it moves the current branch.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the ref to update.
+/// * `new` - The object the ref should point to.
+/// * `expected` - The value the ref must have before the update.
+/// * `message` - The message to record in the reflog.
+/// * `no_deref` - If true, overwrite the ref itself even if it is a symbolic ref.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the ref was updated, or an `anyhow::Error` if the ref is
+/// locked by another process or does not have the expected value.
+pub(crate) fn update_ref(
+    name: &str,
+    new: &ObjectID,
+    expected: Expected,
+    message: &str,
+    no_deref: bool,
+) -> anyhow::Result<()> {
+    let target = if no_deref {
+        name.to_string()
+    } else {
+        resolve_name(name)?
+    };
+    if target != HEAD {
+        check_name(&target)?;
+    }
+
+    let old = write_ref(&target, new, &expected)?;
+    reflog::append(&target, old.as_ref(), new, message)?;
+    if target != HEAD && current_branch()?.as_deref() == Some(target.as_str()) {
+        reflog::append(HEAD, old.as_ref(), new, message)?;
+    }
+    Ok(())
+}
+
+/// Deletes a ref, both its loose file and its entry in `packed-refs`, along with its reflog.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the ref to delete.
+/// * `expected` - The value the ref must have before it is deleted.
+/// * `no_deref` - If true, delete the ref itself even if it is a symbolic ref.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the ref was deleted, or an `anyhow::Error` if the ref is
+/// locked by another process or does not have the expected value.
+pub(crate) fn delete_ref(name: &str, expected: Expected, no_deref: bool) -> anyhow::Result<()> {
+    let target = if no_deref {
+        name.to_string()
+    } else {
+        resolve_name(name)?
+    };
+
+    let lock = LockFile::acquire(ref_path(&target))?;
+    let old = match read_ref(&target)? {
+        Some(Ref::Direct(oid)) => Some(oid),
+        _ => None,
+    };
+    check_expected(&target, old.as_ref(), &expected)?;
+
+    let path = ref_path(&target);
+    if path.is_file() {
+        std::fs::remove_file(&path).with_context(|| format!("Failed to delete ref {}", target))?;
+    }
+    if read_packed_refs()?.contains_key(&target) {
+        remove_packed_ref(&target)?;
+    }
+    drop(lock);
+
+    reflog::delete(&target)?;
+    remove_empty_parents(&path);
+    Ok(())
+}
+
+/// Renames a ref, moving its reflog along, and points HEAD to the new name if it pointed to the
+/// old one.
+///
+/// # Arguments
+///
+/// * `old` - The full name of the ref to rename.
+/// * `new` - The new full name of the ref.
+/// * `message` - The message to record in the reflogs.
+/// * `force` - If true, replace the ref with the new name if it exists.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the ref was renamed, or an `anyhow::Error` if the old
+/// ref does not exist, the new one exists without `force`, or a ref is locked.
+pub(crate) fn rename_ref(old: &str, new: &str, message: &str, force: bool) -> anyhow::Result<()> {
+    check_name(new)?;
+    let Some(Ref::Direct(oid)) = read_ref(old)? else {
+        anyhow::bail!("refname {} not found", old);
+    };
+    let is_head = current_branch()?.as_deref() == Some(old);
+    if old != new && read_ref(new)?.is_some() {
+        anyhow::ensure!(force, "Cannot rename {}: {} already exists.", old, new);
+        delete_ref(new, Expected::Any, true)?;
+    }
+
+    // The reflog goes through a temporary name, since the new name may be a directory of the old
+    // one, or the other way around.
+    reflog::rename(old, RENAMED_LOG)?;
+    delete_ref(old, Expected::Value(oid.clone()), true)?;
+    reflog::rename(RENAMED_LOG, new)?;
+    // Like git, the reflog records the renamed ref as moving from its value to itself.
+    write_ref(new, &oid, &Expected::Missing)?;
+    reflog::append(new, Some(&oid), &oid, message)?;
+    if is_head {
+        write_symbolic_ref(HEAD, new)?;
+        reflog::append(HEAD, Some(&oid), &oid, message)?;
+    }
+    Ok(())
+}
+
+/// Writes a symbolic ref pointing to another ref.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the symbolic ref, e.g. `HEAD`.
+/// * `target` - The full name of the ref it should point to.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the symbolic ref was written, or an `anyhow::Error` if
+/// the target name is invalid or the ref is locked by another process.
+pub(crate) fn write_symbolic_ref(name: &str, target: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        target.starts_with("refs/"),
+        "Refusing to point {} outside of refs/: {}",
+        name,
+        target
+    );
+    check_name(target)?;
+
+    let mut lock = LockFile::acquire(ref_path(name))?;
+    writeln!(lock, "{}{}", SYMREF_PREFIX, target)
+        .with_context(|| format!("Failed to write symbolic ref {}", name))?;
+    lock.commit()
+        .with_context(|| format!("Failed to update symbolic ref {}", name))
+}
+
+/// Shortens a full ref name for display, e.g. `refs/heads/main` to `main`.
+pub(crate) fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Writes a ref under its lock, after comparing its current value with the expected one.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the previous value of the ref, or an `anyhow::Error` if the
+/// ref is locked by another process or does not have the expected value.
+fn write_ref(name: &str, new: &ObjectID, expected: &Expected) -> anyhow::Result<Option<ObjectID>> {
+    let mut lock = LockFile::acquire(ref_path(name))?;
+    let old = match read_ref(name)? {
+        Some(Ref::Direct(oid)) => Some(oid),
+        _ => None,
+    };
+    check_expected(name, old.as_ref(), expected)?;
+
+    writeln!(lock, "{}", new.hash()).with_context(|| format!("Failed to write ref {}", name))?;
+    lock.commit()
+        .with_context(|| format!("Failed to update ref {}", name))?;
+    Ok(old)
+}
+
+/// Checks the current value of a ref against the expected one.
+fn check_expected(name: &str, old: Option<&ObjectID>, expected: &Expected) -> anyhow::Result<()> {
+    match (expected, old) {
+        (Expected::Any, _) => Ok(()),
+        (Expected::Missing, None) => Ok(()),
+        (Expected::Missing, Some(old)) => {
+            anyhow::bail!(
+                "Cannot lock ref '{}': reference already exists ({}).",
+                name,
+                old
+            )
+        }
+        (Expected::Value(value), Some(old)) if value == old => Ok(()),
+        (Expected::Value(value), Some(old)) => anyhow::bail!(
+            "Cannot lock ref '{}': is at {} but expected {}.",
+            name,
+            old,
+            value
+        ),
+        (Expected::Value(value), None) => anyhow::bail!(
+            "Cannot lock ref '{}': unable to resolve reference, expected {}.",
+            name,
+            value
+        ),
+    }
+}
+
+/// Rewrites `packed-refs` without the given ref, keeping the peeled lines of the other refs.
+fn remove_packed_ref(name: &str) -> anyhow::Result<()> {
+    let path = Path::new(GIT_DIR).join(PACKED_REFS);
+    let mut lock = LockFile::acquire(&path)?;
+    let content = std::fs::read_to_string(&path).context("Failed to read packed-refs.")?;
+
+    let mut skip_peeled = false;
+    for line in content.lines() {
+        if line.starts_with('^') && skip_peeled {
+            continue;
+        }
+        skip_peeled = line.split_once(' ').map(|(_, ref_name)| ref_name) == Some(name);
+        if !skip_peeled {
+            writeln!(lock, "{}", line)?;
+        }
+    }
+    lock.commit().context("Failed to update packed-refs.")
+}
+
+/// Removes the empty directories left behind by a deleted ref, up to `refs/`.
+fn remove_empty_parents(path: &Path) {
+    let refs_dir = ref_path("refs");
+    let mut dir = path.parent();
+    while let Some(parent) = dir {
+        if parent == refs_dir || !parent.starts_with(&refs_dir) {
+            break;
+        }
+        if std::fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    /// Returns the old and new values recorded in the reflog of a ref.
+    fn reflog_values(name: &str) -> Vec<(ObjectID, ObjectID)> {
+        reflog::read(name)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.old, entry.new))
+            .collect()
+    }
+
+    /// Writes the `packed-refs` file, with the header git writes.
+    fn write_packed_refs(lines: &[String]) {
+        let mut content = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+        for line in lines {
+            content.push_str(line);
+            content.push('\n');
+        }
+        std::fs::write(Path::new(GIT_DIR).join(PACKED_REFS), content).unwrap();
+    }
+
+    #[test]
+    fn reads_packed_refs_under_loose_refs() {
+        let repo = TestRepo::new();
+        let (one, two, three) = (repo.blob("one"), repo.blob("two"), repo.blob("three"));
+        write_packed_refs(&[
+            format!("{} refs/heads/main", one),
+            format!("{} refs/heads/topic", one),
+            format!("{} refs/tags/v1", two),
+            format!("^{}", one),
+        ]);
+        repo.set_ref("refs/heads/topic", &three);
+
+        let packed = read_packed_refs().unwrap();
+        assert_eq!(
+            packed.into_iter().collect::<Vec<_>>(),
+            [
+                ("refs/heads/main".to_string(), one.clone()),
+                ("refs/heads/topic".to_string(), one.clone()),
+                ("refs/tags/v1".to_string(), two.clone()),
+            ]
+        );
+        assert_eq!(read_ref("refs/tags/v1").unwrap(), Some(Ref::Direct(two)));
+        assert_eq!(
+            read_ref("refs/heads/topic").unwrap(),
+            Some(Ref::Direct(three.clone()))
+        );
+        assert_eq!(resolve(HEAD).unwrap(), Some(one.clone()));
+        assert_eq!(
+            list_refs("refs/heads/").unwrap(),
+            [
+                ("refs/heads/main".to_string(), one),
+                ("refs/heads/topic".to_string(), three),
+            ]
+        );
+        assert_eq!(read_ref("refs/heads/missing").unwrap(), None);
+    }
+
+    #[test]
+    fn updates_refs_only_from_the_expected_value() {
+        let repo = TestRepo::new();
+        let (one, two) = (repo.blob("one"), repo.blob("two"));
+        let branch = "refs/heads/main";
+
+        update_ref(HEAD, &one, Expected::Missing, "create", false).unwrap();
+        assert_eq!(read_ref(branch).unwrap(), Some(Ref::Direct(one.clone())));
+        assert_eq!(
+            read_ref(HEAD).unwrap(),
+            Some(Ref::Symbolic(branch.to_string()))
+        );
+        assert!(update_ref(branch, &two, Expected::Missing, "again", false).is_err());
+        assert!(update_ref(branch, &two, Expected::Value(two.clone()), "stale", false).is_err());
+        assert_eq!(resolve(branch).unwrap(), Some(one.clone()));
+
+        update_ref(branch, &two, Expected::Value(one.clone()), "move", false).unwrap();
+        assert_eq!(resolve(HEAD).unwrap(), Some(two.clone()));
+        let values = [(ObjectID::zero(), one.clone()), (one.clone(), two.clone())];
+        assert_eq!(reflog_values(branch), values);
+        assert_eq!(reflog_values(HEAD), values);
+
+        // A packed ref is removed along with its loose file.
+        write_packed_refs(&[format!("{} {}", one, branch)]);
+        assert!(delete_ref(branch, Expected::Value(one.clone()), false).is_err());
+        assert!(delete_ref(branch, Expected::Missing, false).is_err());
+        delete_ref(branch, Expected::Value(two), false).unwrap();
+        assert_eq!(read_ref(branch).unwrap(), None);
+        assert!(read_packed_refs().unwrap().is_empty());
+        assert!(reflog::read(branch).unwrap().is_none());
+        assert!(delete_ref(branch, Expected::Value(one), false).is_err());
+    }
+
+    #[test]
+    fn removes_packed_refs_with_their_peeled_lines() {
+        let repo = TestRepo::new();
+        let (one, two, three) = (repo.blob("one"), repo.blob("two"), repo.blob("three"));
+        let lines = [
+            format!("{} refs/tags/v1", one),
+            format!("^{}", three),
+            format!("{} refs/tags/v2", two),
+            format!("^{}", three),
+            format!("{} refs/tags/v3", three),
+        ];
+        write_packed_refs(&lines);
+
+        delete_ref("refs/tags/v2", Expected::Any, false).unwrap();
+        let content = std::fs::read_to_string(Path::new(GIT_DIR).join(PACKED_REFS)).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n{}\n{}\n{}\n",
+                lines[0], lines[1], lines[4]
+            )
+        );
+
+        delete_ref("refs/tags/v1", Expected::Any, false).unwrap();
+        let content = std::fs::read_to_string(Path::new(GIT_DIR).join(PACKED_REFS)).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n{}\n",
+                lines[4]
+            )
+        );
+    }
+
+    #[test]
+    fn renames_refs_with_their_reflogs() {
+        let repo = TestRepo::new();
+        let (one, two) = (repo.blob("one"), repo.blob("two"));
+        update_ref(HEAD, &one, Expected::Missing, "create", false).unwrap();
+        update_ref("refs/heads/other", &two, Expected::Missing, "other", false).unwrap();
+
+        // The new name may be a directory of the old one.
+        rename_ref("refs/heads/main", "refs/heads/main/new", "rename", false).unwrap();
+        assert_eq!(read_ref("refs/heads/main").unwrap(), None);
+        assert_eq!(resolve("refs/heads/main/new").unwrap(), Some(one.clone()));
+        assert_eq!(
+            current_branch().unwrap().as_deref(),
+            Some("refs/heads/main/new")
+        );
+        assert_eq!(
+            reflog_values("refs/heads/main/new"),
+            [(ObjectID::zero(), one.clone()), (one.clone(), one.clone())]
+        );
+
+        assert!(rename_ref("refs/heads/main/new", "refs/heads/other", "rename", false).is_err());
+        assert!(rename_ref("refs/heads/missing", "refs/heads/new", "rename", false).is_err());
+        rename_ref("refs/heads/main/new", "refs/heads/other", "rename", true).unwrap();
+        assert_eq!(resolve("refs/heads/other").unwrap(), Some(one));
+        assert_eq!(read_ref("refs/heads/main/new").unwrap(), None);
+        assert!(!repo.path().join(".git/refs/heads/main").exists());
+        assert_eq!(
+            current_branch().unwrap().as_deref(),
+            Some("refs/heads/other")
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::config;
+use crate::objects::id::ObjectID;
+use anyhow::Context;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Directory holding the reflogs, mirroring the layout of the refs.
+const LOGS_DIR: &str = ".git/logs";
+
+/// Represents an entry of a reflog, recording one update of a ref.
+pub(crate) struct Entry {
+    /// The previous value of the ref, or the null ID if it did not exist.
+    pub(crate) old: ObjectID,
+    /// The new value of the ref.
+    pub(crate) new: ObjectID,
+}
+
+/// Returns the path of the reflog of a ref.
+fn log_path(name: &str) -> PathBuf {
+    Path::new(LOGS_DIR).join(name)
+}
+
+/// Returns true if updates to the given ref should be logged.
+///
+/// Like git with `core.logAllRefUpdates` enabled, `HEAD`, branches and remote-tracking branches
+/// are always logged, and any other ref is logged only if it already has a reflog.
+fn should_log(name: &str) -> bool {
+    name == super::HEAD
+        || name.starts_with("refs/heads/")
+        || name.starts_with("refs/remotes/")
+        || log_path(name).exists()
+}
+
+/// Appends an entry to the reflog of a ref.
+///
+/// Each line of a reflog has the form `<old> <new> <name> <<email>> <timestamp> <tz>\t<message>`.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the ref that was updated.
+/// * `old` - The previous value of the ref, or `None` if it did not exist.
+/// * `new` - The new value of the ref.
+/// * `message` - The reason of the update.
+///
+/// # Returns
+///
+/// Returns a `Result` containing `()` if the entry was appended, or an `anyhow::Error` if the
+/// reflog cannot be written.
+pub(crate) fn append(
+    name: &str,
+    old: Option<&ObjectID>,
+    new: &ObjectID,
+    message: &str,
+) -> anyhow::Result<()> {
+    if !should_log(name) {
+        return Ok(());
+    }
+
+    // A missing identity should not prevent updating the ref itself.
+    let (committer, email) = config::Config::load()?
+        .identity("COMMITTER")
+        .unwrap_or_default();
+    let local = chrono::Local::now();
+    let timezone = format!("{}", local.offset()).replace(':', "");
+    let old = old.cloned().unwrap_or_else(ObjectID::zero);
+    // The message must fit on a single line.
+    let message = message.lines().next().unwrap_or("");
+
+    let path = log_path(name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open reflog: {}", path.display()))?;
+    writeln!(
+        file,
+        "{} {} {} <{}> {} {}\t{}",
+        old,
+        new,
+        committer,
+        email,
+        local.timestamp(),
+        timezone,
+        message
+    )
+    .with_context(|| format!("Failed to write reflog: {}", path.display()))
+}
+
+/// Deletes the reflog of a ref, if it has one.
+pub(crate) fn delete(name: &str) -> anyhow::Result<()> {
+    let path = log_path(name);
+    if path.is_file() {
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to delete reflog: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Moves the reflog of a ref to another ref, if it has one.
+///
+/// # Arguments
+///
+/// * `old` - The full name of the ref whose reflog is moved.
+/// * `new` - The full name of the ref the reflog is moved to.
+pub(crate) fn rename(old: &str, new: &str) -> anyhow::Result<()> {
+    let (old_path, new_path) = (log_path(old), log_path(new));
+    if !old_path.is_file() {
+        return Ok(());
+    }
+    if let Some(parent) = new_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    std::fs::rename(&old_path, &new_path).with_context(|| {
+        format!(
+            "Failed to move reflog {} to {}",
+            old_path.display(),
+            new_path.display()
+        )
+    })
+}
+
+/// Reads the reflog of a ref.
+///
+/// # Arguments
+///
+/// * `name` - The full name of the ref.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the entries from the oldest to the newest, `None` if the ref has
+/// no reflog, or an `anyhow::Error` if the reflog cannot be read or is malformed.
+pub(crate) fn read(name: &str) -> anyhow::Result<Option<Vec<Entry>>> {
+    let path = log_path(name);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read reflog: {}", path.display()))?;
+
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let malformed = || format!("Malformed line in reflog {}: {}", path.display(), line);
+        let mut parts = line.splitn(3, ' ');
+        let (Some(old), Some(new)) = (parts.next(), parts.next()) else {
+            anyhow::bail!(malformed());
+        };
+        entries.push(Entry {
+            old: ObjectID::from_hash(old).with_context(malformed)?,
+            new: ObjectID::from_hash(new).with_context(malformed)?,
+        });
+    }
+    Ok(Some(entries))
+}
@@ -0,0 +1,628 @@
+/// This module walks the commit graph: it lists the commits of revision ranges in the orders
+/// `git log` supports, with path limiting, and computes merge bases.
+use crate::objects::commit::Commit;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::objects::tree::Tree;
+use crate::revision;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// The order in which the commits of a walk are listed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Order {
+    /// Newest commit date first, which may show a parent before its child if clocks were skewed.
+    Default,
+    /// Newest commit date first, but never a parent before all of its children.
+    Date,
+    /// Parents after all of their children, without interleaving lines of history.
+    Topo,
+}
+
+/// Reads commits from the database, keeping the ones already read.
+#[derive(Default)]
+pub(crate) struct CommitGraph {
+    commits: HashMap<ObjectID, Commit>,
+}
+
+impl CommitGraph {
+    /// Returns a commit, reading it from the database the first time.
+    ///
+    /// # Arguments
+    ///
+    /// * `oid` - The ID of the commit.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the commit, or an `anyhow::Error` if the object cannot be
+    /// read or is not a commit.
+    pub(crate) fn get(&mut self, oid: &ObjectID) -> anyhow::Result<&Commit> {
+        if !self.commits.contains_key(oid) {
+            let commit = Commit::read(&oid.hash())?;
+            self.commits.insert(oid.clone(), commit);
+        }
+        Ok(&self.commits[oid])
+    }
+
+    /// Returns the parents of a commit.
+    fn parents(&mut self, oid: &ObjectID) -> anyhow::Result<Vec<ObjectID>> {
+        Ok(self.get(oid)?.parents.clone())
+    }
+
+    /// Returns the committer date of a commit.
+    fn date(&mut self, oid: &ObjectID) -> anyhow::Result<i64> {
+        Ok(self.get(oid)?.committer.timestamp)
+    }
+
+    /// Computes the best common ancestors of two commits, the merge bases.
+    ///
+    /// Both histories are walked from the newest commit down, painting each commit with the side
+    /// it is reachable from. Commits reachable from both sides are candidates, and the ancestors
+    /// of a candidate are not. Candidates that are ancestors of another candidate are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `one` - The first commit.
+    /// * `two` - The second commit.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the merge bases, newest first, which is empty if the commits
+    /// have no common history, or an `anyhow::Error` if a commit cannot be read.
+    pub(crate) fn merge_bases(
+        &mut self,
+        one: &ObjectID,
+        two: &ObjectID,
+    ) -> anyhow::Result<Vec<ObjectID>> {
+        const ONE: u8 = 1;
+        const TWO: u8 = 2;
+        const STALE: u8 = 4;
+        if one == two {
+            return Ok(vec![one.clone()]);
+        }
+
+        let mut flags = HashMap::from([(one.clone(), ONE), (two.clone(), TWO)]);
+        let mut queue = DateQueue::default();
+        queue.push(one.clone(), self.date(one)?);
+        queue.push(two.clone(), self.date(two)?);
+        let mut candidates = Vec::new();
+        while queue.iter().any(|oid| flags[oid] & STALE == 0) {
+            let Some(oid) = queue.pop() else {
+                break;
+            };
+            let mut paint = flags[&oid] & (ONE | TWO | STALE);
+            if paint & (ONE | TWO) == ONE | TWO {
+                // A commit is queued again when it gets more flags, but is a candidate once.
+                if paint & STALE == 0 && !candidates.contains(&oid) {
+                    candidates.push(oid.clone());
+                }
+                paint |= STALE;
+            }
+            for parent in self.parents(&oid)? {
+                let current = flags.entry(parent.clone()).or_default();
+                if *current & paint == paint {
+                    continue;
+                }
+                *current |= paint;
+                let date = self.date(&parent)?;
+                queue.push(parent, date);
+            }
+        }
+
+        let candidates = candidates
+            .into_iter()
+            .filter(|oid| flags[oid] & STALE == 0)
+            .collect::<Vec<_>>();
+        let mut bases = Vec::new();
+        for candidate in &candidates {
+            let mut redundant = false;
+            for other in candidates.iter().filter(|other| *other != candidate) {
+                if self.is_ancestor(candidate, other)? {
+                    redundant = true;
+                    break;
+                }
+            }
+            if !redundant {
+                bases.push(candidate.clone());
+            }
+        }
+        Ok(bases)
+    }
+
+    /// Returns true if `ancestor` is reachable from `descendant`, or is the same commit.
+    pub(crate) fn is_ancestor(
+        &mut self,
+        ancestor: &ObjectID,
+        descendant: &ObjectID,
+    ) -> anyhow::Result<bool> {
+        // No commit older than the ancestor can lead to it, if dates are correct.
+        let min_date = self.date(ancestor)?;
+        let mut seen = HashSet::from([descendant.clone()]);
+        let mut stack = vec![descendant.clone()];
+        while let Some(oid) = stack.pop() {
+            if oid == *ancestor {
+                return Ok(true);
+            }
+            if self.date(&oid)? < min_date {
+                continue;
+            }
+            for parent in self.parents(&oid)? {
+                if seen.insert(parent.clone()) {
+                    stack.push(parent);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// The number of commits older than every listed commit that are still walked once only
+/// uninteresting commits remain, in case one of them leads to a listed commit through a commit
+/// with a wrong date. This is the same number as git's.
+const SLOP: usize = 5;
+
+/// Decides whether the walk goes on after an uninteresting commit was taken, like git's
+/// `still_interesting`.
+///
+/// # Arguments
+///
+/// * `queue` - The commits that remain to be taken.
+/// * `uninteresting` - The commits of the history left out.
+/// * `date` - The date of the last listed commit.
+/// * `slop` - The number of old uninteresting commits that may still be taken.
+///
+/// # Returns
+///
+/// Returns the new number of old uninteresting commits that may still be taken, 0 to stop.
+fn still_interesting(
+    queue: &DateQueue,
+    uninteresting: &HashSet<ObjectID>,
+    date: i64,
+    slop: usize,
+) -> usize {
+    let Some(next) = queue.peek_date() else {
+        return 0;
+    };
+    if date <= next || queue.iter().any(|oid| !uninteresting.contains(oid)) {
+        return SLOP;
+    }
+    slop - 1
+}
+
+/// A queue of commits that pops the newest commit first, and commits with the same date in the
+/// order they were pushed.
+#[derive(Default)]
+struct DateQueue {
+    heap: BinaryHeap<(i64, Reverse<usize>, ObjectID)>,
+    pushed: usize,
+}
+
+impl DateQueue {
+    fn push(&mut self, oid: ObjectID, date: i64) {
+        self.heap.push((date, Reverse(self.pushed), oid));
+        self.pushed += 1;
+    }
+
+    fn pop(&mut self) -> Option<ObjectID> {
+        self.heap.pop().map(|(_, _, oid)| oid)
+    }
+
+    fn peek_date(&self) -> Option<i64> {
+        self.heap.peek().map(|(date, _, _)| *date)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &ObjectID> {
+        self.heap.iter().map(|(_, _, oid)| oid)
+    }
+}
+
+/// Lists the commits reachable from a set of commits and not from another, like `git rev-list`.
+pub(crate) struct RevWalk {
+    pub(crate) graph: CommitGraph,
+    /// The commits whose history is listed.
+    include: Vec<ObjectID>,
+    /// The commits whose history is left out.
+    exclude: Vec<ObjectID>,
+    /// The order of the listed commits.
+    pub(crate) order: Order,
+    /// If true, follow only the first parent of merge commits.
+    pub(crate) first_parent: bool,
+    /// If not empty, list only the commits that change one of these paths, simplifying the
+    /// history like git does by default.
+    pub(crate) paths: Vec<PathBuf>,
+    /// The maximum number of commits to list.
+    pub(crate) max_count: Option<usize>,
+}
+
+impl RevWalk {
+    /// Creates a new walk that lists no commits until revisions are added.
+    pub(crate) fn new() -> RevWalk {
+        RevWalk {
+            graph: CommitGraph::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            order: Order::Default,
+            first_parent: false,
+            paths: Vec::new(),
+            max_count: None,
+        }
+    }
+
+    /// Adds a revision or a revision range to the walk.
+    ///
+    /// * `<rev>` lists the history of the revision.
+    /// * `^<rev>` leaves out the history of the revision.
+    /// * `<a>..<b>` lists the history of `b` that is not in the history of `a`.
+    /// * `<a>...<b>` lists the history of either `a` or `b`, but not of both.
+    ///
+    /// An empty side of a range stands for `HEAD`.
+    ///
+    /// # Arguments
+    ///
+    /// * `rev` - The revision or range, see `revision::resolve`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing `()`, or an `anyhow::Error` if a revision cannot be resolved
+    /// to a commit.
+    pub(crate) fn push(&mut self, rev: &str) -> anyhow::Result<()> {
+        let resolve = |rev: &str| {
+            let rev = if rev.is_empty() { "HEAD" } else { rev };
+            revision::peel(&revision::resolve(rev)?, ObjectKind::Commit)
+        };
+        if let Some(rev) = rev.strip_prefix('^') {
+            self.exclude.push(resolve(rev)?);
+        } else if let Some((one, two)) = rev.split_once("...") {
+            let (one, two) = (resolve(one)?, resolve(two)?);
+            let bases = self.graph.merge_bases(&one, &two)?;
+            self.include.extend([one, two]);
+            self.exclude.extend(bases);
+        } else if let Some((one, two)) = rev.split_once("..") {
+            self.exclude.push(resolve(one)?);
+            self.include.push(resolve(two)?);
+        } else {
+            self.include.push(resolve(rev)?);
+        }
+        Ok(())
+    }
+
+    /// Walks the history and lists the commits.
+    ///
+    /// Commits are taken from the newest down. The history left out is painted as uninteresting
+    /// as it is reached, which may be after some of it was listed when commits share a date or
+    /// clocks were skewed. The walk goes on while uninteresting commits that are not older than
+    /// the last listed commit remain to be seen, see `still_interesting`.
+    /// With paths, a merge that has the same content as one of its parents at these paths is
+    /// replaced by that parent, and other commits are listed only if they change the paths.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the IDs of the commits in the requested order, or an
+    /// `anyhow::Error` if a commit or a tree cannot be read.
+    pub(crate) fn walk(&mut self) -> anyhow::Result<Vec<ObjectID>> {
+        let mut uninteresting = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut visited = HashMap::new();
+        let mut queue = DateQueue::default();
+        for oid in self.exclude.clone() {
+            uninteresting.insert(oid.clone());
+            // The parents are marked right away, so that the history left out is recognised even
+            // if the commit itself is older than they are and is not reached before the walk ends.
+            uninteresting.extend(self.graph.parents(&oid)?);
+            if seen.insert(oid.clone()) {
+                let date = self.graph.date(&oid)?;
+                queue.push(oid, date);
+            }
+        }
+        for oid in self.include.clone() {
+            if seen.insert(oid.clone()) {
+                let date = self.graph.date(&oid)?;
+                queue.push(oid, date);
+            }
+        }
+
+        // Without history to leave out and with the default order, the walk can stop as soon as
+        // enough commits were found.
+        let streaming = self.exclude.is_empty() && self.order == Order::Default;
+        let mut listed = Vec::new();
+        // The date of the last listed commit, and the number of uninteresting commits still to
+        // be taken once only they remain, see `still_interesting`.
+        let mut date = i64::MAX;
+        let mut slop = SLOP;
+        while let Some(oid) = queue.pop() {
+            let mut parents = self.graph.parents(&oid)?;
+            let interesting = !uninteresting.contains(&oid);
+            if interesting {
+                if self.first_parent {
+                    parents.truncate(1);
+                }
+                let (show, followed) = self.simplify(&oid, parents)?;
+                parents = followed;
+                if show {
+                    listed.push(oid.clone());
+                }
+                date = self.graph.date(&oid)?;
+            } else {
+                for parent in &parents {
+                    self.mark_uninteresting(parent, &mut uninteresting, &visited);
+                }
+            }
+            for parent in &parents {
+                if seen.insert(parent.clone()) {
+                    let date = self.graph.date(parent)?;
+                    queue.push(parent.clone(), date);
+                }
+            }
+            visited.insert(oid, parents);
+
+            if streaming && self.max_count.is_some_and(|max| listed.len() >= max) {
+                break;
+            }
+            if !interesting {
+                slop = still_interesting(&queue, &uninteresting, date, slop);
+                if slop == 0 {
+                    break;
+                }
+            }
+        }
+
+        listed.retain(|oid| !uninteresting.contains(oid));
+        if self.order != Order::Default {
+            listed = self.sort_topologically(listed, &visited)?;
+        }
+        if let Some(max) = self.max_count {
+            listed.truncate(max);
+        }
+        Ok(listed)
+    }
+
+    /// Marks a commit and all of its ancestors visited so far as uninteresting. The ancestors
+    /// that were not visited yet are marked when they are.
+    fn mark_uninteresting(
+        &self,
+        oid: &ObjectID,
+        uninteresting: &mut HashSet<ObjectID>,
+        visited: &HashMap<ObjectID, Vec<ObjectID>>,
+    ) {
+        let mut stack = vec![oid.clone()];
+        while let Some(oid) = stack.pop() {
+            if !uninteresting.insert(oid.clone()) {
+                continue;
+            }
+            if let Some(parents) = visited.get(&oid) {
+                stack.extend(parents.iter().cloned());
+            }
+        }
+    }
+
+    /// Decides whether a commit is listed and which of its parents are followed when the walk is
+    /// limited to paths.
+    ///
+    /// A commit is listed if it changes the paths compared to each of its parents. If it has the
+    /// same content as one of its parents, only that parent is followed.
+    fn simplify(
+        &mut self,
+        oid: &ObjectID,
+        parents: Vec<ObjectID>,
+    ) -> anyhow::Result<(bool, Vec<ObjectID>)> {
+        if self.paths.is_empty() {
+            return Ok((true, parents));
+        }
+        let tree = self.graph.get(oid)?.tree.clone();
+        if parents.is_empty() {
+            let mut empty = true;
+            for path in &self.paths {
+                empty &= entry_at(&tree, path)?.is_none();
+            }
+            return Ok((!empty, parents));
+        }
+        for parent in &parents {
+            let parent_tree = self.graph.get(parent)?.tree.clone();
+            let mut same = true;
+            for path in &self.paths {
+                same &= entry_at(&tree, path)? == entry_at(&parent_tree, path)?;
+            }
+            if same {
+                return Ok((false, vec![parent.clone()]));
+            }
+        }
+        Ok((true, parents))
+    }
+
+    /// Sorts commits so that no parent comes before its children. Commits that are ready to be
+    /// listed are taken newest first with `Order::Date`, and the most recently reached first with
+    /// `Order::Topo`, which keeps lines of history together.
+    fn sort_topologically(
+        &mut self,
+        commits: Vec<ObjectID>,
+        parents: &HashMap<ObjectID, Vec<ObjectID>>,
+    ) -> anyhow::Result<Vec<ObjectID>> {
+        let listed = commits.iter().cloned().collect::<HashSet<_>>();
+        let edges = |oid: &ObjectID| {
+            parents
+                .get(oid)
+                .into_iter()
+                .flatten()
+                .filter(|parent| listed.contains(*parent))
+        };
+        let mut children = HashMap::<ObjectID, usize>::new();
+        for oid in &commits {
+            for parent in edges(oid) {
+                *children.entry(parent.clone()).or_default() += 1;
+            }
+        }
+
+        let mut dates = DateQueue::default();
+        let mut stack = Vec::new();
+        for oid in commits.iter().filter(|oid| !children.contains_key(*oid)) {
+            match self.order {
+                Order::Topo => stack.push(oid.clone()),
+                _ => dates.push(oid.clone(), self.graph.date(oid)?),
+            }
+        }
+        stack.reverse();
+
+        let mut sorted = Vec::with_capacity(commits.len());
+        loop {
+            let next = match self.order {
+                Order::Topo => stack.pop(),
+                _ => dates.pop(),
+            };
+            let Some(oid) = next else {
+                break;
+            };
+            for parent in edges(&oid) {
+                let count = children.get_mut(parent).expect("Counted above.");
+                *count -= 1;
+                if *count == 0 {
+                    match self.order {
+                        Order::Topo => stack.push(parent.clone()),
+                        _ => dates.push(parent.clone(), self.graph.date(parent)?),
+                    }
+                }
+            }
+            sorted.push(oid);
+        }
+        Ok(sorted)
+    }
+}
+
+/// Returns the ID of the object at a path in a tree, or `None` if there is no such path. An
+/// empty path stands for the tree itself.
+fn entry_at(tree: &ObjectID, path: &Path) -> anyhow::Result<Option<ObjectID>> {
+    let mut oid = tree.clone();
+    for component in path.components() {
+        let Component::Normal(component) = component else {
+            continue;
+        };
+        let entries = match Tree::from_hash(&oid.hash()) {
+            Ok(entries) => entries,
+            // A file where a directory is expected.
+            Err(_) => return Ok(None),
+        };
+        let entry = entries.into_iter().find(|entry| entry.name() == component);
+        match entry.and_then(|entry| entry.oid().clone()) {
+            Some(entry) => oid = entry,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(oid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    /// Builds the history below on `main`, where `m` merges `e` into `c`, and returns the
+    /// commits. `c` only adds `other`, and `e` only adds `side`. The commits all have the same
+    /// date, so the walk cannot rely on dates to paint the history left out in time.
+    ///
+    /// ```text
+    /// a - b - c - m
+    ///  \         /
+    ///   e -------
+    /// ```
+    fn history(repo: &TestRepo) -> [ObjectID; 5] {
+        let a = repo.commit(&[("file", "1")], &[], "a");
+        let b = repo.commit(&[("file", "2")], &[&a], "b");
+        let c = repo.commit(&[("file", "2"), ("other", "x")], &[&b], "c");
+        let e = repo.commit(&[("file", "1"), ("side", "s")], &[&a], "e");
+        let files = [("file", "2"), ("other", "x"), ("side", "s")];
+        let m = repo.commit(&files, &[&c, &e], "m");
+        repo.set_ref("refs/heads/main", &m);
+        [a, b, c, e, m]
+    }
+
+    /// Lists the commits of the given revisions, with the options set by `setup`.
+    fn walk(revs: &[&str], setup: impl FnOnce(&mut RevWalk)) -> Vec<ObjectID> {
+        let mut walk = RevWalk::new();
+        for rev in revs {
+            walk.push(rev).unwrap();
+        }
+        setup(&mut walk);
+        walk.walk().unwrap()
+    }
+
+    /// Sorts the commits of a walk whose order is not defined by the history alone.
+    fn sorted(mut commits: Vec<ObjectID>) -> Vec<ObjectID> {
+        commits.sort();
+        commits
+    }
+
+    #[test]
+    fn lists_ranges() {
+        let repo = TestRepo::new();
+        let [a, b, c, e, m] = history(&repo);
+        assert_eq!(walk(&["main"], |_| {}).len(), 5);
+        assert_eq!(walk(&[&format!("{}..{}", a, c)], |_| {}), [c.clone(), b]);
+        assert_eq!(
+            sorted(walk(&[&format!("{}..main", c)], |_| {})),
+            sorted(vec![m, e.clone()])
+        );
+        // The range is empty when its start is a descendant of its end, even though the commits
+        // of the end are reached before the start paints them.
+        assert!(walk(&[&format!("{}..{}", c, a)], |_| {}).is_empty());
+        assert!(walk(&[&format!("main..{}", e)], |_| {}).is_empty());
+        // An empty side stands for HEAD.
+        assert_eq!(walk(&[&format!("{}..", c)], |_| {}).len(), 2);
+    }
+
+    #[test]
+    fn lists_symmetric_differences() {
+        let repo = TestRepo::new();
+        let [_, b, c, e, m] = history(&repo);
+        let range = format!("{}...{}", c, e);
+        assert_eq!(sorted(walk(&[&range], |_| {})), sorted(vec![b, c, e]));
+        assert!(walk(&[&format!("{}...main", m)], |_| {}).is_empty());
+    }
+
+    #[test]
+    fn leaves_out_excluded_history() {
+        let repo = TestRepo::new();
+        let [a, b, c, e, m] = history(&repo);
+        assert_eq!(walk(&["main", &format!("^{}", c)], |_| {}).len(), 2);
+        let excluded = [format!("^{}", b), format!("^{}", e)];
+        assert_eq!(
+            walk(&["main", &excluded[0], &excluded[1]], |_| {}),
+            [m, c.clone()]
+        );
+        assert!(walk(&[&a.to_string(), &format!("^{}", c)], |_| {}).is_empty());
+    }
+
+    #[test]
+    fn follows_first_parents() {
+        let repo = TestRepo::new();
+        let [a, b, c, _, m] = history(&repo);
+        let first_parent = |walk: &mut RevWalk| walk.first_parent = true;
+        let expected = [m.clone(), c.clone(), b.clone(), a];
+        assert_eq!(walk(&["main"], first_parent), expected);
+        assert_eq!(walk(&[&format!("{}..main", b)], first_parent), [m, c]);
+    }
+
+    #[test]
+    fn simplifies_history_by_paths() {
+        let repo = TestRepo::new();
+        let [a, b, _, e, _] = history(&repo);
+        let paths = |paths: &[&str]| {
+            let paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+            move |walk: &mut RevWalk| walk.paths = paths
+        };
+        // The merge has the content of `c` at the path, so only `c` is followed, which does not
+        // change the file either.
+        assert_eq!(walk(&["main"], paths(&["file"])), [b, a]);
+        // The merge has the content of `e` at the path, so the other side is not walked.
+        assert_eq!(walk(&["main"], paths(&["side"])), [e]);
+        assert!(walk(&["main"], paths(&["missing"])).is_empty());
+    }
+
+    #[test]
+    fn limits_the_number_of_commits() {
+        let repo = TestRepo::new();
+        let [_, b, c, _, _] = history(&repo);
+        let max_count = |walk: &mut RevWalk| walk.max_count = Some(1);
+        assert_eq!(walk(&["main"], max_count).len(), 1);
+        assert_eq!(walk(&[&format!("{}..{}", b, c)], max_count), [c]);
+    }
+}
@@ -0,0 +1,536 @@
+/// This module resolves revision expressions, such as `HEAD~2`, `main^2`, `v1.0^{tree}` or
+/// `HEAD:src/main.rs`, to object IDs, following the syntax described in gitrevisions(7).
+use crate::index;
+use crate::objects;
+use crate::objects::commit::Commit;
+use crate::objects::id::ObjectID;
+use crate::objects::kind::ObjectKind;
+use crate::objects::pack;
+use crate::objects::tag::Tag;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::tree::Tree;
+use crate::refs;
+use crate::refs::reflog;
+use std::path::Path;
+
+/// Minimum number of hex digits of an abbreviated object ID.
+const MIN_ABBREV: usize = 4;
+/// Default number of hex digits of the object IDs printed in abbreviated form.
+pub(crate) const DEFAULT_ABBREV: usize = 7;
+/// The prefixes and suffixes that turn a short ref name into a full one, in order of precedence.
+const REF_RULES: [(&str, &str); 6] = [
+    ("", ""),
+    ("refs/", ""),
+    ("refs/tags/", ""),
+    ("refs/heads/", ""),
+    ("refs/remotes/", ""),
+    ("refs/remotes/", "/HEAD"),
+];
+
+/// Resolves a revision expression to an object ID.
+///
+/// The expression starts with an object name: a full or abbreviated object ID, a ref name looked
+/// up like git does (`main` is tried as `main`, `refs/main`, `refs/tags/main`, `refs/heads/main`,
+/// `refs/remotes/main` and `refs/remotes/main/HEAD`), `@` for HEAD, or `<ref>@{<n>}` for the n-th
+/// previous value of a ref from its reflog. It may be followed by any number of:
+///
+/// * `~<n>` - The n-th generation ancestor, following only first parents.
+/// * `^<n>` - The n-th parent, with `^0` being the commit itself.
+/// * `^{<type>}` - The object peeled to the given type, and `^{}` the object with tags peeled.
+///
+/// Finally, `<rev>:<path>` names the object at the path in the tree of the revision, and
+/// `:<path>` the object staged at the path in the index, or `:<n>:<path>` the version at stage
+/// `n` of a conflicted file.
+///
+/// # Arguments
+///
+/// * `rev` - The revision expression.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the object ID, or an `anyhow::Error` if the expression is
+/// malformed, ambiguous or names an object that does not exist.
+pub(crate) fn resolve(rev: &str) -> anyhow::Result<ObjectID> {
+    if let Some(path) = rev.strip_prefix(':') {
+        return resolve_index_path(path);
+    }
+    if let Some(colon) = find_path_separator(rev) {
+        let (name, path) = (&rev[..colon], &rev[colon + 1..]);
+        let tree = peel(&resolve(name)?, ObjectKind::Tree)?;
+        return resolve_tree_path(&tree, path, name);
+    }
+
+    let (base, mut suffixes) = split_suffixes(rev);
+    let mut oid = resolve_base(base)?;
+    while let Some(suffix) = suffixes.chars().next() {
+        suffixes = &suffixes[1..];
+        if suffix == '^' && suffixes.starts_with('{') {
+            let end = suffixes
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("Unterminated '^{{' in revision '{}'.", rev))?;
+            oid = peel_to(&oid, &suffixes[1..end])?;
+            suffixes = &suffixes[end + 1..];
+            continue;
+        }
+
+        let digits = suffixes
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(suffixes.len());
+        let n = match &suffixes[..digits] {
+            "" => 1,
+            digits => digits
+                .parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("Invalid number in revision '{}'.", rev))?,
+        };
+        suffixes = &suffixes[digits..];
+
+        let commit = peel(&oid, ObjectKind::Commit)?;
+        oid = match suffix {
+            '~' => nth_ancestor(commit, n, rev)?,
+            _ if n == 0 => commit,
+            _ => Commit::read(&commit.hash())?
+                .parents
+                .get(n - 1)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Revision '{}' has no parent {}.", rev, n))?,
+        };
+    }
+    Ok(oid)
+}
+
+/// Peels an object until it has the given kind: tags are followed to the object they point to,
+/// and commits to their tree.
+///
+/// # Arguments
+///
+/// * `oid` - The ID of the object.
+/// * `kind` - The kind of object wanted.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the ID of the peeled object, or an `anyhow::Error` if the object
+/// cannot be read or cannot be peeled to the given kind.
+pub(crate) fn peel(oid: &ObjectID, kind: ObjectKind) -> anyhow::Result<ObjectID> {
+    let mut oid = oid.clone();
+    loop {
+        let (current, _, _) = objects::read_object(&oid.hash())?;
+        oid = match current {
+            _ if current == kind => return Ok(oid),
+            ObjectKind::Tag => Tag::read(&oid.hash())?.object,
+            ObjectKind::Commit if kind == ObjectKind::Tree => Commit::read(&oid.hash())?.tree,
+            _ => anyhow::bail!("Object {} is a {}, not a {}.", oid, current, kind),
+        };
+    }
+}
+
+/// Returns the shortest abbreviation of an object ID that is unique in the database, with at
+/// least `min_len` hex digits.
+///
+/// # Arguments
+///
+/// * `oid` - The ID of the object.
+/// * `min_len` - The minimum number of hex digits.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the abbreviation, or an `anyhow::Error` if the database cannot
+/// be read.
+pub(crate) fn abbreviate(oid: &ObjectID, min_len: usize) -> anyhow::Result<String> {
+    let hash = oid.hash();
+    let min_len = min_len.clamp(MIN_ABBREV, hash.len());
+    let len = find_abbreviated(&hash[..min_len])?
+        .iter()
+        .filter(|other| *other != oid)
+        .map(|other| common_prefix_len(&hash, &other.hash()) + 1)
+        .fold(min_len, usize::max);
+    Ok(hash[..len.min(hash.len())].to_string())
+}
+
+/// Resolves the object name at the start of a revision expression.
+fn resolve_base(name: &str) -> anyhow::Result<ObjectID> {
+    if let Some((name, selector)) = name
+        .strip_suffix('}')
+        .and_then(|name| name.rsplit_once("@{"))
+    {
+        return resolve_reflog(name, selector);
+    }
+    if name == "@" {
+        return refs::resolve(refs::HEAD)?
+            .ok_or_else(|| anyhow::anyhow!("HEAD does not point to a commit yet."));
+    }
+    if name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+        return ObjectID::from_hash(name.to_lowercase());
+    }
+    if let Some((_, oid)) = dwim_ref(name)? {
+        return Ok(oid);
+    }
+    if name.len() >= MIN_ABBREV && name.chars().all(|c| c.is_ascii_hexdigit()) {
+        let prefix = name.to_lowercase();
+        let candidates = find_abbreviated(&prefix)?;
+        match candidates.as_slice() {
+            [] => {}
+            [oid] => return Ok(oid.clone()),
+            _ => {
+                let mut message = format!("short object ID {} is ambiguous", name);
+                message.push_str("\nhint: The candidates are:");
+                for oid in &candidates {
+                    let (kind, _, _) = objects::read_object(&oid.hash())?;
+                    message.push_str(&format!(
+                        "\nhint:   {} {}",
+                        abbreviate(oid, DEFAULT_ABBREV)?,
+                        kind
+                    ));
+                }
+                anyhow::bail!(message);
+            }
+        }
+    }
+    anyhow::bail!(
+        "ambiguous argument '{}': unknown revision or path not in the working tree.",
+        name
+    )
+}
+
+/// Looks up a short ref name with the rules of `REF_RULES`.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the full name of the first ref that exists and its value, or
+/// `None` if there is none. A warning is printed if several refs match.
+fn dwim_ref(name: &str) -> anyhow::Result<Option<(String, ObjectID)>> {
+    // Names that cannot be part of a ref name are not looked up, so that they cannot point
+    // outside the refs, e.g. `../config`.
+    if name.is_empty() || refs::check_name(&format!("refs/heads/{}", name)).is_err() {
+        return Ok(None);
+    }
+
+    let mut found = Vec::new();
+    for (prefix, suffix) in REF_RULES {
+        let candidate = format!("{}{}{}", prefix, name, suffix);
+        // Outside of `refs/`, only pseudo refs such as `HEAD` or `ORIG_HEAD` are looked up.
+        let pseudo_ref = candidate
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c == '_');
+        if !pseudo_ref && !candidate.starts_with("refs/") {
+            continue;
+        }
+        if let Some(oid) = refs::resolve(&candidate)? {
+            found.push((candidate, oid));
+        }
+    }
+    if found.len() > 1 {
+        eprintln!("warning: refname '{}' is ambiguous.", name);
+    }
+    Ok(found.into_iter().next())
+}
+
+/// Resolves `<name>@{<n>}` to the value the ref had `n` updates ago, as recorded in its reflog.
+/// Without a name, the current branch is used.
+fn resolve_reflog(name: &str, selector: &str) -> anyhow::Result<ObjectID> {
+    let n = selector.parse::<usize>().map_err(|_| {
+        anyhow::anyhow!(
+            "Unsupported reflog selector '@{{{}}}', only numbers are supported.",
+            selector
+        )
+    })?;
+    let full_name = match name {
+        "" => refs::current_branch()?.unwrap_or_else(|| refs::HEAD.to_string()),
+        "@" => refs::HEAD.to_string(),
+        _ => match dwim_ref(name)? {
+            Some((full_name, _)) => full_name,
+            None => anyhow::bail!("unknown revision '{}'", name),
+        },
+    };
+
+    let entries = reflog::read(&full_name)?
+        .ok_or_else(|| anyhow::anyhow!("no reflog for '{}'", full_name))?;
+    // Entry `@{0}` is the newest one; one past the oldest entry is the value before it.
+    match n.cmp(&entries.len()) {
+        std::cmp::Ordering::Less => Ok(entries[entries.len() - 1 - n].new.clone()),
+        std::cmp::Ordering::Equal if entries[0].old != ObjectID::zero() => {
+            Ok(entries[0].old.clone())
+        }
+        _ => anyhow::bail!(
+            "log for '{}' only has {} entries",
+            refs::shorten(&full_name),
+            entries.len()
+        ),
+    }
+}
+
+/// Follows the first parent of a commit `n` times.
+fn nth_ancestor(commit: ObjectID, n: usize, rev: &str) -> anyhow::Result<ObjectID> {
+    let mut commit = commit;
+    for _ in 0..n {
+        commit = Commit::read(&commit.hash())?
+            .parents
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Revision '{}' goes past a root commit.", rev))?;
+    }
+    Ok(commit)
+}
+
+/// Peels an object as requested by a `^{<type>}` suffix.
+fn peel_to(oid: &ObjectID, kind: &str) -> anyhow::Result<ObjectID> {
+    match kind {
+        "" => {
+            let mut oid = oid.clone();
+            while objects::read_object(&oid.hash())?.0 == ObjectKind::Tag {
+                oid = Tag::read(&oid.hash())?.object;
+            }
+            Ok(oid)
+        }
+        "object" => {
+            objects::read_object(&oid.hash())?;
+            Ok(oid.clone())
+        }
+        kind => peel(oid, ObjectKind::parse(kind)?),
+    }
+}
+
+/// Resolves `<tree>:<path>` to the object at the path in the tree. Only a missing path is
+/// reported as such: errors reading the trees along the path are returned as they are.
+fn resolve_tree_path(tree: &ObjectID, path: &str, rev: &str) -> anyhow::Result<ObjectID> {
+    let missing = || anyhow::anyhow!("path '{}' does not exist in '{}'", path, rev);
+    let mut oid = tree.clone();
+    let mut is_tree = true;
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        if !is_tree {
+            return Err(missing());
+        }
+        let entry = Tree::from_hash(&oid.hash())?
+            .into_iter()
+            .find(|entry| entry.name() == component)
+            .ok_or_else(missing)?;
+        is_tree = *entry.mode() == EntryMode::Directory;
+        oid = entry.oid().clone().ok_or_else(missing)?;
+    }
+    Ok(oid)
+}
+
+/// Resolves `:<path>` to the object staged at the path in the index. The path may be preceded by
+/// a stage number, `0:` for a merged file or `1:` to `3:` for the versions of a conflicted file.
+fn resolve_index_path(path: &str) -> anyhow::Result<ObjectID> {
+    let (stage, path) = match path.as_bytes() {
+        [stage @ b'0'..=b'3', b':', ..] => (u16::from(stage - b'0'), &path[2..]),
+        _ => (0, path),
+    };
+    let index = index::Index::init(".git/index")?;
+    match index.entry_at(Path::new(path), stage) {
+        Some(entry) => Ok(entry.oid.clone()),
+        None if index.entry(Path::new(path)).is_some() => {
+            anyhow::bail!(
+                "path '{}' is in the index, but not at stage {}",
+                path,
+                stage
+            )
+        }
+        None if Path::new(path).exists() => {
+            anyhow::bail!("path '{}' exists on disk, but not in the index", path)
+        }
+        None => anyhow::bail!(
+            "path '{}' does not exist (neither on disk nor in the index)",
+            path
+        ),
+    }
+}
+
+/// Returns the position of the colon separating a revision from a path, skipping the colons
+/// inside `@{...}` and `^{...}`.
+fn find_path_separator(rev: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in rev.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ':' if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits a revision into the object name and the chain of `~` and `^` suffixes that follows it.
+fn split_suffixes(rev: &str) -> (&str, &str) {
+    let mut depth = 0usize;
+    for (i, c) in rev.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            '~' | '^' if depth == 0 => return rev.split_at(i),
+            _ => {}
+        }
+    }
+    (rev, "")
+}
+
+/// Lists the objects whose hash starts with the given lowercase hex prefix, in the loose objects
+/// and in the packs.
+fn find_abbreviated(prefix: &str) -> anyhow::Result<Vec<ObjectID>> {
+    let mut oids = Vec::new();
+
+    let dir = Path::new(".git/objects").join(&prefix[..2]);
+    if dir.is_dir() {
+        for direntry in std::fs::read_dir(&dir)? {
+            let hash = format!(
+                "{}{}",
+                &prefix[..2],
+                direntry?.file_name().to_string_lossy()
+            );
+            if hash.starts_with(prefix) {
+                if let Ok(oid) = ObjectID::from_hash(&hash) {
+                    oids.push(oid);
+                }
+            }
+        }
+    }
+
+    for pack in pack::packs()?.iter() {
+        let packed = pack.index().oids();
+        let start = packed.partition_point(|oid| oid.hash().as_str() < prefix);
+        oids.extend(
+            packed[start..]
+                .iter()
+                .take_while(|oid| oid.hash().starts_with(prefix))
+                .cloned(),
+        );
+    }
+
+    oids.sort();
+    oids.dedup();
+    Ok(oids)
+}
+
+/// Returns the number of leading characters two strings have in common.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::signature::Signature;
+    use crate::objects::Object;
+    use crate::testing::TestRepo;
+
+    /// Builds the history `a - b - d` where `d` merges `c`, a child of `a`, on `main`, with the
+    /// annotated tag `v1.0` on `b`, and returns the commits.
+    fn history(repo: &TestRepo) -> [ObjectID; 4] {
+        let a = repo.commit(&[("file", "a\n")], &[], "a");
+        let b = repo.commit(&[("file", "b\n"), ("dir/sub", "sub\n")], &[&a], "b");
+        let c = repo.commit(&[("file", "c\n")], &[&a], "c");
+        let d = repo.commit(&[("file", "d\n")], &[&b, &c], "d");
+        repo.set_ref("refs/heads/main", &d);
+        repo.set_ref("refs/heads/side", &c);
+        let tagger = Signature::parse(b"T <t@example.com> 1700000000 +0000").unwrap();
+        let tag = Tag::new(
+            b.clone(),
+            ObjectKind::Commit,
+            "v1.0".to_string(),
+            tagger,
+            "release\n".to_string(),
+        )
+        .write()
+        .unwrap();
+        repo.set_ref("refs/tags/v1.0", &tag);
+        [a, b, c, d]
+    }
+
+    #[test]
+    fn resolves_ancestors_and_parents() {
+        let repo = TestRepo::new();
+        let [a, b, c, d] = history(&repo);
+        let resolved = |rev: &str| resolve(rev).unwrap();
+        assert_eq!(resolved("HEAD"), d);
+        assert_eq!(resolved("@"), d);
+        assert_eq!(resolved("main"), d);
+        assert_eq!(resolved("refs/heads/side"), c);
+        assert_eq!(resolved("HEAD^"), b);
+        assert_eq!(resolved("HEAD^1"), b);
+        assert_eq!(resolved("HEAD^2"), c);
+        assert_eq!(resolved("HEAD^0"), d);
+        assert_eq!(resolved("HEAD~"), b);
+        assert_eq!(resolved("HEAD~2"), a);
+        assert_eq!(resolved("HEAD~0"), d);
+        assert_eq!(resolved("main^2~1"), a);
+        assert_eq!(resolved("HEAD^^"), a);
+        assert!(resolve("HEAD^3").is_err());
+        assert!(resolve("HEAD~3").is_err());
+        assert!(resolve("missing").is_err());
+    }
+
+    #[test]
+    fn peels_tags_and_commits() {
+        let repo = TestRepo::new();
+        let [_, b, _, _] = history(&repo);
+        let tag = resolve("v1.0").unwrap();
+        assert_eq!(resolve("refs/tags/v1.0").unwrap(), tag);
+        assert_eq!(resolve("v1.0^{}").unwrap(), b);
+        assert_eq!(resolve("v1.0^{commit}").unwrap(), b);
+        assert_eq!(resolve("v1.0^{tag}").unwrap(), tag);
+        assert_eq!(resolve("v1.0~0").unwrap(), b);
+        let tree = Commit::read(&b.hash()).unwrap().tree;
+        assert_eq!(resolve("v1.0^{tree}").unwrap(), tree);
+        assert_eq!(resolve("v1.0^{object}").unwrap(), tag);
+        assert!(resolve("v1.0^{blob}").is_err());
+        assert!(resolve("v1.0^{tree").is_err());
+    }
+
+    #[test]
+    fn resolves_paths_in_trees() {
+        let repo = TestRepo::new();
+        history(&repo);
+        assert_eq!(resolve("v1.0:file").unwrap(), repo.blob("b\n"));
+        assert_eq!(resolve("HEAD~1:dir/sub").unwrap(), repo.blob("sub\n"));
+        assert_eq!(resolve("HEAD^{tree}:file").unwrap(), repo.blob("d\n"));
+        let dir = resolve("HEAD^:dir").unwrap();
+        assert_eq!(resolve("HEAD^:dir/").unwrap(), dir);
+        let error = resolve("HEAD:dir/sub").unwrap_err();
+        assert_eq!(error.to_string(), "path 'dir/sub' does not exist in 'HEAD'");
+        assert!(resolve("HEAD^:file/sub").is_err());
+    }
+
+    #[test]
+    fn resolves_abbreviated_object_ids() {
+        let repo = TestRepo::new();
+        let [a, ..] = history(&repo);
+        let hash = a.hash();
+        assert_eq!(resolve(&hash).unwrap(), a);
+        assert_eq!(resolve(&hash[..7]).unwrap(), a);
+        assert_eq!(resolve(&format!("{}~0", &hash[..10])).unwrap(), a);
+        assert!(resolve(&hash[..3]).is_err());
+
+        let abbreviation = abbreviate(&a, DEFAULT_ABBREV).unwrap();
+        assert_eq!(abbreviation, hash[..DEFAULT_ABBREV]);
+        assert_eq!(abbreviate(&a, 1).unwrap(), hash[..MIN_ABBREV]);
+    }
+
+    #[test]
+    fn reports_ambiguous_abbreviations() {
+        let repo = TestRepo::new();
+        // Write blobs until two of them share the first four hex digits.
+        let mut seen = std::collections::HashMap::new();
+        let (one, two) = (0..)
+            .find_map(|i| {
+                let oid = repo.blob(&i.to_string());
+                seen.insert(oid.hash()[..4].to_string(), oid.clone())
+                    .filter(|other| *other != oid)
+                    .map(|other| (other, oid))
+            })
+            .unwrap();
+        let prefix = &one.hash()[..4];
+        assert!(resolve(prefix).is_err());
+        let len = common_prefix_len(&one.hash(), &two.hash()) + 1;
+        assert_eq!(abbreviate(&one, MIN_ABBREV).unwrap().len(), len);
+    }
+
+    #[test]
+    fn splits_revisions() {
+        assert_eq!(split_suffixes("main~2^{tree}"), ("main", "~2^{tree}"));
+        assert_eq!(split_suffixes("v1.0"), ("v1.0", ""));
+        assert_eq!(find_path_separator("HEAD:src/main.rs"), Some(4));
+        assert_eq!(find_path_separator("HEAD^{/fix: bug}"), None);
+        assert_eq!(find_path_separator("main"), None);
+    }
+}
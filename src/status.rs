@@ -0,0 +1,223 @@
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::tree::{self, compare, Change, FileChange, Files};
+use crate::index::entry::IndexEntry;
+use crate::index::Index;
+use crate::objects::blob::Blob;
+use crate::objects::commit::Commit;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::tree::Tree;
+use crate::objects::Object;
+use crate::refs;
+use crate::workspace::Workspace;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Represents the state of the working tree and the index, compared to HEAD.
+pub(crate) struct Status {
+    /// Changes between the HEAD tree and the index, sorted by path, with the renamed files.
+    pub(crate) staged: Vec<FileChange>,
+    /// Changes between the index and the working tree, sorted by path.
+    pub(crate) unstaged: Vec<(PathBuf, Change)>,
+    /// Files in the working tree that are not in the index, sorted by path. Directories that
+    /// contain no tracked files at all are listed once, with a trailing slash.
+    pub(crate) untracked: Vec<String>,
+    /// Conflicted files, with the versions they have in the index, sorted by path.
+    pub(crate) unmerged: Vec<(PathBuf, Conflict)>,
+}
+
+/// Represents the versions a conflicted file has in the index, among the versions of the merge
+/// base, of our side and of their side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Conflict {
+    BothDeleted,
+    AddedByUs,
+    DeletedByThem,
+    AddedByThem,
+    DeletedByUs,
+    BothAdded,
+    BothModified,
+}
+
+impl Conflict {
+    /// Returns the conflict of a file from the stages of its entries.
+    fn from_stages(stages: &[Option<&IndexEntry>; 3]) -> Conflict {
+        match (
+            stages[0].is_some(),
+            stages[1].is_some(),
+            stages[2].is_some(),
+        ) {
+            (true, true, true) => Conflict::BothModified,
+            (false, true, true) => Conflict::BothAdded,
+            (true, true, false) => Conflict::DeletedByThem,
+            (true, false, true) => Conflict::DeletedByUs,
+            (false, true, false) => Conflict::AddedByUs,
+            (false, false, true) => Conflict::AddedByThem,
+            _ => Conflict::BothDeleted,
+        }
+    }
+
+    /// Returns the two letters used for the conflict in the short status format.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Conflict::BothDeleted => "DD",
+            Conflict::AddedByUs => "AU",
+            Conflict::DeletedByThem => "UD",
+            Conflict::AddedByThem => "UA",
+            Conflict::DeletedByUs => "DU",
+            Conflict::BothAdded => "AA",
+            Conflict::BothModified => "UU",
+        }
+    }
+
+    /// Returns the description used for the conflict in the long status format.
+    pub(crate) fn description(&self) -> &'static str {
+        match self {
+            Conflict::BothDeleted => "both deleted",
+            Conflict::AddedByUs => "added by us",
+            Conflict::DeletedByThem => "deleted by them",
+            Conflict::AddedByThem => "added by them",
+            Conflict::DeletedByUs => "deleted by us",
+            Conflict::BothAdded => "both added",
+            Conflict::BothModified => "both modified",
+        }
+    }
+}
+
+impl Status {
+    /// Computes the status of the repository.
+    ///
+    /// The HEAD tree is compared with the index by object ID and mode, and the deleted and added
+    /// files are paired to find the renamed ones, see `rename::detect_renames`. The index is compared with
+    /// the working tree by stat data first; only files whose stat data changed are hashed again.
+    /// Conflicted files are only listed as unmerged.
+    ///
+    /// # Arguments
+    ///
+    /// * `workspace` - The working tree.
+    /// * `index` - The index.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `Status`, or an `anyhow::Error` if HEAD, the index or the
+    /// working tree cannot be read.
+    pub(crate) fn compute(workspace: &Workspace, index: &Index) -> anyhow::Result<Status> {
+        // List the conflicted files, which are left out of the other changes.
+        let conflicts = index.conflicts();
+        let entries = index.entries();
+
+        // Compare the HEAD tree with the index.
+        let mut head = head_files()?;
+        head.retain(|path, _| !conflicts.contains_key(path.as_path()));
+        let staged = tree::diff_files(&head, &index_files(entries));
+        let staged = rename::detect_renames(staged, &[], &RenameOptions::default())?;
+        let index_paths = entries
+            .iter()
+            .map(|entry| entry.path().to_path_buf())
+            .collect::<BTreeSet<_>>();
+
+        // Compare the index with the working tree.
+        let mut unstaged = Vec::new();
+        for entry in entries.iter().filter(|entry| entry.stage() == 0) {
+            if entry.is_assumed_unchanged() {
+                continue;
+            }
+            let path = workspace.dir.join(entry.path());
+            let Ok(meta) = std::fs::symlink_metadata(&path) else {
+                unstaged.push((entry.path().to_path_buf(), Change::Deleted));
+                continue;
+            };
+            if meta.is_dir() {
+                unstaged.push((entry.path().to_path_buf(), Change::Deleted));
+                continue;
+            }
+            if index.stat_matches(entry, &meta) {
+                continue;
+            }
+            let oid = Blob::from_worktree(&path)?
+                .hash()
+                .with_context(|| format!("Failed to hash {}", path.display()))?;
+            let mode = EntryMode::from_metadata(&meta);
+            let index_mode = EntryMode::from_index_mode(entry.mode);
+            if let Some(change) = compare(mode, &oid, index_mode, &entry.oid) {
+                unstaged.push((entry.path().to_path_buf(), change));
+            }
+        }
+
+        // List the untracked files, collapsing directories without any tracked file.
+        let tracked_dirs = index_paths
+            .iter()
+            .flat_map(|path| path.ancestors().skip(1))
+            .map(Path::to_path_buf)
+            .collect::<BTreeSet<_>>();
+        let mut untracked = BTreeSet::new();
+        for path in workspace.list_files()? {
+            if index_paths.contains(&path) {
+                continue;
+            }
+            let untracked_dir = path
+                .ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_os_str().is_empty() && !tracked_dirs.contains(*dir))
+                .last();
+            match untracked_dir {
+                Some(dir) => untracked.insert(format!("{}/", dir.display())),
+                None => untracked.insert(path.display().to_string()),
+            };
+        }
+
+        unstaged.sort_by(|a, b| compare_paths(&a.0, &b.0));
+        let mut untracked = untracked.into_iter().collect::<Vec<_>>();
+        untracked.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+
+        let mut unmerged = conflicts
+            .iter()
+            .map(|(path, stages)| (path.to_path_buf(), Conflict::from_stages(stages)))
+            .collect::<Vec<_>>();
+        unmerged.sort_by(|a, b| compare_paths(&a.0, &b.0));
+
+        Ok(Status {
+            staged,
+            unstaged,
+            untracked,
+            unmerged,
+        })
+    }
+
+    /// Returns true if there are no staged changes, no unstaged changes, no untracked files and
+    /// no conflicted files.
+    pub(crate) fn is_clean(&self) -> bool {
+        self.staged.is_empty()
+            && self.unstaged.is_empty()
+            && self.untracked.is_empty()
+            && self.unmerged.is_empty()
+    }
+}
+
+/// Returns the files of the HEAD commit, or none if HEAD does not point to a commit yet.
+pub(crate) fn head_files() -> anyhow::Result<Files> {
+    match refs::resolve(refs::HEAD)? {
+        Some(head) => Tree::flatten(&Commit::read(&head.hash())?.tree.hash()),
+        None => Ok(Files::new()),
+    }
+}
+
+/// Returns the files of the index. Entries with a mode that is not one of a file are left out, and
+/// so are the versions of conflicted files.
+pub(crate) fn index_files(entries: &[IndexEntry]) -> Files {
+    entries
+        .iter()
+        .filter(|entry| entry.stage() == 0)
+        .filter_map(|entry| {
+            let mode = EntryMode::from_index_mode(entry.mode)?;
+            Some((entry.path().to_path_buf(), (mode, entry.oid.clone())))
+        })
+        .collect()
+}
+
+/// Orders paths by their bytes, the way git orders the entries of the index.
+pub(crate) fn compare_paths(a: &Path, b: &Path) -> std::cmp::Ordering {
+    a.as_os_str()
+        .as_encoded_bytes()
+        .cmp(b.as_os_str().as_encoded_bytes())
+}
@@ -0,0 +1,156 @@
+/// This module contains helpers for the unit tests that need a repository.
+use crate::cmp::compare_base_name;
+use crate::objects::blob::Blob;
+use crate::objects::commit::Commit;
+use crate::objects::id::ObjectID;
+use crate::objects::signature::Signature;
+use crate::objects::tree::mode::EntryMode;
+use crate::objects::tree::Tree;
+use crate::objects::Object;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// The current directory is shared by the whole process, so the tests that work in a repository
+/// take turns.
+static CURRENT_DIR: Mutex<()> = Mutex::new(());
+
+/// Represents an empty repository in a temporary directory, which is the current directory while
+/// the repository is alive.
+pub(crate) struct TestRepo {
+    dir: tempfile::TempDir,
+    previous: PathBuf,
+    _guard: MutexGuard<'static, ()>,
+}
+
+/// Represents a directory of the files given to `TestRepo::tree`.
+#[derive(Default)]
+struct Dir<'a> {
+    files: BTreeMap<&'a str, &'a str>,
+    dirs: BTreeMap<&'a str, Dir<'a>>,
+}
+
+impl TestRepo {
+    /// Creates an empty repository, whose HEAD points to the unborn branch `main`, and makes it
+    /// the current directory.
+    pub(crate) fn new() -> TestRepo {
+        let guard = CURRENT_DIR.lock().unwrap_or_else(PoisonError::into_inner);
+        let dir = tempfile::tempdir().unwrap();
+        let previous = std::env::current_dir().unwrap();
+        std::env::set_current_dir(dir.path()).unwrap();
+        for subdir in ["objects", "refs/heads", "refs/tags"] {
+            std::fs::create_dir_all(format!(".git/{}", subdir)).unwrap();
+        }
+        std::fs::write(".git/HEAD", "ref: refs/heads/main\n").unwrap();
+        // Forget the packs opened in the repository of a previous test.
+        crate::objects::pack::reload_packs().unwrap();
+        TestRepo {
+            dir,
+            previous,
+            _guard: guard,
+        }
+    }
+
+    /// Returns the path of the working tree.
+    pub(crate) fn path(&self) -> &std::path::Path {
+        self.dir.path()
+    }
+
+    /// Writes a blob and returns its ID.
+    pub(crate) fn blob(&self, data: &str) -> ObjectID {
+        Blob::new(data.len() as u64, data.as_bytes())
+            .write()
+            .unwrap()
+    }
+
+    /// Writes the trees of the given files, whose paths may contain directories, and returns the
+    /// ID of the root tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The path and the contents of each file.
+    pub(crate) fn tree(&self, files: &[(&str, &str)]) -> ObjectID {
+        let mut root = Dir::default();
+        for (path, data) in files {
+            let mut dir = &mut root;
+            let mut components = path.split('/').collect::<Vec<_>>();
+            let name = components.pop().unwrap();
+            for component in components {
+                dir = dir.dirs.entry(component).or_default();
+            }
+            dir.files.insert(name, data);
+        }
+        self.write_dir(&root)
+    }
+
+    /// Writes the tree of a directory, after the trees of its subdirectories.
+    fn write_dir(&self, dir: &Dir) -> ObjectID {
+        let mut entries = Vec::new();
+        for (name, data) in &dir.files {
+            entries.push((*name, EntryMode::NonExecutable, self.blob(data)));
+        }
+        for (name, subdir) in &dir.dirs {
+            entries.push((*name, EntryMode::Directory, self.write_dir(subdir)));
+        }
+        entries.sort_by(|(name1, mode1, _), (name2, mode2, _)| {
+            compare_base_name(OsStr::new(name1), mode1, OsStr::new(name2), mode2)
+        });
+
+        let mut data = Vec::new();
+        for (name, mode, oid) in entries {
+            let mode = if mode == EntryMode::Directory {
+                "40000"
+            } else {
+                "100644"
+            };
+            data.extend(format!("{} {}\0", mode, name).as_bytes());
+            data.extend(oid.to_bytes());
+        }
+        let oid = Tree::new(data.len() as u64, data.as_slice())
+            .write()
+            .unwrap();
+        oid
+    }
+
+    /// Writes a commit of the given files and returns its ID. The commits all have the same
+    /// author and date, so they are told apart by their message.
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The path and the contents of each file of the tree.
+    /// * `parents` - The parent commits.
+    /// * `message` - The commit message.
+    pub(crate) fn commit(
+        &self,
+        files: &[(&str, &str)],
+        parents: &[&ObjectID],
+        message: &str,
+    ) -> ObjectID {
+        let signature =
+            Signature::parse(b"A U Thor <author@example.com> 1700000000 +0000").unwrap();
+        Commit::new(
+            self.tree(files),
+            parents.iter().map(|&parent| parent.clone()).collect(),
+            signature.clone(),
+            signature,
+            format!("{}\n", message),
+        )
+        .write()
+        .unwrap()
+    }
+
+    /// Points a ref to an object.
+    pub(crate) fn set_ref(&self, name: &str, oid: &ObjectID) {
+        let path = self.path().join(".git").join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, format!("{}\n", oid)).unwrap();
+    }
+}
+
+impl Drop for TestRepo {
+    /// Goes back to the previous current directory, before the repository is removed.
+    fn drop(&mut self) {
+        let _ = std::env::set_current_dir(&self.previous);
+    }
+}
@@ -1,38 +1,106 @@
-use std::path::PathBuf;
+use crate::ignore::IgnoreRules;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
 
 const REPO_DIRNAME: &str = ".git";
 
 pub struct Workspace {
     pub dir: PathBuf,
-    pub ignore_list: Vec<PathBuf>,
+    pub ignore: IgnoreRules,
 }
 
-#[allow(dead_code)]
 impl Workspace {
-    pub fn new(path: &PathBuf) -> Self {
-        if let Some(git_dir) = find_git_dir(path.clone()) {
-            let dir = git_dir.parent().unwrap().to_path_buf();
-            let ignore_list = vec![dir.join(REPO_DIRNAME)];
-            Workspace { dir, ignore_list }
-        } else {
-            panic!("fatal: not a git repository (or any of the parent directories): .git");
-        }
+    /// Opens the working tree containing the given path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path inside the working tree.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `Workspace`, or an `anyhow::Error` if the path is not
+    /// inside a repository or its ignore rules cannot be read.
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let Some(git_dir) = find_git_dir(path.to_path_buf()) else {
+            anyhow::bail!("not a git repository (or any of the parent directories): .git");
+        };
+        let dir = git_dir.parent().unwrap().to_path_buf();
+        let ignore = IgnoreRules::load(&dir)?;
+        Ok(Workspace { dir, ignore })
     }
 
-    pub fn list_repo(&self) -> Vec<PathBuf> {
-        let mut list = vec![];
+    /// Checks whether a path of the working tree is ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to the workspace directory.
+    /// * `is_dir` - Whether the path is a directory.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> anyhow::Result<bool> {
+        self.ignore.is_ignored(path, is_dir)
+    }
 
-        let entries = self.dir.read_dir().unwrap();
-        for entry in entries {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            if self.ignore_list.contains(&path) {
+    /// Lists the files of the working tree, recursively.
+    ///
+    /// Ignored files are skipped and ignored directories are not descended into. Symbolic links
+    /// are listed without being followed.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the paths of the files relative to the workspace directory,
+    /// or an `anyhow::Error` if a directory cannot be read.
+    pub fn list_files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        self.list_files_in(Path::new(""), false)
+    }
+
+    /// Lists the files of a directory of the working tree, recursively.
+    ///
+    /// The repository directory is never listed, and symbolic links are listed without being
+    /// followed.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory to list, relative to the workspace directory.
+    /// * `include_ignored` - If false, ignored files are skipped and ignored directories are not
+    ///   descended into.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the paths of the files relative to the workspace directory,
+    /// or an `anyhow::Error` if a directory cannot be read.
+    pub fn list_files_in(&self, dir: &Path, include_ignored: bool) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut error = None;
+        let walker = walkdir::WalkDir::new(self.dir.join(dir))
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| {
+                let path = entry.path().strip_prefix(&self.dir).unwrap_or(entry.path());
+                let ignored = if include_ignored {
+                    path.file_name().is_some_and(|name| name == REPO_DIRNAME)
+                } else {
+                    match self.is_ignored(path, entry.file_type().is_dir()) {
+                        Ok(ignored) => ignored,
+                        Err(err) => {
+                            error.get_or_insert(err);
+                            true
+                        }
+                    }
+                };
+                !ignored
+            });
+        for entry in walker {
+            let entry = entry.context("Failed to walk the working tree.")?;
+            if entry.file_type().is_dir() {
                 continue;
             }
-            println!("{:?}", path);
-            list.push(path);
+            let path = entry.path().strip_prefix(&self.dir)?;
+            files.push(path.to_path_buf());
+        }
+        match error {
+            Some(err) => Err(err),
+            None => Ok(files),
         }
-        list
     }
 }
 